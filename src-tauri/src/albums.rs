@@ -0,0 +1,473 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::database::{Album, Database, ImageFilter, ImageRecord};
+use crate::search_query;
+
+/// Create an album, or a folder to group albums in
+pub fn create_album(
+    db: &Database,
+    name: &str,
+    parent_id: Option<i64>,
+    is_folder: bool,
+) -> Result<Album, String> {
+    let name = validate_name(name)?;
+    if let Some(parent_id) = parent_id {
+        require_folder(db, parent_id)?;
+    }
+
+    let id = db
+        .create_album(name, parent_id, is_folder)
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    load_album(db, id)
+}
+
+/// Create a smart album, whose images are whatever currently matches a search query or filter
+pub fn create_smart_album(
+    db: &Database,
+    name: &str,
+    parent_id: Option<i64>,
+    query: Option<&str>,
+    filter: Option<ImageFilter>,
+) -> Result<Album, String> {
+    let name = validate_name(name)?;
+    if let Some(parent_id) = parent_id {
+        require_folder(db, parent_id)?;
+    }
+    let (query, filter) = smart_filter(query, filter)?;
+
+    let id = db
+        .create_smart_album(name, parent_id, &filter, query)
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    load_album(db, id)
+}
+
+/// Replace the query or filter of a smart album
+pub fn update_smart_album(
+    db: &Database,
+    album_id: i64,
+    query: Option<&str>,
+    filter: Option<ImageFilter>,
+) -> Result<Album, String> {
+    let album = get_album(db, album_id)?;
+    if album.smart_filter.is_none() {
+        return Err(format!("'{}' is not a smart album", album.name));
+    }
+    let (query, filter) = smart_filter(query, filter)?;
+
+    db.set_smart_album_filter(album_id, &filter, query)
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    load_album(db, album_id)
+}
+
+/// All albums and folders, with the current image count of smart albums filled in
+pub fn list_albums(db: &Database) -> Result<Vec<Album>, String> {
+    let mut albums = db.get_albums().map_err(|e| format!("Database error: {}", e))?;
+    for album in &mut albums {
+        count_smart_album(db, album)?;
+    }
+    Ok(albums)
+}
+
+/// Current image count of every smart album by album ID
+pub fn smart_album_counts(db: &Database) -> Result<HashMap<i64, i64>, String> {
+    list_albums(db).map(|albums| {
+        albums
+            .into_iter()
+            .filter(|album| album.smart_filter.is_some())
+            .map(|album| (album.id, album.item_count))
+            .collect()
+    })
+}
+
+pub fn rename_album(db: &Database, album_id: i64, name: &str) -> Result<Album, String> {
+    let name = validate_name(name)?;
+    get_album(db, album_id)?;
+
+    db.rename_album(album_id, name)
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    load_album(db, album_id)
+}
+
+/// Set the cover of an album, or clear it with None
+pub fn set_cover(db: &Database, album_id: i64, image_id: Option<i64>) -> Result<Album, String> {
+    get_album(db, album_id)?;
+    if let Some(image_id) = image_id {
+        require_image(db, image_id)?;
+    }
+
+    db.set_album_cover(album_id, image_id)
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    load_album(db, album_id)
+}
+
+/// Move an album into a folder, or to the top level with None, at a position among its siblings
+pub fn move_album(
+    db: &Database,
+    album_id: i64,
+    parent_id: Option<i64>,
+    position: usize,
+) -> Result<Album, String> {
+    get_album(db, album_id)?;
+
+    if let Some(parent_id) = parent_id {
+        require_folder(db, parent_id)?;
+
+        // Walk up from the new parent to make sure the album is not moved into itself
+        let mut ancestor = Some(parent_id);
+        while let Some(id) = ancestor {
+            if id == album_id {
+                return Err("An album folder cannot be moved into itself".to_string());
+            }
+            ancestor = get_album(db, id)?.parent_id;
+        }
+    }
+
+    db.move_album(album_id, parent_id, position)
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    load_album(db, album_id)
+}
+
+/// Delete an album or folder; the images in it stay in the library
+pub fn delete_album(db: &Database, album_id: i64) -> Result<(), String> {
+    get_album(db, album_id)?;
+
+    db.delete_album(album_id)
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    Ok(())
+}
+
+/// Add images to the end of an album, returning how many were not already in it
+pub fn add_images(db: &Database, album_id: i64, image_ids: &[i64]) -> Result<usize, String> {
+    require_manual_album(db, album_id)?;
+    for &image_id in image_ids {
+        require_image(db, image_id)?;
+    }
+
+    db.add_album_items(album_id, image_ids)
+        .map_err(|e| format!("Database error: {}", e))
+}
+
+/// Remove images from an album, returning how many were in it
+pub fn remove_images(db: &Database, album_id: i64, image_ids: &[i64]) -> Result<usize, String> {
+    require_manual_album(db, album_id)?;
+
+    db.remove_album_items(album_id, image_ids)
+        .map_err(|e| format!("Database error: {}", e))
+}
+
+/// Images in an album, in album order, or matching the filter of a smart album
+pub fn get_images(db: &Database, album_id: i64) -> Result<Vec<ImageRecord>, String> {
+    let album = require_album(db, album_id)?;
+
+    match album.smart_filter {
+        Some(filter) => db.query_images(&filter),
+        None => db.get_album_items(album_id),
+    }
+    .map_err(|e| format!("Database error: {}", e))
+}
+
+/// Reorder an album; `image_ids` must list every image in the album outside the trash exactly once
+pub fn reorder_images(db: &Database, album_id: i64, image_ids: &[i64]) -> Result<(), String> {
+    require_manual_album(db, album_id)?;
+
+    let current: HashSet<i64> = db
+        .get_album_item_ids(album_id)
+        .map_err(|e| format!("Database error: {}", e))?
+        .into_iter()
+        .collect();
+    let requested: HashSet<i64> = image_ids.iter().copied().collect();
+
+    if requested.len() != image_ids.len() {
+        return Err("The new order lists an image more than once".to_string());
+    }
+    if requested != current {
+        return Err(format!(
+            "The new order has {} images but the album has {}, or lists images not in the album",
+            image_ids.len(),
+            current.len()
+        ));
+    }
+
+    db.reorder_album_items(album_id, image_ids)
+        .map_err(|e| format!("Database error: {}", e))
+}
+
+fn get_album(db: &Database, album_id: i64) -> Result<Album, String> {
+    db.get_album(album_id)
+        .map_err(|e| format!("Database error: {}", e))?
+        .ok_or_else(|| format!("Album {} not found", album_id))
+}
+
+/// Get an album with the image count of a smart album filled in
+fn load_album(db: &Database, album_id: i64) -> Result<Album, String> {
+    let mut album = get_album(db, album_id)?;
+    count_smart_album(db, &mut album)?;
+    Ok(album)
+}
+
+fn count_smart_album(db: &Database, album: &mut Album) -> Result<(), String> {
+    if let Some(filter) = &album.smart_filter {
+        album.item_count = db
+            .count_images(filter)
+            .map_err(|e| format!("Database error: {}", e))? as i64;
+    }
+    Ok(())
+}
+
+/// Get an album that can hold images
+fn require_album(db: &Database, album_id: i64) -> Result<Album, String> {
+    let album = get_album(db, album_id)?;
+    if album.is_folder {
+        return Err(format!("'{}' is a folder and can only contain albums", album.name));
+    }
+    Ok(album)
+}
+
+/// Get an album whose images are added and ordered by hand
+fn require_manual_album(db: &Database, album_id: i64) -> Result<Album, String> {
+    let album = require_album(db, album_id)?;
+    if album.smart_filter.is_some() {
+        return Err(format!(
+            "'{}' is a smart album; its images follow its filter",
+            album.name
+        ));
+    }
+    Ok(album)
+}
+
+/// Resolve the filter of a smart album from a search query or a filter, but not both
+fn smart_filter(
+    query: Option<&str>,
+    filter: Option<ImageFilter>,
+) -> Result<(Option<&str>, ImageFilter), String> {
+    match (query.map(str::trim).filter(|q| !q.is_empty()), filter) {
+        (Some(_), Some(_)) => Err("A smart album takes either a query or a filter, not both".to_string()),
+        (Some(query), None) => search_query::parse_query(query)
+            .map(|filter| (Some(query), filter))
+            .map_err(|e| e.to_string()),
+        (None, Some(filter)) => Ok((None, filter)),
+        (None, None) => Err("A smart album needs a query or a filter".to_string()),
+    }
+}
+
+/// Get an album folder that can hold albums
+fn require_folder(db: &Database, folder_id: i64) -> Result<Album, String> {
+    let folder = get_album(db, folder_id)?;
+    if !folder.is_folder {
+        return Err(format!("'{}' is an album, not a folder", folder.name));
+    }
+    Ok(folder)
+}
+
+fn require_image(db: &Database, image_id: i64) -> Result<(), String> {
+    db.get_image_by_id(image_id)
+        .map_err(|e| format!("Database error: {}", e))?
+        .map(|_| ())
+        .ok_or_else(|| format!("Image {} not found", image_id))
+}
+
+fn validate_name(name: &str) -> Result<&str, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Album name cannot be empty".to_string());
+    }
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::MediaType;
+    use chrono::Utc;
+
+    fn create_test_db() -> (tempfile::TempDir, Database, Vec<i64>) {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+
+        let ids = (0..4)
+            .map(|i| {
+                let path = format!("/photos/{}.jpg", i);
+                db.insert_image(
+                    &path, "s.jpg", "m.jpg", &path, MediaType::Image,
+                    None, None, None, None, None, 100, 100, None, None, 1024, Utc::now(),
+                )
+                .unwrap()
+            })
+            .collect();
+
+        (temp_dir, db, ids)
+    }
+
+    fn item_ids(db: &Database, album_id: i64) -> Vec<i64> {
+        get_images(db, album_id).unwrap().iter().map(|i| i.id).collect()
+    }
+
+    #[test]
+    fn test_add_remove_and_reorder_images() {
+        let (_temp_dir, db, ids) = create_test_db();
+        let album = create_album(&db, "  Summer  ", None, false).unwrap();
+        assert_eq!(album.name, "Summer");
+
+        assert_eq!(add_images(&db, album.id, &[ids[2], ids[0]]).unwrap(), 2);
+        // Images already in the album are skipped
+        assert_eq!(add_images(&db, album.id, &[ids[0], ids[1]]).unwrap(), 1);
+        assert_eq!(item_ids(&db, album.id), vec![ids[2], ids[0], ids[1]]);
+
+        reorder_images(&db, album.id, &[ids[1], ids[2], ids[0]]).unwrap();
+        assert_eq!(item_ids(&db, album.id), vec![ids[1], ids[2], ids[0]]);
+
+        assert!(reorder_images(&db, album.id, &[ids[1], ids[2]]).is_err());
+        assert!(reorder_images(&db, album.id, &[ids[1], ids[2], ids[2]]).is_err());
+        assert!(reorder_images(&db, album.id, &[ids[1], ids[2], ids[3]]).is_err());
+
+        assert_eq!(remove_images(&db, album.id, &[ids[2], ids[3]]).unwrap(), 1);
+        assert_eq!(item_ids(&db, album.id), vec![ids[1], ids[0]]);
+        assert_eq!(get_album(&db, album.id).unwrap().item_count, 2);
+    }
+
+    #[test]
+    fn test_reorder_keeps_trashed_images_after_the_rest() {
+        let (_temp_dir, db, ids) = create_test_db();
+        let album = create_album(&db, "Album", None, false).unwrap();
+        add_images(&db, album.id, &ids).unwrap();
+
+        db.trash_image(ids[1], None).unwrap();
+        reorder_images(&db, album.id, &[ids[3], ids[2], ids[0]]).unwrap();
+        db.restore_image(ids[1]).unwrap();
+
+        assert_eq!(item_ids(&db, album.id), vec![ids[3], ids[2], ids[0], ids[1]]);
+    }
+
+    #[test]
+    fn test_folders_and_album_order() {
+        let (_temp_dir, db, ids) = create_test_db();
+        let trips = create_album(&db, "Trips", None, true).unwrap();
+        let italy = create_album(&db, "Italy", Some(trips.id), false).unwrap();
+        let japan = create_album(&db, "Japan", Some(trips.id), false).unwrap();
+        let loose = create_album(&db, "Loose", None, false).unwrap();
+        assert_eq!((italy.sort_order, japan.sort_order), (0, 1));
+
+        // Folders hold albums, albums hold images
+        assert!(add_images(&db, trips.id, &[ids[0]]).is_err());
+        assert!(create_album(&db, "Nested", Some(italy.id), false).is_err());
+        assert!(move_album(&db, trips.id, Some(trips.id), 0).is_err());
+
+        let moved = move_album(&db, loose.id, Some(trips.id), 0).unwrap();
+        assert_eq!(moved.parent_id, Some(trips.id));
+
+        let children: Vec<String> = db
+            .get_albums()
+            .unwrap()
+            .into_iter()
+            .filter(|a| a.parent_id == Some(trips.id))
+            .map(|a| a.name)
+            .collect();
+        assert_eq!(children, vec!["Loose", "Italy", "Japan"]);
+
+        let inner = create_album(&db, "Inner", Some(trips.id), true).unwrap();
+        assert!(move_album(&db, trips.id, Some(inner.id), 0).is_err());
+    }
+
+    #[test]
+    fn test_deleting_albums_and_images() {
+        let (_temp_dir, db, ids) = create_test_db();
+        let folder = create_album(&db, "Folder", None, true).unwrap();
+        let album = create_album(&db, "Album", Some(folder.id), false).unwrap();
+        add_images(&db, album.id, &ids).unwrap();
+        set_cover(&db, album.id, Some(ids[1])).unwrap();
+
+        // Deleting an image removes it from albums and clears it as a cover
+        db.delete_image(ids[1]).unwrap();
+        assert_eq!(item_ids(&db, album.id), vec![ids[0], ids[2], ids[3]]);
+        assert_eq!(get_album(&db, album.id).unwrap().cover_image_id, None);
+
+        // Deleting a folder deletes its albums but keeps the images
+        delete_album(&db, folder.id).unwrap();
+        assert!(db.get_album(album.id).unwrap().is_none());
+        assert!(db.get_album_item_ids(album.id).unwrap().is_empty());
+        assert!(db.get_image_by_id(ids[0]).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let (_temp_dir, db, _ids) = create_test_db();
+        let album = create_album(&db, "Album", None, false).unwrap();
+
+        assert!(create_album(&db, "   ", None, false).is_err());
+        assert!(rename_album(&db, album.id, "").is_err());
+        assert!(set_cover(&db, album.id, Some(9999)).is_err());
+        assert!(add_images(&db, album.id, &[9999]).is_err());
+        assert!(delete_album(&db, 9999).is_err());
+
+        assert_eq!(rename_album(&db, album.id, "Renamed").unwrap().name, "Renamed");
+    }
+
+    #[test]
+    fn test_smart_album_follows_library() {
+        let (_temp_dir, db, ids) = create_test_db();
+        let insert_video = |path: &str, make: &str, date: &str, duration: f64| {
+            let date = chrono::DateTime::parse_from_rfc3339(date).unwrap().with_timezone(&Utc);
+            db.insert_image(
+                path, "s.jpg", "m.jpg", path, MediaType::Video,
+                Some(date), Some(make), None, None, None, 100, 100, Some(duration), None, 1024, Utc::now(),
+            )
+            .unwrap()
+        };
+        let long_sony = insert_video("/videos/a.mp4", "Sony", "2024-05-01T10:00:00Z", 95.0);
+        insert_video("/videos/b.mp4", "Sony", "2024-05-01T10:00:00Z", 20.0);
+        insert_video("/videos/c.mp4", "Sony", "2023-05-01T10:00:00Z", 95.0);
+
+        let query = "type:video duration>1m make:sony date:2024";
+        let smart = create_smart_album(&db, "Long Sony clips", None, Some(query), None).unwrap();
+        assert_eq!(smart.smart_query.as_deref(), Some(query));
+        assert_eq!(smart.item_count, 1);
+        assert_eq!(item_ids(&db, smart.id), vec![long_sony]);
+
+        // Newly imported matches show up in the album and its count
+        let later = insert_video("/videos/d.mp4", "SONY", "2024-09-01T10:00:00Z", 300.0);
+        assert_eq!(smart_album_counts(&db).unwrap()[&smart.id], 2);
+        assert_eq!(item_ids(&db, smart.id), vec![later, long_sony]);
+
+        // The stored filter round-trips through the database
+        let stored = db.get_album(smart.id).unwrap().unwrap();
+        assert_eq!(stored.smart_filter, Some(search_query::parse_query(query).unwrap()));
+
+        let filter = ImageFilter {
+            media_type: Some(MediaType::Image),
+            ..Default::default()
+        };
+        let updated = update_smart_album(&db, smart.id, None, Some(filter)).unwrap();
+        assert_eq!(updated.smart_query, None);
+        assert_eq!(updated.item_count, ids.len() as i64);
+
+        // Smart albums are not edited by hand
+        assert!(add_images(&db, smart.id, &[ids[0]]).is_err());
+        assert!(reorder_images(&db, smart.id, &ids).is_err());
+    }
+
+    #[test]
+    fn test_smart_album_validation() {
+        let (_temp_dir, db, _ids) = create_test_db();
+        let folder = create_album(&db, "Folder", None, true).unwrap();
+        let album = create_album(&db, "Album", None, false).unwrap();
+
+        let smart = create_smart_album(&db, "Beach", Some(folder.id), Some("tag:beach"), None).unwrap();
+        assert_eq!(smart.parent_id, Some(folder.id));
+        assert_eq!(list_albums(&db).unwrap().len(), 3);
+
+        assert!(create_smart_album(&db, "None", None, None, None).is_err());
+        assert!(create_smart_album(&db, "Blank", None, Some("  "), None).is_err());
+        assert!(create_smart_album(&db, "Both", None, Some("tag:a"), Some(ImageFilter::default())).is_err());
+        assert!(create_smart_album(&db, "Bad", None, Some("type:audio"), None).is_err());
+        assert!(create_smart_album(&db, "Nested", Some(album.id), Some("tag:a"), None).is_err());
+        assert!(update_smart_album(&db, album.id, Some("tag:a"), None).is_err());
+        assert!(update_smart_album(&db, smart.id, Some("after:someday"), None).is_err());
+    }
+}
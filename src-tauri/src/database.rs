@@ -1,10 +1,15 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
 use rusqlite::{params, Connection, Result, Row};
 use serde::{Deserialize, Serialize};
+use std::ops::Bound;
 use std::path::PathBuf;
 use std::sync::Mutex;
 
+use crate::edits::EditRecipe;
+use crate::geo::{self, GeoBounds};
+use crate::metadata::{CaptureSettings, ImageMetadata, VideoProperties};
 use crate::migrations;
+use crate::storage::UploadSession;
 
 /// Media type enum
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -30,6 +35,71 @@ impl MediaType {
     }
 }
 
+/// Color label used to mark images while culling
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorLabel {
+    Red,
+    Yellow,
+    Green,
+    Blue,
+    Purple,
+}
+
+impl ColorLabel {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ColorLabel::Red => "red",
+            ColorLabel::Yellow => "yellow",
+            ColorLabel::Green => "green",
+            ColorLabel::Blue => "blue",
+            ColorLabel::Purple => "purple",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "red" => Some(ColorLabel::Red),
+            "yellow" => Some(ColorLabel::Yellow),
+            "green" => Some(ColorLabel::Green),
+            "blue" => Some(ColorLabel::Blue),
+            "purple" => Some(ColorLabel::Purple),
+            _ => None,
+        }
+    }
+}
+
+/// Pick/reject flag used to mark images while culling
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Flag {
+    #[default]
+    Unflagged,
+    Pick,
+    Reject,
+}
+
+impl Flag {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Flag::Unflagged => "unflagged",
+            Flag::Pick => "pick",
+            Flag::Reject => "reject",
+        }
+    }
+
+    pub fn parse(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "pick" => Flag::Pick,
+            "reject" => Flag::Reject,
+            _ => Flag::Unflagged,
+        }
+    }
+}
+
+/// Highest star rating
+pub const MAX_RATING: u8 = 5;
+
 /// Image record stored in database (also handles video records)
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ImageRecord {
@@ -53,6 +123,38 @@ pub struct ImageRecord {
     pub created_at: DateTime<Utc>,
     pub synced_at: Option<DateTime<Utc>>,
     pub sync_status: String,
+    /// Upload quality the file was last synced at: "original", "high" or "medium"
+    pub sync_quality: Option<String>,
+    /// Place names resolved from the GPS coordinates
+    pub country: Option<String>,
+    pub region: Option<String>,
+    pub city: Option<String>,
+    /// Star rating from 0 (unrated) to `MAX_RATING`
+    pub rating: u8,
+    pub color_label: Option<ColorLabel>,
+    pub flag: Flag,
+    pub favorite: bool,
+    /// When the image was moved to the trash, None if it is in the library
+    pub trashed_at: Option<DateTime<Utc>>,
+    /// Where the original file was moved while in the trash, if it was moved
+    pub trash_path: Option<String>,
+    /// Caption written by the user or read from an XMP sidecar
+    pub description: Option<String>,
+    /// Exposure and lens settings from EXIF
+    #[serde(flatten)]
+    pub capture_settings: CaptureSettings,
+    /// Frame rate, rotation and audio track of videos
+    #[serde(flatten)]
+    pub video_properties: VideoProperties,
+}
+
+impl ImageRecord {
+    /// When the photo was taken on the clock where it was taken, as far as the camera recorded its offset
+    pub fn local_capture_date(&self) -> Option<NaiveDateTime> {
+        let offset = self.capture_settings.utc_offset_minutes.unwrap_or(0);
+        self.capture_date
+            .map(|date| date.naive_utc() + Duration::minutes(i64::from(offset)))
+    }
 }
 
 /// Tag associated with an image
@@ -65,14 +167,64 @@ pub struct Tag {
     pub created_at: DateTime<Utc>,
 }
 
+/// Album, or album folder, with its manually ordered position among its siblings
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Album {
+    pub id: i64,
+    pub name: String,
+    /// Folder containing the album, None at the top level
+    pub parent_id: Option<i64>,
+    /// Folders hold other albums instead of images
+    pub is_folder: bool,
+    pub cover_image_id: Option<i64>,
+    pub sort_order: i64,
+    /// Number of images in the album
+    pub item_count: i64,
+    /// Filter that selects the images of a smart album, None for regular albums
+    pub smart_filter: Option<ImageFilter>,
+    /// Search query the smart filter was parsed from, if it was typed
+    pub smart_query: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
 /// Filter criteria for querying images
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
 pub struct ImageFilter {
+    /// Local capture time range, as wall-clock times in UTC: a photo taken at
+    /// 23:30 in UTC+2 falls on that day rather than the next
     pub date_range: Option<(DateTime<Utc>, DateTime<Utc>)>,
     pub location: Option<(f64, f64, f64)>, // (latitude, longitude, radius_km)
+    /// Map viewport to restrict results to
+    pub bounds: Option<GeoBounds>,
+    pub country: Option<String>,
+    pub region: Option<String>,
+    pub city: Option<String>,
     pub tags: Option<Vec<String>>,
     pub camera_model: Option<String>,
+    pub camera_make: Option<String>,
     pub media_type: Option<MediaType>, // Filter by media type (image/video)
+    /// Video duration in seconds
+    pub duration_range: Option<(Bound<f64>, Bound<f64>)>,
+    /// Aperture as an f-number
+    pub aperture_range: Option<(Bound<f64>, Bound<f64>)>,
+    /// Focal length in millimeters, as recorded by the camera
+    pub focal_length_range: Option<(Bound<f64>, Bound<f64>)>,
+    pub iso_range: Option<(Bound<f64>, Bound<f64>)>,
+    /// Exposure time in seconds
+    pub exposure_time_range: Option<(Bound<f64>, Bound<f64>)>,
+    /// Part of the lens model, ignoring case
+    pub lens: Option<String>,
+    /// Whether the flash fired
+    pub flash: Option<bool>,
+    /// Full-text search over file names, folders, tags and camera; results are ranked by relevance
+    pub text: Option<String>,
+    /// Inclusive range of star ratings
+    pub rating_range: Option<(u8, u8)>,
+    pub color_label: Option<ColorLabel>,
+    pub flag: Option<Flag>,
+    pub favorite: Option<bool>,
 }
 
 pub struct Database {
@@ -168,14 +320,11 @@ impl Database {
     pub fn get_image_by_id(&self, id: i64) -> Result<Option<ImageRecord>> {
         let conn = self.conn.lock().unwrap();
         
-        let mut stmt = conn.prepare(
-            "SELECT id, path, media_type, thumbnail_small, thumbnail_medium, checksum,
-                    capture_date, camera_make, camera_model,
-                    gps_latitude, gps_longitude, width, height,
-                    duration_seconds, video_codec,
-                    file_size, file_modified, created_at, synced_at, sync_status
-             FROM images WHERE id = ?1"
-        )?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {}
+             FROM images WHERE id = ?1",
+            IMAGE_COLUMNS
+        ))?;
 
         let result = stmt.query_row(params![id], |row| Ok(parse_image_row(row)?));
 
@@ -190,14 +339,11 @@ impl Database {
     pub fn get_image_by_path(&self, path: &str) -> Result<Option<ImageRecord>> {
         let conn = self.conn.lock().unwrap();
         
-        let mut stmt = conn.prepare(
-            "SELECT id, path, media_type, thumbnail_small, thumbnail_medium, checksum,
-                    capture_date, camera_make, camera_model,
-                    gps_latitude, gps_longitude, width, height,
-                    duration_seconds, video_codec,
-                    file_size, file_modified, created_at, synced_at, sync_status
-             FROM images WHERE path = ?1"
-        )?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {}
+             FROM images WHERE path = ?1",
+            IMAGE_COLUMNS
+        ))?;
 
         let result = stmt.query_row(params![path], |row| Ok(parse_image_row(row)?));
 
@@ -212,14 +358,11 @@ impl Database {
     pub fn get_image_by_checksum(&self, checksum: &str) -> Result<Option<ImageRecord>> {
         let conn = self.conn.lock().unwrap();
         
-        let mut stmt = conn.prepare(
-            "SELECT id, path, media_type, thumbnail_small, thumbnail_medium, checksum,
-                    capture_date, camera_make, camera_model,
-                    gps_latitude, gps_longitude, width, height,
-                    duration_seconds, video_codec,
-                    file_size, file_modified, created_at, synced_at, sync_status
-             FROM images WHERE checksum = ?1"
-        )?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {}
+             FROM images WHERE checksum = ?1",
+            IMAGE_COLUMNS
+        ))?;
 
         let result = stmt.query_row(params![checksum], |row| Ok(parse_image_row(row)?));
 
@@ -233,84 +376,58 @@ impl Database {
     /// Query images with filters
     pub fn query_images(&self, filter: &ImageFilter) -> Result<Vec<ImageRecord>> {
         let conn = self.conn.lock().unwrap();
-        
-        let mut query = String::from(
-            "SELECT DISTINCT i.id, i.path, i.media_type, i.thumbnail_small, i.thumbnail_medium, i.checksum,
-                    i.capture_date, i.camera_make, i.camera_model,
-                    i.gps_latitude, i.gps_longitude, i.width, i.height,
-                    i.duration_seconds, i.video_codec,
-                    i.file_size, i.file_modified, i.created_at, i.synced_at, i.sync_status
-             FROM images i"
-        );
+        let clauses = FilterClauses::new(filter);
 
-        let mut conditions = Vec::new();
-        let mut params_vec: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+        let order = if clauses.ranked {
+            "f.rank, i.capture_date DESC"
+        } else {
+            "i.capture_date DESC"
+        };
+        let query = format!(
+            "SELECT DISTINCT {} FROM images i{} WHERE {} ORDER BY {}",
+            *IMAGE_COLUMNS_ALIASED, clauses.joins, clauses.conditions, order
+        );
 
-        // Add tag filter if specified
-        if let Some(tags) = &filter.tags {
-            if !tags.is_empty() {
-                query.push_str(" INNER JOIN tags t ON i.id = t.image_id");
-                let placeholders = tags.iter().map(|_| "?").collect::<Vec<_>>().join(",");
-                conditions.push(format!("t.label IN ({})", placeholders));
-                for tag in tags {
-                    params_vec.push(Box::new(tag.clone()));
-                }
-            }
-        }
+        let mut stmt = conn.prepare(&query)?;
+        let mut images = stmt.query_map(clauses.params().as_slice(), parse_image_row)?
+            .collect::<Result<Vec<_>, _>>()?;
 
-        // Add media type filter
-        if let Some(media_type) = &filter.media_type {
-            conditions.push("i.media_type = ?".to_string());
-            params_vec.push(Box::new(media_type.as_str().to_string()));
-        }
+        images.retain(|image| within_location(filter, image.gps_latitude, image.gps_longitude));
 
-        // Add date range filter
-        if let Some((start, end)) = &filter.date_range {
-            conditions.push("i.capture_date BETWEEN ? AND ?".to_string());
-            params_vec.push(Box::new(start.to_rfc3339()));
-            params_vec.push(Box::new(end.to_rfc3339()));
-        }
+        Ok(images)
+    }
 
-        // Add location filter (simple bounding box for now)
-        if let Some((lat, lon, radius_km)) = &filter.location {
-            // Approximate degrees per km (at equator)
-            let degrees_per_km = 1.0 / 111.0;
-            let lat_delta = radius_km * degrees_per_km;
-            let lon_delta = radius_km * degrees_per_km / lat.cos();
+    /// Count the images matching a filter, without loading them
+    pub fn count_images(&self, filter: &ImageFilter) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let clauses = FilterClauses::new(filter);
 
-            conditions.push(
-                "i.gps_latitude BETWEEN ? AND ? AND i.gps_longitude BETWEEN ? AND ?".to_string()
+        // Exact positions are checked here, so only those are loaded
+        if filter.location.is_some() || filter.bounds.is_some() {
+            let query = format!(
+                "SELECT DISTINCT i.id, i.gps_latitude, i.gps_longitude FROM images i{} WHERE {}",
+                clauses.joins, clauses.conditions
             );
-            params_vec.push(Box::new(lat - lat_delta));
-            params_vec.push(Box::new(lat + lat_delta));
-            params_vec.push(Box::new(lon - lon_delta));
-            params_vec.push(Box::new(lon + lon_delta));
-        }
-
-        // Add camera model filter
-        if let Some(model) = &filter.camera_model {
-            conditions.push("i.camera_model = ?".to_string());
-            params_vec.push(Box::new(model.clone()));
-        }
-
-        // Build WHERE clause
-        if !conditions.is_empty() {
-            query.push_str(" WHERE ");
-            query.push_str(&conditions.join(" AND "));
+            let mut stmt = conn.prepare(&query)?;
+            let positions = stmt
+                .query_map(clauses.params().as_slice(), |row| {
+                    Ok((row.get::<_, Option<f64>>(1)?, row.get::<_, Option<f64>>(2)?))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            return Ok(positions
+                .into_iter()
+                .filter(|&(lat, lon)| within_location(filter, lat, lon))
+                .count());
         }
 
-        query.push_str(" ORDER BY i.capture_date DESC");
-
-        let mut stmt = conn.prepare(&query)?;
-        
-        let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec.iter().map(|p| p.as_ref()).collect();
-        
-        let images = stmt.query_map(params_refs.as_slice(), |row| {
-            Ok(parse_image_row(row)?)
-        })?
-        .collect::<Result<Vec<_>, _>>()?;
+        let query = format!(
+            "SELECT COUNT(DISTINCT i.id) FROM images i{} WHERE {}",
+            clauses.joins, clauses.conditions
+        );
+        let count: i64 = conn.query_row(&query, clauses.params().as_slice(), |row| row.get(0))?;
 
-        Ok(images)
+        Ok(count as usize)
     }
 
     /// Get tags for an image
@@ -333,50 +450,1219 @@ impl Database {
         })?
         .collect::<Result<Vec<_>, _>>()?;
 
-        Ok(tags)
-    }
+        Ok(tags)
+    }
+
+    /// Update image path (for handling file moves)
+    pub fn update_image_path(&self, checksum: &str, new_path: &str) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        
+        let updated = conn.execute(
+            "UPDATE images SET path = ?1 WHERE checksum = ?2",
+            params![new_path, checksum],
+        )?;
+
+        Ok(updated)
+    }
+
+    /// Update the content-derived fields of an image after its file changed on disk
+    /// The record keeps its ID (and therefore tags and embeddings) and is marked for re-sync
+    #[allow(clippy::too_many_arguments)]
+    pub fn update_image_content(
+        &self,
+        id: i64,
+        thumbnail_small: &str,
+        thumbnail_medium: &str,
+        checksum: &str,
+        capture_date: Option<DateTime<Utc>>,
+        camera_make: Option<&str>,
+        camera_model: Option<&str>,
+        gps_latitude: Option<f64>,
+        gps_longitude: Option<f64>,
+        width: u32,
+        height: u32,
+        duration_seconds: Option<f64>,
+        video_codec: Option<&str>,
+        file_size: u64,
+        file_modified: DateTime<Utc>,
+    ) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+
+        let updated = conn.execute(
+            "UPDATE images SET
+                thumbnail_small = ?1, thumbnail_medium = ?2, checksum = ?3,
+                capture_date = ?4, camera_make = ?5, camera_model = ?6,
+                gps_latitude = ?7, gps_longitude = ?8, width = ?9, height = ?10,
+                duration_seconds = ?11, video_codec = ?12,
+                file_size = ?13, file_modified = ?14,
+                sync_status = 'pending', synced_at = NULL
+             WHERE id = ?15",
+            params![
+                thumbnail_small,
+                thumbnail_medium,
+                checksum,
+                capture_date.map(|dt| dt.to_rfc3339()),
+                camera_make,
+                camera_model,
+                gps_latitude,
+                gps_longitude,
+                width,
+                height,
+                duration_seconds,
+                video_codec,
+                file_size as i64,
+                file_modified.to_rfc3339(),
+                id,
+            ],
+        )?;
+
+        Ok(updated)
+    }
+
+    /// Update the recorded size and modification time of an unchanged file
+    pub fn update_file_stats(
+        &self,
+        id: i64,
+        file_size: u64,
+        file_modified: DateTime<Utc>,
+    ) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+
+        let updated = conn.execute(
+            "UPDATE images SET file_size = ?1, file_modified = ?2 WHERE id = ?3",
+            params![file_size as i64, file_modified.to_rfc3339(), id],
+        )?;
+
+        Ok(updated)
+    }
+
+    /// Record a file whose metadata was rewritten without changing the image
+    pub fn update_file_checksum(
+        &self,
+        id: i64,
+        checksum: &str,
+        file_size: u64,
+        file_modified: DateTime<Utc>,
+    ) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+
+        let updated = conn.execute(
+            "UPDATE images SET checksum = ?1, file_size = ?2, file_modified = ?3 WHERE id = ?4",
+            params![checksum, file_size as i64, file_modified.to_rfc3339(), id],
+        )?;
+
+        Ok(updated)
+    }
+
+    /// Update the path of a single image (for renames detected by path)
+    pub fn rename_image_path(&self, old_path: &str, new_path: &str) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+
+        let updated = conn.execute(
+            "UPDATE images SET path = ?1 WHERE path = ?2",
+            params![new_path, old_path],
+        )?;
+
+        Ok(updated)
+    }
+
+    /// Rewrite the paths of every image below a folder that was moved or renamed
+    pub fn rename_folder_paths(&self, old_folder: &str, new_folder: &str) -> Result<usize> {
+        let images = self.get_images_under_path(old_folder)?;
+        let old_folder = old_folder.trim_end_matches(['/', '\\']);
+        let new_folder = new_folder.trim_end_matches(['/', '\\']);
+
+        let conn = self.conn.lock().unwrap();
+        let tx = conn.unchecked_transaction()?;
+
+        let mut updated = 0;
+        for image in images {
+            let new_path = format!("{}{}", new_folder, &image.path[old_folder.len()..]);
+            updated += tx.execute(
+                "UPDATE images SET path = ?1 WHERE id = ?2",
+                params![new_path, image.id],
+            )?;
+        }
+
+        tx.commit()?;
+
+        Ok(updated)
+    }
+
+    /// Get all images stored below a folder (recursively)
+    pub fn get_images_under_path(&self, folder: &str) -> Result<Vec<ImageRecord>> {
+        let conn = self.conn.lock().unwrap();
+
+        // Match "<folder>/<anything>" case-sensitively, unlike LIKE
+        let prefix = format!("{}{}", folder.trim_end_matches(['/', '\\']), std::path::MAIN_SEPARATOR);
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {}
+             FROM images WHERE substr(path, 1, length(?1)) = ?1",
+            IMAGE_COLUMNS
+        ))?;
+
+        let images = stmt.query_map(params![prefix], parse_image_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(images)
+    }
+
+    /// Count the images that share a checksum
+    pub fn count_images_by_checksum(&self, checksum: &str) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.query_row(
+            "SELECT COUNT(*) FROM images WHERE checksum = ?1",
+            params![checksum],
+            |row| row.get(0),
+        )
+    }
+
+    /// Save an embedding for an image, replacing any previous one
+    pub fn save_embedding(&self, image_id: i64, embedding: &[f32], model_version: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            "INSERT OR REPLACE INTO embeddings (image_id, embedding, model_version) VALUES (?1, ?2, ?3)",
+            params![image_id, encode_embedding(embedding), model_version],
+        )?;
+
+        Ok(())
+    }
+
+    /// Get the embedding of an image for a model version
+    pub fn get_embedding(&self, image_id: i64, model_version: &str) -> Result<Option<Vec<f32>>> {
+        let conn = self.conn.lock().unwrap();
+
+        let result = conn.query_row(
+            "SELECT embedding FROM embeddings WHERE image_id = ?1 AND model_version = ?2",
+            params![image_id, model_version],
+            |row| row.get::<_, Vec<u8>>(0),
+        );
+
+        match result {
+            Ok(bytes) => Ok(Some(decode_embedding(&bytes))),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the embeddings of images outside the trash as (image_id, embedding),
+    /// optionally for one model version only
+    pub fn get_embeddings(&self, model_version: Option<&str>) -> Result<Vec<(i64, Vec<f32>)>> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn.prepare(
+            "SELECT e.image_id, e.embedding FROM embeddings e
+             JOIN images i ON i.id = e.image_id
+             WHERE (?1 IS NULL OR e.model_version = ?1) AND i.trashed_at IS NULL
+             ORDER BY e.image_id"
+        )?;
+
+        let embeddings = stmt
+            .query_map(params![model_version], |row| {
+                Ok((row.get(0)?, decode_embedding(&row.get::<_, Vec<u8>>(1)?)))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(embeddings)
+    }
+
+    /// Get a value that changes whenever the embeddings of a model version change
+    ///
+    /// Returns (row count, highest row ID, sum of row IDs) over the embeddings
+    /// of images outside the trash. Row IDs are never reused, so any insert or
+    /// replace raises the highest ID, any delete or trashing lowers the count
+    /// and restoring one image while trashing another changes the sum.
+    pub fn get_embeddings_signature(&self, model_version: &str) -> Result<(i64, i64, i64)> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.query_row(
+            "SELECT COUNT(*), COALESCE(MAX(e.id), 0), COALESCE(SUM(e.id), 0) FROM embeddings e
+             JOIN images i ON i.id = e.image_id
+             WHERE e.model_version = ?1 AND i.trashed_at IS NULL",
+            params![model_version],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+    }
+
+    /// Get every image whose checksum is shared with another image, grouped by checksum
+    pub fn get_images_with_duplicate_checksums(&self) -> Result<Vec<ImageRecord>> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {}
+             FROM images
+             WHERE trashed_at IS NULL AND checksum IN (
+                 SELECT checksum FROM images WHERE trashed_at IS NULL
+                 GROUP BY checksum HAVING COUNT(*) > 1
+             )
+             ORDER BY checksum, path",
+            IMAGE_COLUMNS
+        ))?;
+
+        let images = stmt.query_map([], parse_image_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(images)
+    }
+
+    /// Save the perceptual hashes of an image, replacing any previous ones
+    pub fn save_perceptual_hash(&self, image_id: i64, dhash: u64, phash: u64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            "INSERT OR REPLACE INTO perceptual_hashes (image_id, dhash, phash)
+             VALUES (?1, ?2, ?3)",
+            params![image_id, dhash as i64, phash as i64],
+        )?;
+
+        Ok(())
+    }
+
+    /// Get the perceptual hashes of images outside the trash as (image_id, dhash, phash)
+    pub fn get_perceptual_hashes(&self) -> Result<Vec<(i64, u64, u64)>> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn.prepare(
+            "SELECT h.image_id, h.dhash, h.phash FROM perceptual_hashes h
+             JOIN images i ON i.id = h.image_id
+             WHERE i.trashed_at IS NULL"
+        )?;
+
+        let hashes = stmt
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get::<_, i64>(1)? as u64, row.get::<_, i64>(2)? as u64))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(hashes)
+    }
+
+    /// Get images that have not been perceptually hashed yet
+    pub fn get_images_without_perceptual_hash(&self) -> Result<Vec<ImageRecord>> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {}
+             FROM images i
+             LEFT JOIN perceptual_hashes h ON h.image_id = i.id
+             WHERE h.image_id IS NULL AND i.media_type = 'image' AND i.trashed_at IS NULL",
+            *IMAGE_COLUMNS_ALIASED
+        ))?;
+
+        let images = stmt.query_map([], parse_image_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(images)
+    }
+
+    /// Store the place names resolved from an image's coordinates, marking it as looked up
+    pub fn set_image_place(
+        &self,
+        image_id: i64,
+        country: Option<&str>,
+        region: Option<&str>,
+        city: Option<&str>,
+    ) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+
+        let updated = conn.execute(
+            "UPDATE images SET country = ?1, region = ?2, city = ?3, place_looked_up = 1 WHERE id = ?4",
+            params![country, region, city, image_id],
+        )?;
+
+        Ok(updated)
+    }
+
+    /// Set the star rating of several images, returning how many were updated
+    pub fn set_images_rating(&self, image_ids: &[i64], rating: u8) -> Result<usize> {
+        self.update_images_column(image_ids, "rating", &rating)
+    }
+
+    /// Set or clear the color label of several images
+    pub fn set_images_color_label(&self, image_ids: &[i64], label: Option<ColorLabel>) -> Result<usize> {
+        self.update_images_column(image_ids, "color_label", &label.map(|l| l.as_str().to_string()))
+    }
+
+    /// Set the pick/reject flag of several images
+    pub fn set_images_flag(&self, image_ids: &[i64], flag: Flag) -> Result<usize> {
+        self.update_images_column(image_ids, "flag", &flag.as_str())
+    }
+
+    /// Mark or unmark several images as favorites
+    pub fn set_images_favorite(&self, image_ids: &[i64], favorite: bool) -> Result<usize> {
+        self.update_images_column(image_ids, "favorite", &favorite)
+    }
+
+    /// Set or clear the description of several images
+    pub fn set_images_description(&self, image_ids: &[i64], description: Option<&str>) -> Result<usize> {
+        self.update_images_column(image_ids, "description", &description)
+    }
+
+    /// Replace what was read from a file's metadata, leaving its content and sync state alone
+    pub fn update_image_metadata(&self, image_id: i64, metadata: &ImageMetadata) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+
+        let updated = conn.execute(
+            "UPDATE images SET
+                capture_date = ?1, camera_make = ?2, camera_model = ?3,
+                gps_latitude = ?4, gps_longitude = ?5, width = ?6, height = ?7,
+                duration_seconds = ?8, video_codec = ?9
+             WHERE id = ?10",
+            params![
+                metadata.capture_date.map(|dt| dt.to_rfc3339()),
+                metadata.camera_make,
+                metadata.camera_model,
+                metadata.gps_latitude,
+                metadata.gps_longitude,
+                metadata.width,
+                metadata.height,
+                metadata.duration_seconds,
+                metadata.video_codec,
+                image_id,
+            ],
+        )?;
+
+        Ok(updated)
+    }
+
+    /// Record the version of the metadata extraction an image's file was read with
+    pub fn set_metadata_version(&self, image_id: i64, version: i64) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            "UPDATE images SET metadata_version = ?1 WHERE id = ?2",
+            params![version, image_id],
+        )
+    }
+
+    /// Images whose files were read by an older metadata extraction than `version`
+    pub fn get_images_with_metadata_before(&self, version: i64) -> Result<Vec<ImageRecord>> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {}
+             FROM images
+             WHERE metadata_version < ?1 AND trashed_at IS NULL
+             ORDER BY id",
+            IMAGE_COLUMNS
+        ))?;
+
+        let images = stmt.query_map(params![version], parse_image_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(images)
+    }
+
+    /// Store the exposure and lens settings read from an image's EXIF
+    pub fn set_capture_settings(&self, image_id: i64, settings: &CaptureSettings) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+
+        let updated = conn.execute(
+            "UPDATE images SET exposure_time = ?1, aperture = ?2, iso = ?3, focal_length = ?4,
+                    focal_length_35mm = ?5, lens_model = ?6, flash = ?7, orientation = ?8,
+                    utc_offset_minutes = ?9
+             WHERE id = ?10",
+            params![
+                settings.exposure_time,
+                settings.aperture,
+                settings.iso,
+                settings.focal_length,
+                settings.focal_length_35mm,
+                settings.lens_model,
+                settings.flash,
+                settings.orientation,
+                settings.utc_offset_minutes,
+                image_id,
+            ],
+        )?;
+
+        Ok(updated)
+    }
+
+    /// Store the stream properties read from a video's container
+    pub fn set_video_properties(&self, image_id: i64, properties: &VideoProperties) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+
+        let updated = conn.execute(
+            "UPDATE images SET frame_rate = ?1, bit_rate = ?2, rotation = ?3, audio_codec = ?4,
+                    audio_channels = ?5
+             WHERE id = ?6",
+            params![
+                properties.frame_rate,
+                properties.bit_rate.map(|bit_rate| bit_rate as i64),
+                properties.rotation,
+                properties.audio_codec,
+                properties.audio_channels,
+                image_id,
+            ],
+        )?;
+
+        Ok(updated)
+    }
+
+    /// Set one column to the same value on several images in a single transaction
+    fn update_images_column(&self, image_ids: &[i64], column: &str, value: &dyn rusqlite::ToSql) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let tx = conn.unchecked_transaction()?;
+
+        let mut updated = 0;
+        {
+            let mut stmt = tx.prepare(&format!("UPDATE images SET {} = ?1 WHERE id = ?2", column))?;
+            for image_id in image_ids {
+                updated += stmt.execute(params![value, image_id])?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(updated)
+    }
+
+    /// Get geotagged images whose place has not been looked up yet, as (id, latitude, longitude)
+    pub fn get_images_without_place(&self) -> Result<Vec<(i64, f64, f64)>> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn.prepare(
+            "SELECT id, gps_latitude, gps_longitude FROM images
+             WHERE place_looked_up = 0 AND gps_latitude IS NOT NULL AND gps_longitude IS NOT NULL"
+        )?;
+
+        let images = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(images)
+    }
+
+    /// Create an album or album folder after the existing albums of its parent
+    pub fn create_album(&self, name: &str, parent_id: Option<i64>, is_folder: bool) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            "INSERT INTO albums (name, parent_id, is_folder, sort_order)
+             VALUES (?1, ?2, ?3, (SELECT COALESCE(MAX(sort_order), -1) + 1 FROM albums WHERE parent_id IS ?2))",
+            params![name, parent_id, is_folder],
+        )?;
+
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Get an album by ID
+    pub fn get_album(&self, id: i64) -> Result<Option<Album>> {
+        let conn = self.conn.lock().unwrap();
+
+        let result = conn.query_row(
+            &format!("{} WHERE a.id = ?1", ALBUM_SELECT),
+            params![id],
+            parse_album_row,
+        );
+
+        match result {
+            Ok(album) => Ok(Some(album)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get all albums and folders, each level in its manual order
+    pub fn get_albums(&self) -> Result<Vec<Album>> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn.prepare(&format!("{} ORDER BY a.parent_id, a.sort_order, a.id", ALBUM_SELECT))?;
+        let albums = stmt.query_map([], parse_album_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(albums)
+    }
+
+    /// Create a smart album after the existing albums of its parent
+    pub fn create_smart_album(
+        &self,
+        name: &str,
+        parent_id: Option<i64>,
+        filter: &ImageFilter,
+        query: Option<&str>,
+    ) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            "INSERT INTO albums (name, parent_id, smart_filter, smart_query, sort_order)
+             VALUES (?1, ?2, ?3, ?4, (SELECT COALESCE(MAX(sort_order), -1) + 1 FROM albums WHERE parent_id IS ?2))",
+            params![name, parent_id, encode_filter(filter)?, query],
+        )?;
+
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Replace the filter of a smart album
+    pub fn set_smart_album_filter(&self, id: i64, filter: &ImageFilter, query: Option<&str>) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            "UPDATE albums SET smart_filter = ?1, smart_query = ?2, updated_at = CURRENT_TIMESTAMP
+             WHERE id = ?3 AND smart_filter IS NOT NULL",
+            params![encode_filter(filter)?, query, id],
+        )
+    }
+
+    /// Rename an album
+    pub fn rename_album(&self, id: i64, name: &str) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            "UPDATE albums SET name = ?1, updated_at = CURRENT_TIMESTAMP WHERE id = ?2",
+            params![name, id],
+        )
+    }
+
+    /// Set or clear the cover image of an album
+    pub fn set_album_cover(&self, id: i64, image_id: Option<i64>) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            "UPDATE albums SET cover_image_id = ?1, updated_at = CURRENT_TIMESTAMP WHERE id = ?2",
+            params![image_id, id],
+        )
+    }
+
+    /// Move an album into a folder (or the top level) at a position among its new siblings
+    pub fn move_album(&self, id: i64, parent_id: Option<i64>, position: usize) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let tx = conn.unchecked_transaction()?;
+
+        let mut siblings: Vec<i64> = tx
+            .prepare("SELECT id FROM albums WHERE parent_id IS ?1 AND id != ?2 ORDER BY sort_order, id")?
+            .query_map(params![parent_id, id], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        siblings.insert(position.min(siblings.len()), id);
+
+        tx.execute(
+            "UPDATE albums SET parent_id = ?1, updated_at = CURRENT_TIMESTAMP WHERE id = ?2",
+            params![parent_id, id],
+        )?;
+        for (sort_order, sibling) in siblings.iter().enumerate() {
+            tx.execute(
+                "UPDATE albums SET sort_order = ?1 WHERE id = ?2",
+                params![sort_order as i64, sibling],
+            )?;
+        }
+
+        tx.commit()
+    }
+
+    /// Delete an album, and the albums inside it if it is a folder
+    ///
+    /// Images are not affected, only their membership of the deleted albums.
+    pub fn delete_album(&self, id: i64) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute("DELETE FROM albums WHERE id = ?1", params![id])
+    }
+
+    /// Append images to the end of an album, skipping images already in it
+    pub fn add_album_items(&self, album_id: i64, image_ids: &[i64]) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let tx = conn.unchecked_transaction()?;
+
+        let mut added = 0;
+        for image_id in image_ids {
+            added += tx.execute(
+                "INSERT OR IGNORE INTO album_items (album_id, image_id, position)
+                 VALUES (?1, ?2, (SELECT COALESCE(MAX(position), -1) + 1 FROM album_items WHERE album_id = ?1))",
+                params![album_id, image_id],
+            )?;
+        }
+        tx.execute(
+            "UPDATE albums SET updated_at = CURRENT_TIMESTAMP WHERE id = ?1",
+            params![album_id],
+        )?;
+
+        tx.commit()?;
+        Ok(added)
+    }
+
+    /// Remove images from an album
+    pub fn remove_album_items(&self, album_id: i64, image_ids: &[i64]) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let tx = conn.unchecked_transaction()?;
+
+        let mut removed = 0;
+        for image_id in image_ids {
+            removed += tx.execute(
+                "DELETE FROM album_items WHERE album_id = ?1 AND image_id = ?2",
+                params![album_id, image_id],
+            )?;
+        }
+        tx.execute(
+            "UPDATE albums SET updated_at = CURRENT_TIMESTAMP WHERE id = ?1",
+            params![album_id],
+        )?;
+
+        tx.commit()?;
+        Ok(removed)
+    }
+
+    /// Get the images of an album in their manual order
+    pub fn get_album_items(&self, album_id: i64) -> Result<Vec<ImageRecord>> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {}
+             FROM album_items ai
+             INNER JOIN images i ON i.id = ai.image_id
+             WHERE ai.album_id = ?1 AND i.trashed_at IS NULL
+             ORDER BY ai.position",
+            *IMAGE_COLUMNS_ALIASED
+        ))?;
+
+        let images = stmt.query_map(params![album_id], parse_image_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(images)
+    }
+
+    /// Get the image IDs of an album in their manual order, leaving out trashed images
+    pub fn get_album_item_ids(&self, album_id: i64) -> Result<Vec<i64>> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn.prepare(
+            "SELECT ai.image_id FROM album_items ai
+             INNER JOIN images i ON i.id = ai.image_id
+             WHERE ai.album_id = ?1 AND i.trashed_at IS NULL
+             ORDER BY ai.position"
+        )?;
+        let ids = stmt.query_map(params![album_id], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(ids)
+    }
+
+    /// Give the images of an album the order of `image_ids`
+    ///
+    /// Every item is renumbered. Items left out, such as trashed images, keep
+    /// their relative order after the given ones.
+    pub fn reorder_album_items(&self, album_id: i64, image_ids: &[i64]) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let tx = conn.unchecked_transaction()?;
+
+        let others: Vec<i64> = tx
+            .prepare("SELECT image_id FROM album_items WHERE album_id = ?1 ORDER BY position")?
+            .query_map(params![album_id], |row| row.get(0))?
+            .collect::<Result<Vec<i64>, _>>()?
+            .into_iter()
+            .filter(|image_id| !image_ids.contains(image_id))
+            .collect();
+
+        for (position, image_id) in image_ids.iter().chain(&others).enumerate() {
+            tx.execute(
+                "UPDATE album_items SET position = ?1 WHERE album_id = ?2 AND image_id = ?3",
+                params![position as i64, album_id, image_id],
+            )?;
+        }
+        tx.execute(
+            "UPDATE albums SET updated_at = CURRENT_TIMESTAMP WHERE id = ?1",
+            params![album_id],
+        )?;
+
+        tx.commit()
+    }
+
+    /// Get the edit recipe of an image, None if it has no edits
+    pub fn get_edit_recipe(&self, image_id: i64) -> Result<Option<EditRecipe>> {
+        let conn = self.conn.lock().unwrap();
+
+        let result = conn.query_row(
+            "SELECT recipe FROM image_edits WHERE image_id = ?1",
+            params![image_id],
+            |row| row.get::<_, String>(0),
+        );
+
+        match result {
+            Ok(json) => serde_json::from_str(&json)
+                .map(Some)
+                .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e))),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Save the edit recipe of an image, or remove its edits with None
+    pub fn set_edit_recipe(&self, image_id: i64, recipe: Option<&EditRecipe>) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+
+        match recipe {
+            Some(recipe) => {
+                let json = serde_json::to_string(recipe)
+                    .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+                conn.execute(
+                    "INSERT INTO image_edits (image_id, recipe) VALUES (?1, ?2)
+                     ON CONFLICT(image_id) DO UPDATE SET recipe = excluded.recipe, updated_at = CURRENT_TIMESTAMP",
+                    params![image_id, json],
+                )
+            }
+            None => conn.execute("DELETE FROM image_edits WHERE image_id = ?1", params![image_id]),
+        }
+    }
+
+    /// Get the session of an unfinished upload to a storage provider
+    pub fn get_upload_session(&self, provider: &str, remote_name: &str) -> Result<Option<UploadSession>> {
+        let conn = self.conn.lock().unwrap();
+
+        let result = conn.query_row(
+            "SELECT state, uploaded, size FROM upload_sessions WHERE provider = ?1 AND remote_name = ?2",
+            params![provider, remote_name],
+            |row| {
+                Ok(UploadSession {
+                    state: row.get(0)?,
+                    uploaded: row.get::<_, i64>(1)? as u64,
+                    size: row.get::<_, i64>(2)? as u64,
+                })
+            },
+        );
+
+        match result {
+            Ok(session) => Ok(Some(session)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Save the progress of an upload, or remove its session with None once it has finished
+    pub fn set_upload_session(
+        &self,
+        provider: &str,
+        remote_name: &str,
+        session: Option<&UploadSession>,
+    ) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+
+        match session {
+            Some(session) => conn.execute(
+                "INSERT INTO upload_sessions (provider, remote_name, state, uploaded, size) VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT(provider, remote_name) DO UPDATE SET
+                    state = excluded.state, uploaded = excluded.uploaded, size = excluded.size,
+                    updated_at = CURRENT_TIMESTAMP",
+                params![provider, remote_name, session.state, session.uploaded as i64, session.size as i64],
+            ),
+            None => conn.execute(
+                "DELETE FROM upload_sessions WHERE provider = ?1 AND remote_name = ?2",
+                params![provider, remote_name],
+            ),
+        }
+    }
+
+    /// Upload sessions of a provider that are no longer worth resuming, as (remote_name, session)
+    ///
+    /// Those are sessions not saved since `before`, and sessions of files that
+    /// are no longer waiting to be synced because they were trashed, excluded
+    /// or uploaded another way.
+    pub fn get_abandoned_upload_sessions(
+        &self,
+        provider: &str,
+        before: DateTime<Utc>,
+    ) -> Result<Vec<(String, UploadSession)>> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn.prepare(
+            "SELECT s.remote_name, s.state, s.uploaded, s.size FROM upload_sessions s
+             WHERE s.provider = ?1 AND (s.updated_at < ?2 OR NOT EXISTS (
+                 SELECT 1 FROM images i
+                 WHERE i.sync_status IN ('pending', 'failed') AND i.trashed_at IS NULL
+                   AND substr(s.remote_name, 1, length(i.checksum)) = i.checksum
+             ))
+             ORDER BY s.remote_name",
+        )?;
+
+        let sessions = stmt
+            .query_map(params![provider, before.format(SQLITE_DATETIME).to_string()], |row| {
+                Ok((
+                    row.get(0)?,
+                    UploadSession {
+                        state: row.get(1)?,
+                        uploaded: row.get::<_, i64>(2)? as u64,
+                        size: row.get::<_, i64>(3)? as u64,
+                    },
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(sessions)
+    }
+
+    /// IDs of images waiting to be synced or whose last sync failed, oldest first
+    pub fn get_unsynced_image_ids(&self) -> Result<Vec<i64>> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn.prepare(
+            "SELECT id FROM images
+             WHERE sync_status IN ('pending', 'failed') AND trashed_at IS NULL
+             ORDER BY id",
+        )?;
+        let ids = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<i64>>>()?;
+
+        Ok(ids)
+    }
+
+    /// Put synced images back in the sync queue, for a storage backend that has none of them yet
+    ///
+    /// Files the backend already has are skipped when they come up.
+    pub fn requeue_synced_images(&self) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            "UPDATE images SET sync_status = 'pending', synced_at = NULL, sync_quality = NULL
+             WHERE sync_status = 'synced'",
+            [],
+        )
+    }
+
+    /// Put images left out by the exclude patterns back in the sync queue, to be checked against new patterns
+    pub fn requeue_excluded_images(&self) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            "UPDATE images SET sync_status = 'pending' WHERE sync_status = 'excluded'",
+            [],
+        )
+    }
+
+    /// Point an image at newly rendered thumbnails
+    pub fn set_image_thumbnails(&self, image_id: i64, thumbnail_small: &str, thumbnail_medium: &str) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            "UPDATE images SET thumbnail_small = ?1, thumbnail_medium = ?2 WHERE id = ?3",
+            params![thumbnail_small, thumbnail_medium, image_id],
+        )
+    }
+
+    /// Count the images that use a thumbnail file, as either size
+    pub fn count_images_with_thumbnail(&self, path: &str) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.query_row(
+            "SELECT COUNT(*) FROM images WHERE thumbnail_small = ?1 OR thumbnail_medium = ?1",
+            params![path],
+            |row| row.get(0),
+        )
+    }
+
+    /// Move an image to the trash, recording where its file was moved if it was
+    pub fn trash_image(&self, id: i64, trash_path: Option<&str>) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            "UPDATE images SET trashed_at = ?1, trash_path = ?2 WHERE id = ?3 AND trashed_at IS NULL",
+            params![Utc::now().to_rfc3339(), trash_path, id],
+        )
+    }
+
+    /// Take an image out of the trash
+    pub fn restore_image(&self, id: i64) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            "UPDATE images SET trashed_at = NULL, trash_path = NULL WHERE id = ?1 AND trashed_at IS NOT NULL",
+            params![id],
+        )
+    }
+
+    /// Get the images in the trash, most recently trashed first
+    pub fn get_trashed_images(&self) -> Result<Vec<ImageRecord>> {
+        self.get_images_trashed_before(None)
+    }
+
+    /// Get the images trashed before a time, or all of them with None
+    pub fn get_images_trashed_before(&self, cutoff: Option<DateTime<Utc>>) -> Result<Vec<ImageRecord>> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {}
+             FROM images
+             WHERE trashed_at IS NOT NULL AND (?1 IS NULL OR trashed_at < ?1)
+             ORDER BY trashed_at DESC",
+            IMAGE_COLUMNS
+        ))?;
+
+        let images = stmt.query_map(params![cutoff.map(|c| c.to_rfc3339())], parse_image_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(images)
+    }
+
+    /// Delete an image record and associated data
+    pub fn delete_image(&self, id: i64) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        
+        // Foreign key constraints will cascade delete tags and embeddings
+        let deleted = conn.execute(
+            "DELETE FROM images WHERE id = ?1",
+            params![id],
+        )?;
+
+        Ok(deleted)
+    }
+
+    /// Delete an image by path
+    ///
+    /// Images in the trash are kept, since their file may have been moved to
+    /// the trash folder; they are deleted when the trash is purged.
+    pub fn delete_image_by_path(&self, path: &str) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        
+        let deleted = conn.execute(
+            "DELETE FROM images WHERE path = ?1 AND trashed_at IS NULL",
+            params![path],
+        )?;
+
+        Ok(deleted)
+    }
+
+    /// Get a reference to the connection for queries
+    pub fn connection(&self) -> &Mutex<Connection> {
+        &self.conn
+    }
+}
+
+/// Insert a file that exists on disk with its real checksum and size, for tests that read it back
+#[cfg(test)]
+pub(crate) fn insert_test_media(
+    db: &Database,
+    path: &std::path::Path,
+    media_type: MediaType,
+    width: u32,
+    height: u32,
+) -> ImageRecord {
+    let checksum = crate::sync::CloudSyncManager::compute_checksum(path).unwrap();
+    let id = db
+        .insert_image(
+            path.to_str().unwrap(),
+            "/thumb_small.jpg",
+            "/thumb_medium.jpg",
+            &checksum,
+            media_type,
+            None,
+            None,
+            None,
+            None,
+            None,
+            width,
+            height,
+            None,
+            None,
+            std::fs::metadata(path).unwrap().len(),
+            Utc::now(),
+        )
+        .unwrap();
+    db.get_image_by_id(id).unwrap().unwrap()
+}
+
+/// Joins and conditions selecting the images that match a filter, with their parameters in order
+struct FilterClauses {
+    /// Joins following `FROM images i`
+    joins: String,
+    conditions: String,
+    params: Vec<Box<dyn rusqlite::ToSql>>,
+    /// Whether the full-text join, which ranks matches as `f.rank`, is present
+    ranked: bool,
+}
+
+impl FilterClauses {
+    fn new(filter: &ImageFilter) -> Self {
+        let mut joins = String::new();
+        // Images in the trash only show up in the trash
+        let mut conditions = vec!["i.trashed_at IS NULL".to_string()];
+        let mut params_vec: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+
+        // Add full-text filter; its placeholder comes first in the query
+        let fts = filter.text.as_deref().and_then(fts_query);
+        if let Some(fts) = &fts {
+            joins.push_str(
+                " INNER JOIN (
+                    SELECT rowid AS image_id, bm25(images_fts, 4.0, 2.0, 8.0, 1.0, 1.0) AS rank
+                    FROM images_fts WHERE images_fts MATCH ?
+                 ) f ON i.id = f.image_id"
+            );
+            params_vec.push(Box::new(fts.clone()));
+        }
+
+        // Add tag filter if specified
+        if let Some(tags) = &filter.tags {
+            if !tags.is_empty() {
+                joins.push_str(" INNER JOIN tags t ON i.id = t.image_id");
+                let placeholders = tags.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+                conditions.push(format!("t.label IN ({})", placeholders));
+                for tag in tags {
+                    params_vec.push(Box::new(tag.clone()));
+                }
+            }
+        }
+
+        // Add media type filter
+        if let Some(media_type) = &filter.media_type {
+            conditions.push("i.media_type = ?".to_string());
+            params_vec.push(Box::new(media_type.as_str().to_string()));
+        }
+
+        // Add date range filter
+        if let Some((start, end)) = &filter.date_range {
+            conditions.push(format!("{} BETWEEN ? AND ?", LOCAL_CAPTURE_DATE));
+            params_vec.push(Box::new(start.format(SQLITE_DATETIME).to_string()));
+            params_vec.push(Box::new(end.format(SQLITE_DATETIME).to_string()));
+        }
+
+        // Add location filters, narrowed down with the R*Tree index first
+        let radius = filter
+            .location
+            .map(|(lat, lon, radius_km)| GeoBounds::around(lat, lon, radius_km));
+        for bounds in radius.iter().chain(&filter.bounds) {
+            let ranges = bounds.longitude_ranges();
+            let longitudes = vec!["(max_lon >= ? AND min_lon <= ?)"; ranges.len()].join(" OR ");
+            conditions.push(format!(
+                "i.id IN (SELECT id FROM images_geo WHERE max_lat >= ? AND min_lat <= ? AND ({}))",
+                longitudes
+            ));
+            params_vec.push(Box::new(bounds.south));
+            params_vec.push(Box::new(bounds.north));
+            for (west, east) in ranges {
+                params_vec.push(Box::new(west));
+                params_vec.push(Box::new(east));
+            }
+        }
+
+        // Add camera filters
+        if let Some(model) = &filter.camera_model {
+            conditions.push("i.camera_model = ? COLLATE NOCASE".to_string());
+            params_vec.push(Box::new(model.clone()));
+        }
+        if let Some(make) = &filter.camera_make {
+            conditions.push("i.camera_make = ? COLLATE NOCASE".to_string());
+            params_vec.push(Box::new(make.clone()));
+        }
+
+        // Add place filters
+        for (column, value) in [("country", &filter.country), ("region", &filter.region), ("city", &filter.city)] {
+            if let Some(value) = value {
+                conditions.push(format!("i.{} = ? COLLATE NOCASE", column));
+                params_vec.push(Box::new(value.clone()));
+            }
+        }
 
-    /// Update image path (for handling file moves)
-    pub fn update_image_path(&self, checksum: &str, new_path: &str) -> Result<usize> {
-        let conn = self.conn.lock().unwrap();
-        
-        let updated = conn.execute(
-            "UPDATE images SET path = ?1 WHERE checksum = ?2",
-            params![new_path, checksum],
-        )?;
+        // Add duration and exposure filters
+        for (column, range) in [
+            ("duration_seconds", &filter.duration_range),
+            ("aperture", &filter.aperture_range),
+            ("focal_length", &filter.focal_length_range),
+            ("iso", &filter.iso_range),
+            ("exposure_time", &filter.exposure_time_range),
+        ] {
+            let Some((lower, upper)) = range else {
+                continue;
+            };
+            for (bound, inclusive, exclusive) in [(lower, ">=", ">"), (upper, "<=", "<")] {
+                let (op, value) = match bound {
+                    Bound::Included(value) => (inclusive, value),
+                    Bound::Excluded(value) => (exclusive, value),
+                    Bound::Unbounded => continue,
+                };
+                conditions.push(format!("i.{} {} ?", column, op));
+                params_vec.push(Box::new(*value));
+            }
+        }
 
-        Ok(updated)
-    }
+        // Add lens and flash filters
+        if let Some(lens) = &filter.lens {
+            let escaped = lens
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            conditions.push("i.lens_model LIKE ? ESCAPE '\\'".to_string());
+            params_vec.push(Box::new(format!("%{}%", escaped)));
+        }
+        if let Some(flash) = filter.flash {
+            conditions.push("i.flash = ?".to_string());
+            params_vec.push(Box::new(flash));
+        }
 
-    /// Delete an image record and associated data
-    pub fn delete_image(&self, id: i64) -> Result<usize> {
-        let conn = self.conn.lock().unwrap();
-        
-        // Foreign key constraints will cascade delete tags and embeddings
-        let deleted = conn.execute(
-            "DELETE FROM images WHERE id = ?1",
-            params![id],
-        )?;
+        // Add curation filters
+        if let Some((min, max)) = filter.rating_range {
+            conditions.push("i.rating BETWEEN ? AND ?".to_string());
+            params_vec.push(Box::new(min));
+            params_vec.push(Box::new(max));
+        }
+        if let Some(label) = &filter.color_label {
+            conditions.push("i.color_label = ?".to_string());
+            params_vec.push(Box::new(label.as_str().to_string()));
+        }
+        if let Some(flag) = &filter.flag {
+            conditions.push("i.flag = ?".to_string());
+            params_vec.push(Box::new(flag.as_str().to_string()));
+        }
+        if let Some(favorite) = filter.favorite {
+            conditions.push("i.favorite = ?".to_string());
+            params_vec.push(Box::new(favorite));
+        }
 
-        Ok(deleted)
-    }
+        FilterClauses {
+            joins,
+            conditions: conditions.join(" AND "),
+            params: params_vec,
+            ranked: fts.is_some(),
+        }
 
-    /// Delete an image by path
-    pub fn delete_image_by_path(&self, path: &str) -> Result<usize> {
-        let conn = self.conn.lock().unwrap();
-        
-        let deleted = conn.execute(
-            "DELETE FROM images WHERE path = ?1",
-            params![path],
-        )?;
+    }
 
-        Ok(deleted)
+    fn params(&self) -> Vec<&dyn rusqlite::ToSql> {
+        self.params.iter().map(|p| p.as_ref()).collect()
     }
+}
 
-    /// Get a reference to the connection for queries
-    pub fn connection(&self) -> &Mutex<Connection> {
-        &self.conn
+/// Whether an image is within the location filters by its exact position
+///
+/// The location index only holds single-precision boxes, so queries narrowed
+/// down with it check exact positions afterwards.
+fn within_location(filter: &ImageFilter, latitude: Option<f64>, longitude: Option<f64>) -> bool {
+    if filter.location.is_none() && filter.bounds.is_none() {
+        return true;
     }
+    let (Some(lat), Some(lon)) = (latitude, longitude) else {
+        return false;
+    };
+    filter.location.map_or(true, |(center_lat, center_lon, radius_km)| {
+        geo::haversine_km(center_lat, center_lon, lat, lon) <= radius_km
+    }) && filter.bounds.map_or(true, |bounds| bounds.contains(lat, lon))
+}
+
+/// Local capture time of an image in SQL, formatted as `SQLITE_DATETIME`
+const LOCAL_CAPTURE_DATE: &str =
+    "datetime(i.capture_date, COALESCE(i.utc_offset_minutes, 0) || ' minutes')";
+
+/// Format of the times SQLite's `datetime` returns
+const SQLITE_DATETIME: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Columns read by `parse_image_row`, in the order it reads them
+const IMAGE_COLUMNS: &str = "id, path, media_type, thumbnail_small, thumbnail_medium, checksum, \
+    capture_date, camera_make, camera_model, \
+    gps_latitude, gps_longitude, width, height, \
+    duration_seconds, video_codec, \
+    file_size, file_modified, created_at, synced_at, sync_status, \
+    country, region, city, \
+    rating, color_label, flag, favorite, trashed_at, trash_path, \
+    description, \
+    exposure_time, aperture, iso, focal_length, focal_length_35mm, \
+    lens_model, flash, orientation, utc_offset_minutes, \
+    frame_rate, bit_rate, rotation, audio_codec, audio_channels, sync_quality";
+
+lazy_static::lazy_static! {
+    /// `IMAGE_COLUMNS` qualified with the `i` alias, for queries that join other tables
+    static ref IMAGE_COLUMNS_ALIASED: String = IMAGE_COLUMNS
+        .split(", ")
+        .map(|column| format!("i.{}", column))
+        .collect::<Vec<_>>()
+        .join(", ");
 }
 
 /// Parse an image row from a query result
@@ -404,9 +1690,123 @@ fn parse_image_row(row: &Row) -> Result<ImageRecord> {
         synced_at: row.get::<_, Option<String>>(18)?
             .and_then(|s| parse_datetime(&s).ok()),
         sync_status: row.get(19)?,
+        country: row.get(20)?,
+        region: row.get(21)?,
+        city: row.get(22)?,
+        rating: row.get(23)?,
+        color_label: row.get::<_, Option<String>>(24)?
+            .and_then(|s| ColorLabel::parse(&s)),
+        flag: Flag::parse(&row.get::<_, String>(25)?),
+        favorite: row.get(26)?,
+        trashed_at: row.get::<_, Option<String>>(27)?
+            .and_then(|s| parse_datetime(&s).ok()),
+        trash_path: row.get(28)?,
+        description: row.get(29)?,
+        capture_settings: CaptureSettings {
+            exposure_time: row.get(30)?,
+            aperture: row.get(31)?,
+            iso: row.get(32)?,
+            focal_length: row.get(33)?,
+            focal_length_35mm: row.get(34)?,
+            lens_model: row.get(35)?,
+            flash: row.get(36)?,
+            orientation: row.get(37)?,
+            utc_offset_minutes: row.get(38)?,
+        },
+        video_properties: VideoProperties {
+            frame_rate: row.get(39)?,
+            bit_rate: row.get::<_, Option<i64>>(40)?.map(|bit_rate| bit_rate as u64),
+            rotation: row.get(41)?,
+            audio_codec: row.get(42)?,
+            audio_channels: row.get(43)?,
+        },
+        sync_quality: row.get(44)?,
+    })
+}
+
+/// Columns read by `parse_album_row`
+const ALBUM_SELECT: &str =
+    "SELECT a.id, a.name, a.parent_id, a.is_folder, a.cover_image_id, a.sort_order,
+            (SELECT COUNT(*) FROM album_items ai INNER JOIN images i ON i.id = ai.image_id
+             WHERE ai.album_id = a.id AND i.trashed_at IS NULL),
+            a.smart_filter, a.smart_query, a.created_at, a.updated_at
+     FROM albums a";
+
+/// Parse an album row from a query result
+fn parse_album_row(row: &Row) -> Result<Album> {
+    Ok(Album {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        parent_id: row.get(2)?,
+        is_folder: row.get(3)?,
+        cover_image_id: row.get(4)?,
+        sort_order: row.get(5)?,
+        item_count: row.get(6)?,
+        smart_filter: row.get::<_, Option<String>>(7)?
+            .map(|json| decode_filter(&json))
+            .transpose()?,
+        smart_query: row.get(8)?,
+        created_at: parse_datetime(&row.get::<_, String>(9)?)?,
+        updated_at: parse_datetime(&row.get::<_, String>(10)?)?,
     })
 }
 
+/// Serialize a filter for storage as JSON
+fn encode_filter(filter: &ImageFilter) -> Result<String> {
+    serde_json::to_string(filter).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
+}
+
+/// Deserialize a filter stored as JSON
+fn decode_filter(json: &str) -> Result<ImageFilter> {
+    serde_json::from_str(json)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(7, rusqlite::types::Type::Text, Box::new(e)))
+}
+
+/// Turn free-form search text into an FTS5 query
+///
+/// Double-quoted text is matched as a phrase and every other word as a prefix,
+/// so partially typed words still match. All terms must match. Returns None
+/// when the text contains nothing searchable.
+fn fts_query(text: &str) -> Option<String> {
+    let mut terms = Vec::new();
+
+    for (i, part) in text.split('"').enumerate() {
+        // Odd parts sit between quotes; an unclosed quote runs to the end
+        if i % 2 == 1 {
+            if part.chars().any(char::is_alphanumeric) {
+                terms.push(format!("\"{}\"", part.trim()));
+            }
+            continue;
+        }
+
+        for word in part.split_whitespace() {
+            let word = word.trim_end_matches('*');
+            if word.chars().any(char::is_alphanumeric) {
+                terms.push(format!("\"{}\"*", word));
+            }
+        }
+    }
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+/// Serialize an embedding as little-endian f32 values
+fn encode_embedding(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|f| f.to_le_bytes()).collect()
+}
+
+/// Deserialize an embedding stored as little-endian f32 values
+fn decode_embedding(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect()
+}
+
 /// Parse a datetime string in RFC3339 format or SQLite format
 fn parse_datetime(s: &str) -> Result<DateTime<Utc>> {
     // Try RFC3339 format first
@@ -477,6 +1877,96 @@ mod tests {
         let _ = fs::remove_file(&db_path);
     }
 
+    #[test]
+    fn test_image_columns_aliased() {
+        let columns: Vec<&str> = IMAGE_COLUMNS_ALIASED.split(", ").collect();
+        assert_eq!(columns.len(), IMAGE_COLUMNS.split(", ").count());
+        assert_eq!(columns.first(), Some(&"i.id"));
+        assert_eq!(columns.last(), Some(&"i.sync_quality"));
+        assert!(columns.iter().all(|column| column.starts_with("i.") && !column.contains(char::is_whitespace)));
+    }
+
+    #[test]
+    fn test_abandoned_upload_sessions() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let mut images = Vec::new();
+        for name in ["clip.mp4", "trashed.mp4", "stale.mp4"] {
+            let path = temp_dir.path().join(name);
+            fs::write(&path, name).unwrap();
+            images.push(insert_test_media(&db, &path, MediaType::Video, 100, 100));
+        }
+        db.trash_image(images[1].id, None).unwrap();
+
+        let session = UploadSession {
+            state: "upload".to_string(),
+            uploaded: 5,
+            size: 10,
+        };
+        let names: Vec<String> = images.iter().map(|i| format!("{}-medium_clip.mp4", i.checksum)).collect();
+        for name in &names {
+            db.set_upload_session("S3", name, Some(&session)).unwrap();
+        }
+        db.set_upload_session("local folder", &names[1], Some(&session)).unwrap();
+        db.connection()
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE upload_sessions SET updated_at = '2020-01-01 00:00:00' WHERE remote_name = ?1",
+                params![names[2]],
+            )
+            .unwrap();
+
+        let abandoned = |provider| {
+            let mut names: Vec<String> = db
+                .get_abandoned_upload_sessions(provider, Utc::now() - Duration::days(7))
+                .unwrap()
+                .into_iter()
+                .map(|(name, found)| {
+                    assert_eq!(found, session);
+                    name
+                })
+                .collect();
+            names.sort();
+            names
+        };
+
+        // The trashed file's session and the one untouched for years
+        let mut expected = vec![names[1].clone(), names[2].clone()];
+        expected.sort();
+        assert_eq!(abandoned("S3"), expected);
+        assert_eq!(abandoned("local folder"), vec![names[1].clone()]);
+    }
+
+    #[test]
+    fn test_requeue_synced_images() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let mut image_ids = Vec::new();
+        for name in ["IMG_0001.jpg", "IMG_0002.jpg", "IMG_0003.jpg"] {
+            let path = temp_dir.path().join(name);
+            fs::write(&path, name).unwrap();
+            image_ids.push(insert_test_media(&db, &path, MediaType::Image, 100, 100).id);
+        }
+        {
+            let conn = db.connection().lock().unwrap();
+            conn.execute(
+                "UPDATE images SET sync_status = 'synced', synced_at = ?1, sync_quality = 'high' WHERE id = ?2",
+                params![Utc::now().to_rfc3339(), image_ids[0]],
+            )
+            .unwrap();
+            conn.execute("UPDATE images SET sync_status = 'excluded' WHERE id = ?1", params![image_ids[1]])
+                .unwrap();
+        }
+        assert_eq!(db.get_unsynced_image_ids().unwrap(), vec![image_ids[2]]);
+
+        // Excluded images stay out of the queue
+        assert_eq!(db.requeue_synced_images().unwrap(), 1);
+        assert_eq!(db.get_unsynced_image_ids().unwrap(), vec![image_ids[0], image_ids[2]]);
+        let image = db.get_image_by_id(image_ids[0]).unwrap().unwrap();
+        assert_eq!((image.sync_status.as_str(), image.synced_at, image.sync_quality), ("pending", None, None));
+    }
+
     #[test]
     fn test_insert_and_retrieve_image() {
         let temp_dir = std::env::temp_dir();
@@ -740,6 +2230,16 @@ mod tests {
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].path, "/path/to/image1.jpg");
 
+        // Counts match the queries, counting images with several matching tags once
+        db.insert_tag(img1_id, "portrait", 0.5).unwrap();
+        let filter = ImageFilter {
+            tags: Some(vec!["landscape".to_string(), "portrait".to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(db.query_images(&filter).unwrap().len(), 2);
+        assert_eq!(db.count_images(&filter).unwrap(), 2);
+        assert_eq!(db.count_images(&ImageFilter::default()).unwrap(), 2);
+
         // Clean up
         let _ = fs::remove_file(&db_path);
     }
@@ -866,6 +2366,459 @@ mod tests {
         // Clean up
         let _ = fs::remove_file(&db_path);
     }
+
+    fn insert_search_image(db: &Database, path: &str, make: Option<&str>, model: Option<&str>) -> i64 {
+        db.insert_image(
+            path, "s.jpg", "m.jpg", path, MediaType::Image,
+            None, make, model, None, None, 100, 100, None, None, 1024, Utc::now(),
+        )
+        .unwrap()
+    }
+
+    fn search(db: &Database, text: &str) -> Vec<String> {
+        let filter = ImageFilter {
+            text: Some(text.to_string()),
+            ..Default::default()
+        };
+        db.query_images(&filter).unwrap().into_iter().map(|i| i.path).collect()
+    }
+
+    #[test]
+    fn test_fts_query_syntax() {
+        assert_eq!(fts_query("sun"), Some("\"sun\"*".to_string()));
+        assert_eq!(fts_query("  beach  sun* "), Some("\"beach\"* \"sun\"*".to_string()));
+        assert_eq!(
+            fts_query("\"golden gate\" bridge"),
+            Some("\"golden gate\" \"bridge\"*".to_string())
+        );
+        assert_eq!(fts_query("\"unclosed phrase"), Some("\"unclosed phrase\"".to_string()));
+        assert_eq!(fts_query("  * - \"\" "), None);
+    }
+
+    #[test]
+    fn test_text_search_matches_all_fields() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+
+        let beach = insert_search_image(&db, "/photos/Hawaii 2024/IMG_0001.jpg", Some("Canon"), Some("EOS R5"));
+        insert_search_image(&db, "/photos/Home/DSC_0002.jpg", Some("Nikon"), Some("D850"));
+        db.insert_tag(beach, "sunset", 0.9).unwrap();
+
+        assert_eq!(search(&db, "hawaii"), vec!["/photos/Hawaii 2024/IMG_0001.jpg"]);
+        assert_eq!(search(&db, "dsc_0002"), vec!["/photos/Home/DSC_0002.jpg"]);
+        assert_eq!(search(&db, "nikon"), vec!["/photos/Home/DSC_0002.jpg"]);
+        assert_eq!(search(&db, "r5"), vec!["/photos/Hawaii 2024/IMG_0001.jpg"]);
+        assert_eq!(search(&db, "SUNSET"), vec!["/photos/Hawaii 2024/IMG_0001.jpg"]);
+        assert_eq!(search(&db, "photos").len(), 2);
+        assert!(search(&db, "paris").is_empty());
+    }
+
+    #[test]
+    fn test_text_search_prefix_and_phrase() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+
+        let first = insert_search_image(&db, "/photos/a.jpg", None, None);
+        let second = insert_search_image(&db, "/photos/b.jpg", None, None);
+        db.insert_tag(first, "golden gate", 0.9).unwrap();
+        db.insert_tag(second, "golden retriever", 0.9).unwrap();
+        db.insert_tag(second, "gate", 0.9).unwrap();
+
+        // Prefixes match partially typed words
+        assert_eq!(search(&db, "retr"), vec!["/photos/b.jpg"]);
+        assert_eq!(search(&db, "gold").len(), 2);
+
+        // Quoted phrases need the words next to each other
+        assert_eq!(search(&db, "\"golden gate\""), vec!["/photos/a.jpg"]);
+        assert_eq!(search(&db, "golden gate").len(), 2);
+    }
+
+    #[test]
+    fn test_text_search_ranks_and_combines_with_filters() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+
+        let weak = insert_search_image(&db, "/photos/dog/a.jpg", Some("Canon"), Some("EOS R5"));
+        let strong = insert_search_image(&db, "/photos/b.jpg", Some("Nikon"), Some("D850"));
+        db.insert_tag(weak, "cat", 0.9).unwrap();
+        db.insert_tag(strong, "dog", 0.9).unwrap();
+
+        // A tag match outranks a folder match
+        assert_eq!(search(&db, "dog"), vec!["/photos/b.jpg", "/photos/dog/a.jpg"]);
+
+        let filter = ImageFilter {
+            text: Some("dog".to_string()),
+            camera_model: Some("EOS R5".to_string()),
+            tags: Some(vec!["cat".to_string()]),
+            ..Default::default()
+        };
+        let results = db.query_images(&filter).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, weak);
+    }
+
+    #[test]
+    fn test_query_by_duration_and_camera_make() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+
+        for (path, duration) in [("/v/short.mp4", 10.0), ("/v/medium.mp4", 30.0), ("/v/long.mp4", 90.0)] {
+            db.insert_image(
+                path, "s.jpg", "m.jpg", path, MediaType::Video,
+                None, Some("Fujifilm"), None, None, None, 1920, 1080, Some(duration), None, 1024, Utc::now(),
+            )
+            .unwrap();
+        }
+
+        let paths = |filter: &ImageFilter| {
+            let mut paths: Vec<String> = db.query_images(filter).unwrap().into_iter().map(|i| i.path).collect();
+            paths.sort();
+            paths
+        };
+
+        let filter = ImageFilter {
+            duration_range: Some((Bound::Excluded(30.0), Bound::Unbounded)),
+            ..Default::default()
+        };
+        assert_eq!(paths(&filter), vec!["/v/long.mp4"]);
+
+        let filter = ImageFilter {
+            duration_range: Some((Bound::Included(30.0), Bound::Included(90.0))),
+            camera_make: Some("FUJIFILM".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(paths(&filter), vec!["/v/long.mp4", "/v/medium.mp4"]);
+
+        let filter = ImageFilter {
+            camera_make: Some("Canon".to_string()),
+            ..Default::default()
+        };
+        assert!(paths(&filter).is_empty());
+
+        let video = db.get_image_by_path("/v/long.mp4").unwrap().unwrap();
+        let properties = VideoProperties {
+            frame_rate: Some(59.94),
+            bit_rate: Some(24_000_000),
+            rotation: Some(90),
+            audio_codec: Some("aac".to_string()),
+            audio_channels: Some(2),
+        };
+        assert_eq!(db.set_video_properties(video.id, &properties).unwrap(), 1);
+        assert_eq!(db.get_image_by_id(video.id).unwrap().unwrap().video_properties, properties);
+    }
+
+    #[test]
+    fn test_images_with_older_metadata() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let photo = temp_dir.path().join("IMG_0001.jpg");
+        let video = temp_dir.path().join("VID_0001.mp4");
+        fs::write(&photo, b"jpeg").unwrap();
+        fs::write(&video, b"mp4").unwrap();
+        let photo = insert_test_media(&db, &photo, MediaType::Image, 100, 100);
+        let video = insert_test_media(&db, &video, MediaType::Video, 1920, 1080);
+
+        // Videos are read again too, for their creation time, location and rotation
+        let stale = |version| {
+            db.get_images_with_metadata_before(version).unwrap().into_iter().map(|i| i.id).collect::<Vec<_>>()
+        };
+        assert_eq!(stale(1), vec![photo.id, video.id]);
+
+        db.set_metadata_version(video.id, 1).unwrap();
+        assert_eq!(stale(1), vec![photo.id]);
+        assert_eq!(stale(2), vec![photo.id, video.id]);
+    }
+
+    #[test]
+    fn test_date_filter_uses_local_capture_time() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+
+        // 23:30 on the 15th in UTC+2, stored as 21:30 UTC
+        let late = chrono::TimeZone::with_ymd_and_hms(&Utc, 2024, 6, 15, 21, 30, 0).unwrap();
+        // 00:30 on the 16th in UTC+2, stored as 22:30 UTC on the 15th
+        let after_midnight = late + Duration::hours(1);
+        for (path, capture_date) in [("/p/late.jpg", late), ("/p/after_midnight.jpg", after_midnight)] {
+            let id = db.insert_image(
+                path, "s.jpg", "m.jpg", path, MediaType::Image,
+                Some(capture_date), None, None, None, None, 100, 100, None, None, 1024, Utc::now(),
+            )
+            .unwrap();
+            let settings = CaptureSettings {
+                utc_offset_minutes: Some(120),
+                ..Default::default()
+            };
+            db.set_capture_settings(id, &settings).unwrap();
+        }
+
+        let paths = |query: &str| {
+            let filter = crate::search_query::parse_query(query).unwrap();
+            db.query_images(&filter).unwrap().into_iter().map(|i| i.path).collect::<Vec<_>>()
+        };
+        assert_eq!(paths("date:2024-06-15"), vec!["/p/late.jpg"]);
+        assert_eq!(paths("date:2024-06-16"), vec!["/p/after_midnight.jpg"]);
+        assert_eq!(paths("before:2024-06-16"), vec!["/p/late.jpg"]);
+
+        let image = db.get_image_by_path("/p/after_midnight.jpg").unwrap().unwrap();
+        assert_eq!(image.local_capture_date().unwrap().to_string(), "2024-06-16 00:30:00");
+    }
+
+    #[test]
+    fn test_capture_settings_round_trip_and_filter() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+
+        let shots = [
+            ("/p/wide.jpg", 1.4, 35.0, 200, "XF35mmF1.4 R"),
+            ("/p/stopped.jpg", 8.0, 35.0, 100, "XF35mmF1.4 R"),
+            ("/p/portrait.jpg", 1.4, 56.0, 800, "XF56mmF1.2 R"),
+        ];
+        for (path, aperture, focal_length, iso, lens) in shots {
+            let id = db.insert_image(
+                path, "s.jpg", "m.jpg", path, MediaType::Image,
+                None, None, None, None, None, 100, 100, None, None, 1024, Utc::now(),
+            )
+            .unwrap();
+            let settings = CaptureSettings {
+                aperture: Some(aperture),
+                focal_length: Some(focal_length),
+                iso: Some(iso),
+                lens_model: Some(lens.to_string()),
+                flash: Some(false),
+                utc_offset_minutes: Some(-300),
+                ..Default::default()
+            };
+            assert_eq!(db.set_capture_settings(id, &settings).unwrap(), 1);
+            assert_eq!(db.get_image_by_id(id).unwrap().unwrap().capture_settings, settings);
+        }
+
+        let paths = |filter: &ImageFilter| {
+            let mut paths: Vec<String> = db.query_images(filter).unwrap().into_iter().map(|i| i.path).collect();
+            paths.sort();
+            paths
+        };
+
+        // All shots at f/1.4 with the 35mm
+        let filter = ImageFilter {
+            aperture_range: Some((Bound::Included(1.35), Bound::Included(1.45))),
+            lens: Some("35mm".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(paths(&filter), vec!["/p/wide.jpg"]);
+
+        let filter = ImageFilter {
+            focal_length_range: Some((Bound::Excluded(35.0), Bound::Unbounded)),
+            iso_range: Some((Bound::Included(400.0), Bound::Unbounded)),
+            flash: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(paths(&filter), vec!["/p/portrait.jpg"]);
+
+        // LIKE wildcards in the lens are matched literally
+        let filter = ImageFilter {
+            lens: Some("XF_5".to_string()),
+            ..Default::default()
+        };
+        assert!(paths(&filter).is_empty());
+    }
+
+    #[test]
+    fn test_curation_fields_bulk_update_and_filter() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+
+        let ids: Vec<i64> = (0..4)
+            .map(|i| {
+                let path = format!("/shoot/{}.jpg", i);
+                db.insert_image(
+                    &path, "s.jpg", "m.jpg", &path, MediaType::Image,
+                    None, None, None, None, None, 100, 100, None, None, 1024, Utc::now(),
+                )
+                .unwrap()
+            })
+            .collect();
+
+        // New images start unrated and unflagged
+        let image = db.get_image_by_id(ids[0]).unwrap().unwrap();
+        assert_eq!((image.rating, image.color_label, image.flag, image.favorite), (0, None, Flag::Unflagged, false));
+
+        assert_eq!(db.set_images_rating(&[ids[0], ids[1], 9999], 4).unwrap(), 2);
+        db.set_images_rating(&[ids[2]], 2).unwrap();
+        db.set_images_color_label(&[ids[0], ids[2]], Some(ColorLabel::Green)).unwrap();
+        db.set_images_flag(&[ids[0], ids[1]], Flag::Pick).unwrap();
+        db.set_images_flag(&[ids[3]], Flag::Reject).unwrap();
+        db.set_images_favorite(&[ids[1]], true).unwrap();
+
+        let image = db.get_image_by_id(ids[0]).unwrap().unwrap();
+        assert_eq!((image.rating, image.color_label, image.flag), (4, Some(ColorLabel::Green), Flag::Pick));
+
+        let matching = |filter: ImageFilter| {
+            let mut found: Vec<i64> = db.query_images(&filter).unwrap().into_iter().map(|i| i.id).collect();
+            found.sort();
+            found
+        };
+        assert_eq!(matching(ImageFilter { rating_range: Some((3, 5)), ..Default::default() }), vec![ids[0], ids[1]]);
+        assert_eq!(matching(ImageFilter { rating_range: Some((0, 0)), ..Default::default() }), vec![ids[3]]);
+        assert_eq!(matching(ImageFilter { flag: Some(Flag::Unflagged), ..Default::default() }), vec![ids[2]]);
+        assert_eq!(matching(ImageFilter { favorite: Some(true), ..Default::default() }), vec![ids[1]]);
+        assert_eq!(
+            matching(ImageFilter {
+                color_label: Some(ColorLabel::Green),
+                flag: Some(Flag::Pick),
+                ..Default::default()
+            }),
+            vec![ids[0]]
+        );
+
+        // Clearing a label and the rating
+        db.set_images_color_label(&[ids[0]], None).unwrap();
+        db.set_images_rating(&[ids[0]], 0).unwrap();
+        let image = db.get_image_by_id(ids[0]).unwrap().unwrap();
+        assert_eq!((image.rating, image.color_label), (0, None));
+
+        // The schema rejects ratings above five
+        assert!(db.set_images_rating(&[ids[0]], MAX_RATING + 1).is_err());
+    }
+
+    fn insert_geotagged_image(db: &Database, path: &str, lat: f64, lon: f64) -> i64 {
+        db.insert_image(
+            path, "s.jpg", "m.jpg", path, MediaType::Image,
+            None, None, None, Some(lat), Some(lon), 100, 100, None, None, 1024, Utc::now(),
+        )
+        .unwrap()
+    }
+
+    fn geo_paths(db: &Database, filter: &ImageFilter) -> Vec<String> {
+        let mut paths: Vec<String> = db.query_images(filter).unwrap().into_iter().map(|i| i.path).collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn test_location_filter_uses_great_circle_distance() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+
+        insert_geotagged_image(&db, "/geo/center.jpg", 48.8566, 2.3522);
+        insert_geotagged_image(&db, "/geo/north.jpg", 48.9, 2.3522);
+        // Inside the bounding box of a 10 km circle but outside the circle itself
+        insert_geotagged_image(&db, "/geo/corner.jpg", 48.93, 2.46);
+        insert_geotagged_image(&db, "/geo/far.jpg", 51.5074, -0.1278);
+        db.insert_image(
+            "/geo/untagged.jpg", "s.jpg", "m.jpg", "untagged", MediaType::Image,
+            None, None, None, None, None, 100, 100, None, None, 1024, Utc::now(),
+        )
+        .unwrap();
+
+        let filter = ImageFilter {
+            location: Some((48.8566, 2.3522, 10.0)),
+            ..Default::default()
+        };
+        assert_eq!(geo_paths(&db, &filter), vec!["/geo/center.jpg", "/geo/north.jpg"]);
+        assert_eq!(db.count_images(&filter).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_location_filter_across_antimeridian_and_poles() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+
+        insert_geotagged_image(&db, "/geo/east.jpg", -16.5, 179.95);
+        insert_geotagged_image(&db, "/geo/west.jpg", -16.5, -179.95);
+        insert_geotagged_image(&db, "/geo/pole_a.jpg", 89.95, 10.0);
+        insert_geotagged_image(&db, "/geo/pole_b.jpg", 89.95, -170.0);
+
+        let filter = ImageFilter {
+            location: Some((-16.5, 179.99, 20.0)),
+            ..Default::default()
+        };
+        assert_eq!(geo_paths(&db, &filter), vec!["/geo/east.jpg", "/geo/west.jpg"]);
+
+        let filter = ImageFilter {
+            location: Some((89.95, 10.0, 20.0)),
+            ..Default::default()
+        };
+        assert_eq!(geo_paths(&db, &filter), vec!["/geo/pole_a.jpg", "/geo/pole_b.jpg"]);
+    }
+
+    #[test]
+    fn test_bounds_filter_and_index_updates() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+
+        let fiji = insert_geotagged_image(&db, "/geo/fiji.jpg", -17.7, 178.0);
+        insert_geotagged_image(&db, "/geo/samoa.jpg", -13.8, -172.0);
+        insert_geotagged_image(&db, "/geo/sydney.jpg", -33.9, 151.2);
+
+        // A viewport crossing the antimeridian
+        let filter = ImageFilter {
+            bounds: Some(GeoBounds::new(-20.0, 170.0, -10.0, -170.0).unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(geo_paths(&db, &filter), vec!["/geo/fiji.jpg", "/geo/samoa.jpg"]);
+
+        // Moving and deleting images keeps the index current
+        db.connection()
+            .lock()
+            .unwrap()
+            .execute("UPDATE images SET gps_latitude = -33.8, gps_longitude = 151.0 WHERE id = ?1", [fiji])
+            .unwrap();
+        assert_eq!(geo_paths(&db, &filter), vec!["/geo/samoa.jpg"]);
+
+        let filter = ImageFilter {
+            bounds: Some(GeoBounds::new(-35.0, 150.0, -33.0, 152.0).unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(geo_paths(&db, &filter), vec!["/geo/fiji.jpg", "/geo/sydney.jpg"]);
+
+        db.delete_image(fiji).unwrap();
+        assert_eq!(geo_paths(&db, &filter), vec!["/geo/sydney.jpg"]);
+    }
+
+    #[test]
+    fn test_text_index_follows_changes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+
+        let id = insert_search_image(&db, "/photos/old/a.jpg", None, None);
+        let tag_id = db.insert_tag(id, "mountain", 0.9).unwrap();
+        assert_eq!(search(&db, "mountain").len(), 1);
+
+        db.connection()
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM tags WHERE id = ?1", [tag_id])
+            .unwrap();
+        assert!(search(&db, "mountain").is_empty());
+
+        db.rename_folder_paths("/photos/old", "/photos/new").unwrap();
+        assert!(search(&db, "old").is_empty());
+        assert_eq!(search(&db, "new"), vec!["/photos/new/a.jpg"]);
+
+        db.delete_image(id).unwrap();
+        assert!(search(&db, "new").is_empty());
+    }
+
+    #[test]
+    fn test_rename_folder_paths_is_case_sensitive() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+
+        let upper = insert_search_image(&db, "/lib/Shoot/a.jpg", None, None);
+        let lower = insert_search_image(&db, "/lib/shoot/b.jpg", None, None);
+        let wildcard = insert_search_image(&db, "/lib/Sh_ot/c.jpg", None, None);
+
+        let paths = |folder: &str| -> Vec<String> {
+            db.get_images_under_path(folder).unwrap().into_iter().map(|i| i.path).collect()
+        };
+        assert_eq!(paths("/lib/Shoot"), vec!["/lib/Shoot/a.jpg"]);
+        assert_eq!(paths("/lib/Sh_ot/"), vec!["/lib/Sh_ot/c.jpg"]);
+
+        assert_eq!(db.rename_folder_paths("/lib/Shoot", "/lib/Trip").unwrap(), 1);
+        assert_eq!(db.get_image_by_id(upper).unwrap().unwrap().path, "/lib/Trip/a.jpg");
+        assert_eq!(db.get_image_by_id(lower).unwrap().unwrap().path, "/lib/shoot/b.jpg");
+        assert_eq!(db.get_image_by_id(wildcard).unwrap().unwrap().path, "/lib/Sh_ot/c.jpg");
+    }
 }
 
 #[cfg(test)]
@@ -1116,8 +3069,8 @@ mod property_tests {
                 }
             }
 
-            // Test location filtering (if any images have GPS data)
-            if let Some(first_image) = images.iter().find(|img| img.gps_latitude.is_some()) {
+            // Test location filtering (if any inserted images have GPS data)
+            if let Some(first_image) = images.iter().take(image_ids.len()).find(|img| img.gps_latitude.is_some()) {
                 if let (Some(lat), Some(lon)) = (first_image.gps_latitude, first_image.gps_longitude) {
                     let filter = ImageFilter {
                         location: Some((lat, lon, 10.0)), // 10km radius
@@ -1126,21 +3079,16 @@ mod property_tests {
                     
                     let results = db.query_images(&filter).unwrap();
                     
-                    // All returned images should have GPS coordinates within approximate range
+                    // The image itself is found, and every result lies within the radius
+                    prop_assert!(results.iter().any(|r| r.path == first_image.path));
                     for result in &results {
-                        if let (Some(result_lat), Some(result_lon)) = (result.gps_latitude, result.gps_longitude) {
-                            // Simple bounding box check (not exact distance)
-                            let degrees_per_km = 1.0 / 111.0;
-                            let lat_delta = 10.0 * degrees_per_km;
-                            let lon_delta = 10.0 * degrees_per_km / lat.cos();
-                            
-                            prop_assert!(
-                                result_lat >= lat - lat_delta && result_lat <= lat + lat_delta &&
-                                result_lon >= lon - lon_delta && result_lon <= lon + lon_delta,
-                                "Image GPS ({}, {}) not within range of ({}, {})",
-                                result_lat, result_lon, lat, lon
-                            );
-                        }
+                        let (result_lat, result_lon) = (result.gps_latitude.unwrap(), result.gps_longitude.unwrap());
+                        let distance = geo::haversine_km(lat, lon, result_lat, result_lon);
+                        prop_assert!(
+                            distance <= 10.0,
+                            "Image GPS ({}, {}) is {} km from ({}, {})",
+                            result_lat, result_lon, distance, lat, lon
+                        );
                     }
                 }
             }
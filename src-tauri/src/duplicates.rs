@@ -0,0 +1,589 @@
+use image::imageops::FilterType;
+use image::DynamicImage;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use crate::database::{Database, ImageRecord};
+use crate::thumbnail;
+
+/// Default Hamming distance (out of 64 bits) below which images are near-duplicates
+pub const DEFAULT_NEAR_DUPLICATE_THRESHOLD: u32 = 8;
+
+/// Perceptual hash algorithm used to compare images
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    /// Difference hash: fast, sensitive to crops and gradients
+    DHash,
+    /// DCT-based hash: robust to re-encoding, resizing and mild edits
+    #[default]
+    PHash,
+}
+
+/// Perceptual hashes of an image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PerceptualHash {
+    pub dhash: u64,
+    pub phash: u64,
+}
+
+impl PerceptualHash {
+    /// Compute both hashes for an image
+    pub fn of(img: &DynamicImage) -> Self {
+        Self {
+            dhash: dhash(img),
+            phash: phash(img),
+        }
+    }
+
+    /// Get the hash for an algorithm
+    pub fn get(&self, algorithm: HashAlgorithm) -> u64 {
+        match algorithm {
+            HashAlgorithm::DHash => self.dhash,
+            HashAlgorithm::PHash => self.phash,
+        }
+    }
+}
+
+/// A group of images that are duplicates of each other
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicateGroup {
+    /// Images in the group, ordered by path
+    pub images: Vec<ImageRecord>,
+    /// Largest Hamming distance between two images in the group (0 for exact duplicates)
+    pub max_distance: u32,
+}
+
+/// Compute the difference hash of an image
+///
+/// The image is reduced to 9x8 grayscale and each bit records whether a pixel
+/// is brighter than its right-hand neighbour.
+pub fn dhash(img: &DynamicImage) -> u64 {
+    let small = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+
+    hash
+}
+
+/// Compute the DCT-based perceptual hash of an image
+///
+/// The image is reduced to 32x32 grayscale and transformed with a 2D DCT. Each
+/// bit records whether one of the 8x8 lowest-frequency coefficients is above
+/// their median (the DC term is excluded from the median).
+pub fn phash(img: &DynamicImage) -> u64 {
+    const SIZE: usize = 32;
+
+    let small = img
+        .resize_exact(SIZE as u32, SIZE as u32, FilterType::Triangle)
+        .to_luma8();
+    let pixels: Vec<f64> = small.pixels().map(|p| p[0] as f64).collect();
+
+    // Only the 8x8 low-frequency corner is needed, so compute just those coefficients
+    let cosines: Vec<f64> = (0..8 * SIZE)
+        .map(|i| {
+            let (u, x) = (i / SIZE, i % SIZE);
+            (std::f64::consts::PI * u as f64 * (2 * x + 1) as f64 / (2 * SIZE) as f64).cos()
+        })
+        .collect();
+
+    // Rows first, then columns
+    let mut rows = vec![0.0; SIZE * 8];
+    for y in 0..SIZE {
+        for u in 0..8 {
+            rows[y * 8 + u] = (0..SIZE)
+                .map(|x| pixels[y * SIZE + x] * cosines[u * SIZE + x])
+                .sum();
+        }
+    }
+
+    let mut coefficients = [0.0; 64];
+    for v in 0..8 {
+        for u in 0..8 {
+            coefficients[v * 8 + u] = (0..SIZE)
+                .map(|y| rows[y * 8 + u] * cosines[v * SIZE + y])
+                .sum();
+        }
+    }
+
+    let mut sorted: Vec<f64> = coefficients[1..].to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = (sorted[31] + sorted[32]) / 2.0;
+
+    coefficients
+        .iter()
+        .fold(0u64, |hash, &c| (hash << 1) | (c > median) as u64)
+}
+
+/// Number of differing bits between two hashes
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Compute perceptual hashes from an image's small thumbnail
+///
+/// Hashes only need a few dozen pixels, so reading the cached thumbnail avoids
+/// decoding the (possibly RAW or HEIC) original a second time.
+pub fn hash_thumbnail(thumbnail_path: &str) -> Result<PerceptualHash, String> {
+    let img = image::open(Path::new(thumbnail_path))
+        .map_err(|e| format!("Failed to open thumbnail {}: {}", thumbnail_path, e))?;
+
+    Ok(PerceptualHash::of(&img))
+}
+
+/// Compute perceptual hashes from an original file
+///
+/// Used for edited images, whose thumbnails show the edits while duplicates
+/// are found by how the photos were taken.
+pub fn hash_original(path: &str) -> Result<PerceptualHash, String> {
+    let img = thumbnail::load_image(Path::new(path))?;
+
+    Ok(PerceptualHash::of(&img))
+}
+
+/// Hash the thumbnail of an image and store the result
+pub fn store_hash(db: &Database, image_id: i64, thumbnail_path: &str) -> Result<(), String> {
+    let hash = hash_thumbnail(thumbnail_path)?;
+
+    db.save_perceptual_hash(image_id, hash.dhash, hash.phash)
+        .map_err(|e| format!("Failed to save perceptual hash: {}", e))
+}
+
+/// Hash every image imported before perceptual hashing existed
+///
+/// Returns the number of images hashed. Images whose thumbnail can't be read are skipped.
+pub fn backfill_hashes(db: &Database) -> Result<usize, String> {
+    let images = db
+        .get_images_without_perceptual_hash()
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    let mut edited = HashSet::new();
+    for image in &images {
+        let recipe = db
+            .get_edit_recipe(image.id)
+            .map_err(|e| format!("Database error: {}", e))?;
+        if recipe.is_some_and(|recipe| !recipe.is_identity()) {
+            edited.insert(image.id);
+        }
+    }
+
+    let hashes: Vec<(i64, PerceptualHash)> = images
+        .par_iter()
+        .filter_map(|image| {
+            let hash = if edited.contains(&image.id) {
+                hash_original(&image.path)
+            } else {
+                hash_thumbnail(&image.thumbnail_small)
+            };
+            match hash {
+                Ok(hash) => Some((image.id, hash)),
+                Err(e) => {
+                    log::warn!("Skipping perceptual hash for {}: {}", image.path, e);
+                    None
+                }
+            }
+        })
+        .collect();
+
+    for (image_id, hash) in &hashes {
+        db.save_perceptual_hash(*image_id, hash.dhash, hash.phash)
+            .map_err(|e| format!("Failed to save perceptual hash: {}", e))?;
+    }
+
+    Ok(hashes.len())
+}
+
+/// Find groups of files with identical content (same checksum)
+pub fn find_exact_duplicates(db: &Database) -> Result<Vec<DuplicateGroup>, String> {
+    let images = db
+        .get_images_with_duplicate_checksums()
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    // Rows arrive ordered by checksum, so each group is a contiguous run
+    let mut groups: Vec<DuplicateGroup> = Vec::new();
+    for image in images {
+        match groups.last_mut() {
+            Some(group) if group.images[0].checksum == image.checksum => group.images.push(image),
+            _ => groups.push(DuplicateGroup {
+                images: vec![image],
+                max_distance: 0,
+            }),
+        }
+    }
+
+    Ok(groups)
+}
+
+/// Find clusters of visually similar images
+///
+/// Images are linked when their hashes differ by at most `threshold` bits and
+/// clusters are the connected components of those links, so a burst where each
+/// frame is close to the next ends up in one cluster. Images that have not been
+/// hashed yet are hashed first.
+pub fn find_near_duplicates(
+    db: &Database,
+    algorithm: HashAlgorithm,
+    threshold: u32,
+) -> Result<Vec<DuplicateGroup>, String> {
+    backfill_hashes(db)?;
+
+    let hashes: Vec<(i64, u64)> = db
+        .get_perceptual_hashes()
+        .map_err(|e| format!("Database error: {}", e))?
+        .into_iter()
+        .map(|(id, dhash, phash)| (id, PerceptualHash { dhash, phash }.get(algorithm)))
+        .collect();
+
+    let mut clusters = UnionFind::new(hashes.len());
+    let mut tree = BkTree::default();
+    for (index, &(_, hash)) in hashes.iter().enumerate() {
+        for neighbour in tree.within(hash, threshold) {
+            clusters.union(index, neighbour);
+        }
+        tree.insert(hash, index);
+    }
+
+    let mut members: HashMap<usize, Vec<usize>> = HashMap::new();
+    for index in 0..hashes.len() {
+        members.entry(clusters.find(index)).or_default().push(index);
+    }
+
+    let mut groups = Vec::new();
+    for indices in members.into_values().filter(|m| m.len() > 1) {
+        let mut max_distance = 0;
+        for (i, &a) in indices.iter().enumerate() {
+            for &b in &indices[i + 1..] {
+                max_distance = max_distance.max(hamming_distance(hashes[a].1, hashes[b].1));
+            }
+        }
+
+        let mut images = Vec::with_capacity(indices.len());
+        for index in indices {
+            if let Some(image) = db
+                .get_image_by_id(hashes[index].0)
+                .map_err(|e| format!("Database error: {}", e))?
+            {
+                images.push(image);
+            }
+        }
+        images.sort_by(|a, b| a.path.cmp(&b.path));
+
+        groups.push(DuplicateGroup { images, max_distance });
+    }
+
+    // Tightest clusters first, then by path for a stable order
+    groups.sort_by(|a, b| {
+        a.max_distance
+            .cmp(&b.max_distance)
+            .then_with(|| a.images[0].path.cmp(&b.images[0].path))
+    });
+
+    Ok(groups)
+}
+
+/// Burkhard-Keller tree over Hamming distance, for finding hashes within a radius
+#[derive(Default)]
+struct BkTree {
+    nodes: Vec<BkNode>,
+}
+
+struct BkNode {
+    hash: u64,
+    index: usize,
+    children: HashMap<u32, usize>,
+}
+
+impl BkTree {
+    fn insert(&mut self, hash: u64, index: usize) {
+        let new_node = self.nodes.len();
+        if new_node == 0 {
+            self.nodes.push(BkNode { hash, index, children: HashMap::new() });
+            return;
+        }
+
+        let mut current = 0;
+        loop {
+            let distance = hamming_distance(self.nodes[current].hash, hash);
+            match self.nodes[current].children.get(&distance) {
+                Some(&child) => current = child,
+                None => {
+                    self.nodes[current].children.insert(distance, new_node);
+                    self.nodes.push(BkNode { hash, index, children: HashMap::new() });
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Indices of all hashes within `radius` of `hash`
+    fn within(&self, hash: u64, radius: u32) -> Vec<usize> {
+        let mut found = Vec::new();
+        if self.nodes.is_empty() {
+            return found;
+        }
+
+        let mut stack = vec![0];
+        while let Some(current) = stack.pop() {
+            let node = &self.nodes[current];
+            let distance = hamming_distance(node.hash, hash);
+            if distance <= radius {
+                found.push(node.index);
+            }
+
+            // Triangle inequality: only children at these distances can be in range
+            let low = distance.saturating_sub(radius);
+            let high = distance + radius;
+            stack.extend(
+                node.children
+                    .iter()
+                    .filter(|(d, _)| (low..=high).contains(*d))
+                    .map(|(_, &child)| child),
+            );
+        }
+
+        found
+    }
+}
+
+/// Disjoint-set forest for building clusters
+struct UnionFind {
+    parents: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(size: usize) -> Self {
+        Self {
+            parents: (0..size).collect(),
+        }
+    }
+
+    fn find(&mut self, index: usize) -> usize {
+        let mut root = index;
+        while self.parents[root] != root {
+            root = self.parents[root];
+        }
+
+        // Path compression
+        let mut current = index;
+        while self.parents[current] != root {
+            let next = self.parents[current];
+            self.parents[current] = root;
+            current = next;
+        }
+
+        root
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (root_a, root_b) = (self.find(a), self.find(b));
+        if root_a != root_b {
+            self.parents[root_b] = root_a;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::MediaType;
+    use chrono::Utc;
+    use image::{ImageBuffer, ImageFormat, Rgb};
+
+    /// A smooth gradient with a bright square whose position depends on `variant`
+    fn create_test_image(width: u32, height: u32, variant: u32) -> DynamicImage {
+        let img = ImageBuffer::from_fn(width, height, |x, y| {
+            let in_square = (x * 4 / width == variant % 4) && (y * 4 / height == variant / 4 % 4);
+            if in_square {
+                Rgb([255, 255, 255])
+            } else {
+                let v = ((x + y) * 200 / (width + height)) as u8;
+                Rgb([v, v / 2, 255 - v])
+            }
+        });
+        DynamicImage::ImageRgb8(img)
+    }
+
+    fn insert_image(db: &Database, dir: &Path, name: &str, checksum: &str, img: &DynamicImage) -> i64 {
+        let thumbnail = dir.join(format!("{}_small.jpg", name));
+        img.resize(150, 150, FilterType::Lanczos3)
+            .save_with_format(&thumbnail, ImageFormat::Jpeg)
+            .unwrap();
+
+        db.insert_image(
+            &dir.join(name).to_string_lossy(),
+            &thumbnail.to_string_lossy(),
+            &thumbnail.to_string_lossy(),
+            checksum,
+            MediaType::Image,
+            None,
+            None,
+            None,
+            None,
+            None,
+            img.width(),
+            img.height(),
+            None,
+            None,
+            1024,
+            Utc::now(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_hashes_survive_resizing_and_reencoding() {
+        let original = create_test_image(800, 600, 5);
+        let resized = DynamicImage::ImageRgb8(
+            image::load_from_memory(&{
+                let mut bytes = Vec::new();
+                original
+                    .resize(200, 150, FilterType::Lanczos3)
+                    .write_to(&mut std::io::Cursor::new(&mut bytes), ImageFormat::Jpeg)
+                    .unwrap();
+                bytes
+            })
+            .unwrap()
+            .to_rgb8(),
+        );
+
+        let a = PerceptualHash::of(&original);
+        let b = PerceptualHash::of(&resized);
+        assert!(hamming_distance(a.phash, b.phash) <= 4);
+        assert!(hamming_distance(a.dhash, b.dhash) <= 4);
+    }
+
+    #[test]
+    fn test_hashes_differ_for_different_images() {
+        let img = create_test_image(400, 300, 0);
+        let a = PerceptualHash::of(&img);
+        let b = PerceptualHash::of(&img.fliph());
+
+        assert!(hamming_distance(a.phash, b.phash) > DEFAULT_NEAR_DUPLICATE_THRESHOLD);
+        assert!(hamming_distance(a.dhash, b.dhash) > DEFAULT_NEAR_DUPLICATE_THRESHOLD);
+    }
+
+    #[test]
+    fn test_hamming_distance() {
+        assert_eq!(hamming_distance(0, 0), 0);
+        assert_eq!(hamming_distance(0b1011, 0b0001), 2);
+        assert_eq!(hamming_distance(u64::MAX, 0), 64);
+    }
+
+    #[test]
+    fn test_bk_tree_within() {
+        let mut tree = BkTree::default();
+        let hashes = [0u64, 0b1, 0b11, 0b1111_0000, u64::MAX];
+        for (index, &hash) in hashes.iter().enumerate() {
+            tree.insert(hash, index);
+        }
+
+        let mut found = tree.within(0, 2);
+        found.sort();
+        assert_eq!(found, vec![0, 1, 2]);
+        assert_eq!(tree.within(u64::MAX, 0), vec![4]);
+    }
+
+    #[test]
+    fn test_find_exact_duplicates() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let img = create_test_image(64, 48, 0);
+
+        insert_image(&db, temp_dir.path(), "a.jpg", "same", &img);
+        insert_image(&db, temp_dir.path(), "copy of a.jpg", "same", &img);
+        insert_image(&db, temp_dir.path(), "b.jpg", "other", &img);
+
+        let groups = find_exact_duplicates(&db).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].images.len(), 2);
+        assert!(groups[0].images.iter().all(|i| i.checksum == "same"));
+    }
+
+    #[test]
+    fn test_find_near_duplicates_clusters_similar_images() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+
+        let original = create_test_image(800, 600, 5);
+        let edited = original.brighten(10);
+        let unrelated = create_test_image(800, 600, 10).fliph();
+
+        let a = insert_image(&db, temp_dir.path(), "IMG_0001.jpg", "1", &original);
+        let b = insert_image(&db, temp_dir.path(), "IMG_0001-edit.jpg", "2", &edited);
+        insert_image(&db, temp_dir.path(), "IMG_0002.jpg", "3", &unrelated);
+
+        // Hashes are backfilled for images imported without them
+        let groups = find_near_duplicates(&db, HashAlgorithm::PHash, DEFAULT_NEAR_DUPLICATE_THRESHOLD).unwrap();
+        assert_eq!(db.get_perceptual_hashes().unwrap().len(), 3);
+
+        assert_eq!(groups.len(), 1);
+        let mut ids: Vec<i64> = groups[0].images.iter().map(|i| i.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![a, b]);
+        assert!(groups[0].max_distance <= DEFAULT_NEAR_DUPLICATE_THRESHOLD);
+    }
+
+    #[test]
+    fn test_find_near_duplicates_ignores_trashed_images() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let img = create_test_image(800, 600, 5);
+
+        let a = insert_image(&db, temp_dir.path(), "IMG_0001.jpg", "1", &img);
+        let b = insert_image(&db, temp_dir.path(), "IMG_0001-copy.jpg", "2", &img.brighten(10));
+        assert_eq!(find_near_duplicates(&db, HashAlgorithm::PHash, DEFAULT_NEAR_DUPLICATE_THRESHOLD).unwrap().len(), 1);
+
+        db.trash_image(b, None).unwrap();
+        assert!(find_near_duplicates(&db, HashAlgorithm::PHash, DEFAULT_NEAR_DUPLICATE_THRESHOLD).unwrap().is_empty());
+
+        db.restore_image(b).unwrap();
+        let groups = find_near_duplicates(&db, HashAlgorithm::PHash, DEFAULT_NEAR_DUPLICATE_THRESHOLD).unwrap();
+        let mut ids: Vec<i64> = groups[0].images.iter().map(|i| i.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![a, b]);
+    }
+
+    #[test]
+    fn test_edited_images_are_hashed_as_taken() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let original = create_test_image(800, 600, 5);
+        original.save(temp_dir.path().join("IMG_0001.jpg")).unwrap();
+
+        // The thumbnail shows the image flipped by its edits
+        let id = insert_image(&db, temp_dir.path(), "IMG_0001.jpg", "1", &original.fliph());
+        let recipe = crate::edits::EditRecipe {
+            flip_horizontal: true,
+            ..Default::default()
+        };
+        db.set_edit_recipe(id, Some(&recipe)).unwrap();
+
+        assert_eq!(backfill_hashes(&db).unwrap(), 1);
+        let (_, _, phash) = db.get_perceptual_hashes().unwrap()[0];
+        assert!(hamming_distance(phash, PerceptualHash::of(&original).phash) <= 4);
+    }
+
+    #[test]
+    fn test_perceptual_hash_deleted_with_image() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let img = create_test_image(64, 48, 0);
+
+        let id = insert_image(&db, temp_dir.path(), "a.jpg", "a", &img);
+        store_hash(&db, id, &temp_dir.path().join("a.jpg_small.jpg").to_string_lossy()).unwrap();
+        assert_eq!(db.get_perceptual_hashes().unwrap().len(), 1);
+
+        db.delete_image(id).unwrap();
+        assert!(db.get_perceptual_hashes().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,378 @@
+use image::{DynamicImage, GenericImageView, Rgba};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+use crate::database::{Database, ImageRecord, MediaType};
+use crate::thumbnail;
+
+/// Strongest exposure change in stops
+pub const MAX_EXPOSURE: f64 = 5.0;
+
+/// Gain applied to the red/blue or green channel at full white balance strength
+const WHITE_BALANCE_GAIN: f64 = 0.25;
+
+/// Non-destructive edits for an image, applied when rendering thumbnails and exports
+///
+/// Geometry is applied to the upright image in the order flip, rotate, crop;
+/// tone adjustments follow. The original file is never modified.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct EditRecipe {
+    /// Crop applied after flipping and rotating
+    pub crop: Option<CropRect>,
+    /// Clockwise rotation in degrees, a multiple of 90
+    pub rotation: i32,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+    /// Exposure change in stops, from -5 to 5
+    pub exposure: f64,
+    /// Contrast from -1 (flat) to 1
+    pub contrast: f64,
+    /// Saturation from -1 (black and white) to 1
+    pub saturation: f64,
+    /// White balance from -1 (cooler) to 1 (warmer)
+    pub temperature: f64,
+    /// White balance from -1 (greener) to 1 (more magenta)
+    pub tint: f64,
+}
+
+/// Crop rectangle as fractions of the image width and height
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct CropRect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl EditRecipe {
+    /// Whether the recipe leaves the image unchanged
+    pub fn is_identity(&self) -> bool {
+        self.normalized() == EditRecipe::default()
+    }
+
+    /// Short hash identifying the rendered result, used in thumbnail cache keys
+    pub fn hash(&self) -> String {
+        let json = serde_json::to_string(&self.normalized()).unwrap_or_default();
+        let digest = Sha256::digest(json.as_bytes());
+        format!("{:x}", digest)[..12].to_string()
+    }
+
+    /// Check that every value is within range
+    pub fn validate(&self) -> Result<(), String> {
+        if self.rotation % 90 != 0 {
+            return Err(format!("Rotation must be a multiple of 90 degrees, got {}", self.rotation));
+        }
+        if !self.exposure.is_finite() || self.exposure.abs() > MAX_EXPOSURE {
+            return Err(format!(
+                "Exposure must be between -{} and {} stops, got {}",
+                MAX_EXPOSURE, MAX_EXPOSURE, self.exposure
+            ));
+        }
+        for (name, value) in [
+            ("Contrast", self.contrast),
+            ("Saturation", self.saturation),
+            ("Temperature", self.temperature),
+            ("Tint", self.tint),
+        ] {
+            if !value.is_finite() || value.abs() > 1.0 {
+                return Err(format!("{} must be between -1 and 1, got {}", name, value));
+            }
+        }
+        if let Some(crop) = &self.crop {
+            let values = [crop.x, crop.y, crop.width, crop.height];
+            if values.iter().any(|v| !v.is_finite())
+                || crop.x < 0.0
+                || crop.y < 0.0
+                || crop.width <= 0.0
+                || crop.height <= 0.0
+                || crop.x + crop.width > 1.0 + f64::EPSILON
+                || crop.y + crop.height > 1.0 + f64::EPSILON
+            {
+                return Err("Crop must be a non-empty rectangle inside the image".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the whole recipe
+    pub fn apply(&self, img: DynamicImage) -> DynamicImage {
+        self.apply_adjustments(self.apply_geometry(img))
+    }
+
+    /// Apply the flip, rotation and crop
+    pub fn apply_geometry(&self, img: DynamicImage) -> DynamicImage {
+        let mut img = img;
+        if self.flip_horizontal {
+            img = img.fliph();
+        }
+        if self.flip_vertical {
+            img = img.flipv();
+        }
+        img = match self.rotation.rem_euclid(360) {
+            90 => img.rotate90(),
+            180 => img.rotate180(),
+            270 => img.rotate270(),
+            _ => img,
+        };
+
+        if let Some(crop) = &self.crop {
+            let (width, height) = img.dimensions();
+            let x = ((crop.x * width as f64).round() as u32).min(width - 1);
+            let y = ((crop.y * height as f64).round() as u32).min(height - 1);
+            let crop_width = ((crop.width * width as f64).round() as u32).clamp(1, width - x);
+            let crop_height = ((crop.height * height as f64).round() as u32).clamp(1, height - y);
+            img = img.crop_imm(x, y, crop_width, crop_height);
+        }
+
+        img
+    }
+
+    /// Apply the exposure, white balance, contrast and saturation
+    ///
+    /// These work per pixel, so thumbnails can be adjusted after resizing.
+    pub fn apply_adjustments(&self, img: DynamicImage) -> DynamicImage {
+        if self.exposure == 0.0
+            && self.contrast == 0.0
+            && self.saturation == 0.0
+            && self.temperature == 0.0
+            && self.tint == 0.0
+        {
+            return img;
+        }
+
+        // Exposure, white balance and contrast act on each channel on its own,
+        // so they are precomputed into lookup tables
+        let exposure = 2f64.powf(self.exposure);
+        let gains = [
+            exposure * (1.0 + WHITE_BALANCE_GAIN * self.temperature),
+            exposure * (1.0 - WHITE_BALANCE_GAIN * self.tint),
+            exposure * (1.0 - WHITE_BALANCE_GAIN * self.temperature),
+        ];
+        let tables = gains.map(|gain| {
+            let mut table = [0f64; 256];
+            for (value, entry) in table.iter_mut().enumerate() {
+                // Exposure is applied to linear light, contrast around middle grey
+                let linear = (value as f64 / 255.0).powf(2.2) * gain;
+                let encoded = linear.min(1.0).powf(1.0 / 2.2);
+                *entry = ((encoded - 0.5) * (1.0 + self.contrast) + 0.5).clamp(0.0, 1.0);
+            }
+            table
+        });
+
+        let has_alpha = img.color().has_alpha();
+        let mut pixels = img.to_rgba8();
+        for Rgba(pixel) in pixels.pixels_mut() {
+            let [r, g, b] = [0, 1, 2].map(|c| tables[c][pixel[c] as usize]);
+            let luma = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+            for (c, value) in [r, g, b].into_iter().enumerate() {
+                let saturated = luma + (value - luma) * (1.0 + self.saturation);
+                pixel[c] = (saturated.clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+        }
+
+        if has_alpha {
+            DynamicImage::ImageRgba8(pixels)
+        } else {
+            DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(pixels).to_rgb8())
+        }
+    }
+
+    /// The same edits with the rotation in 0..360 and a full-image crop removed
+    fn normalized(&self) -> EditRecipe {
+        let full_image = |crop: &CropRect| {
+            crop.x == 0.0 && crop.y == 0.0 && crop.width == 1.0 && crop.height == 1.0
+        };
+        EditRecipe {
+            crop: self.crop.filter(|crop| !full_image(crop)),
+            rotation: self.rotation.rem_euclid(360),
+            ..self.clone()
+        }
+    }
+}
+
+/// Get the edits of an image, which are empty if it was never edited
+pub fn get_recipe(db: &Database, image_id: i64) -> Result<EditRecipe, String> {
+    require_photo(db, image_id)?;
+
+    db.get_edit_recipe(image_id)
+        .map(|recipe| recipe.unwrap_or_default())
+        .map_err(|e| format!("Database error: {}", e))
+}
+
+/// Save the edits of an image and render its thumbnails with them
+///
+/// An identity recipe removes the edits. Returns the image with its new
+/// thumbnail paths.
+pub fn set_recipe(
+    db: &Database,
+    image_id: i64,
+    recipe: &EditRecipe,
+    cache_dir: &Path,
+) -> Result<ImageRecord, String> {
+    recipe.validate()?;
+    let image = require_photo(db, image_id)?;
+
+    let stored = Some(recipe).filter(|r| !r.is_identity());
+    let thumbnails = thumbnail::generate_edited_thumbnails(&image.path, &image.checksum, stored, cache_dir)?;
+
+    db.set_edit_recipe(image_id, stored)
+        .and_then(|_| db.set_image_thumbnails(image_id, &thumbnails.small, &thumbnails.medium))
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    // Thumbnails of earlier edits are not needed anymore once no image uses them
+    for old in [&image.thumbnail_small, &image.thumbnail_medium] {
+        if *old != thumbnails.small && *old != thumbnails.medium {
+            let unused = db
+                .count_images_with_thumbnail(old)
+                .map(|count| count == 0)
+                .unwrap_or(false);
+            if unused {
+                let _ = fs::remove_file(old);
+            }
+        }
+    }
+
+    db.get_image_by_id(image_id)
+        .map_err(|e| format!("Database error: {}", e))?
+        .ok_or_else(|| format!("Image {} not found", image_id))
+}
+
+fn require_photo(db: &Database, image_id: i64) -> Result<ImageRecord, String> {
+    let image = db
+        .get_image_by_id(image_id)
+        .map_err(|e| format!("Database error: {}", e))?
+        .ok_or_else(|| format!("Image {} not found", image_id))?;
+
+    if image.media_type != MediaType::Image {
+        return Err("Only photos can be edited".to_string());
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use image::{ImageFormat, RgbImage};
+
+    fn grey(value: u8) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(4, 2, image::Rgb([value, value, value])))
+    }
+
+    #[test]
+    fn test_identity_and_hash() {
+        assert!(EditRecipe::default().is_identity());
+
+        let full_crop = EditRecipe {
+            crop: Some(CropRect { x: 0.0, y: 0.0, width: 1.0, height: 1.0 }),
+            rotation: 360,
+            ..Default::default()
+        };
+        assert!(full_crop.is_identity());
+        assert_eq!(full_crop.hash(), EditRecipe::default().hash());
+
+        let rotated = EditRecipe { rotation: -90, ..Default::default() };
+        let same = EditRecipe { rotation: 270, ..Default::default() };
+        assert!(!rotated.is_identity());
+        assert_eq!(rotated.hash(), same.hash());
+        assert_ne!(rotated.hash(), EditRecipe { exposure: 0.5, ..Default::default() }.hash());
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(EditRecipe { rotation: 45, ..Default::default() }.validate().is_err());
+        assert!(EditRecipe { exposure: 6.0, ..Default::default() }.validate().is_err());
+        assert!(EditRecipe { contrast: f64::NAN, ..Default::default() }.validate().is_err());
+        assert!(EditRecipe { tint: -1.5, ..Default::default() }.validate().is_err());
+
+        let crop = |x, y, width, height| EditRecipe {
+            crop: Some(CropRect { x, y, width, height }),
+            ..Default::default()
+        };
+        assert!(crop(0.25, 0.25, 0.5, 0.75).validate().is_ok());
+        assert!(crop(0.5, 0.0, 0.6, 1.0).validate().is_err());
+        assert!(crop(0.0, 0.0, 0.0, 1.0).validate().is_err());
+    }
+
+    #[test]
+    fn test_geometry() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(40, 20, |x, _| image::Rgb([x as u8, 0, 0])));
+
+        let rotated = EditRecipe { rotation: 90, ..Default::default() }.apply_geometry(img.clone());
+        assert_eq!(rotated.dimensions(), (20, 40));
+
+        let cropped = EditRecipe {
+            crop: Some(CropRect { x: 0.5, y: 0.0, width: 0.5, height: 0.5 }),
+            ..Default::default()
+        }
+        .apply_geometry(img.clone());
+        assert_eq!(cropped.dimensions(), (20, 10));
+        assert_eq!(cropped.to_rgb8().get_pixel(0, 0)[0], 20);
+
+        let flipped = EditRecipe { flip_horizontal: true, ..Default::default() }.apply_geometry(img);
+        assert_eq!(flipped.to_rgb8().get_pixel(0, 0)[0], 39);
+    }
+
+    #[test]
+    fn test_adjustments() {
+        let pixel = |recipe: EditRecipe, value: u8| *recipe.apply(grey(value)).to_rgb8().get_pixel(0, 0);
+
+        assert!(pixel(EditRecipe { exposure: 1.0, ..Default::default() }, 100)[0] > 100);
+        assert!(pixel(EditRecipe { exposure: -1.0, ..Default::default() }, 100)[0] < 100);
+        assert_eq!(pixel(EditRecipe { contrast: -1.0, ..Default::default() }, 10).0, [128, 128, 128]);
+
+        let warm = pixel(EditRecipe { temperature: 1.0, ..Default::default() }, 128);
+        assert!(warm[0] > warm[2]);
+
+        let colorful = DynamicImage::ImageRgb8(RgbImage::from_pixel(1, 1, image::Rgb([200, 50, 50])));
+        let mono = EditRecipe { saturation: -1.0, ..Default::default() }.apply(colorful);
+        let [r, g, b] = mono.to_rgb8().get_pixel(0, 0).0;
+        assert!(r == g && g == b);
+    }
+
+    #[test]
+    fn test_set_recipe_renders_thumbnails_without_touching_original() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let cache_dir = temp_dir.path().join("thumbnails");
+
+        let original = temp_dir.path().join("photo.png");
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(400, 200, image::Rgb([120, 90, 60])))
+            .save_with_format(&original, ImageFormat::Png)
+            .unwrap();
+        let original_bytes = fs::read(&original).unwrap();
+        let path = original.to_str().unwrap();
+        let checksum = thumbnail::compute_checksum(&original).unwrap();
+        let plain = thumbnail::generate_thumbnails_with_checksum(path, &checksum, &cache_dir).unwrap();
+        let id = db
+            .insert_image(
+                path, &plain.small, &plain.medium, &checksum, MediaType::Image,
+                None, None, None, None, None, 400, 200, None, None, 1024, Utc::now(),
+            )
+            .unwrap();
+
+        let recipe = EditRecipe { rotation: 90, exposure: 0.5, ..Default::default() };
+        let edited = set_recipe(&db, id, &recipe, &cache_dir).unwrap();
+        assert!(edited.thumbnail_medium.contains(&recipe.hash()));
+        assert_eq!(image::open(&edited.thumbnail_medium).unwrap().dimensions(), (600, 1200));
+        assert_eq!(get_recipe(&db, id).unwrap(), recipe);
+        assert_eq!(fs::read(&original).unwrap(), original_bytes);
+
+        // Changing the edits replaces the previous edited thumbnails
+        let recipe = EditRecipe { flip_vertical: true, ..Default::default() };
+        let reedited = set_recipe(&db, id, &recipe, &cache_dir).unwrap();
+        assert_ne!(reedited.thumbnail_small, edited.thumbnail_small);
+        assert!(!Path::new(&edited.thumbnail_small).exists());
+
+        // Resetting goes back to the plain thumbnails
+        let reset = set_recipe(&db, id, &EditRecipe::default(), &cache_dir).unwrap();
+        assert_eq!(reset.thumbnail_small, plain.small);
+        assert_eq!(db.get_edit_recipe(id).unwrap(), None);
+        assert!(Path::new(&plain.small).exists());
+
+        assert!(set_recipe(&db, id, &EditRecipe { rotation: 10, ..Default::default() }, &cache_dir).is_err());
+    }
+}
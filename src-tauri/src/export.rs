@@ -0,0 +1,750 @@
+use exif::experimental::Writer;
+use exif::{Context, Field, In, Reader, Tag, Value};
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageEncoder};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Cursor, Write};
+use std::path::{Path, PathBuf};
+
+use crate::database::{Database, ImageRecord, MediaType};
+use crate::edits::EditRecipe;
+use crate::thumbnail;
+
+/// Encoder speed for AVIF, from 1 (slowest, smallest) to 10
+const AVIF_SPEED: u8 = 6;
+
+/// Placeholders available in filename templates
+const TEMPLATE_TOKENS: [&str; 7] = ["name", "date", "time", "camera", "make", "seq", "id"];
+
+/// File format of exported images
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Jpeg,
+    Png,
+    Webp,
+    Avif,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Jpeg => "jpg",
+            ExportFormat::Png => "png",
+            ExportFormat::Webp => "webp",
+            ExportFormat::Avif => "avif",
+        }
+    }
+}
+
+/// Which EXIF metadata is carried over from the originals
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MetadataMode {
+    #[default]
+    Keep,
+    /// Keep everything except the location
+    StripGps,
+    StripAll,
+}
+
+/// Options for an export
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    /// Quality from 1 to 100, used by the lossy formats (JPEG, WebP and AVIF)
+    ///
+    /// WebP at quality 100 is written lossless.
+    pub quality: u8,
+    /// Longest edge in pixels; larger images are scaled down, smaller ones are kept as they are
+    pub long_edge: Option<u32>,
+    /// File name without extension, with placeholders such as `{date}_{camera}_{seq}`
+    pub filename_template: String,
+    pub metadata: MetadataMode,
+    /// Render the non-destructive edits into the exported files
+    pub apply_edits: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            format: ExportFormat::Jpeg,
+            quality: 90,
+            long_edge: None,
+            filename_template: "{name}".to_string(),
+            metadata: MetadataMode::Keep,
+            apply_edits: true,
+        }
+    }
+}
+
+impl ExportOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=100).contains(&self.quality) {
+            return Err(format!("Quality must be between 1 and 100, got {}", self.quality));
+        }
+        if self.long_edge == Some(0) {
+            return Err("Long edge must be at least 1 pixel".to_string());
+        }
+        validate_template(&self.filename_template)
+    }
+}
+
+/// Progress of an export, emitted before each image is written
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportProgress {
+    pub current: usize,
+    pub total: usize,
+    pub current_file: String,
+    pub percentage: f64,
+}
+
+/// Outcome of an export
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExportResult {
+    /// Paths of the written files
+    pub exported: Vec<String>,
+    pub failed: Vec<ExportError>,
+}
+
+/// An image that could not be exported
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportError {
+    pub image_id: i64,
+    pub path: String,
+    pub error: String,
+}
+
+/// Export images into a folder, converting them as described by `options`
+///
+/// Images that fail are reported in the result and do not stop the export.
+/// Existing files are never overwritten; a number is appended to the name instead.
+pub fn export_images<F>(
+    db: &Database,
+    image_ids: &[i64],
+    target_folder: &Path,
+    options: &ExportOptions,
+    progress_callback: F,
+) -> Result<ExportResult, String>
+where
+    F: Fn(ExportProgress),
+{
+    options.validate()?;
+    fs::create_dir_all(target_folder)
+        .map_err(|e| format!("Failed to create export folder: {}", e))?;
+
+    let mut result = ExportResult::default();
+    let total = image_ids.len();
+
+    for (index, &image_id) in image_ids.iter().enumerate() {
+        let image = match db.get_image_by_id(image_id) {
+            Ok(Some(image)) => image,
+            Ok(None) => {
+                result.failed.push(ExportError {
+                    image_id,
+                    path: String::new(),
+                    error: "Media file not found in database".to_string(),
+                });
+                continue;
+            }
+            Err(e) => {
+                result.failed.push(ExportError {
+                    image_id,
+                    path: String::new(),
+                    error: format!("Database error: {}", e),
+                });
+                continue;
+            }
+        };
+
+        progress_callback(ExportProgress {
+            current: index + 1,
+            total,
+            current_file: image.path.clone(),
+            percentage: ((index + 1) as f64 / total as f64) * 100.0,
+        });
+
+        let name = render_template(&options.filename_template, &image, index + 1, total);
+        let destination = unique_destination(target_folder, &name, options.format.extension());
+
+        match export_image(db, &image, &destination, options) {
+            Ok(()) => result.exported.push(destination.to_string_lossy().to_string()),
+            Err(error) => {
+                // Don't leave a half-written file behind
+                let _ = fs::remove_file(&destination);
+                result.failed.push(ExportError {
+                    image_id,
+                    path: image.path.clone(),
+                    error,
+                });
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+fn export_image(
+    db: &Database,
+    image: &ImageRecord,
+    destination: &Path,
+    options: &ExportOptions,
+) -> Result<(), String> {
+    let recipe = if options.apply_edits {
+        db.get_edit_recipe(image.id)
+            .map_err(|e| format!("Database error: {}", e))?
+    } else {
+        None
+    };
+
+    convert_image(image, recipe.as_ref(), destination, options)
+}
+
+/// Write a photo to `destination` with `recipe` applied, converted as described by `options`
+///
+/// `options.apply_edits` is ignored; pass no recipe to keep the original look.
+pub fn convert_image(
+    image: &ImageRecord,
+    recipe: Option<&EditRecipe>,
+    destination: &Path,
+    options: &ExportOptions,
+) -> Result<(), String> {
+    if image.media_type != MediaType::Image {
+        return Err("Only photos can be exported".to_string());
+    }
+
+    let source = Path::new(image.trash_path.as_deref().unwrap_or(&image.path));
+    let mut img = load_original(source, source_long_edge(options.long_edge, recipe))?;
+
+    if let Some(recipe) = recipe {
+        img = recipe.apply(img);
+    }
+
+    if let Some(long_edge) = options.long_edge {
+        img = fit_long_edge(img, long_edge);
+    }
+
+    let exif = match options.metadata {
+        MetadataMode::StripAll => None,
+        mode => copy_exif(source, mode == MetadataMode::StripGps)?,
+    };
+
+    encode(&img, destination, options, exif)
+}
+
+/// Load the upright original at full resolution
+///
+/// RAW files are demosaiced when the build supports it, and otherwise
+/// exported from their embedded preview.
+fn load_original(path: &Path, long_edge: Option<u32>) -> Result<DynamicImage, String> {
+    if crate::raw::is_raw_path(path) {
+        if let Ok(img) = crate::raw::demosaic(path, long_edge.unwrap_or(u32::MAX)) {
+            return Ok(img);
+        }
+    }
+
+    thumbnail::load_image(path)
+}
+
+/// Long edge the original must be loaded at for the edited result to reach `long_edge`
+///
+/// A crop keeps only part of the image, so the original is loaded larger by
+/// as much as the crop shrinks it.
+fn source_long_edge(long_edge: Option<u32>, recipe: Option<&EditRecipe>) -> Option<u32> {
+    let long_edge = long_edge?;
+    match recipe.and_then(|recipe| recipe.crop.as_ref()) {
+        Some(crop) => {
+            let scaled = (long_edge as f64 / crop.width.min(crop.height)).ceil();
+            Some(if scaled < u32::MAX as f64 { scaled as u32 } else { u32::MAX })
+        }
+        None => Some(long_edge),
+    }
+}
+
+/// Scale an image down so its longest edge is at most `long_edge` pixels
+fn fit_long_edge(img: DynamicImage, long_edge: u32) -> DynamicImage {
+    let (width, height) = img.dimensions();
+    if width.max(height) <= long_edge {
+        return img;
+    }
+
+    img.resize(long_edge, long_edge, FilterType::Lanczos3)
+}
+
+fn encode(
+    img: &DynamicImage,
+    destination: &Path,
+    options: &ExportOptions,
+    exif: Option<Vec<u8>>,
+) -> Result<(), String> {
+    let file = File::create(destination)
+        .map_err(|e| format!("Failed to create {}: {}", destination.display(), e))?;
+    let mut writer = BufWriter::new(file);
+
+    let encoded = match options.format {
+        ExportFormat::Jpeg => {
+            let mut encoder = JpegEncoder::new_with_quality(&mut writer, options.quality);
+            attach_exif(&mut encoder, exif);
+            img.write_with_encoder(encoder)
+        }
+        ExportFormat::Png => {
+            let mut encoder = PngEncoder::new(&mut writer);
+            attach_exif(&mut encoder, exif);
+            img.write_with_encoder(encoder)
+        }
+        ExportFormat::Webp => {
+            let webp = encode_webp(img, options.quality, exif)?;
+            writer.write_all(&webp).map_err(image::ImageError::IoError)
+        }
+        ExportFormat::Avif => {
+            // The AVIF encoder cannot embed EXIF metadata
+            let encoder = AvifEncoder::new_with_speed_quality(&mut writer, AVIF_SPEED, options.quality);
+            let img = if img.color().has_alpha() {
+                DynamicImage::ImageRgba8(img.to_rgba8())
+            } else {
+                DynamicImage::ImageRgb8(img.to_rgb8())
+            };
+            img.write_with_encoder(encoder)
+        }
+    };
+
+    encoded.map_err(|e| format!("Failed to encode image: {}", e))?;
+    writer
+        .into_inner()
+        .map_err(|e| format!("Failed to write {}: {}", destination.display(), e.error()))?;
+
+    Ok(())
+}
+
+/// Encode a WebP file with libwebp, lossless at quality 100
+fn encode_webp(img: &DynamicImage, quality: u8, exif: Option<Vec<u8>>) -> Result<Vec<u8>, String> {
+    let (width, height) = img.dimensions();
+    let has_alpha = img.color().has_alpha();
+    let pixels = if has_alpha {
+        img.to_rgba8().into_raw()
+    } else {
+        img.to_rgb8().into_raw()
+    };
+    let encoder = if has_alpha {
+        webp::Encoder::from_rgba(&pixels, width, height)
+    } else {
+        webp::Encoder::from_rgb(&pixels, width, height)
+    };
+
+    let encoded = encoder
+        .encode_simple(quality == 100, f32::from(quality))
+        .map_err(|e| format!("Failed to encode image: {:?}", e))?;
+
+    Ok(match exif {
+        Some(exif) => add_webp_exif(&encoded, &exif, width, height, has_alpha),
+        None => encoded.to_vec(),
+    })
+}
+
+/// Add an EXIF chunk to a WebP file, since libwebp's simple encoder writes no metadata
+///
+/// Files in the simple format get the extended format's VP8X header first.
+fn add_webp_exif(webp: &[u8], exif: &[u8], width: u32, height: u32, has_alpha: bool) -> Vec<u8> {
+    const EXIF_FLAG: u8 = 0x08;
+    const ALPHA_FLAG: u8 = 0x10;
+
+    // Skip the "RIFF" <size> "WEBP" header, it is rewritten with the new size
+    let chunks = &webp[12..];
+    let mut body = b"WEBP".to_vec();
+    if chunks.starts_with(b"VP8X") {
+        body.extend_from_slice(chunks);
+        body[4 + 8] |= EXIF_FLAG;
+    } else {
+        body.extend_from_slice(b"VP8X");
+        body.extend_from_slice(&10u32.to_le_bytes());
+        body.push(EXIF_FLAG | if has_alpha { ALPHA_FLAG } else { 0 });
+        body.extend_from_slice(&[0; 3]);
+        body.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        body.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        body.extend_from_slice(chunks);
+    }
+
+    body.extend_from_slice(b"EXIF");
+    body.extend_from_slice(&(exif.len() as u32).to_le_bytes());
+    body.extend_from_slice(exif);
+    if exif.len() % 2 == 1 {
+        body.push(0);
+    }
+
+    let mut file = b"RIFF".to_vec();
+    file.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    file.extend_from_slice(&body);
+    file
+}
+
+fn attach_exif(encoder: &mut impl ImageEncoder, exif: Option<Vec<u8>>) {
+    if let Some(exif) = exif {
+        // Only called for encoders that support EXIF
+        let _ = encoder.set_exif_metadata(exif);
+    }
+}
+
+/// Rebuild the EXIF metadata of an original for an exported copy
+///
+/// Only the primary image's fields are kept. The orientation is reset since
+/// exported pixels are already upright, and fields describing the original
+/// pixels (dimensions, maker notes, the embedded thumbnail) are dropped.
+/// Returns None when the original has no EXIF metadata.
+fn copy_exif(path: &Path, strip_gps: bool) -> Result<Option<Vec<u8>>, String> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => return Err(format!("Failed to open image: {}", e)),
+    };
+    let exif = match Reader::new().read_from_container(&mut BufReader::new(file)) {
+        Ok(exif) => exif,
+        Err(_) => return Ok(None),
+    };
+
+    let fields: Vec<&Field> = exif
+        .fields()
+        .filter(|field| field.ifd_num == In::PRIMARY)
+        .filter(|field| !(strip_gps && field.tag.context() == Context::Gps))
+        .filter(|field| {
+            !matches!(
+                field.tag,
+                Tag::Orientation | Tag::MakerNote | Tag::PixelXDimension | Tag::PixelYDimension
+            )
+        })
+        .collect();
+
+    let orientation = Field {
+        tag: Tag::Orientation,
+        ifd_num: In::PRIMARY,
+        value: Value::Short(vec![1]),
+    };
+
+    let mut writer = Writer::new();
+    writer.push_field(&orientation);
+    for field in fields {
+        writer.push_field(field);
+    }
+
+    let mut buffer = Cursor::new(Vec::new());
+    writer
+        .write(&mut buffer, exif.little_endian())
+        .map_err(|e| format!("Failed to write EXIF metadata: {}", e))?;
+
+    Ok(Some(buffer.into_inner()))
+}
+
+fn validate_template(template: &str) -> Result<(), String> {
+    if template.trim().is_empty() {
+        return Err("Filename template cannot be empty".to_string());
+    }
+
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("Unclosed placeholder in filename template: {}", template))?;
+        let token = &rest[start + 1..start + end];
+        if !TEMPLATE_TOKENS.contains(&token) {
+            return Err(format!(
+                "Unknown placeholder {{{}}} in filename template, expected one of {}",
+                token,
+                TEMPLATE_TOKENS.map(|t| format!("{{{}}}", t)).join(", ")
+            ));
+        }
+        rest = &rest[start + end + 1..];
+    }
+
+    Ok(())
+}
+
+/// Fill in the placeholders of a filename template for an image
+///
+/// `seq` is the image's 1-based position in the export, zero-padded to the
+/// width of `total`.
+fn render_template(template: &str, image: &ImageRecord, seq: usize, total: usize) -> String {
+    let date = image.local_capture_date().unwrap_or(image.file_modified.naive_utc());
+    let name = Path::new(&image.path)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let width = total.to_string().len().max(3);
+
+    let rendered = template
+        .replace("{name}", &name)
+        .replace("{date}", &date.format("%Y-%m-%d").to_string())
+        .replace("{time}", &date.format("%H%M%S").to_string())
+        .replace("{camera}", image.camera_model.as_deref().unwrap_or("unknown"))
+        .replace("{make}", image.camera_make.as_deref().unwrap_or("unknown"))
+        .replace("{seq}", &format!("{:0width$}", seq, width = width))
+        .replace("{id}", &image.id.to_string());
+
+    let sanitized = sanitize_file_name(&rendered);
+    if sanitized.is_empty() {
+        format!("image_{}", image.id)
+    } else {
+        sanitized
+    }
+}
+
+/// Replace characters that are not allowed in file names on common platforms
+fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>()
+        .trim_matches(|c: char| c == '.' || c.is_whitespace())
+        .to_string()
+}
+
+/// Path for an exported file that doesn't exist yet, numbering the name on collisions
+fn unique_destination(folder: &Path, name: &str, extension: &str) -> PathBuf {
+    let mut destination = folder.join(format!("{}.{}", name, extension));
+    let mut counter = 2;
+    while destination.exists() {
+        destination = folder.join(format!("{}_{}.{}", name, counter, extension));
+        counter += 1;
+    }
+    destination
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::edits::EditRecipe;
+    use chrono::{TimeZone, Utc};
+    use image::{ImageFormat, RgbImage};
+
+    /// JPEG with camera and GPS metadata, rotated by its EXIF orientation
+    fn write_tagged_jpeg(path: &Path, width: u32, height: u32) {
+        let fields = [
+            Field {
+                tag: Tag::Model,
+                ifd_num: In::PRIMARY,
+                value: Value::Ascii(vec![b"X100V".to_vec()]),
+            },
+            Field {
+                tag: Tag::Orientation,
+                ifd_num: In::PRIMARY,
+                value: Value::Short(vec![6]),
+            },
+            Field {
+                tag: Tag::GPSLatitudeRef,
+                ifd_num: In::PRIMARY,
+                value: Value::Ascii(vec![b"N".to_vec()]),
+            },
+        ];
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut exif = Cursor::new(Vec::new());
+        writer.write(&mut exif, false).unwrap();
+
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, image::Rgb([200, 80, 40])));
+        let mut encoder = JpegEncoder::new_with_quality(File::create(path).unwrap(), 90);
+        encoder.set_exif_metadata(exif.into_inner()).unwrap();
+        img.write_with_encoder(encoder).unwrap();
+    }
+
+    fn create_library() -> (tempfile::TempDir, Database, i64) {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let path = temp_dir.path().join("DSCF0001.jpg");
+        write_tagged_jpeg(&path, 400, 200);
+        let path = path.to_str().unwrap();
+
+        let image_id = db
+            .insert_image(
+                path, "s.jpg", "m.jpg", path, MediaType::Image,
+                Some(Utc.with_ymd_and_hms(2024, 5, 17, 9, 30, 0).unwrap()),
+                Some("FUJIFILM"), Some("X100V"), Some(48.85), Some(2.29),
+                200, 400, None, None, 1024, Utc::now(),
+            )
+            .unwrap();
+
+        (temp_dir, db, image_id)
+    }
+
+    fn read_exif(path: &Path) -> Option<exif::Exif> {
+        let file = File::open(path).unwrap();
+        Reader::new().read_from_container(&mut BufReader::new(file)).ok()
+    }
+
+    #[test]
+    fn test_template_rendering_and_validation() {
+        let (_temp_dir, db, image_id) = create_library();
+        let image = db.get_image_by_id(image_id).unwrap().unwrap();
+
+        assert_eq!(render_template("{date}_{camera}_{seq}", &image, 7, 12), "2024-05-17_X100V_007");
+        assert_eq!(render_template("{name}-{time}", &image, 1, 1), "DSCF0001-093000");
+        assert_eq!(render_template("{make}/{seq}", &image, 42, 1500), "FUJIFILM_0042");
+        assert_eq!(render_template("...", &image, 1, 1), format!("image_{}", image_id));
+
+        assert!(validate_template("{date}_{seq}").is_ok());
+        assert!(validate_template("{lens}").is_err());
+        assert!(validate_template("{date").is_err());
+        assert!(validate_template(" ").is_err());
+    }
+
+    #[test]
+    fn test_template_dates_are_local_to_the_capture() {
+        let (_temp_dir, db, image_id) = create_library();
+        let settings = crate::metadata::CaptureSettings {
+            utc_offset_minutes: Some(-600),
+            ..Default::default()
+        };
+        db.set_capture_settings(image_id, &settings).unwrap();
+        let image = db.get_image_by_id(image_id).unwrap().unwrap();
+
+        // 09:30 UTC was still the evening before in UTC-10
+        assert_eq!(render_template("{date}_{time}", &image, 1, 1), "2024-05-16_233000");
+    }
+
+    #[test]
+    fn test_export_resizes_converts_and_numbers_collisions() {
+        let (temp_dir, db, image_id) = create_library();
+        let target = temp_dir.path().join("export");
+        let options = ExportOptions {
+            format: ExportFormat::Png,
+            long_edge: Some(100),
+            filename_template: "{camera}".to_string(),
+            ..Default::default()
+        };
+
+        let progress = std::cell::RefCell::new(Vec::new());
+        let result = export_images(&db, &[image_id, image_id, 9999], &target, &options, |p| {
+            progress.borrow_mut().push(p.current)
+        })
+        .unwrap();
+
+        assert_eq!(result.failed.len(), 1);
+        assert_eq!(*progress.borrow(), vec![1, 2]);
+        assert_eq!(
+            result.exported,
+            vec![
+                target.join("X100V.png").to_string_lossy().to_string(),
+                target.join("X100V_2.png").to_string_lossy().to_string(),
+            ]
+        );
+
+        // The original is 400x200 but stored rotated, so the export is upright and portrait
+        let exported = image::open(&result.exported[0]).unwrap();
+        assert_eq!(image::guess_format(&fs::read(&result.exported[0]).unwrap()).unwrap(), ImageFormat::Png);
+        assert_eq!(exported.dimensions(), (50, 100));
+
+        // Smaller images are never scaled up
+        let options = ExportOptions {
+            long_edge: Some(10_000),
+            ..Default::default()
+        };
+        let result = export_images(&db, &[image_id], &target, &options, |_| {}).unwrap();
+        assert_eq!(image::open(&result.exported[0]).unwrap().dimensions(), (200, 400));
+
+        let invalid = ExportOptions {
+            quality: 0,
+            ..Default::default()
+        };
+        assert!(export_images(&db, &[image_id], &target, &invalid, |_| {}).is_err());
+    }
+
+    #[test]
+    fn test_export_webp_quality() {
+        let (temp_dir, db, image_id) = create_library();
+        let target = temp_dir.path().join("export");
+        let export = |quality: u8, metadata: MetadataMode| {
+            let options = ExportOptions {
+                format: ExportFormat::Webp,
+                quality,
+                metadata,
+                ..Default::default()
+            };
+            let result = export_images(&db, &[image_id], &target, &options, |_| {}).unwrap();
+            PathBuf::from(&result.exported[0])
+        };
+
+        let lossy = export(50, MetadataMode::Keep);
+        let lossless = export(100, MetadataMode::Keep);
+        assert_eq!(image::guess_format(&fs::read(&lossy).unwrap()).unwrap(), ImageFormat::WebP);
+        assert_eq!(image::open(&lossy).unwrap().dimensions(), (200, 400));
+        assert_eq!(image::open(&lossless).unwrap().dimensions(), (200, 400));
+        let has_chunk = |path: &Path, chunk: &[u8]| fs::read(path).unwrap().windows(4).any(|w| w == chunk);
+        assert!(has_chunk(&lossy, b"VP8 ") && !has_chunk(&lossy, b"VP8L"));
+        assert!(has_chunk(&lossless, b"VP8L"));
+
+        // Metadata is carried over whether or not the encoding is lossless
+        for path in [&lossy, &lossless] {
+            let exif = read_exif(path).unwrap();
+            assert!(exif.get_field(Tag::Model, In::PRIMARY).is_some());
+        }
+        assert!(read_exif(&export(50, MetadataMode::StripAll)).is_none());
+    }
+
+    #[test]
+    fn test_cropped_originals_are_loaded_larger() {
+        let crop = EditRecipe {
+            crop: Some(crate::edits::CropRect { x: 0.25, y: 0.0, width: 0.5, height: 0.8 }),
+            ..Default::default()
+        };
+
+        assert_eq!(source_long_edge(None, Some(&crop)), None);
+        assert_eq!(source_long_edge(Some(1000), None), Some(1000));
+        assert_eq!(source_long_edge(Some(1000), Some(&EditRecipe::default())), Some(1000));
+        assert_eq!(source_long_edge(Some(1000), Some(&crop)), Some(2000));
+    }
+
+    #[test]
+    fn test_export_metadata_modes() {
+        let (temp_dir, db, image_id) = create_library();
+        let target = temp_dir.path().join("export");
+        let export = |metadata: MetadataMode| {
+            let options = ExportOptions {
+                metadata,
+                ..Default::default()
+            };
+            let result = export_images(&db, &[image_id], &target, &options, |_| {}).unwrap();
+            PathBuf::from(&result.exported[0])
+        };
+
+        let exif = read_exif(&export(MetadataMode::Keep)).unwrap();
+        assert!(exif.get_field(Tag::Model, In::PRIMARY).is_some());
+        assert!(exif.get_field(Tag::GPSLatitudeRef, In::PRIMARY).is_some());
+        // Pixels are written upright, so viewers must not rotate them again
+        let orientation = exif.get_field(Tag::Orientation, In::PRIMARY).unwrap();
+        assert_eq!(orientation.value.get_uint(0), Some(1));
+
+        let exif = read_exif(&export(MetadataMode::StripGps)).unwrap();
+        assert!(exif.get_field(Tag::Model, In::PRIMARY).is_some());
+        assert!(exif.get_field(Tag::GPSLatitudeRef, In::PRIMARY).is_none());
+
+        assert!(read_exif(&export(MetadataMode::StripAll)).is_none());
+    }
+
+    #[test]
+    fn test_export_applies_edits() {
+        let (temp_dir, db, image_id) = create_library();
+        let target = temp_dir.path().join("export");
+        let recipe = EditRecipe {
+            rotation: 90,
+            ..Default::default()
+        };
+        db.set_edit_recipe(image_id, Some(&recipe)).unwrap();
+
+        let result = export_images(&db, &[image_id], &target, &ExportOptions::default(), |_| {}).unwrap();
+        assert_eq!(image::open(&result.exported[0]).unwrap().dimensions(), (400, 200));
+
+        let options = ExportOptions {
+            apply_edits: false,
+            ..Default::default()
+        };
+        let result = export_images(&db, &[image_id], &target, &options, |_| {}).unwrap();
+        assert_eq!(image::open(&result.exported[0]).unwrap().dimensions(), (200, 400));
+    }
+}
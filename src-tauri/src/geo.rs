@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+use std::f64::consts::{FRAC_PI_2, PI};
+
+/// Mean Earth radius used for great-circle distances
+pub const EARTH_RADIUS_KM: f64 = 6371.0088;
+
+/// A latitude/longitude rectangle in degrees, such as a map viewport
+///
+/// A box whose `west` edge is greater than its `east` edge crosses the
+/// antimeridian.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct GeoBounds {
+    pub south: f64,
+    pub west: f64,
+    pub north: f64,
+    pub east: f64,
+}
+
+impl GeoBounds {
+    /// Create a box, checking that its edges are valid coordinates
+    pub fn new(south: f64, west: f64, north: f64, east: f64) -> Result<Self, String> {
+        for latitude in [south, north] {
+            validate_coordinates(latitude, 0.0)?;
+        }
+        for longitude in [west, east] {
+            validate_coordinates(0.0, longitude)?;
+        }
+        if south > north {
+            return Err(format!(
+                "South edge {} must not be north of north edge {}",
+                south, north
+            ));
+        }
+
+        Ok(Self { south, west, north, east })
+    }
+
+    /// Smallest box containing every point within `radius_km` of a point
+    ///
+    /// Near the poles the box spans every longitude, and near the antimeridian
+    /// its longitudes wrap around.
+    pub fn around(latitude: f64, longitude: f64, radius_km: f64) -> Self {
+        let angular_radius = radius_km / EARTH_RADIUS_KM;
+        let lat = latitude.to_radians();
+        let lon = longitude.to_radians();
+
+        let south = lat - angular_radius;
+        let north = lat + angular_radius;
+
+        if south <= -FRAC_PI_2 || north >= FRAC_PI_2 || angular_radius >= PI {
+            // The circle contains a pole
+            return Self {
+                south: south.max(-FRAC_PI_2).to_degrees(),
+                west: -180.0,
+                north: north.min(FRAC_PI_2).to_degrees(),
+                east: 180.0,
+            };
+        }
+
+        let delta_lon = (angular_radius.sin() / lat.cos()).asin();
+        let mut west = lon - delta_lon;
+        let mut east = lon + delta_lon;
+        if west < -PI {
+            west += 2.0 * PI;
+        }
+        if east > PI {
+            east -= 2.0 * PI;
+        }
+
+        Self {
+            south: south.to_degrees(),
+            west: west.to_degrees(),
+            north: north.to_degrees(),
+            east: east.to_degrees(),
+        }
+    }
+
+    pub fn contains(&self, latitude: f64, longitude: f64) -> bool {
+        (self.south..=self.north).contains(&latitude)
+            && self
+                .longitude_ranges()
+                .iter()
+                .any(|(west, east)| (*west..=*east).contains(&longitude))
+    }
+
+    /// Longitude ranges covered by the box, split in two if it crosses the antimeridian
+    pub fn longitude_ranges(&self) -> Vec<(f64, f64)> {
+        if self.west <= self.east {
+            vec![(self.west, self.east)]
+        } else {
+            vec![(self.west, 180.0), (-180.0, self.east)]
+        }
+    }
+}
+
+/// Great-circle distance between two points in kilometres
+pub fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (lon2 - lon1).to_radians();
+
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+}
+
+/// Check that a latitude and longitude are within range
+pub fn validate_coordinates(latitude: f64, longitude: f64) -> Result<(), String> {
+    if !(-90.0..=90.0).contains(&latitude) {
+        return Err(format!("Latitude {} must be between -90 and 90", latitude));
+    }
+    if !(-180.0..=180.0).contains(&longitude) {
+        return Err(format!("Longitude {} must be between -180 and 180", longitude));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_haversine_known_distances() {
+        // Paris to London
+        let distance = haversine_km(48.8566, 2.3522, 51.5074, -0.1278);
+        assert!((distance - 343.5).abs() < 1.0, "got {}", distance);
+
+        // Across the antimeridian
+        let distance = haversine_km(0.0, 179.9, 0.0, -179.9);
+        assert!((distance - 22.24).abs() < 0.1, "got {}", distance);
+
+        assert_eq!(haversine_km(10.0, 20.0, 10.0, 20.0), 0.0);
+    }
+
+    #[test]
+    fn test_bounds_around_point() {
+        let bounds = GeoBounds::around(48.85, 2.35, 10.0);
+        assert!(bounds.south < 48.85 && bounds.north > 48.85);
+        assert!(bounds.west < 2.35 && bounds.east > 2.35);
+        // Longitude degrees are shorter away from the equator
+        assert!(bounds.east - bounds.west > bounds.north - bounds.south);
+        assert!(bounds.contains(48.9, 2.4));
+        assert!(!bounds.contains(49.0, 2.35));
+    }
+
+    #[test]
+    fn test_bounds_wrap_across_antimeridian() {
+        let bounds = GeoBounds::around(0.0, 179.95, 30.0);
+        assert!(bounds.west > bounds.east);
+        assert_eq!(bounds.longitude_ranges().len(), 2);
+        assert!(bounds.contains(0.0, -179.9));
+        assert!(bounds.contains(0.0, 179.9));
+        assert!(!bounds.contains(0.0, 0.0));
+    }
+
+    #[test]
+    fn test_bounds_near_pole_cover_all_longitudes() {
+        let bounds = GeoBounds::around(89.9, 0.0, 50.0);
+        assert_eq!(bounds.north, 90.0);
+        assert_eq!((bounds.west, bounds.east), (-180.0, 180.0));
+        assert!(bounds.contains(89.95, 135.0));
+    }
+
+    #[test]
+    fn test_bounds_validation() {
+        assert!(GeoBounds::new(10.0, 170.0, 20.0, -170.0).is_ok());
+        assert!(GeoBounds::new(20.0, 0.0, 10.0, 1.0).is_err());
+        assert!(GeoBounds::new(-91.0, 0.0, 10.0, 1.0).is_err());
+        assert!(GeoBounds::new(0.0, 0.0, 10.0, 181.0).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use reverse_geocoder::ReverseGeocoder;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::database::Database;
+use crate::geo;
+
+/// English country names by ISO 3166-1 alpha-2 code
+const COUNTRIES: &str = include_str!("../data/countries.tsv");
+
+/// Coordinates further than this from the nearest known town are left unnamed,
+/// which keeps photos taken at sea from being attributed to the nearest coast
+const MAX_PLACE_DISTANCE_KM: f64 = 100.0;
+
+lazy_static::lazy_static! {
+    /// Loaded on first use, since indexing the bundled GeoNames data takes a moment
+    static ref GEOCODER: Geocoder = Geocoder::new();
+}
+
+/// Place names for a location
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Place {
+    pub country: String,
+    /// First-level administrative division, such as a state or province
+    pub region: Option<String>,
+    pub city: String,
+}
+
+/// Offline reverse geocoder over the GeoNames towns bundled with `reverse_geocoder`
+struct Geocoder {
+    towns: ReverseGeocoder,
+    countries: HashMap<&'static str, &'static str>,
+}
+
+impl Geocoder {
+    fn new() -> Self {
+        let countries = COUNTRIES
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split_once('\t'))
+            .collect();
+
+        Self {
+            towns: ReverseGeocoder::new(),
+            countries,
+        }
+    }
+
+    fn lookup(&self, latitude: f64, longitude: f64) -> Option<Place> {
+        let town = self.towns.search((latitude, longitude)).record;
+        if geo::haversine_km(latitude, longitude, town.lat, town.lon) > MAX_PLACE_DISTANCE_KM {
+            return None;
+        }
+
+        let country = self
+            .countries
+            .get(town.cc.as_str())
+            .map_or_else(|| town.cc.clone(), |name| name.to_string());
+
+        Some(Place {
+            country,
+            region: Some(town.admin1.clone()).filter(|r| !r.is_empty()),
+            city: town.name.clone(),
+        })
+    }
+}
+
+/// Resolve coordinates to the nearest town, without any network access
+pub fn reverse_geocode(latitude: f64, longitude: f64) -> Option<Place> {
+    if geo::validate_coordinates(latitude, longitude).is_err() {
+        return None;
+    }
+
+    GEOCODER.lookup(latitude, longitude)
+}
+
+/// Resolve and store the place of an image, clearing it for images without GPS data
+pub fn store_place(
+    db: &Database,
+    image_id: i64,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+) -> Result<Option<Place>, String> {
+    let place = match (latitude, longitude) {
+        (Some(latitude), Some(longitude)) => reverse_geocode(latitude, longitude),
+        _ => None,
+    };
+
+    db.set_image_place(
+        image_id,
+        place.as_ref().map(|p| p.country.as_str()),
+        place.as_ref().and_then(|p| p.region.as_deref()),
+        place.as_ref().map(|p| p.city.as_str()),
+    )
+    .map_err(|e| format!("Database error: {}", e))?;
+
+    Ok(place)
+}
+
+/// Resolve places for geotagged images that have none yet, returning how many were named
+pub fn backfill_places(db: &Database) -> Result<usize, String> {
+    let images = db
+        .get_images_without_place()
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    let mut named = 0;
+    for (image_id, latitude, longitude) in images {
+        if store_place(db, image_id, Some(latitude), Some(longitude))?.is_some() {
+            named += 1;
+        }
+    }
+
+    Ok(named)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{ImageFilter, MediaType};
+    use chrono::Utc;
+
+    #[test]
+    fn test_reverse_geocode_known_places() {
+        let place = reverse_geocode(48.8584, 2.2945).unwrap();
+        assert_eq!(place.country, "France");
+        assert_eq!(place.region.as_deref(), Some("Ile-de-France"));
+
+        let place = reverse_geocode(51.5007, -0.1246).unwrap();
+        assert_eq!(place.country, "United Kingdom");
+        assert_eq!(place.region.as_deref(), Some("England"));
+
+        let place = reverse_geocode(35.6586, 139.7454).unwrap();
+        assert_eq!(place.country, "Japan");
+    }
+
+    #[test]
+    fn test_reverse_geocode_remote_and_invalid_coordinates() {
+        // The middle of the Pacific
+        assert!(reverse_geocode(-30.0, -130.0).is_none());
+        assert!(reverse_geocode(91.0, 0.0).is_none());
+        assert!(reverse_geocode(f64::NAN, 0.0).is_none());
+    }
+
+    #[test]
+    fn test_store_and_backfill_places() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+
+        let insert = |path: &str, gps: Option<(f64, f64)>| {
+            db.insert_image(
+                path, "s.jpg", "m.jpg", path, MediaType::Image,
+                None, None, None, gps.map(|g| g.0), gps.map(|g| g.1),
+                100, 100, None, None, 1024, Utc::now(),
+            )
+            .unwrap()
+        };
+        let rome = insert("/photos/rome.jpg", Some((41.8902, 12.4922)));
+        let sea = insert("/photos/sea.jpg", Some((-30.0, -130.0)));
+        let untagged = insert("/photos/untagged.jpg", None);
+
+        assert_eq!(backfill_places(&db).unwrap(), 1);
+
+        let image = db.get_image_by_id(rome).unwrap().unwrap();
+        assert_eq!(image.country.as_deref(), Some("Italy"));
+        assert_eq!(image.region.as_deref(), Some("Latium"));
+        assert!(image.city.is_some());
+        assert!(db.get_image_by_id(sea).unwrap().unwrap().country.is_none());
+        assert!(db.get_image_by_id(untagged).unwrap().unwrap().country.is_none());
+
+        // Coordinates without a place are not looked up again
+        assert!(db.get_images_without_place().unwrap().is_empty());
+        assert_eq!(backfill_places(&db).unwrap(), 0);
+
+        let filter = ImageFilter {
+            country: Some("italy".to_string()),
+            ..Default::default()
+        };
+        let results = db.query_images(&filter).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, rome);
+
+        // Losing the GPS data clears the place
+        store_place(&db, rome, None, None).unwrap();
+        assert!(db.get_image_by_id(rome).unwrap().unwrap().country.is_none());
+    }
+}
@@ -0,0 +1,1054 @@
+use chrono::{DateTime, Utc};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::database::{self, Database, ImageRecord};
+use crate::duplicates;
+use crate::edits::EditRecipe;
+use crate::geocoder;
+use crate::metadata::{self, ImageMetadata};
+use crate::scanner::{self, MediaFile, MediaType};
+use crate::settings::FormatConfig;
+use crate::thumbnail::{self, ThumbnailPaths};
+use crate::xmp;
+
+/// Result of importing a folder into the library
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportResult {
+    /// Number of media files added to the library
+    pub imported: usize,
+    /// Number of media files that were already in the library
+    pub skipped: usize,
+    /// Files that could not be imported
+    pub failed: Vec<ImportError>,
+    /// Total number of media files discovered by the scan
+    pub total: usize,
+}
+
+/// Error information for a file that failed to import
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportError {
+    /// Path of the file that failed
+    pub path: String,
+    /// Error message
+    pub error: String,
+}
+
+/// Outcome of importing a single file
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStatus {
+    Imported,
+    Skipped,
+    Failed,
+}
+
+/// A file whose record was moved to a new path
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MovedFile {
+    /// Previous path in the library
+    pub from: String,
+    /// New path on disk
+    pub to: String,
+}
+
+/// Differences found between the library and the filesystem by a rescan
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RescanReport {
+    /// Files that were not in the library and have been imported
+    pub added: Vec<String>,
+    /// Files whose content changed and whose records were refreshed
+    pub updated: Vec<String>,
+    /// Files that were moved or renamed
+    pub moved: Vec<MovedFile>,
+    /// Files that no longer exist and were removed from the library
+    pub removed: Vec<String>,
+    /// Number of files that were unchanged
+    pub unchanged: usize,
+    /// Files that could not be reconciled
+    pub failed: Vec<ImportError>,
+}
+
+/// Change detected for a single file during a rescan
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileChange {
+    Added,
+    Updated,
+    Moved,
+    Removed,
+    Unchanged,
+    Failed,
+}
+
+/// Progress event emitted after each file is reconciled during a rescan
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RescanProgress {
+    /// Number of files reconciled so far
+    pub current: usize,
+    /// Total number of files to reconcile
+    pub total: usize,
+    /// File that was just reconciled
+    pub current_file: String,
+    /// Completion percentage (0-100)
+    pub percentage: f64,
+    /// What changed for the file
+    pub change: FileChange,
+}
+
+/// Progress event emitted after each file is processed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportProgress {
+    /// Number of files processed so far
+    pub current: usize,
+    /// Total number of files to process
+    pub total: usize,
+    /// File that was just processed
+    pub current_file: String,
+    /// Completion percentage (0-100)
+    pub percentage: f64,
+    /// What happened to the file
+    pub status: ImportStatus,
+}
+
+/// Scan a folder and import every media file into the library
+///
+/// Metadata extraction, thumbnail generation and the database insert run per
+/// file on the Rayon thread pool. Files already in the library (by path) are
+/// skipped, and one file failing never aborts the rest of the import.
+pub fn import_folder<F>(
+    folder_path: &str,
+    config: Option<FormatConfig>,
+    cache_dir: &Path,
+    db: &Database,
+    progress_callback: F,
+) -> Result<ImportResult, String>
+where
+    F: Fn(ImportProgress) + Sync,
+{
+    let scan = scanner::scan_folder(folder_path, config)?;
+
+    let mut result = import_files(&scan.media_files, cache_dir, db, progress_callback);
+
+    // Surface walk errors alongside per-file failures
+    result.failed.extend(scan.errors.into_iter().map(|e| ImportError {
+        path: e.path,
+        error: e.message,
+    }));
+
+    Ok(result)
+}
+
+/// Import a list of already-discovered media files into the library
+pub fn import_files<F>(
+    media_files: &[MediaFile],
+    cache_dir: &Path,
+    db: &Database,
+    progress_callback: F,
+) -> ImportResult
+where
+    F: Fn(ImportProgress) + Sync,
+{
+    let total = media_files.len();
+    let processed = AtomicUsize::new(0);
+
+    let outcomes: Vec<Result<Option<i64>, String>> = media_files
+        .par_iter()
+        .map(|file| {
+            let outcome = import_media_file(file, cache_dir, db);
+
+            let status = match &outcome {
+                Ok(Some(_)) => ImportStatus::Imported,
+                Ok(None) => ImportStatus::Skipped,
+                Err(e) => {
+                    log::warn!("Failed to import {}: {}", file.path, e);
+                    ImportStatus::Failed
+                }
+            };
+
+            let current = processed.fetch_add(1, Ordering::SeqCst) + 1;
+            progress_callback(ImportProgress {
+                current,
+                total,
+                current_file: file.path.clone(),
+                percentage: (current as f64 / total as f64) * 100.0,
+                status,
+            });
+
+            outcome
+        })
+        .collect();
+
+    let mut imported = 0;
+    let mut skipped = 0;
+    let mut failed = Vec::new();
+
+    for (file, outcome) in media_files.iter().zip(outcomes) {
+        match outcome {
+            Ok(Some(_)) => imported += 1,
+            Ok(None) => skipped += 1,
+            Err(error) => failed.push(ImportError {
+                path: file.path.clone(),
+                error,
+            }),
+        }
+    }
+
+    ImportResult {
+        imported,
+        skipped,
+        failed,
+        total,
+    }
+}
+
+/// Import a single media file into the library
+///
+/// Returns the new record ID, or `None` if the path is already in the library.
+pub fn import_media_file(
+    file: &MediaFile,
+    cache_dir: &Path,
+    db: &Database,
+) -> Result<Option<i64>, String> {
+    if db
+        .get_image_by_path(&file.path)
+        .map_err(|e| format!("Database error: {}", e))?
+        .is_some()
+    {
+        return Ok(None);
+    }
+
+    let checksum = thumbnail::compute_checksum(Path::new(&file.path))?;
+
+    import_with_checksum(file, &checksum, cache_dir, db).map(Some)
+}
+
+/// Import a media file whose checksum has already been computed
+fn import_with_checksum(
+    file: &MediaFile,
+    checksum: &str,
+    cache_dir: &Path,
+    db: &Database,
+) -> Result<i64, String> {
+    let (metadata, thumbnails) = process_media_file(file, checksum, None, cache_dir)?;
+
+    let id = insert_media_record(db, file.media_type, &metadata, checksum, &thumbnails)?;
+    store_perceptual_hash(db, id, file, &thumbnails);
+    store_place(db, id, &metadata);
+    store_xmp_metadata(db, id, &metadata);
+
+    Ok(id)
+}
+
+/// Hash an image's unedited thumbnail for duplicate detection
+///
+/// Failures are only logged: the image is already imported, and missing hashes
+/// are backfilled the next time duplicates are searched.
+fn store_perceptual_hash(db: &Database, id: i64, file: &MediaFile, unedited_thumbnails: &ThumbnailPaths) {
+    if file.media_type != MediaType::Image {
+        return;
+    }
+
+    if let Err(e) = duplicates::store_hash(db, id, &unedited_thumbnails.small) {
+        log::warn!("Failed to hash {}: {}", file.path, e);
+    }
+}
+
+/// Name the place an image was taken from its GPS coordinates
+///
+/// Like hashing, failures are only logged and picked up by the backfill on
+/// the next start.
+fn store_place(db: &Database, id: i64, metadata: &ImageMetadata) {
+    if let Err(e) = geocoder::store_place(db, id, metadata.gps_latitude, metadata.gps_longitude) {
+        log::warn!("Failed to geocode {}: {}", metadata.path, e);
+    }
+}
+
+/// Take keywords, rating and description from the XMP of a file, such as
+/// Lightroom and darktable sidecars
+///
+/// Failures are only logged, and the file stays imported without them.
+fn store_xmp_metadata(db: &Database, id: i64, metadata: &ImageMetadata) {
+    if let Err(e) = xmp::import_metadata(db, id, Path::new(&metadata.path)) {
+        log::warn!("Failed to read XMP of {}: {}", metadata.path, e);
+    }
+}
+
+/// Extract metadata and generate thumbnails for a file, with its edits applied if it has any
+fn process_media_file(
+    file: &MediaFile,
+    checksum: &str,
+    recipe: Option<&EditRecipe>,
+    cache_dir: &Path,
+) -> Result<(ImageMetadata, ThumbnailPaths), String> {
+    let metadata = match file.media_type {
+        MediaType::Image => metadata::extract_metadata(&file.path)?,
+        MediaType::Video => metadata::extract_video_metadata(&file.path)?,
+    };
+
+    let thumbnails = match file.media_type {
+        MediaType::Image => {
+            thumbnail::generate_edited_thumbnails(&file.path, checksum, recipe, cache_dir)?
+        }
+        MediaType::Video => {
+            thumbnail::generate_video_thumbnails_with_checksum(&file.path, checksum, cache_dir)?
+        }
+    };
+
+    Ok((metadata, thumbnails))
+}
+
+/// Insert a processed media file into the database
+fn insert_media_record(
+    db: &Database,
+    media_type: MediaType,
+    metadata: &ImageMetadata,
+    checksum: &str,
+    thumbnails: &ThumbnailPaths,
+) -> Result<i64, String> {
+    let id = db.insert_image(
+        &metadata.path,
+        &thumbnails.small,
+        &thumbnails.medium,
+        checksum,
+        to_database_media_type(media_type),
+        metadata.capture_date,
+        metadata.camera_make.as_deref(),
+        metadata.camera_model.as_deref(),
+        metadata.gps_latitude,
+        metadata.gps_longitude,
+        metadata.width,
+        metadata.height,
+        metadata.duration_seconds,
+        metadata.video_codec.as_deref(),
+        metadata.file_size,
+        metadata.file_modified,
+    )
+    .map_err(|e| format!("Failed to insert {}: {}", metadata.path, e))?;
+
+    store_extracted_metadata(db, id, metadata)?;
+
+    Ok(id)
+}
+
+/// Store the settings and stream properties read from a file, and which extraction read them
+fn store_extracted_metadata(db: &Database, id: i64, metadata: &ImageMetadata) -> Result<(), String> {
+    db.set_capture_settings(id, &metadata.capture_settings)
+        .and_then(|_| db.set_video_properties(id, &metadata.video_properties))
+        .and_then(|_| db.set_metadata_version(id, metadata::METADATA_VERSION))
+        .map_err(|e| format!("Failed to store metadata of {}: {}", metadata.path, e))?;
+
+    Ok(())
+}
+
+/// Re-process a file whose content changed and update its existing record
+fn update_media_record(
+    db: &Database,
+    record: &ImageRecord,
+    file: &MediaFile,
+    checksum: &str,
+    cache_dir: &Path,
+) -> Result<(), String> {
+    // Edits survive changes to the original
+    let recipe = db
+        .get_edit_recipe(record.id)
+        .map_err(|e| format!("Database error: {}", e))?;
+    let (metadata, thumbnails) = process_media_file(file, checksum, recipe.as_ref(), cache_dir)?;
+
+    db.update_image_content(
+        record.id,
+        &thumbnails.small,
+        &thumbnails.medium,
+        checksum,
+        metadata.capture_date,
+        metadata.camera_make.as_deref(),
+        metadata.camera_model.as_deref(),
+        metadata.gps_latitude,
+        metadata.gps_longitude,
+        metadata.width,
+        metadata.height,
+        metadata.duration_seconds,
+        metadata.video_codec.as_deref(),
+        metadata.file_size,
+        metadata.file_modified,
+    )
+    .map_err(|e| format!("Failed to update {}: {}", record.path, e))?;
+    store_extracted_metadata(db, record.id, &metadata)?;
+
+    // Duplicates are found by how photos were taken, not how they were edited
+    match recipe.filter(|recipe| !recipe.is_identity()) {
+        Some(_) if file.media_type == MediaType::Image => {
+            match thumbnail::generate_edited_thumbnails(&file.path, checksum, None, cache_dir) {
+                Ok(unedited) => store_perceptual_hash(db, record.id, file, &unedited),
+                Err(e) => log::warn!("Failed to hash {}: {}", file.path, e),
+            }
+        }
+        _ => store_perceptual_hash(db, record.id, file, &thumbnails),
+    }
+    store_place(db, record.id, &metadata);
+    store_xmp_metadata(db, record.id, &metadata);
+
+    Ok(())
+}
+
+/// Bring the library record for a single file in line with the file on disk
+///
+/// New files are imported, changed files are re-processed and unchanged files
+/// are left alone.
+pub fn reconcile_file(
+    file: &MediaFile,
+    cache_dir: &Path,
+    db: &Database,
+) -> Result<FileChange, String> {
+    let record = db
+        .get_image_by_path(&file.path)
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    match classify_file(file, record.as_ref(), cache_dir, db)? {
+        Classification::Unchanged => Ok(FileChange::Unchanged),
+        Classification::Updated => Ok(FileChange::Updated),
+        Classification::New(checksum) => {
+            import_with_checksum(file, &checksum, cache_dir, db)?;
+            Ok(FileChange::Added)
+        }
+    }
+}
+
+/// Rescan a folder and reconcile the library with what is on disk
+///
+/// Each file is classified against the `images` table using its size,
+/// modification time and checksum:
+/// - unchanged size and mtime: nothing to do
+/// - same path, different checksum: re-processed in place (updated)
+/// - new path whose checksum matches a record whose file disappeared: moved
+/// - new path otherwise: imported (added)
+/// - records whose file no longer exists: removed
+pub fn rescan_folder<F>(
+    folder_path: &str,
+    config: Option<FormatConfig>,
+    cache_dir: &Path,
+    db: &Database,
+    progress_callback: F,
+) -> Result<RescanReport, String>
+where
+    F: Fn(RescanProgress) + Sync,
+{
+    let scan = scanner::scan_folder(folder_path, config)?;
+
+    let known = db
+        .get_images_under_path(folder_path)
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    let on_disk: HashSet<&str> = scan.media_files.iter().map(|f| f.path.as_str()).collect();
+
+    // Records whose file is gone. Files that still exist but are no longer
+    // matched by the format config are left alone, and so are trashed images,
+    // whose file may have been moved to the trash folder.
+    let mut missing: Vec<ImageRecord> = known
+        .iter()
+        .filter(|r| r.trashed_at.is_none())
+        .filter(|r| !on_disk.contains(r.path.as_str()) && !Path::new(&r.path).exists())
+        .cloned()
+        .collect();
+
+    let known_by_path: HashMap<&str, &ImageRecord> =
+        known.iter().map(|r| (r.path.as_str(), r)).collect();
+
+    let total = scan.media_files.len() + missing.len();
+    let processed = AtomicUsize::new(0);
+    let report_progress = |path: &str, change: FileChange| {
+        let current = processed.fetch_add(1, Ordering::SeqCst) + 1;
+        progress_callback(RescanProgress {
+            current,
+            total,
+            current_file: path.to_string(),
+            percentage: (current as f64 / total as f64) * 100.0,
+            change,
+        });
+    };
+
+    // First pass: reconcile files already in the library, and checksum new ones
+    let classified: Vec<(&MediaFile, Result<Classification, String>)> = scan
+        .media_files
+        .par_iter()
+        .map(|file| {
+            let outcome = classify_file(file, known_by_path.get(file.path.as_str()).copied(), cache_dir, db);
+
+            match &outcome {
+                Ok(Classification::Unchanged) => report_progress(&file.path, FileChange::Unchanged),
+                Ok(Classification::Updated) => report_progress(&file.path, FileChange::Updated),
+                Ok(Classification::New(_)) => {}
+                Err(e) => {
+                    log::warn!("Failed to rescan {}: {}", file.path, e);
+                    report_progress(&file.path, FileChange::Failed);
+                }
+            }
+
+            (file, outcome)
+        })
+        .collect();
+
+    let mut report = RescanReport::default();
+    let mut new_files = Vec::new();
+
+    for (file, outcome) in classified {
+        match outcome {
+            Ok(Classification::Unchanged) => report.unchanged += 1,
+            Ok(Classification::Updated) => report.updated.push(file.path.clone()),
+            Ok(Classification::New(checksum)) => new_files.push((file, checksum)),
+            Err(error) => report.failed.push(ImportError {
+                path: file.path.clone(),
+                error,
+            }),
+        }
+    }
+
+    // Second pass: match new paths against vanished records to detect moves
+    let mut to_import = Vec::new();
+
+    for (file, checksum) in new_files {
+        let candidate = missing.iter().position(|r| r.checksum == checksum);
+        let unique = db
+            .count_images_by_checksum(&checksum)
+            .map(|count| count == 1)
+            .unwrap_or(false);
+
+        match candidate {
+            // update_image_path is keyed by checksum, so only use it when the
+            // checksum identifies exactly one record
+            Some(index) if unique => {
+                let record = missing.remove(index);
+                match db.update_image_path(&checksum, &file.path) {
+                    Ok(_) => {
+                        report_progress(&file.path, FileChange::Moved);
+                        report.moved.push(MovedFile {
+                            from: record.path,
+                            to: file.path.clone(),
+                        });
+                    }
+                    Err(e) => {
+                        report_progress(&file.path, FileChange::Failed);
+                        report.failed.push(ImportError {
+                            path: file.path.clone(),
+                            error: format!("Failed to update moved file: {}", e),
+                        });
+                    }
+                }
+            }
+            _ => to_import.push((file, checksum)),
+        }
+    }
+
+    // Third pass: import genuinely new files
+    let imported: Vec<(&MediaFile, Result<i64, String>)> = to_import
+        .par_iter()
+        .map(|(file, checksum)| {
+            let outcome = import_with_checksum(file, checksum, cache_dir, db);
+            match &outcome {
+                Ok(_) => report_progress(&file.path, FileChange::Added),
+                Err(e) => {
+                    log::warn!("Failed to import {}: {}", file.path, e);
+                    report_progress(&file.path, FileChange::Failed);
+                }
+            }
+            (*file, outcome)
+        })
+        .collect();
+
+    for (file, outcome) in imported {
+        match outcome {
+            Ok(_) => report.added.push(file.path.clone()),
+            Err(error) => report.failed.push(ImportError {
+                path: file.path.clone(),
+                error,
+            }),
+        }
+    }
+
+    // Finally, drop records whose files were deleted
+    for record in missing {
+        match db.delete_image_by_path(&record.path) {
+            Ok(_) => {
+                report_progress(&record.path, FileChange::Removed);
+                report.removed.push(record.path);
+            }
+            Err(e) => {
+                report_progress(&record.path, FileChange::Failed);
+                report.failed.push(ImportError {
+                    path: record.path,
+                    error: format!("Failed to remove deleted file: {}", e),
+                });
+            }
+        }
+    }
+
+    report.failed.extend(scan.errors.into_iter().map(|e| ImportError {
+        path: e.path,
+        error: e.message,
+    }));
+
+    Ok(report)
+}
+
+/// Read the metadata of files imported by an older version of the extraction again
+///
+/// Rescans skip files whose size and modification time are unchanged, so this
+/// is how existing libraries get what newer versions read, such as exposure
+/// settings of photos or the creation time, location and bitrate of videos.
+/// Returns how many records were updated; files that can't be read, such as
+/// videos while FFmpeg is missing, are tried again on the next start.
+pub fn backfill_metadata(db: &Database) -> Result<usize, String> {
+    let records = db
+        .get_images_with_metadata_before(metadata::METADATA_VERSION)
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    let mut updated = 0;
+    for record in records {
+        let metadata = match record.media_type {
+            database::MediaType::Image => metadata::extract_metadata(&record.path),
+            database::MediaType::Video => metadata::extract_video_metadata(&record.path),
+        };
+        let metadata = match metadata {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                log::warn!("Failed to read metadata of {}: {}", record.path, e);
+                continue;
+            }
+        };
+
+        db.update_image_metadata(record.id, &metadata)
+            .map_err(|e| format!("Failed to update {}: {}", record.path, e))?;
+        store_extracted_metadata(db, record.id, &metadata)?;
+        if (metadata.gps_latitude, metadata.gps_longitude) != (record.gps_latitude, record.gps_longitude) {
+            store_place(db, record.id, &metadata);
+        }
+        updated += 1;
+    }
+
+    Ok(updated)
+}
+
+/// Classification of a file found on disk during a rescan
+enum Classification {
+    /// Already in the library and unchanged
+    Unchanged,
+    /// Already in the library, content changed and record updated
+    Updated,
+    /// Not in the library under this path (carries the computed checksum)
+    New(String),
+}
+
+/// Compare a file on disk against its library record (if any)
+fn classify_file(
+    file: &MediaFile,
+    record: Option<&ImageRecord>,
+    cache_dir: &Path,
+    db: &Database,
+) -> Result<Classification, String> {
+    let path = Path::new(&file.path);
+
+    let record = match record {
+        Some(record) => record,
+        None => return Ok(Classification::New(thumbnail::compute_checksum(path)?)),
+    };
+
+    let file_metadata = std::fs::metadata(path)
+        .map_err(|e| format!("Failed to read file metadata: {}", e))?;
+    let file_size = file_metadata.len();
+    let file_modified = DateTime::<Utc>::from(
+        file_metadata
+            .modified()
+            .map_err(|e| format!("Failed to read file modified time: {}", e))?,
+    );
+
+    if file_size == record.file_size
+        && file_modified.timestamp_millis() == record.file_modified.timestamp_millis()
+    {
+        return Ok(Classification::Unchanged);
+    }
+
+    // Size or mtime differ: the checksum decides whether the content really changed
+    let checksum = thumbnail::compute_checksum(path)?;
+
+    if checksum == record.checksum {
+        db.update_file_stats(record.id, file_size, file_modified)
+            .map_err(|e| format!("Failed to update {}: {}", record.path, e))?;
+        return Ok(Classification::Unchanged);
+    }
+
+    update_media_record(db, record, file, &checksum, cache_dir)?;
+
+    Ok(Classification::Updated)
+}
+
+/// Convert a scanner media type into the database representation
+pub(crate) fn to_database_media_type(media_type: MediaType) -> database::MediaType {
+    match media_type {
+        MediaType::Image => database::MediaType::Image,
+        MediaType::Video => database::MediaType::Video,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgb};
+    use std::fs;
+    use std::sync::Mutex;
+
+    fn create_test_image(path: &Path, width: u32, height: u32) {
+        let img = ImageBuffer::from_fn(width, height, |x, y| {
+            Rgb([(x % 256) as u8, (y % 256) as u8, 128])
+        });
+        img.save(path).unwrap();
+    }
+
+    fn create_test_db(temp_dir: &tempfile::TempDir) -> Database {
+        Database::new(temp_dir.path().join("cura.db")).unwrap()
+    }
+
+    #[test]
+    fn test_import_folder_inserts_records() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let library = temp_dir.path().join("library");
+        fs::create_dir_all(library.join("nested")).unwrap();
+        create_test_image(&library.join("one.png"), 320, 240);
+        create_test_image(&library.join("nested/two.jpg"), 200, 400);
+        fs::write(library.join("notes.txt"), b"not media").unwrap();
+
+        let db = create_test_db(&temp_dir);
+        let cache_dir = temp_dir.path().join("thumbnails");
+
+        let result = import_folder(library.to_str().unwrap(), None, &cache_dir, &db, |_| {}).unwrap();
+
+        assert_eq!(result.total, 2);
+        assert_eq!(result.imported, 2);
+        assert_eq!(result.skipped, 0);
+        assert!(result.failed.is_empty());
+
+        let path = library.join("one.png");
+        let record = db.get_image_by_path(path.to_str().unwrap()).unwrap().unwrap();
+        assert_eq!(record.media_type, database::MediaType::Image);
+        assert_eq!(record.checksum, thumbnail::compute_checksum(&path).unwrap());
+        assert!(Path::new(&record.thumbnail_small).exists());
+        assert!(Path::new(&record.thumbnail_medium).exists());
+    }
+
+    #[test]
+    fn test_import_folder_reads_xmp_sidecars() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let library = temp_dir.path().join("library");
+        fs::create_dir_all(&library).unwrap();
+        create_test_image(&library.join("tagged.jpg"), 64, 64);
+        create_test_image(&library.join("plain.jpg"), 64, 64);
+        fs::write(
+            library.join("tagged.jpg.xmp"),
+            r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="" xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/" xmp:Rating="5">
+   <dc:subject><rdf:Bag><rdf:li>mountains</rdf:li><rdf:li>snow</rdf:li></rdf:Bag></dc:subject>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#,
+        )
+        .unwrap();
+
+        let db = create_test_db(&temp_dir);
+        let cache_dir = temp_dir.path().join("thumbnails");
+        let result = import_folder(library.to_str().unwrap(), None, &cache_dir, &db, |_| {}).unwrap();
+        assert_eq!(result.imported, 2);
+
+        let tagged = db.get_image_by_path(library.join("tagged.jpg").to_str().unwrap()).unwrap().unwrap();
+        let labels: Vec<String> = db.get_tags_for_image(tagged.id).unwrap().into_iter().map(|t| t.label).collect();
+        assert_eq!(labels, vec!["mountains", "snow"]);
+        assert_eq!(tagged.rating, 5);
+
+        let plain = db.get_image_by_path(library.join("plain.jpg").to_str().unwrap()).unwrap().unwrap();
+        assert!(db.get_tags_for_image(plain.id).unwrap().is_empty());
+        assert_eq!(plain.rating, 0);
+    }
+
+    #[test]
+    fn test_import_folder_skips_existing_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let library = temp_dir.path().join("library");
+        fs::create_dir_all(&library).unwrap();
+        create_test_image(&library.join("photo.png"), 100, 100);
+
+        let db = create_test_db(&temp_dir);
+        let cache_dir = temp_dir.path().join("thumbnails");
+
+        let first = import_folder(library.to_str().unwrap(), None, &cache_dir, &db, |_| {}).unwrap();
+        assert_eq!(first.imported, 1);
+
+        let second = import_folder(library.to_str().unwrap(), None, &cache_dir, &db, |_| {}).unwrap();
+        assert_eq!(second.imported, 0);
+        assert_eq!(second.skipped, 1);
+    }
+
+    #[test]
+    fn test_import_folder_isolates_failures() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let library = temp_dir.path().join("library");
+        fs::create_dir_all(&library).unwrap();
+        create_test_image(&library.join("good.png"), 100, 100);
+        fs::write(library.join("broken.jpg"), b"not really a jpeg").unwrap();
+
+        let db = create_test_db(&temp_dir);
+        let cache_dir = temp_dir.path().join("thumbnails");
+
+        let result = import_folder(library.to_str().unwrap(), None, &cache_dir, &db, |_| {}).unwrap();
+
+        assert_eq!(result.imported, 1);
+        assert_eq!(result.failed.len(), 1);
+        assert!(result.failed[0].path.ends_with("broken.jpg"));
+
+        let broken = library.join("broken.jpg");
+        assert!(db.get_image_by_path(broken.to_str().unwrap()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_import_folder_reports_progress_for_every_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let library = temp_dir.path().join("library");
+        fs::create_dir_all(&library).unwrap();
+        for i in 0..5 {
+            create_test_image(&library.join(format!("img{}.png", i)), 64, 64);
+        }
+
+        let db = create_test_db(&temp_dir);
+        let cache_dir = temp_dir.path().join("thumbnails");
+        let events = Mutex::new(Vec::new());
+
+        import_folder(library.to_str().unwrap(), None, &cache_dir, &db, |progress| {
+            events.lock().unwrap().push(progress);
+        })
+        .unwrap();
+
+        let events = events.into_inner().unwrap();
+        assert_eq!(events.len(), 5);
+        assert!(events.iter().all(|p| p.total == 5 && p.status == ImportStatus::Imported));
+
+        let mut currents: Vec<usize> = events.iter().map(|p| p.current).collect();
+        currents.sort();
+        assert_eq!(currents, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_changed_edited_images_are_hashed_as_taken() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let library = temp_dir.path().join("library");
+        fs::create_dir_all(&library).unwrap();
+        let path = library.join("photo.png");
+        create_test_image(&path, 300, 200);
+
+        let db = create_test_db(&temp_dir);
+        let cache_dir = temp_dir.path().join("thumbnails");
+        let root = library.to_str().unwrap();
+        import_folder(root, None, &cache_dir, &db, |_| {}).unwrap();
+        let id = db.get_image_by_path(path.to_str().unwrap()).unwrap().unwrap().id;
+        let recipe = EditRecipe {
+            flip_horizontal: true,
+            ..Default::default()
+        };
+        db.set_edit_recipe(id, Some(&recipe)).unwrap();
+
+        // The file changes while it has edits, so its thumbnails are rendered with them
+        create_test_image(&path, 400, 200);
+        let report = rescan_folder(root, None, &cache_dir, &db, |_| {}).unwrap();
+        assert_eq!(report.updated.len(), 1);
+
+        // The hash is of the unedited thumbnail rather than the one shown
+        let record = db.get_image_by_id(id).unwrap().unwrap();
+        let unedited = cache_dir.join(format!("{}_small.jpg", record.checksum));
+        let (_, dhash, phash) = db.get_perceptual_hashes().unwrap()[0];
+        let expected = duplicates::hash_thumbnail(unedited.to_str().unwrap()).unwrap();
+        assert_eq!((dhash, phash), (expected.dhash, expected.phash));
+        assert_ne!(duplicates::hash_thumbnail(&record.thumbnail_small).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_rescan_folder_reports_differences() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let library = temp_dir.path().join("library");
+        fs::create_dir_all(library.join("sub")).unwrap();
+        create_test_image(&library.join("keep.png"), 64, 64);
+        create_test_image(&library.join("edit.png"), 64, 64);
+        create_test_image(&library.join("move.png"), 80, 80);
+        create_test_image(&library.join("delete.png"), 96, 96);
+
+        let db = create_test_db(&temp_dir);
+        let cache_dir = temp_dir.path().join("thumbnails");
+        let root = library.to_str().unwrap();
+
+        import_folder(root, None, &cache_dir, &db, |_| {}).unwrap();
+
+        let path_of = |name: &str| library.join(name).to_str().unwrap().to_string();
+        let edit_id = db.get_image_by_path(&path_of("edit.png")).unwrap().unwrap().id;
+        let move_id = db.get_image_by_path(&path_of("move.png")).unwrap().unwrap().id;
+
+        // Change the filesystem behind the library's back
+        create_test_image(&library.join("edit.png"), 128, 32);
+        fs::rename(library.join("move.png"), library.join("sub/moved.png")).unwrap();
+        fs::remove_file(library.join("delete.png")).unwrap();
+        create_test_image(&library.join("new.png"), 50, 50);
+
+        let report = rescan_folder(root, None, &cache_dir, &db, |_| {}).unwrap();
+
+        assert!(report.failed.is_empty(), "unexpected failures: {:?}", report.failed);
+        assert_eq!(report.unchanged, 1);
+        assert_eq!(report.added, vec![path_of("new.png")]);
+        assert_eq!(report.updated, vec![path_of("edit.png")]);
+        assert_eq!(report.removed, vec![path_of("delete.png")]);
+        assert_eq!(
+            report.moved,
+            vec![MovedFile {
+                from: path_of("move.png"),
+                to: path_of("sub/moved.png"),
+            }]
+        );
+
+        // Updated and moved files keep their record IDs
+        let edited = db.get_image_by_id(edit_id).unwrap().unwrap();
+        assert_eq!(edited.checksum, thumbnail::compute_checksum(&library.join("edit.png")).unwrap());
+        assert_eq!(edited.sync_status, "pending");
+
+        let moved = db.get_image_by_id(move_id).unwrap().unwrap();
+        assert_eq!(moved.path, path_of("sub/moved.png"));
+
+        assert!(db.get_image_by_path(&path_of("delete.png")).unwrap().is_none());
+        assert!(db.get_image_by_path(&path_of("new.png")).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_rescan_folder_without_changes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let library = temp_dir.path().join("library");
+        fs::create_dir_all(&library).unwrap();
+        create_test_image(&library.join("a.png"), 64, 64);
+        create_test_image(&library.join("b.png"), 32, 32);
+
+        let db = create_test_db(&temp_dir);
+        let cache_dir = temp_dir.path().join("thumbnails");
+        let root = library.to_str().unwrap();
+
+        import_folder(root, None, &cache_dir, &db, |_| {}).unwrap();
+        let events = Mutex::new(Vec::new());
+        let report = rescan_folder(root, None, &cache_dir, &db, |p| events.lock().unwrap().push(p)).unwrap();
+
+        assert_eq!(report.unchanged, 2);
+        assert!(report.added.is_empty());
+        assert!(report.updated.is_empty());
+        assert!(report.moved.is_empty());
+        assert!(report.removed.is_empty());
+
+        let events = events.into_inner().unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|p| p.change == FileChange::Unchanged));
+    }
+
+    #[test]
+    fn test_rescan_folder_ignores_other_folders() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let library = temp_dir.path().join("library");
+        let sibling = temp_dir.path().join("library_other");
+        fs::create_dir_all(&library).unwrap();
+        fs::create_dir_all(&sibling).unwrap();
+        create_test_image(&library.join("a.png"), 64, 64);
+        create_test_image(&sibling.join("b.png"), 64, 64);
+
+        let db = create_test_db(&temp_dir);
+        let cache_dir = temp_dir.path().join("thumbnails");
+
+        import_folder(library.to_str().unwrap(), None, &cache_dir, &db, |_| {}).unwrap();
+        import_folder(sibling.to_str().unwrap(), None, &cache_dir, &db, |_| {}).unwrap();
+
+        // Deleting the sibling folder must not affect a rescan of the library
+        fs::remove_dir_all(&sibling).unwrap();
+        let report = rescan_folder(library.to_str().unwrap(), None, &cache_dir, &db, |_| {}).unwrap();
+
+        assert!(report.removed.is_empty());
+        let sibling_file = sibling.join("b.png");
+        assert!(db.get_image_by_path(sibling_file.to_str().unwrap()).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_backfill_metadata_reads_files_imported_earlier() {
+        use exif::experimental::Writer;
+        use exif::{Field, In, Rational, Tag, Value};
+        use image::ImageEncoder;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let library = temp_dir.path().join("library");
+        fs::create_dir_all(&library).unwrap();
+
+        let fields = [
+            Field {
+                tag: Tag::DateTimeOriginal,
+                ifd_num: In::PRIMARY,
+                value: Value::Ascii(vec![b"2024:06:15 14:30:00".to_vec()]),
+            },
+            Field {
+                tag: Tag::OffsetTimeOriginal,
+                ifd_num: In::PRIMARY,
+                value: Value::Ascii(vec![b"+02:00".to_vec()]),
+            },
+            Field {
+                tag: Tag::FNumber,
+                ifd_num: In::PRIMARY,
+                value: Value::Rational(vec![Rational { num: 14, denom: 10 }]),
+            },
+            Field {
+                tag: Tag::PhotographicSensitivity,
+                ifd_num: In::PRIMARY,
+                value: Value::Short(vec![400]),
+            },
+        ];
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut exif = std::io::Cursor::new(Vec::new());
+        writer.write(&mut exif, false).unwrap();
+        let photo = library.join("DSCF0001.jpg");
+        let mut encoder = image::codecs::jpeg::JpegEncoder::new(fs::File::create(&photo).unwrap());
+        encoder.set_exif_metadata(exif.into_inner()).unwrap();
+        image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(8, 4, Rgb([120, 120, 120])))
+            .write_with_encoder(encoder)
+            .unwrap();
+        create_test_image(&library.join("no_exif.png"), 16, 16);
+
+        let db = create_test_db(&temp_dir);
+        let cache_dir = temp_dir.path().join("thumbnails");
+        import_folder(library.to_str().unwrap(), None, &cache_dir, &db, |_| {}).unwrap();
+        assert_eq!(backfill_metadata(&db).unwrap(), 0);
+
+        // What an import before exposure settings and UTC offsets were read left behind
+        let record = db.get_image_by_path(photo.to_str().unwrap()).unwrap().unwrap();
+        db.set_capture_settings(record.id, &metadata::CaptureSettings::default()).unwrap();
+        {
+            let conn = db.connection().lock().unwrap();
+            conn.execute(
+                "UPDATE images SET metadata_version = 0, capture_date = '2024-06-15T14:30:00+00:00', sync_status = 'synced'",
+                [],
+            )
+            .unwrap();
+        }
+
+        assert_eq!(backfill_metadata(&db).unwrap(), 2);
+        let backfilled = db.get_image_by_id(record.id).unwrap().unwrap();
+        assert_eq!(backfilled.capture_settings.aperture, Some(1.4));
+        assert_eq!(backfilled.capture_settings.iso, Some(400));
+        assert_eq!(backfilled.capture_settings.utc_offset_minutes, Some(120));
+        assert_eq!(backfilled.capture_date.unwrap().to_rfc3339(), "2024-06-15T12:30:00+00:00");
+        // The file itself didn't change, so it isn't synced again
+        assert_eq!(backfilled.sync_status, "synced");
+
+        // Files without EXIF aren't read on every start
+        assert_eq!(backfill_metadata(&db).unwrap(), 0);
+    }
+}
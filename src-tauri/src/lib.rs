@@ -1,15 +1,31 @@
+mod albums;
 mod auth;
 mod database;
+mod duplicates;
+mod edits;
+mod export;
 mod ffmpeg;
+mod geo;
+mod geocoder;
+mod importer;
 mod logging;
 mod metadata;
 mod migrations;
 mod performance;
+mod raw;
+mod rendition;
 mod scanner;
+mod scheduler;
+mod search_query;
 mod settings;
+mod similarity;
+mod storage;
 mod sync;
 pub mod thumbnail; // Made public for performance tests
+mod trash;
 mod updater;
+mod watcher;
+mod xmp;
 
 use tauri::Manager;
 use tauri::Emitter;
@@ -144,6 +160,260 @@ fn generate_video_thumbnails(
     }
 }
 
+/// Tauri command to import a folder into the library
+/// Scans, extracts metadata, generates thumbnails and inserts records in one backend job,
+/// emitting an `import-progress` event for every processed file
+#[tauri::command]
+async fn import_folder(
+    folder_path: String,
+    config: Option<settings::FormatConfig>,
+    app_handle: tauri::AppHandle,
+) -> Result<importer::ImportResult, String> {
+    logging::log_info("import", &format!("Starting import of folder: {}", folder_path));
+
+    // Get the app data directory for thumbnail cache
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| {
+            logging::log_error("import", "Failed to get app data directory", &e);
+            logging::user_friendly_error(&e)
+        })?;
+
+    let cache_dir = app_data_dir.join("thumbnails");
+
+    // Fall back to the configured formats when the caller doesn't provide any
+    let config = config.or_else(|| {
+        app_handle
+            .state::<settings::SettingsManager>()
+            .get_settings()
+            .ok()
+            .map(|s| s.format_config)
+    });
+
+    // Run the import off the async runtime since it is CPU and disk bound
+    let handle = app_handle.clone();
+    let import_result = tauri::async_runtime::spawn_blocking(move || {
+        let db = handle.state::<database::Database>();
+        let emitter = handle.clone();
+
+        importer::import_folder(&folder_path, config, &cache_dir, db.inner(), move |progress| {
+            let _ = emitter.emit("import-progress", progress);
+        })
+    })
+    .await
+    .map_err(|e| {
+        let io_error = std::io::Error::new(std::io::ErrorKind::Other, e.to_string());
+        logging::log_error("import", "Import task failed", &io_error);
+        logging::user_friendly_error(&io_error)
+    })?;
+
+    match import_result {
+        Ok(result) => {
+            logging::log_info("import", &format!(
+                "Import completed: {} imported, {} skipped, {} failed",
+                result.imported,
+                result.skipped,
+                result.failed.len()
+            ));
+
+            // Log any failures
+            for error in &result.failed {
+                logging::log_warning("import", &format!("Import failed for {}: {}", error.path, error.error));
+            }
+
+            emit_smart_album_counts(&app_handle);
+            app_handle.state::<scheduler::SyncScheduler>().library_changed();
+            Ok(result)
+        }
+        Err(e) => {
+            let io_error = std::io::Error::new(std::io::ErrorKind::Other, e.clone());
+            logging::log_error("import", "Failed to import folder", &io_error);
+            Err(logging::user_friendly_error(&io_error))
+        }
+    }
+}
+
+/// Tauri command to rescan a library folder and reconcile it with the database
+/// Returns the added/updated/moved/removed files and emits `rescan-progress` events
+#[tauri::command]
+async fn rescan_folder(
+    folder_path: String,
+    config: Option<settings::FormatConfig>,
+    app_handle: tauri::AppHandle,
+) -> Result<importer::RescanReport, String> {
+    logging::log_info("import", &format!("Starting rescan of folder: {}", folder_path));
+
+    // Get the app data directory for thumbnail cache
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| {
+            logging::log_error("import", "Failed to get app data directory", &e);
+            logging::user_friendly_error(&e)
+        })?;
+
+    let cache_dir = app_data_dir.join("thumbnails");
+
+    // Fall back to the configured formats when the caller doesn't provide any
+    let config = config.or_else(|| {
+        app_handle
+            .state::<settings::SettingsManager>()
+            .get_settings()
+            .ok()
+            .map(|s| s.format_config)
+    });
+
+    let handle = app_handle.clone();
+    let rescan_result = tauri::async_runtime::spawn_blocking(move || {
+        let db = handle.state::<database::Database>();
+        let emitter = handle.clone();
+
+        importer::rescan_folder(&folder_path, config, &cache_dir, db.inner(), move |progress| {
+            let _ = emitter.emit("rescan-progress", progress);
+        })
+    })
+    .await
+    .map_err(|e| {
+        let io_error = std::io::Error::new(std::io::ErrorKind::Other, e.to_string());
+        logging::log_error("import", "Rescan task failed", &io_error);
+        logging::user_friendly_error(&io_error)
+    })?;
+
+    match rescan_result {
+        Ok(report) => {
+            logging::log_info("import", &format!(
+                "Rescan completed: {} added, {} updated, {} moved, {} removed, {} unchanged, {} failed",
+                report.added.len(),
+                report.updated.len(),
+                report.moved.len(),
+                report.removed.len(),
+                report.unchanged,
+                report.failed.len()
+            ));
+
+            for error in &report.failed {
+                logging::log_warning("import", &format!("Rescan failed for {}: {}", error.path, error.error));
+            }
+
+            emit_smart_album_counts(&app_handle);
+            app_handle.state::<scheduler::SyncScheduler>().library_changed();
+            Ok(report)
+        }
+        Err(e) => {
+            let io_error = std::io::Error::new(std::io::ErrorKind::Other, e.clone());
+            logging::log_error("import", "Failed to rescan folder", &io_error);
+            Err(logging::user_friendly_error(&io_error))
+        }
+    }
+}
+
+/// Tauri command to start watching a library folder for changes
+/// New, changed, moved and deleted files are imported automatically and reported via `library-changed` events
+#[tauri::command]
+fn watch_folder(
+    folder_path: String,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    logging::log_info("watcher", &format!("Watching folder: {}", folder_path));
+
+    let watcher = app_handle.state::<watcher::LibraryWatcher>();
+    watcher.watch(&folder_path).map_err(|e| {
+        let io_error = std::io::Error::new(std::io::ErrorKind::Other, e.clone());
+        logging::log_error("watcher", &format!("Failed to watch folder: {}", folder_path), &io_error);
+        logging::user_friendly_error(&io_error)
+    })?;
+
+    // Persist the folder so it is watched again on the next launch
+    let settings_manager = app_handle.state::<settings::SettingsManager>();
+    let mut settings = settings_manager.get_settings()?;
+    if !settings.watched_folders.contains(&folder_path) {
+        settings.watched_folders.push(folder_path);
+        settings_manager.save_settings(settings)?;
+    }
+
+    Ok(())
+}
+
+/// Tauri command to stop watching a library folder
+#[tauri::command]
+fn unwatch_folder(
+    folder_path: String,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    logging::log_info("watcher", &format!("Unwatching folder: {}", folder_path));
+
+    let watcher = app_handle.state::<watcher::LibraryWatcher>();
+    watcher.unwatch(&folder_path).map_err(|e| {
+        let io_error = std::io::Error::new(std::io::ErrorKind::Other, e.clone());
+        logging::log_error("watcher", &format!("Failed to unwatch folder: {}", folder_path), &io_error);
+        logging::user_friendly_error(&io_error)
+    })?;
+
+    let settings_manager = app_handle.state::<settings::SettingsManager>();
+    let mut settings = settings_manager.get_settings()?;
+    if settings.watched_folders.contains(&folder_path) {
+        settings.watched_folders.retain(|f| f != &folder_path);
+        settings_manager.save_settings(settings)?;
+    }
+
+    Ok(())
+}
+
+/// Tauri command to get the folders currently being watched
+#[tauri::command]
+fn get_watched_folders(app_handle: tauri::AppHandle) -> Vec<String> {
+    app_handle.state::<watcher::LibraryWatcher>().watched_folders()
+}
+
+/// Tauri command to find groups of files with identical content
+#[tauri::command]
+fn find_exact_duplicates(
+    app_handle: tauri::AppHandle,
+) -> Result<Vec<duplicates::DuplicateGroup>, String> {
+    logging::log_debug("duplicates", "Searching for exact duplicates");
+
+    let db = app_handle.state::<database::Database>();
+
+    duplicates::find_exact_duplicates(db.inner()).map_err(|e| {
+        let io_error = std::io::Error::new(std::io::ErrorKind::Other, e.clone());
+        logging::log_error("duplicates", "Failed to find exact duplicates", &io_error);
+        logging::user_friendly_error(&io_error)
+    })
+}
+
+/// Tauri command to find clusters of visually similar images
+/// `threshold` is the maximum Hamming distance (0-64) between linked images
+#[tauri::command]
+async fn find_near_duplicates(
+    threshold: Option<u32>,
+    algorithm: Option<duplicates::HashAlgorithm>,
+    app_handle: tauri::AppHandle,
+) -> Result<Vec<duplicates::DuplicateGroup>, String> {
+    let threshold = threshold.unwrap_or(duplicates::DEFAULT_NEAR_DUPLICATE_THRESHOLD);
+    let algorithm = algorithm.unwrap_or_default();
+    logging::log_info("duplicates", &format!("Searching for near duplicates within distance {}", threshold));
+
+    // Hashing images imported before this feature existed can take a while
+    let handle = app_handle.clone();
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let db = handle.state::<database::Database>();
+        duplicates::find_near_duplicates(db.inner(), algorithm, threshold)
+    })
+    .await
+    .map_err(|e| {
+        let io_error = std::io::Error::new(std::io::ErrorKind::Other, e.to_string());
+        logging::log_error("duplicates", "Near-duplicate search task failed", &io_error);
+        logging::user_friendly_error(&io_error)
+    })?;
+
+    result.map_err(|e| {
+        let io_error = std::io::Error::new(std::io::ErrorKind::Other, e.clone());
+        logging::log_error("duplicates", "Failed to find near duplicates", &io_error);
+        logging::user_friendly_error(&io_error)
+    })
+}
+
 /// Tauri command to get codec performance metrics
 #[tauri::command]
 fn get_codec_performance_metrics() -> Vec<thumbnail::CodecPerformanceMetrics> {
@@ -151,131 +421,721 @@ fn get_codec_performance_metrics() -> Vec<thumbnail::CodecPerformanceMetrics> {
     thumbnail::get_codec_performance_metrics()
 }
 
-/// Tauri command to reset codec performance metrics
+/// Tauri command to reset codec performance metrics
+#[tauri::command]
+fn reset_codec_performance_metrics() {
+    logging::log_debug("thumbnail", "Resetting codec performance metrics");
+    thumbnail::reset_codec_performance_metrics();
+}
+
+/// Tauri command to save tags for an image
+#[tauri::command]
+fn save_tags(
+    image_id: i64,
+    tags: Vec<(String, f64)>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    logging::log_debug("tags", &format!("Saving {} tags for image ID: {}", tags.len(), image_id));
+    
+    let db = app_handle.state::<database::Database>();
+    
+    for (label, confidence) in tags {
+        db.insert_tag(image_id, &label, confidence)
+            .map_err(|e| {
+                logging::log_error("tags", &format!("Failed to insert tag '{}' for image {}", label, image_id), &e);
+                logging::user_friendly_error(&e)
+            })?;
+    }
+    write_back_metadata(&app_handle, &[image_id]);
+    
+    logging::log_debug("tags", &format!("Tags saved successfully for image ID: {}", image_id));
+    Ok(())
+}
+
+/// Tauri command to search images with filters
+///
+/// `query` is parsed with the search query language; the other arguments are
+/// applied on top of it.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn search_images(
+    query: Option<String>,
+    text: Option<String>,
+    tags: Option<Vec<String>>,
+    date_start: Option<String>,
+    date_end: Option<String>,
+    camera_model: Option<String>,
+    media_type: Option<String>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    radius_km: Option<f64>,
+    app_handle: tauri::AppHandle,
+) -> Result<Vec<database::ImageRecord>, String> {
+    logging::log_debug("search", "Executing image search");
+    
+    let db = app_handle.state::<database::Database>();
+    
+    // Parse date range if provided
+    let date_range = if let (Some(start_str), Some(end_str)) = (date_start, date_end) {
+        let start = chrono::DateTime::parse_from_rfc3339(&start_str)
+            .map_err(|e| {
+                logging::log_warning("search", &format!("Invalid start date format: {}", e));
+                format!("Invalid start date: {}", e)
+            })?
+            .with_timezone(&chrono::Utc);
+        let end = chrono::DateTime::parse_from_rfc3339(&end_str)
+            .map_err(|e| {
+                logging::log_warning("search", &format!("Invalid end date format: {}", e));
+                format!("Invalid end date: {}", e)
+            })?
+            .with_timezone(&chrono::Utc);
+        Some((start, end))
+    } else {
+        None
+    };
+    
+    // Parse media type if provided
+    let parsed_media_type = media_type.and_then(|mt| {
+        match mt.to_lowercase().as_str() {
+            "image" => Some(database::MediaType::Image),
+            "video" => Some(database::MediaType::Video),
+            "all" | "" => None, // "all" means no filter
+            _ => {
+                logging::log_warning("search", &format!("Invalid media type: {}", mt));
+                None
+            }
+        }
+    });
+    
+    // Build filter, starting from the query string if one was typed
+    let mut filter = match query.as_deref() {
+        Some(query) => search_query::parse_query(query).map_err(|e| {
+            logging::log_warning("search", &format!("Invalid search query: {}", e));
+            e.to_string()
+        })?,
+        None => database::ImageFilter::default(),
+    };
+    if let Some(tags) = tags {
+        filter.tags.get_or_insert_with(Vec::new).extend(tags);
+    }
+    if date_range.is_some() {
+        filter.date_range = date_range;
+    }
+    if camera_model.is_some() {
+        filter.camera_model = camera_model;
+    }
+    if parsed_media_type.is_some() {
+        filter.media_type = parsed_media_type;
+    }
+    if let (Some(latitude), Some(longitude), Some(radius_km)) = (latitude, longitude, radius_km) {
+        geo::validate_coordinates(latitude, longitude)?;
+        if !radius_km.is_finite() || radius_km <= 0.0 {
+            return Err(format!("Search radius must be greater than zero, got {}", radius_km));
+        }
+        filter.location = Some((latitude, longitude, radius_km));
+    }
+    if let Some(text) = text {
+        filter.text = Some(match filter.text.take() {
+            Some(query_text) => format!("{} {}", query_text, text),
+            None => text,
+        });
+    }
+    
+    // Query database; text matches are ranked by relevance
+    let images = db.query_images(&filter)
+        .map_err(|e| {
+            logging::log_error("search", "Failed to query images", &e);
+            logging::user_friendly_error(&e)
+        })?;
+    
+    logging::log_debug("search", &format!("Search completed: {} results found", images.len()));
+    Ok(images)
+}
+
+/// Tauri command to get tags for an image
+#[tauri::command]
+fn get_image_tags(
+    image_id: i64,
+    app_handle: tauri::AppHandle,
+) -> Result<Vec<database::Tag>, String> {
+    let db = app_handle.state::<database::Database>();
+    
+    db.get_tags_for_image(image_id)
+        .map_err(|e| {
+            logging::log_error("tags", &format!("Failed to get tags for image {}", image_id), &e);
+            logging::user_friendly_error(&e)
+        })
+}
+
+/// Tauri command to get the geotagged images inside a map viewport
+///
+/// A viewport whose west edge is greater than its east edge crosses the antimeridian.
+#[tauri::command]
+fn get_images_in_bounds(
+    south: f64,
+    west: f64,
+    north: f64,
+    east: f64,
+    app_handle: tauri::AppHandle,
+) -> Result<Vec<database::ImageRecord>, String> {
+    let bounds = geo::GeoBounds::new(south, west, north, east)?;
+    let db = app_handle.state::<database::Database>();
+
+    let filter = database::ImageFilter {
+        bounds: Some(bounds),
+        ..Default::default()
+    };
+
+    db.query_images(&filter)
+        .map_err(|e| {
+            logging::log_error("search", "Failed to query images in bounds", &e);
+            logging::user_friendly_error(&e)
+        })
+}
+
+/// Tauri command to create an album, or a folder of albums when `is_folder` is set
+#[tauri::command]
+fn create_album(
+    name: String,
+    parent_id: Option<i64>,
+    is_folder: Option<bool>,
+    app_handle: tauri::AppHandle,
+) -> Result<database::Album, String> {
+    let db = app_handle.state::<database::Database>();
+
+    albums::create_album(db.inner(), &name, parent_id, is_folder.unwrap_or(false)).map_err(|e| {
+        logging::log_warning("albums", &format!("Failed to create album: {}", e));
+        e
+    })
+}
+
+/// Tauri command to create a smart album from a search query or a filter
+#[tauri::command]
+fn create_smart_album(
+    name: String,
+    parent_id: Option<i64>,
+    query: Option<String>,
+    filter: Option<database::ImageFilter>,
+    app_handle: tauri::AppHandle,
+) -> Result<database::Album, String> {
+    let db = app_handle.state::<database::Database>();
+
+    albums::create_smart_album(db.inner(), &name, parent_id, query.as_deref(), filter).map_err(|e| {
+        logging::log_warning("albums", &format!("Failed to create smart album: {}", e));
+        e
+    })
+}
+
+/// Tauri command to replace the search query or filter of a smart album
+#[tauri::command]
+fn update_smart_album(
+    album_id: i64,
+    query: Option<String>,
+    filter: Option<database::ImageFilter>,
+    app_handle: tauri::AppHandle,
+) -> Result<database::Album, String> {
+    let db = app_handle.state::<database::Database>();
+
+    albums::update_smart_album(db.inner(), album_id, query.as_deref(), filter).map_err(|e| {
+        logging::log_warning("albums", &format!("Failed to update smart album: {}", e));
+        e
+    })
+}
+
+/// Tauri command to get the current image count of every smart album
+#[tauri::command]
+fn get_smart_album_counts(
+    app_handle: tauri::AppHandle,
+) -> Result<std::collections::HashMap<i64, i64>, String> {
+    let db = app_handle.state::<database::Database>();
+
+    albums::smart_album_counts(db.inner()).map_err(|e| {
+        logging::log_warning("albums", &format!("Failed to count smart albums: {}", e));
+        e
+    })
+}
+
+/// Tell the frontend the new smart album counts after images were imported or changed
+fn emit_smart_album_counts(app_handle: &tauri::AppHandle) {
+    let db = app_handle.state::<database::Database>();
+
+    match albums::smart_album_counts(db.inner()) {
+        Ok(counts) if !counts.is_empty() => {
+            let _ = app_handle.emit("smart-album-counts", counts);
+        }
+        Ok(_) => {}
+        Err(e) => logging::log_warning("albums", &format!("Failed to count smart albums: {}", e)),
+    }
+}
+
+/// Tauri command to list all albums and folders in their manual order
+#[tauri::command]
+fn get_albums(
+    app_handle: tauri::AppHandle,
+) -> Result<Vec<database::Album>, String> {
+    let db = app_handle.state::<database::Database>();
+
+    albums::list_albums(db.inner()).map_err(|e| {
+        logging::log_warning("albums", &format!("Failed to list albums: {}", e));
+        e
+    })
+}
+
+/// Tauri command to rename an album or folder
+#[tauri::command]
+fn rename_album(
+    album_id: i64,
+    name: String,
+    app_handle: tauri::AppHandle,
+) -> Result<database::Album, String> {
+    let db = app_handle.state::<database::Database>();
+
+    albums::rename_album(db.inner(), album_id, &name).map_err(|e| {
+        logging::log_warning("albums", &format!("Failed to rename album: {}", e));
+        e
+    })
+}
+
+/// Tauri command to set or clear the cover image of an album
+#[tauri::command]
+fn set_album_cover(
+    album_id: i64,
+    image_id: Option<i64>,
+    app_handle: tauri::AppHandle,
+) -> Result<database::Album, String> {
+    let db = app_handle.state::<database::Database>();
+
+    albums::set_cover(db.inner(), album_id, image_id).map_err(|e| {
+        logging::log_warning("albums", &format!("Failed to set album cover: {}", e));
+        e
+    })
+}
+
+/// Tauri command to move an album into a folder, or to the top level, at a position
+#[tauri::command]
+fn move_album(
+    album_id: i64,
+    parent_id: Option<i64>,
+    position: usize,
+    app_handle: tauri::AppHandle,
+) -> Result<database::Album, String> {
+    let db = app_handle.state::<database::Database>();
+
+    albums::move_album(db.inner(), album_id, parent_id, position).map_err(|e| {
+        logging::log_warning("albums", &format!("Failed to move album: {}", e));
+        e
+    })
+}
+
+/// Tauri command to delete an album or folder, keeping its images in the library
+#[tauri::command]
+fn delete_album(
+    album_id: i64,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    let db = app_handle.state::<database::Database>();
+
+    albums::delete_album(db.inner(), album_id).map_err(|e| {
+        logging::log_warning("albums", &format!("Failed to delete album: {}", e));
+        e
+    })
+}
+
+/// Tauri command to add images to the end of an album
+/// Returns how many images were not already in the album
+#[tauri::command]
+fn add_images_to_album(
+    album_id: i64,
+    image_ids: Vec<i64>,
+    app_handle: tauri::AppHandle,
+) -> Result<usize, String> {
+    let db = app_handle.state::<database::Database>();
+
+    albums::add_images(db.inner(), album_id, &image_ids).map_err(|e| {
+        logging::log_warning("albums", &format!("Failed to add images to album: {}", e));
+        e
+    })
+}
+
+/// Tauri command to remove images from an album
+#[tauri::command]
+fn remove_images_from_album(
+    album_id: i64,
+    image_ids: Vec<i64>,
+    app_handle: tauri::AppHandle,
+) -> Result<usize, String> {
+    let db = app_handle.state::<database::Database>();
+
+    albums::remove_images(db.inner(), album_id, &image_ids).map_err(|e| {
+        logging::log_warning("albums", &format!("Failed to remove images from album: {}", e));
+        e
+    })
+}
+
+/// Tauri command to get the images of an album in their manual order,
+/// or the images currently matching a smart album
+#[tauri::command]
+fn get_album_images(
+    album_id: i64,
+    app_handle: tauri::AppHandle,
+) -> Result<Vec<database::ImageRecord>, String> {
+    let db = app_handle.state::<database::Database>();
+
+    albums::get_images(db.inner(), album_id).map_err(|e| {
+        logging::log_warning("albums", &format!("Failed to get album images: {}", e));
+        e
+    })
+}
+
+/// Tauri command to reorder an album, given all of its image IDs in the new order
+#[tauri::command]
+fn reorder_album_images(
+    album_id: i64,
+    image_ids: Vec<i64>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    let db = app_handle.state::<database::Database>();
+
+    albums::reorder_images(db.inner(), album_id, &image_ids).map_err(|e| {
+        logging::log_warning("albums", &format!("Failed to reorder album: {}", e));
+        e
+    })
+}
+
+/// Tauri command to set the star rating of several images, 0 to clear it
+/// Returns how many images were updated
+#[tauri::command]
+fn set_images_rating(
+    image_ids: Vec<i64>,
+    rating: u8,
+    app_handle: tauri::AppHandle,
+) -> Result<usize, String> {
+    if rating > database::MAX_RATING {
+        return Err(format!("Rating must be between 0 and {}", database::MAX_RATING));
+    }
+
+    let db = app_handle.state::<database::Database>();
+
+    let updated = db.set_images_rating(&image_ids, rating)
+        .map_err(|e| {
+            logging::log_error("curation", "Failed to set rating", &e);
+            logging::user_friendly_error(&e)
+        })?;
+    write_back_metadata(&app_handle, &image_ids);
+
+    Ok(updated)
+}
+
+/// Tauri command to set or clear the color label of several images
+#[tauri::command]
+fn set_images_color_label(
+    image_ids: Vec<i64>,
+    label: Option<database::ColorLabel>,
+    app_handle: tauri::AppHandle,
+) -> Result<usize, String> {
+    let db = app_handle.state::<database::Database>();
+
+    db.set_images_color_label(&image_ids, label)
+        .map_err(|e| {
+            logging::log_error("curation", "Failed to set color label", &e);
+            logging::user_friendly_error(&e)
+        })
+}
+
+/// Tauri command to pick, reject or unflag several images
+#[tauri::command]
+fn set_images_flag(
+    image_ids: Vec<i64>,
+    flag: database::Flag,
+    app_handle: tauri::AppHandle,
+) -> Result<usize, String> {
+    let db = app_handle.state::<database::Database>();
+
+    let updated = db.set_images_flag(&image_ids, flag)
+        .map_err(|e| {
+            logging::log_error("curation", "Failed to set flag", &e);
+            logging::user_friendly_error(&e)
+        })?;
+    // Rejected images are written with a rating of -1
+    write_back_metadata(&app_handle, &image_ids);
+
+    Ok(updated)
+}
+
+/// Tauri command to mark or unmark several images as favorites
+#[tauri::command]
+fn set_images_favorite(
+    image_ids: Vec<i64>,
+    favorite: bool,
+    app_handle: tauri::AppHandle,
+) -> Result<usize, String> {
+    let db = app_handle.state::<database::Database>();
+
+    db.set_images_favorite(&image_ids, favorite)
+        .map_err(|e| {
+            logging::log_error("curation", "Failed to set favorite", &e);
+            logging::user_friendly_error(&e)
+        })
+}
+
+/// Tauri command to set or clear the description of several images
+#[tauri::command]
+fn set_images_description(
+    image_ids: Vec<i64>,
+    description: Option<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<usize, String> {
+    let db = app_handle.state::<database::Database>();
+    let description = description.filter(|d| !d.trim().is_empty());
+
+    let updated = db.set_images_description(&image_ids, description.as_deref())
+        .map_err(|e| {
+            logging::log_error("curation", "Failed to set description", &e);
+            logging::user_friendly_error(&e)
+        })?;
+    write_back_metadata(&app_handle, &image_ids);
+
+    Ok(updated)
+}
+
+/// Tauri command to write the tags, ratings and descriptions of images to XMP
+/// Used to write existing metadata when write-back is turned on, or into a different destination
+#[tauri::command]
+fn write_xmp_metadata(
+    image_ids: Vec<i64>,
+    mode: settings::WriteBackMode,
+    app_handle: tauri::AppHandle,
+) -> Result<xmp::WriteBackReport, String> {
+    logging::log_info("xmp", &format!("Writing XMP metadata for {} images", image_ids.len()));
+
+    let db = app_handle.state::<database::Database>();
+    let report = xmp::write_back(db.inner(), &image_ids, mode);
+
+    for error in &report.failed {
+        logging::log_warning("xmp", &format!("Failed to write metadata for {}: {}", error.path, error.error));
+    }
+
+    Ok(report)
+}
+
+/// Write changed metadata back to the originals when the settings ask for it
+/// Failures are logged; the change is already saved in the library
+fn write_back_metadata(app_handle: &tauri::AppHandle, image_ids: &[i64]) {
+    let mode = app_handle
+        .state::<settings::SettingsManager>()
+        .get_settings()
+        .map(|s| s.metadata_config.write_back)
+        .unwrap_or_default();
+    if mode == settings::WriteBackMode::Off {
+        return;
+    }
+
+    let db = app_handle.state::<database::Database>();
+    let report = xmp::write_back(db.inner(), image_ids, mode);
+
+    for error in &report.failed {
+        logging::log_warning("xmp", &format!("Failed to write metadata for {}: {}", error.path, error.error));
+    }
+}
+
+/// Tauri command to get the edit recipe of a photo, empty if it was never edited
+#[tauri::command]
+fn get_edit_recipe(
+    image_id: i64,
+    app_handle: tauri::AppHandle,
+) -> Result<edits::EditRecipe, String> {
+    let db = app_handle.state::<database::Database>();
+
+    edits::get_recipe(db.inner(), image_id).map_err(|e| {
+        logging::log_warning("edits", &format!("Failed to get edits: {}", e));
+        e
+    })
+}
+
+/// Tauri command to save the edits of a photo and re-render its thumbnails
+/// Saving an empty recipe resets the photo; the original file is never modified
+#[tauri::command]
+async fn set_edit_recipe(
+    image_id: i64,
+    recipe: edits::EditRecipe,
+    app_handle: tauri::AppHandle,
+) -> Result<database::ImageRecord, String> {
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| {
+            logging::log_error("edits", "Failed to get app data directory", &e);
+            logging::user_friendly_error(&e)
+        })?;
+    let cache_dir = app_data_dir.join("thumbnails");
+
+    // Rendering decodes the full image, so keep it off the async runtime
+    let handle = app_handle.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let db = handle.state::<database::Database>();
+        edits::set_recipe(db.inner(), image_id, &recipe, &cache_dir)
+    })
+    .await
+    .map_err(|e| {
+        let io_error = std::io::Error::new(std::io::ErrorKind::Other, e.to_string());
+        logging::log_error("edits", "Edit task failed", &io_error);
+        logging::user_friendly_error(&io_error)
+    })?
+    .map_err(|e| {
+        logging::log_warning("edits", &format!("Failed to save edits: {}", e));
+        e
+    })
+}
+
+/// Tauri command to export images into a folder
+/// Emits "export-progress" events while the files are written
 #[tauri::command]
-fn reset_codec_performance_metrics() {
-    logging::log_debug("thumbnail", "Resetting codec performance metrics");
-    thumbnail::reset_codec_performance_metrics();
+async fn export_images(
+    image_ids: Vec<i64>,
+    target_folder: String,
+    options: export::ExportOptions,
+    app_handle: tauri::AppHandle,
+) -> Result<export::ExportResult, String> {
+    logging::log_info("export", &format!("Exporting {} images to {}", image_ids.len(), target_folder));
+
+    // Decoding and encoding full-size images is slow, so keep it off the async runtime
+    let handle = app_handle.clone();
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let db = handle.state::<database::Database>();
+        export::export_images(db.inner(), &image_ids, std::path::Path::new(&target_folder), &options, |progress| {
+            let _ = handle.emit("export-progress", progress);
+        })
+    })
+    .await
+    .map_err(|e| {
+        let io_error = std::io::Error::new(std::io::ErrorKind::Other, e.to_string());
+        logging::log_error("export", "Export task failed", &io_error);
+        logging::user_friendly_error(&io_error)
+    })?
+    .map_err(|e| {
+        logging::log_warning("export", &format!("Export failed: {}", e));
+        e
+    })?;
+
+    logging::log_info("export", &format!("Export completed: {} exported, {} failed",
+        result.exported.len(), result.failed.len()));
+    for error in &result.failed {
+        logging::log_warning("export", &format!("Export failed for image {}: {}", error.image_id, error.error));
+    }
+
+    Ok(result)
 }
 
-/// Tauri command to save tags for an image
+/// Tauri command to move images to the trash
+/// Originals are moved into the app's trash folder when the trash settings ask for it
 #[tauri::command]
-fn save_tags(
-    image_id: i64,
-    tags: Vec<(String, f64)>,
+fn move_images_to_trash(
+    image_ids: Vec<i64>,
     app_handle: tauri::AppHandle,
-) -> Result<(), String> {
-    logging::log_debug("tags", &format!("Saving {} tags for image ID: {}", tags.len(), image_id));
-    
-    let db = app_handle.state::<database::Database>();
-    
-    for (label, confidence) in tags {
-        db.insert_tag(image_id, &label, confidence)
+) -> Result<trash::TrashReport, String> {
+    let move_files = app_handle
+        .state::<settings::SettingsManager>()
+        .get_settings()
+        .map(|s| s.trash_config.move_files)
+        .unwrap_or(false);
+
+    let trash_dir = if move_files {
+        let app_data_dir = app_handle
+            .path()
+            .app_data_dir()
             .map_err(|e| {
-                logging::log_error("tags", &format!("Failed to insert tag '{}' for image {}", label, image_id), &e);
+                logging::log_error("trash", "Failed to get app data directory", &e);
                 logging::user_friendly_error(&e)
             })?;
+        Some(app_data_dir.join("trash"))
+    } else {
+        None
+    };
+
+    let db = app_handle.state::<database::Database>();
+    let report = trash::move_to_trash(db.inner(), &image_ids, trash_dir.as_deref());
+
+    for error in &report.failed {
+        logging::log_warning("trash", &format!("Failed to trash {}: {}", error.path, error.error));
     }
-    
-    logging::log_debug("tags", &format!("Tags saved successfully for image ID: {}", image_id));
-    Ok(())
+    emit_smart_album_counts(&app_handle);
+
+    Ok(report)
 }
 
-/// Tauri command to search images with filters
+/// Tauri command to restore images from the trash
 #[tauri::command]
-fn search_images(
-    text: Option<String>,
-    tags: Option<Vec<String>>,
-    date_start: Option<String>,
-    date_end: Option<String>,
-    camera_model: Option<String>,
-    media_type: Option<String>,
+fn restore_images_from_trash(
+    image_ids: Vec<i64>,
     app_handle: tauri::AppHandle,
-) -> Result<Vec<database::ImageRecord>, String> {
-    logging::log_debug("search", "Executing image search");
-    
+) -> Result<trash::TrashReport, String> {
     let db = app_handle.state::<database::Database>();
-    
-    // Parse date range if provided
-    let date_range = if let (Some(start_str), Some(end_str)) = (date_start, date_end) {
-        let start = chrono::DateTime::parse_from_rfc3339(&start_str)
-            .map_err(|e| {
-                logging::log_warning("search", &format!("Invalid start date format: {}", e));
-                format!("Invalid start date: {}", e)
-            })?
-            .with_timezone(&chrono::Utc);
-        let end = chrono::DateTime::parse_from_rfc3339(&end_str)
-            .map_err(|e| {
-                logging::log_warning("search", &format!("Invalid end date format: {}", e));
-                format!("Invalid end date: {}", e)
-            })?
-            .with_timezone(&chrono::Utc);
-        Some((start, end))
-    } else {
-        None
-    };
-    
-    // Parse media type if provided
-    let parsed_media_type = media_type.and_then(|mt| {
-        match mt.to_lowercase().as_str() {
-            "image" => Some(database::MediaType::Image),
-            "video" => Some(database::MediaType::Video),
-            "all" | "" => None, // "all" means no filter
-            _ => {
-                logging::log_warning("search", &format!("Invalid media type: {}", mt));
-                None
-            }
-        }
-    });
-    
-    // Build filter
-    let filter = database::ImageFilter {
-        date_range,
-        location: None, // Location filtering not implemented yet
-        tags,
-        camera_model,
-        media_type: parsed_media_type,
-    };
-    
-    // Query database
-    let mut images = db.query_images(&filter)
-        .map_err(|e| {
-            logging::log_error("search", "Failed to query images", &e);
-            logging::user_friendly_error(&e)
-        })?;
-    
-    // If text search is provided, filter by text in tags (simple implementation)
-    if let Some(search_text) = text {
-        let search_lower = search_text.to_lowercase();
-        images.retain(|img| {
-            // Get tags for this image
-            if let Ok(img_tags) = db.get_tags_for_image(img.id) {
-                img_tags.iter().any(|tag| tag.label.to_lowercase().contains(&search_lower))
-            } else {
-                false
-            }
-        });
+    let report = trash::restore(db.inner(), &image_ids);
+
+    for error in &report.failed {
+        logging::log_warning("trash", &format!("Failed to restore {}: {}", error.path, error.error));
     }
-    
-    logging::log_debug("search", &format!("Search completed: {} results found", images.len()));
-    Ok(images)
+    emit_smart_album_counts(&app_handle);
+
+    Ok(report)
 }
 
-/// Tauri command to get tags for an image
+/// Tauri command to list the images in the trash, most recently trashed first
 #[tauri::command]
-fn get_image_tags(
-    image_id: i64,
+fn get_trashed_images(
     app_handle: tauri::AppHandle,
-) -> Result<Vec<database::Tag>, String> {
+) -> Result<Vec<database::ImageRecord>, String> {
     let db = app_handle.state::<database::Database>();
-    
-    db.get_tags_for_image(image_id)
+
+    db.get_trashed_images()
         .map_err(|e| {
-            logging::log_error("tags", &format!("Failed to get tags for image {}", image_id), &e);
+            logging::log_error("trash", "Failed to list trash", &e);
             logging::user_friendly_error(&e)
         })
 }
 
+/// Tauri command to permanently delete everything in the trash, including the files
+#[tauri::command]
+fn empty_trash(
+    app_handle: tauri::AppHandle,
+) -> Result<trash::TrashReport, String> {
+    let db = app_handle.state::<database::Database>();
+
+    let report = trash::empty_trash(db.inner()).map_err(|e| {
+        logging::log_warning("trash", &format!("Failed to empty trash: {}", e));
+        e
+    })?;
+
+    for error in &report.failed {
+        logging::log_warning("trash", &format!("Failed to delete {}: {}", error.path, error.error));
+    }
+    logging::log_info("trash", &format!("Emptied trash: {} items deleted", report.image_ids.len()));
+
+    Ok(report)
+}
+
+/// Permanently delete images that have been in the trash longer than the configured retention
+fn purge_expired_trash(app_handle: &tauri::AppHandle) {
+    let retention_days = app_handle
+        .state::<settings::SettingsManager>()
+        .get_settings()
+        .map(|s| s.trash_config.retention_days)
+        .unwrap_or_default();
+    let db = app_handle.state::<database::Database>();
+
+    match trash::purge_expired(db.inner(), retention_days) {
+        Ok(report) => {
+            for error in &report.failed {
+                logging::log_warning("trash", &format!("Failed to purge {}: {}", error.path, error.error));
+            }
+            if !report.image_ids.is_empty() {
+                logging::log_info("trash", &format!("Purged {} expired items from the trash", report.image_ids.len()));
+            }
+        }
+        Err(e) => logging::log_warning("trash", &format!("Failed to purge trash: {}", e)),
+    }
+}
+
 /// Tauri command to get an image by ID
 #[tauri::command]
 fn get_image_by_id(
@@ -292,6 +1152,7 @@ fn get_image_by_id(
 }
 
 /// Tauri command to save an embedding for an image
+/// Embeddings are stored as f32
 #[tauri::command]
 fn save_embedding(
     image_id: i64,
@@ -303,23 +1164,13 @@ fn save_embedding(
     
     let db = app_handle.state::<database::Database>();
     
-    // Convert embedding to bytes
-    let embedding_bytes: Vec<u8> = embedding
-        .iter()
-        .flat_map(|f| f.to_le_bytes())
-        .collect();
-    
-    let conn = db.connection().lock().unwrap();
+    let embedding: Vec<f32> = embedding.iter().map(|&f| f as f32).collect();
     
-    // Insert or replace embedding
-    conn.execute(
-        "INSERT OR REPLACE INTO embeddings (image_id, embedding, model_version) VALUES (?1, ?2, ?3)",
-        rusqlite::params![image_id, embedding_bytes, model_version],
-    )
-    .map_err(|e| {
-        logging::log_error("embeddings", &format!("Failed to save embedding for image {}", image_id), &e);
-        logging::user_friendly_error(&e)
-    })?;
+    db.save_embedding(image_id, &embedding, &model_version)
+        .map_err(|e| {
+            logging::log_error("embeddings", &format!("Failed to save embedding for image {}", image_id), &e);
+            logging::user_friendly_error(&e)
+        })?;
     
     logging::log_debug("embeddings", &format!("Embedding saved successfully for image ID: {}", image_id));
     Ok(())
@@ -333,46 +1184,57 @@ fn get_all_embeddings(
     logging::log_debug("embeddings", "Retrieving all embeddings");
     
     let db = app_handle.state::<database::Database>();
-    let conn = db.connection().lock().unwrap();
-    
-    let mut stmt = conn
-        .prepare("SELECT image_id, embedding FROM embeddings")
-        .map_err(|e| {
-            logging::log_error("embeddings", "Failed to prepare statement", &e);
-            logging::user_friendly_error(&e)
-        })?;
     
-    let embeddings = stmt
-        .query_map([], |row| {
-            let image_id: i64 = row.get(0)?;
-            let embedding_bytes: Vec<u8> = row.get(1)?;
-            
-            // Convert bytes back to f64 vector
-            let embedding: Vec<f64> = embedding_bytes
-                .chunks_exact(8)
-                .map(|chunk| {
-                    let bytes: [u8; 8] = chunk.try_into().unwrap();
-                    f64::from_le_bytes(bytes)
-                })
-                .collect();
-            
-            Ok((image_id, embedding))
-        })
+    let embeddings = db.get_embeddings(None)
         .map_err(|e| {
             logging::log_error("embeddings", "Failed to query embeddings", &e);
             logging::user_friendly_error(&e)
         })?
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|e| {
-            let io_error = std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to collect embeddings: {}", e));
-            logging::log_error("embeddings", "Failed to collect embeddings", &io_error);
-            logging::user_friendly_error(&io_error)
-        })?;
+        .into_iter()
+        .map(|(image_id, embedding)| (image_id, embedding.into_iter().map(f64::from).collect()))
+        .collect::<Vec<_>>();
     
     logging::log_debug("embeddings", &format!("Retrieved {} embeddings", embeddings.len()));
     Ok(embeddings)
 }
 
+/// Tauri command to find the images most similar to an image or an embedding vector
+/// Exactly one of `image_id` and `vector` must be given; results are ordered by descending score
+#[tauri::command]
+async fn find_similar(
+    image_id: Option<i64>,
+    vector: Option<Vec<f32>>,
+    k: usize,
+    model_version: String,
+    app_handle: tauri::AppHandle,
+) -> Result<Vec<similarity::SimilarImage>, String> {
+    logging::log_debug("embeddings", &format!("Finding {} similar images for model {}", k, model_version));
+
+    let handle = app_handle.clone();
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let db = handle.state::<database::Database>();
+        let index = handle.state::<similarity::EmbeddingIndex>();
+
+        match (image_id, vector) {
+            (Some(image_id), None) => index.find_similar_to_image(db.inner(), image_id, k, &model_version),
+            (None, Some(vector)) => index.find_similar(db.inner(), &vector, k, &model_version),
+            _ => Err("Provide either an image ID or a vector".to_string()),
+        }
+    })
+    .await
+    .map_err(|e| {
+        let io_error = std::io::Error::new(std::io::ErrorKind::Other, e.to_string());
+        logging::log_error("embeddings", "Similarity search task failed", &io_error);
+        logging::user_friendly_error(&io_error)
+    })?;
+
+    result.map_err(|e| {
+        let io_error = std::io::Error::new(std::io::ErrorKind::Other, e.clone());
+        logging::log_error("embeddings", "Failed to find similar images", &io_error);
+        e
+    })
+}
+
 /// Tauri command to authenticate with Google Drive
 #[tauri::command]
 async fn authenticate_google_drive(
@@ -380,13 +1242,7 @@ async fn authenticate_google_drive(
 ) -> Result<auth::AuthStatus, String> {
     logging::log_info("auth", "Starting Google Drive authentication");
     
-    // For now, use placeholder credentials - in production, these should be configured
-    let client_id = std::env::var("GOOGLE_CLIENT_ID")
-        .unwrap_or_else(|_| "placeholder_client_id".to_string());
-    let client_secret = std::env::var("GOOGLE_CLIENT_SECRET")
-        .unwrap_or_else(|_| "placeholder_client_secret".to_string());
-
-    let auth = auth::GoogleDriveAuth::new(client_id, client_secret);
+    let auth = google_drive_auth();
 
     // Start OAuth flow and get authorization URL
     let auth_url = auth.start_auth_flow().map_err(|e| {
@@ -447,12 +1303,7 @@ async fn handle_oauth_callback(
 /// Tauri command to check if user is authenticated
 #[tauri::command]
 fn is_authenticated(_app_handle: tauri::AppHandle) -> Result<bool, String> {
-    let client_id = std::env::var("GOOGLE_CLIENT_ID")
-        .unwrap_or_else(|_| "placeholder_client_id".to_string());
-    let client_secret = std::env::var("GOOGLE_CLIENT_SECRET")
-        .unwrap_or_else(|_| "placeholder_client_secret".to_string());
-
-    let auth = auth::GoogleDriveAuth::new(client_id, client_secret);
+    let auth = google_drive_auth();
 
     match auth.get_tokens() {
         Ok(_) => Ok(true),
@@ -460,23 +1311,47 @@ fn is_authenticated(_app_handle: tauri::AppHandle) -> Result<bool, String> {
     }
 }
 
-/// Tauri command to sync images to Google Drive
-#[tauri::command]
-async fn sync_to_drive(
-    image_ids: Vec<i64>,
-    app_handle: tauri::AppHandle,
-) -> Result<sync::SyncResult, String> {
-    logging::log_info("sync", &format!("Starting sync of {} images to Google Drive", image_ids.len()));
-    
+/// Google Drive credentials, with placeholders until real ones are configured
+fn google_drive_auth() -> auth::GoogleDriveAuth {
     let client_id = std::env::var("GOOGLE_CLIENT_ID")
         .unwrap_or_else(|_| "placeholder_client_id".to_string());
     let client_secret = std::env::var("GOOGLE_CLIENT_SECRET")
         .unwrap_or_else(|_| "placeholder_client_secret".to_string());
 
-    let auth = std::sync::Arc::new(auth::GoogleDriveAuth::new(client_id, client_secret));
+    auth::GoogleDriveAuth::new(client_id, client_secret)
+}
+
+/// Upload images to a storage provider, emitting "sync-progress" events
+async fn sync_images(
+    provider: std::sync::Arc<dyn storage::StorageProvider>,
+    image_ids: Vec<i64>,
+    app_handle: tauri::AppHandle,
+) -> Result<sync::SyncResult, String> {
+    logging::log_info("sync", &format!("Starting sync of {} images to {}", image_ids.len(), provider.name()));
+
     let db = app_handle.state::<database::Database>();
 
-    let sync_manager = sync::CloudSyncManager::new(auth, db.inner());
+    let settings = app_handle
+        .state::<settings::SettingsManager>()
+        .get_settings()?;
+    let quality = rendition::UploadQuality::from_setting(&settings.sync_config.upload_quality)?;
+    let rendition_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| {
+            logging::log_error("sync", "Failed to get app data directory", &e);
+            logging::user_friendly_error(&e)
+        })?
+        .join("renditions");
+
+    let exclude = sync::ExcludeFilter::skipping_invalid(
+        &settings.sync_config.exclude_patterns,
+        settings.sync_config.exclude_case_sensitive,
+    );
+
+    let sync_manager = sync::CloudSyncManager::new(provider, db.inner())
+        .with_upload_quality(quality, rendition_dir)
+        .with_exclude_filter(exclude);
 
     // Create progress callback that emits events
     let app_handle_clone = app_handle.clone();
@@ -484,7 +1359,7 @@ async fn sync_to_drive(
         let _ = app_handle_clone.emit("sync-progress", progress);
     };
 
-    match sync_manager.sync_to_drive(image_ids, progress_callback).await {
+    match sync_manager.sync(image_ids, progress_callback).await {
         Ok(result) => {
             logging::log_info("sync", &format!("Sync completed: {} uploaded, {} skipped, {} failed", 
                 result.uploaded, result.skipped, result.failed.len()));
@@ -504,6 +1379,73 @@ async fn sync_to_drive(
     }
 }
 
+/// Tauri command to sync images to Google Drive
+#[tauri::command]
+async fn sync_to_drive(
+    image_ids: Vec<i64>,
+    app_handle: tauri::AppHandle,
+) -> Result<sync::SyncResult, String> {
+    let auth = std::sync::Arc::new(google_drive_auth());
+    let provider = std::sync::Arc::new(storage::GoogleDriveProvider::new(auth));
+
+    sync_images(provider, image_ids, app_handle).await
+}
+
+/// Tauri command to sync images to the storage backend chosen in the sync settings
+#[tauri::command]
+async fn sync_to_storage(
+    image_ids: Vec<i64>,
+    app_handle: tauri::AppHandle,
+) -> Result<sync::SyncResult, String> {
+    let settings = app_handle
+        .state::<settings::SettingsManager>()
+        .get_settings()?;
+
+    let auth = std::sync::Arc::new(google_drive_auth());
+    let provider = storage::create_provider(&settings.sync_config.storage, auth).map_err(|e| {
+        let io_error = std::io::Error::new(std::io::ErrorKind::Other, e.clone());
+        logging::log_error("sync", "Failed to set up storage backend", &io_error);
+        e
+    })?;
+
+    sync_images(provider, image_ids, app_handle).await
+}
+
+/// Tauri command to save the password of the configured storage backend in the system keychain
+#[tauri::command]
+fn set_storage_secret(
+    secret: String,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    let settings = app_handle
+        .state::<settings::SettingsManager>()
+        .get_settings()?;
+
+    storage::save_secret(&settings.sync_config.storage, &secret)
+}
+
+/// Tauri command to get the state of the background sync
+#[tauri::command]
+fn get_sync_status(app_handle: tauri::AppHandle) -> Result<scheduler::SyncSchedulerStatus, String> {
+    app_handle.state::<scheduler::SyncScheduler>().status()
+}
+
+/// Tauri command to pause the background sync after the current batch
+#[tauri::command]
+fn pause_sync(app_handle: tauri::AppHandle) -> Result<(), String> {
+    logging::log_info("sync", "Pausing background sync");
+    app_handle.state::<scheduler::SyncScheduler>().pause();
+    Ok(())
+}
+
+/// Tauri command to resume the background sync, syncing what queued up while paused
+#[tauri::command]
+fn resume_sync(app_handle: tauri::AppHandle) -> Result<(), String> {
+    logging::log_info("sync", "Resuming background sync");
+    app_handle.state::<scheduler::SyncScheduler>().resume();
+    Ok(())
+}
+
 /// Tauri command to get current settings
 #[tauri::command]
 fn get_settings(
@@ -530,13 +1472,36 @@ fn save_settings(
     logging::log_info("settings", "Saving settings");
     
     let settings_manager = app_handle.state::<settings::SettingsManager>();
+    let old_sync_config = settings_manager.get_settings()?.sync_config;
+    let exclusions_changed = old_sync_config.exclude_patterns != new_settings.sync_config.exclude_patterns
+        || old_sync_config.exclude_case_sensitive != new_settings.sync_config.exclude_case_sensitive;
+    let storage_changed = old_sync_config.storage != new_settings.sync_config.storage;
     
     settings_manager.save_settings(new_settings)
         .map_err(|e| {
             let io_error = std::io::Error::new(std::io::ErrorKind::Other, e.clone());
             logging::log_error("settings", "Failed to save settings", &io_error);
             e // Return original error message for validation errors
-        })
+        })?;
+
+    let db = app_handle.state::<database::Database>();
+
+    // Files excluded by the old patterns may be included now
+    if exclusions_changed {
+        if let Err(e) = db.requeue_excluded_images() {
+            logging::log_error("settings", "Failed to requeue excluded images", &e);
+        }
+    }
+
+    // Files synced to the old storage backend still have to reach the new one
+    if storage_changed {
+        if let Err(e) = db.requeue_synced_images() {
+            logging::log_error("settings", "Failed to requeue synced images", &e);
+        }
+    }
+
+    app_handle.state::<scheduler::SyncScheduler>().settings_changed();
+    Ok(())
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -550,18 +1515,58 @@ pub fn run() {
       extract_video_metadata,
       generate_thumbnails,
       generate_video_thumbnails,
+      import_folder,
+      rescan_folder,
+      watch_folder,
+      unwatch_folder,
+      get_watched_folders,
+      find_exact_duplicates,
+      find_near_duplicates,
       get_codec_performance_metrics,
       reset_codec_performance_metrics,
       save_tags,
       search_images,
+      get_images_in_bounds,
+      create_album,
+      get_albums,
+      create_smart_album,
+      update_smart_album,
+      get_smart_album_counts,
+      set_images_rating,
+      set_images_color_label,
+      set_images_flag,
+      set_images_favorite,
+      set_images_description,
+      write_xmp_metadata,
+      get_edit_recipe,
+      set_edit_recipe,
+      export_images,
+      move_images_to_trash,
+      restore_images_from_trash,
+      get_trashed_images,
+      empty_trash,
+      rename_album,
+      set_album_cover,
+      move_album,
+      delete_album,
+      add_images_to_album,
+      remove_images_from_album,
+      get_album_images,
+      reorder_album_images,
       get_image_tags,
       get_image_by_id,
       save_embedding,
       get_all_embeddings,
+      find_similar,
       authenticate_google_drive,
       handle_oauth_callback,
       is_authenticated,
       sync_to_drive,
+      sync_to_storage,
+      set_storage_secret,
+      get_sync_status,
+      pause_sync,
+      resume_sync,
       get_settings,
       save_settings,
       settings::get_format_config,
@@ -615,6 +1620,9 @@ pub fn run() {
       // Store database in app state
       app.manage(db);
 
+      // Similarity index is loaded lazily from the embeddings table on first search
+      app.manage(similarity::EmbeddingIndex::new());
+
       // Initialize settings manager
       let config_path = app_data_dir.join("config.json");
       let settings_manager = settings::SettingsManager::new(config_path)
@@ -636,6 +1644,120 @@ pub fn run() {
       // Store settings manager in app state
       app.manage(settings_manager);
 
+      // Sync queued images in the background, as configured in the sync settings
+      let sync_config_handle = app.handle().clone();
+      let sync_queue_handle = app.handle().clone();
+      let sync_handle = app.handle().clone();
+      let sync_scheduler = scheduler::SyncScheduler::new(
+        move || {
+          sync_config_handle
+            .state::<settings::SettingsManager>()
+            .get_settings()
+            .map(|s| s.sync_config)
+            .unwrap_or_default()
+        },
+        move || {
+          sync_queue_handle
+            .state::<database::Database>()
+            .get_unsynced_image_ids()
+            .map_err(|e| format!("Failed to read sync queue: {}", e))
+        },
+        move |image_ids| {
+          let settings = sync_handle.state::<settings::SettingsManager>().get_settings()?;
+          let auth = std::sync::Arc::new(google_drive_auth());
+          let provider = storage::create_provider(&settings.sync_config.storage, auth)?;
+          tauri::async_runtime::block_on(sync_images(provider, image_ids, sync_handle.clone()))
+        },
+      );
+      app.manage(sync_scheduler);
+
+      // Start watching library folders, applying changes on the watcher's own thread
+      let config_handle = app.handle().clone();
+      let changes_handle = app.handle().clone();
+      let cache_dir = app_data_dir.join("thumbnails");
+      let library_watcher = watcher::LibraryWatcher::new(
+        move || {
+          config_handle
+            .state::<settings::SettingsManager>()
+            .get_settings()
+            .map(|s| s.format_config)
+            .unwrap_or_default()
+        },
+        move |changes| {
+          let config = changes_handle
+            .state::<settings::SettingsManager>()
+            .get_settings()
+            .map(|s| s.format_config)
+            .unwrap_or_default();
+          let db = changes_handle.state::<database::Database>();
+          let report = watcher::apply_changes(&changes, &config, &cache_dir, db.inner());
+
+          for error in &report.failed {
+            logging::log_warning("watcher", &format!("Auto-import failed for {}: {}", error.path, error.error));
+          }
+          let _ = changes_handle.emit("library-changed", report);
+          emit_smart_album_counts(&changes_handle);
+          changes_handle.state::<scheduler::SyncScheduler>().library_changed();
+        },
+      )
+      .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+      let watched_folders = app
+        .state::<settings::SettingsManager>()
+        .get_settings()
+        .map(|s| s.watched_folders)
+        .unwrap_or_default();
+      for folder in &watched_folders {
+        if let Err(e) = library_watcher.watch(folder) {
+          logging::log_warning("watcher", &e);
+        }
+      }
+      app.manage(library_watcher);
+
+      // Catch up on anything that changed while the app was closed
+      let rescan_handle = app.handle().clone();
+      let rescan_cache_dir = app_data_dir.join("thumbnails");
+      tauri::async_runtime::spawn_blocking(move || {
+        let config = rescan_handle
+          .state::<settings::SettingsManager>()
+          .get_settings()
+          .ok()
+          .map(|s| s.format_config);
+        let db = rescan_handle.state::<database::Database>();
+
+        for folder in watched_folders {
+          match importer::rescan_folder(&folder, config.clone(), &rescan_cache_dir, db.inner(), |_| {}) {
+            Ok(report) => {
+              let _ = rescan_handle.emit("library-changed", report);
+            }
+            Err(e) => logging::log_warning("watcher", &format!("Startup rescan of {} failed: {}", folder, e)),
+          }
+        }
+
+        // Read what newer versions extract from files imported before them
+        match importer::backfill_metadata(db.inner()) {
+          Ok(0) => {}
+          Ok(updated) => logging::log_info("metadata", &format!("Read metadata of {} files again", updated)),
+          Err(e) => logging::log_warning("metadata", &format!("Failed to read metadata again: {}", e)),
+        }
+
+        // Name places for geotagged images imported before geocoding existed
+        match geocoder::backfill_places(db.inner()) {
+          Ok(0) => {}
+          Ok(named) => logging::log_info("geocoder", &format!("Named places for {} images", named)),
+          Err(e) => logging::log_warning("geocoder", &format!("Failed to name places: {}", e)),
+        }
+        emit_smart_album_counts(&rescan_handle);
+        rescan_handle.state::<scheduler::SyncScheduler>().library_changed();
+      });
+
+      // Purge the trash at startup and then a few times a day
+      let purge_handle = app.handle().clone();
+      std::thread::spawn(move || loop {
+        purge_expired_trash(&purge_handle);
+        std::thread::sleep(std::time::Duration::from_secs(6 * 60 * 60));
+      });
+
       // Initialize performance metrics
       let metrics = std::sync::Arc::new(performance::PerformanceMetrics::new());
       app.manage(metrics);
@@ -7,6 +7,14 @@ use std::path::Path;
 use std::process::Command;
 use log::{error, info};
 
+use crate::raw;
+
+/// Version of what extraction reads from files, stored with each library record
+///
+/// Bump it when extraction learns to read something new, so that files
+/// imported earlier are read again by `importer::backfill_metadata`.
+pub const METADATA_VERSION: i64 = 1;
+
 /// Media metadata extracted from EXIF/video metadata and file system
 /// This struct handles both images and videos
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -35,6 +43,44 @@ pub struct ImageMetadata {
     pub file_size: u64,
     /// File modified timestamp
     pub file_modified: DateTime<Utc>,
+    /// Exposure, lens and flash settings; videos only have the UTC offset
+    pub capture_settings: CaptureSettings,
+    /// Frame rate, rotation and audio track (empty for images)
+    pub video_properties: VideoProperties,
+}
+
+/// Camera settings a photo was taken with, from EXIF
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct CaptureSettings {
+    /// Exposure time in seconds
+    pub exposure_time: Option<f64>,
+    /// Aperture as an f-number
+    pub aperture: Option<f64>,
+    pub iso: Option<u32>,
+    /// Focal length in millimeters
+    pub focal_length: Option<f64>,
+    /// Focal length on a 35mm full frame camera with the same field of view
+    pub focal_length_35mm: Option<f64>,
+    pub lens_model: Option<String>,
+    /// Whether the flash fired
+    pub flash: Option<bool>,
+    /// EXIF orientation, 1-8
+    pub orientation: Option<u16>,
+    /// Offset of the camera's local time from UTC when the photo was taken, in minutes
+    pub utc_offset_minutes: Option<i32>,
+}
+
+/// Properties of a video's streams, from ffprobe
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct VideoProperties {
+    /// Frames per second
+    pub frame_rate: Option<f64>,
+    /// Overall bitrate in bits per second
+    pub bit_rate: Option<u64>,
+    /// Clockwise rotation applied when playing, in degrees
+    pub rotation: Option<u16>,
+    pub audio_codec: Option<String>,
+    pub audio_channels: Option<u32>,
 }
 
 /// Extract metadata from an image file
@@ -65,27 +111,39 @@ pub fn extract_metadata(image_path: &str) -> Result<ImageMetadata, String> {
 
     let exif_result = Reader::new().read_from_container(&mut bufreader);
 
-    let (capture_date, camera_make, camera_model, gps_latitude, gps_longitude, width, height) =
+    // RAW files describe the sensor image in their own IFDs
+    let raw_dimensions = if raw::is_raw_path(path) {
+        raw::image_dimensions(path)
+    } else {
+        None
+    };
+
+    let (capture_date, camera_make, camera_model, gps_latitude, gps_longitude, width, height, capture_settings) =
         match exif_result {
             Ok(exif) => {
-                // Extract capture date
-                let capture_date = extract_datetime(&exif).or_else(|| Some(file_modified));
+                // Extract capture date, in UTC when the camera recorded its offset
+                let (capture_date, utc_offset_minutes) = match extract_datetime(&exif) {
+                    Some((date, offset)) => (Some(date), offset),
+                    None => (Some(file_modified), None),
+                };
 
                 // Extract camera make
-                let camera_make = exif
-                    .get_field(Tag::Make, In::PRIMARY)
-                    .map(|f| f.display_value().to_string());
+                let camera_make = extract_ascii(&exif, Tag::Make);
 
                 // Extract camera model
-                let camera_model = exif
-                    .get_field(Tag::Model, In::PRIMARY)
-                    .map(|f| f.display_value().to_string());
+                let camera_model = extract_ascii(&exif, Tag::Model);
 
                 // Extract GPS coordinates
                 let (gps_latitude, gps_longitude) = extract_gps_coordinates(&exif);
 
                 // Extract image dimensions
-                let (width, height) = extract_dimensions(&exif);
+                let (width, height) = extract_dimensions(&exif, raw_dimensions);
+
+                // Extract exposure and lens settings
+                let capture_settings = CaptureSettings {
+                    utc_offset_minutes,
+                    ..extract_capture_settings(&exif)
+                };
 
                 (
                     capture_date,
@@ -95,11 +153,13 @@ pub fn extract_metadata(image_path: &str) -> Result<ImageMetadata, String> {
                     gps_longitude,
                     width,
                     height,
+                    capture_settings,
                 )
             }
             Err(_) => {
                 // No EXIF data, use fallback values
-                (Some(file_modified), None, None, None, None, 0, 0)
+                let (width, height) = raw_dimensions.unwrap_or((0, 0));
+                (Some(file_modified), None, None, None, None, width, height, CaptureSettings::default())
             }
         };
 
@@ -116,6 +176,8 @@ pub fn extract_metadata(image_path: &str) -> Result<ImageMetadata, String> {
         video_codec: None,
         file_size,
         file_modified,
+        capture_settings,
+        video_properties: VideoProperties::default(),
     })
 }
 
@@ -144,12 +206,12 @@ pub fn extract_video_metadata(video_path: &str) -> Result<ImageMetadata, String>
     // Use FFmpeg to extract video metadata
     info!("Extracting video metadata from: {}", video_path);
     
+    // All streams are read, so the audio track is described too
     let output = Command::new("ffprobe")
         .args([
             "-v", "error",
-            "-select_streams", "v:0",
-            "-show_entries", "stream=width,height,codec_name,duration",
-            "-show_entries", "format=duration",
+            "-show_streams",
+            "-show_format",
             "-of", "json",
             video_path,
         ])
@@ -171,15 +233,32 @@ pub fn extract_video_metadata(video_path: &str) -> Result<ImageMetadata, String>
     let json: serde_json::Value = serde_json::from_str(&stdout)
         .map_err(|e| format!("Failed to parse ffprobe output: {}", e))?;
 
-    // Extract video stream information
+    let metadata = parse_ffprobe_output(&json, video_path, file_size, file_modified)?;
+
+    info!(
+        "Extracted video metadata: {}x{}, codec: {:?}, duration: {:?}s",
+        metadata.width, metadata.height, metadata.video_codec, metadata.duration_seconds
+    );
+
+    Ok(metadata)
+}
+
+/// Build video metadata from ffprobe's JSON description of its streams and container
+fn parse_ffprobe_output(
+    json: &serde_json::Value,
+    video_path: &str,
+    file_size: u64,
+    file_modified: DateTime<Utc>,
+) -> Result<ImageMetadata, String> {
     let streams = json["streams"].as_array()
         .ok_or_else(|| "No video streams found in file".to_string())?;
-    
-    if streams.is_empty() {
-        return Err("No video streams found in file".to_string());
-    }
+    let stream_of_type = |codec_type: &str| {
+        streams.iter().find(|stream| stream["codec_type"].as_str() == Some(codec_type))
+    };
 
-    let video_stream = &streams[0];
+    let video_stream = stream_of_type("video")
+        .ok_or_else(|| "No video streams found in file".to_string())?;
+    let audio_stream = stream_of_type("audio");
 
     // Extract dimensions
     let width = video_stream["width"].as_u64()
@@ -200,48 +279,219 @@ pub fn extract_video_metadata(video_path: &str) -> Result<ImageMetadata, String>
                 .and_then(|s| s.parse::<f64>().ok())
         });
 
-    info!(
-        "Extracted video metadata: {}x{}, codec: {:?}, duration: {:?}s",
-        width, height, video_codec, duration_seconds
-    );
+    // Phones record upright videos as landscape frames with a rotation to apply
+    let rotation = video_rotation(video_stream);
+    let (width, height) = match rotation {
+        Some(90) | Some(270) => (height, width),
+        _ => (width, height),
+    };
+
+    // Container tags hold the camera, location and recording time
+    let format_tag = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|key| json["format"]["tags"][key].as_str())
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+
+    let camera_make = format_tag(&["com.apple.quicktime.make", "com.android.manufacturer", "make"]);
+    let camera_model = format_tag(&["com.apple.quicktime.model", "com.android.model", "model"]);
+
+    let location = format_tag(&["com.apple.quicktime.location.ISO6709", "location", "location-eng"])
+        .and_then(|location| parse_iso6709(&location));
+    let (gps_latitude, gps_longitude) = location.unzip();
+
+    // Apple's creation date keeps the local offset, creation_time is always UTC
+    let local_creation_date = format_tag(&["com.apple.quicktime.creationdate"])
+        .and_then(|date| DateTime::parse_from_str(&date, "%Y-%m-%dT%H:%M:%S%z").ok());
+    let creation_time = format_tag(&["creation_time"])
+        .and_then(|date| DateTime::parse_from_rfc3339(&date).ok())
+        .map(|date| date.with_timezone(&Utc));
+    // Cameras without a clock write the 1904 or 1970 epoch
+    let capture_date = local_creation_date
+        .map(|date| date.with_timezone(&Utc))
+        .or(creation_time)
+        .filter(|date| date.timestamp() > 0)
+        .unwrap_or(file_modified);
+
+    let capture_settings = CaptureSettings {
+        utc_offset_minutes: local_creation_date.map(|date| date.offset().local_minus_utc() / 60),
+        ..Default::default()
+    };
+
+    let stream_number = |stream: &serde_json::Value, key: &str| {
+        stream[key].as_str().and_then(|s| s.parse::<u64>().ok())
+    };
+
+    let video_properties = VideoProperties {
+        frame_rate: parse_frame_rate(&video_stream["avg_frame_rate"])
+            .or_else(|| parse_frame_rate(&video_stream["r_frame_rate"])),
+        bit_rate: stream_number(&json["format"], "bit_rate")
+            .or_else(|| stream_number(video_stream, "bit_rate")),
+        rotation,
+        audio_codec: audio_stream
+            .and_then(|stream| stream["codec_name"].as_str())
+            .map(|s| s.to_string()),
+        audio_channels: audio_stream
+            .and_then(|stream| stream["channels"].as_u64())
+            .map(|channels| channels as u32),
+    };
 
     Ok(ImageMetadata {
         path: video_path.to_string(),
-        capture_date: Some(file_modified), // Use file modified as capture date
-        camera_make: None,
-        camera_model: None,
-        gps_latitude: None,
-        gps_longitude: None,
+        capture_date: Some(capture_date),
+        camera_make,
+        camera_model,
+        gps_latitude,
+        gps_longitude,
         width,
         height,
         duration_seconds,
         video_codec,
         file_size,
         file_modified,
+        capture_settings,
+        video_properties,
     })
 }
 
-/// Extract datetime from EXIF data
-fn extract_datetime(exif: &exif::Exif) -> Option<DateTime<Utc>> {
-    // Try DateTimeOriginal first (when photo was taken)
-    if let Some(field) = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY) {
-        let datetime_str = field.display_value().to_string();
-        if let Some(parsed) = parse_exif_datetime(&datetime_str) {
-            return Some(parsed);
-        }
+/// Clockwise rotation of a video stream in degrees, from its display matrix or `rotate` tag
+fn video_rotation(stream: &serde_json::Value) -> Option<u16> {
+    // The display matrix rotation is counterclockwise
+    let side_data = stream["side_data_list"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find_map(|data| data["rotation"].as_f64())
+        .map(|rotation| -rotation);
+    let tag = stream["tags"]["rotate"].as_str().and_then(|s| s.parse::<f64>().ok());
+
+    side_data
+        .or(tag)
+        .map(|degrees| (degrees.round() as i64).rem_euclid(360) as u16)
+}
+
+/// Parse an ffprobe frame rate such as "30000/1001"
+fn parse_frame_rate(value: &serde_json::Value) -> Option<f64> {
+    let (numerator, denominator) = value.as_str()?.split_once('/')?;
+    let rate = numerator.parse::<f64>().ok()? / denominator.parse::<f64>().ok()?;
+    Some(rate).filter(|rate| rate.is_finite() && *rate > 0.0)
+}
+
+/// Parse an ISO 6709 location such as "+37.3349-122.0090+012.120/" into latitude and longitude
+fn parse_iso6709(location: &str) -> Option<(f64, f64)> {
+    let location = location.trim_end_matches('/');
+
+    // Each coordinate starts with its sign
+    let mut starts: Vec<usize> = location.match_indices(['+', '-']).map(|(i, _)| i).collect();
+    if starts.first() != Some(&0) || starts.len() < 2 {
+        return None;
     }
+    starts.push(location.len());
 
-    // Try DateTime as fallback
-    if let Some(field) = exif.get_field(Tag::DateTime, In::PRIMARY) {
-        let datetime_str = field.display_value().to_string();
-        if let Some(parsed) = parse_exif_datetime(&datetime_str) {
-            return Some(parsed);
-        }
+    let coordinate = |i: usize| location[starts[i]..starts[i + 1]].parse::<f64>().ok();
+    let latitude = coordinate(0).filter(|lat| (-90.0..=90.0).contains(lat))?;
+    let longitude = coordinate(1).filter(|lon| (-180.0..=180.0).contains(lon))?;
+
+    Some((latitude, longitude))
+}
+
+/// Extract datetime from EXIF data, with the offset from UTC it was recorded in
+///
+/// EXIF dates are local camera time. They are converted to UTC with the matching
+/// offset tag when the camera wrote one, and taken as UTC otherwise.
+fn extract_datetime(exif: &exif::Exif) -> Option<(DateTime<Utc>, Option<i32>)> {
+    // Try DateTimeOriginal first (when photo was taken), then DateTime as fallback
+    for (date_tag, offset_tag) in [
+        (Tag::DateTimeOriginal, Tag::OffsetTimeOriginal),
+        (Tag::DateTime, Tag::OffsetTime),
+    ] {
+        let Some(parsed) = extract_ascii(exif, date_tag).and_then(|s| parse_exif_datetime(&s)) else {
+            continue;
+        };
+
+        let offset = extract_ascii(exif, offset_tag).and_then(|s| parse_utc_offset(&s));
+        let utc = parsed - chrono::Duration::minutes(offset.unwrap_or(0) as i64);
+        return Some((utc, offset));
     }
 
     None
 }
 
+/// Parse an EXIF offset time (format: "+HH:MM" or "-HH:MM") into minutes
+fn parse_utc_offset(offset_str: &str) -> Option<i32> {
+    let sign = match offset_str.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+
+    let (hours, minutes) = offset_str[1..].split_once(':')?;
+    let hours = hours.parse::<i32>().ok()?;
+    let minutes = minutes.parse::<i32>().ok()?;
+    if hours > 14 || minutes >= 60 {
+        return None;
+    }
+
+    Some(sign * (hours * 60 + minutes))
+}
+
+/// Extract exposure, lens, flash and orientation settings from EXIF data
+fn extract_capture_settings(exif: &exif::Exif) -> CaptureSettings {
+    // Fall back to the APEX aperture value when the f-number is missing
+    let aperture = extract_rational(exif, Tag::FNumber).or_else(|| {
+        extract_rational(exif, Tag::ApertureValue)
+            .map(|apex| (2f64.powf(apex / 2.0) * 10.0).round() / 10.0)
+    });
+
+    CaptureSettings {
+        exposure_time: extract_rational(exif, Tag::ExposureTime),
+        aperture,
+        iso: extract_uint(exif, Tag::PhotographicSensitivity).filter(|&iso| iso > 0),
+        focal_length: extract_rational(exif, Tag::FocalLength),
+        // 0 means the camera doesn't know the equivalent focal length
+        focal_length_35mm: extract_uint(exif, Tag::FocalLengthIn35mmFilm)
+            .filter(|&f| f > 0)
+            .map(|f| f as f64),
+        lens_model: extract_ascii(exif, Tag::LensModel),
+        // Bit 0 of the flash value tells whether it fired
+        flash: extract_uint(exif, Tag::Flash).map(|flash| flash & 1 == 1),
+        orientation: extract_uint(exif, Tag::Orientation)
+            .filter(|o| (1..=8).contains(o))
+            .map(|o| o as u16),
+        utc_offset_minutes: None,
+    }
+}
+
+/// Extract a positive rational EXIF field as a decimal
+fn extract_rational(exif: &exif::Exif, tag: Tag) -> Option<f64> {
+    let field = exif.get_field(tag, In::PRIMARY)?;
+    let value = match field.value {
+        Value::Rational(ref v) => v.first()?.to_f64(),
+        Value::SRational(ref v) => v.first()?.to_f64(),
+        _ => return None,
+    };
+    Some(value).filter(|v| v.is_finite() && *v > 0.0)
+}
+
+/// Extract an unsigned integer EXIF field, whichever width it was written with
+fn extract_uint(exif: &exif::Exif, tag: Tag) -> Option<u32> {
+    exif.get_field(tag, In::PRIMARY)?.value.get_uint(0)
+}
+
+/// Extract the raw text of an ASCII EXIF field
+/// `display_value` quotes strings and reformats dates, so it can't be used for stored values
+fn extract_ascii(exif: &exif::Exif, tag: Tag) -> Option<String> {
+    let field = exif.get_field(tag, In::PRIMARY)?;
+    match field.value {
+        Value::Ascii(ref v) => v
+            .first()
+            .map(|bytes| String::from_utf8_lossy(bytes).trim_end_matches('\0').trim().to_string())
+            .filter(|s| !s.is_empty()),
+        _ => None,
+    }
+}
+
 /// Parse EXIF datetime string (format: "YYYY:MM:DD HH:MM:SS")
 fn parse_exif_datetime(datetime_str: &str) -> Option<DateTime<Utc>> {
     // EXIF datetime format: "YYYY:MM:DD HH:MM:SS"
@@ -309,25 +559,24 @@ fn extract_gps_coordinate(
 }
 
 /// Extract image dimensions from EXIF data
-fn extract_dimensions(exif: &exif::Exif) -> (u32, u32) {
-    let width = exif
-        .get_field(Tag::PixelXDimension, In::PRIMARY)
-        .or_else(|| exif.get_field(Tag::ImageWidth, In::PRIMARY))
-        .and_then(|f| match f.value {
+/// `raw_dimensions` replaces IFD0's image size, which is only a thumbnail in RAW files
+fn extract_dimensions(exif: &exif::Exif, raw_dimensions: Option<(u32, u32)>) -> (u32, u32) {
+    let value = |tag: Tag| {
+        exif.get_field(tag, In::PRIMARY).and_then(|f| match f.value {
             Value::Short(ref v) if !v.is_empty() => Some(v[0] as u32),
             Value::Long(ref v) if !v.is_empty() => Some(v[0]),
             _ => None,
         })
+    };
+
+    let width = value(Tag::PixelXDimension)
+        .or(raw_dimensions.map(|d| d.0))
+        .or_else(|| value(Tag::ImageWidth))
         .unwrap_or(0);
 
-    let height = exif
-        .get_field(Tag::PixelYDimension, In::PRIMARY)
-        .or_else(|| exif.get_field(Tag::ImageLength, In::PRIMARY))
-        .and_then(|f| match f.value {
-            Value::Short(ref v) if !v.is_empty() => Some(v[0] as u32),
-            Value::Long(ref v) if !v.is_empty() => Some(v[0]),
-            _ => None,
-        })
+    let height = value(Tag::PixelYDimension)
+        .or(raw_dimensions.map(|d| d.1))
+        .or_else(|| value(Tag::ImageLength))
         .unwrap_or(0);
 
     // Handle orientation - if orientation is 5, 6, 7, or 8, dimensions are swapped
@@ -387,6 +636,104 @@ mod tests {
         assert!(metadata.video_codec.is_none()); // Should be None for images
     }
 
+    #[test]
+    fn test_extract_metadata_raw() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let raw_path = temp_dir.path().join("IMG_0001.CR2");
+        fs::write(&raw_path, crate::raw::build_test_raw(b"preview", 6000, 4000)).unwrap();
+
+        let metadata = extract_metadata(raw_path.to_str().unwrap()).unwrap();
+
+        assert_eq!(metadata.camera_make.as_deref(), Some("Canon"));
+        assert_eq!(metadata.camera_model.as_deref(), Some("Canon EOS R5"));
+        assert_eq!(
+            metadata.capture_date.unwrap().format("%Y-%m-%d %H:%M:%S").to_string(),
+            "2024-06-15 14:30:00"
+        );
+        // Sensor size rather than the IFD0 thumbnail size
+        assert_eq!((metadata.width, metadata.height), (6000, 4000));
+    }
+
+    #[test]
+    fn test_extract_capture_settings() {
+        use exif::experimental::Writer;
+        use exif::{Field, Rational};
+        use image::ImageEncoder;
+
+        let ascii = |tag, text: &str| Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![text.as_bytes().to_vec()]),
+        };
+        let rational = |tag, num, denom| Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Rational(vec![Rational { num, denom }]),
+        };
+        let short = |tag, value| Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Short(vec![value]),
+        };
+        let fields = [
+            ascii(Tag::DateTimeOriginal, "2024:06:15 14:30:00"),
+            ascii(Tag::OffsetTimeOriginal, "+02:00"),
+            rational(Tag::ExposureTime, 1, 250),
+            rational(Tag::FNumber, 14, 10),
+            short(Tag::PhotographicSensitivity, 400),
+            rational(Tag::FocalLength, 35, 1),
+            short(Tag::FocalLengthIn35mmFilm, 52),
+            ascii(Tag::LensModel, "XF35mmF1.4 R"),
+            short(Tag::Flash, 0x10),
+            short(Tag::Orientation, 6),
+        ];
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut exif = std::io::Cursor::new(Vec::new());
+        writer.write(&mut exif, false).unwrap();
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let image_path = temp_dir.path().join("DSCF0001.jpg");
+        let img = image::RgbImage::from_pixel(8, 4, image::Rgb([120, 120, 120]));
+        let mut encoder = image::codecs::jpeg::JpegEncoder::new(File::create(&image_path).unwrap());
+        encoder.set_exif_metadata(exif.into_inner()).unwrap();
+        image::DynamicImage::ImageRgb8(img).write_with_encoder(encoder).unwrap();
+
+        let metadata = extract_metadata(image_path.to_str().unwrap()).unwrap();
+
+        // Local camera time is converted to UTC with the recorded offset
+        assert_eq!(
+            metadata.capture_date.unwrap().format("%Y-%m-%d %H:%M:%S").to_string(),
+            "2024-06-15 12:30:00"
+        );
+        assert_eq!(
+            metadata.capture_settings,
+            CaptureSettings {
+                exposure_time: Some(0.004),
+                aperture: Some(1.4),
+                iso: Some(400),
+                focal_length: Some(35.0),
+                focal_length_35mm: Some(52.0),
+                lens_model: Some("XF35mmF1.4 R".to_string()),
+                flash: Some(false),
+                orientation: Some(6),
+                utc_offset_minutes: Some(120),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_utc_offset() {
+        assert_eq!(parse_utc_offset("+02:00"), Some(120));
+        assert_eq!(parse_utc_offset("-05:30"), Some(-330));
+        assert_eq!(parse_utc_offset("+00:00"), Some(0));
+        assert_eq!(parse_utc_offset("   :  "), None);
+        assert_eq!(parse_utc_offset("02:00"), None);
+        assert_eq!(parse_utc_offset("+25:00"), None);
+    }
+
     #[test]
     fn test_extract_metadata_fallback_to_file_timestamp() {
         // Create a file without EXIF data
@@ -406,6 +753,104 @@ mod tests {
         assert!(result.video_codec.is_none());
     }
 
+    #[test]
+    fn test_parse_ffprobe_output_phone_video() {
+        let json = serde_json::json!({
+            "streams": [
+                {
+                    "codec_type": "audio",
+                    "codec_name": "aac",
+                    "channels": 2
+                },
+                {
+                    "codec_type": "video",
+                    "codec_name": "hevc",
+                    "width": 1920,
+                    "height": 1080,
+                    "duration": "12.500000",
+                    "avg_frame_rate": "30000/1001",
+                    "side_data_list": [{ "side_data_type": "Display Matrix", "rotation": -90 }]
+                }
+            ],
+            "format": {
+                "duration": "12.512000",
+                "bit_rate": "8123456",
+                "tags": {
+                    "creation_time": "2024-07-04T18:22:11.000000Z",
+                    "com.apple.quicktime.location.ISO6709": "+48.8584+002.2945+035.000/",
+                    "com.apple.quicktime.make": "Apple",
+                    "com.apple.quicktime.model": "iPhone 15 Pro",
+                    "com.apple.quicktime.creationdate": "2024-07-04T20:22:10+0200"
+                }
+            }
+        });
+        let file_modified = Utc::now();
+
+        let metadata = parse_ffprobe_output(&json, "/v/IMG_0001.MOV", 1024, file_modified).unwrap();
+
+        // Portrait video recorded as a rotated landscape frame
+        assert_eq!((metadata.width, metadata.height), (1080, 1920));
+        assert_eq!(metadata.camera_make.as_deref(), Some("Apple"));
+        assert_eq!(metadata.camera_model.as_deref(), Some("iPhone 15 Pro"));
+        assert_eq!((metadata.gps_latitude, metadata.gps_longitude), (Some(48.8584), Some(2.2945)));
+        assert_eq!(
+            metadata.capture_date.unwrap().format("%Y-%m-%d %H:%M:%S").to_string(),
+            "2024-07-04 18:22:10"
+        );
+        assert_eq!(metadata.capture_settings.utc_offset_minutes, Some(120));
+        assert_eq!(metadata.duration_seconds, Some(12.5));
+        assert_eq!(metadata.video_codec.as_deref(), Some("hevc"));
+
+        let properties = metadata.video_properties;
+        assert!((properties.frame_rate.unwrap() - 29.97).abs() < 0.01);
+        assert_eq!(properties.bit_rate, Some(8123456));
+        assert_eq!(properties.rotation, Some(90));
+        assert_eq!(properties.audio_codec.as_deref(), Some("aac"));
+        assert_eq!(properties.audio_channels, Some(2));
+    }
+
+    #[test]
+    fn test_parse_ffprobe_output_without_tags() {
+        let json = serde_json::json!({
+            "streams": [{
+                "codec_type": "video",
+                "codec_name": "h264",
+                "width": 640,
+                "height": 480,
+                "r_frame_rate": "25/1",
+                "tags": { "rotate": "180" }
+            }],
+            "format": {
+                "duration": "3.0",
+                "tags": { "creation_time": "1970-01-01T00:00:00.000000Z" }
+            }
+        });
+        let file_modified = Utc::now();
+
+        let metadata = parse_ffprobe_output(&json, "/v/clip.mp4", 1024, file_modified).unwrap();
+
+        // An unset camera clock falls back to the file timestamp
+        assert_eq!(metadata.capture_date, Some(file_modified));
+        assert_eq!(metadata.capture_settings, CaptureSettings::default());
+        assert_eq!((metadata.width, metadata.height), (640, 480));
+        assert_eq!(metadata.duration_seconds, Some(3.0));
+        assert_eq!(metadata.video_properties.frame_rate, Some(25.0));
+        assert_eq!(metadata.video_properties.rotation, Some(180));
+        assert!(metadata.video_properties.audio_codec.is_none());
+
+        let audio_only = serde_json::json!({ "streams": [{ "codec_type": "audio" }], "format": {} });
+        assert!(parse_ffprobe_output(&audio_only, "/v/a.m4a", 1, file_modified).is_err());
+    }
+
+    #[test]
+    fn test_parse_iso6709() {
+        assert_eq!(parse_iso6709("+37.3349-122.0090+012.120/"), Some((37.3349, -122.009)));
+        assert_eq!(parse_iso6709("-33.8688+151.2093/"), Some((-33.8688, 151.2093)));
+        assert_eq!(parse_iso6709("+91.0000+000.0000/"), None);
+        assert_eq!(parse_iso6709("37.3349-122.0090/"), None);
+        assert_eq!(parse_iso6709(""), None);
+    }
+
     #[test]
     fn test_extract_video_metadata_file_not_found() {
         let result = extract_video_metadata("/nonexistent/path/video.mp4");
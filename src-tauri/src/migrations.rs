@@ -1,7 +1,7 @@
 use rusqlite::{Connection, Result};
 
 /// Database schema version
-const CURRENT_VERSION: i32 = 2;
+const CURRENT_VERSION: i32 = 19;
 
 /// Initialize or migrate the database schema
 pub fn run_migrations(conn: &Connection) -> Result<()> {
@@ -38,6 +38,108 @@ pub fn run_migrations(conn: &Connection) -> Result<()> {
         record_migration(conn, 2)?;
     }
 
+    if current_version < 3 {
+        println!("Running migration to version 3: Add perceptual hashes");
+        migrate_to_v3(conn)?;
+        record_migration(conn, 3)?;
+    }
+
+    if current_version < 4 {
+        println!("Running migration to version 4: Store embeddings as f32");
+        migrate_to_v4(conn)?;
+        record_migration(conn, 4)?;
+    }
+
+    if current_version < 5 {
+        println!("Running migration to version 5: Add full-text search index");
+        migrate_to_v5(conn)?;
+        record_migration(conn, 5)?;
+    }
+
+    if current_version < 6 {
+        println!("Running migration to version 6: Add location index");
+        migrate_to_v6(conn)?;
+        record_migration(conn, 6)?;
+    }
+
+    if current_version < 7 {
+        println!("Running migration to version 7: Add place names");
+        migrate_to_v7(conn)?;
+        record_migration(conn, 7)?;
+    }
+
+    if current_version < 8 {
+        println!("Running migration to version 8: Add albums");
+        migrate_to_v8(conn)?;
+        record_migration(conn, 8)?;
+    }
+
+    if current_version < 9 {
+        println!("Running migration to version 9: Add smart albums");
+        migrate_to_v9(conn)?;
+        record_migration(conn, 9)?;
+    }
+
+    if current_version < 10 {
+        println!("Running migration to version 10: Add ratings, color labels, flags and favorites");
+        migrate_to_v10(conn)?;
+        record_migration(conn, 10)?;
+    }
+
+    if current_version < 11 {
+        println!("Running migration to version 11: Add trash");
+        migrate_to_v11(conn)?;
+        record_migration(conn, 11)?;
+    }
+
+    if current_version < 12 {
+        println!("Running migration to version 12: Add edit recipes");
+        migrate_to_v12(conn)?;
+        record_migration(conn, 12)?;
+    }
+
+    if current_version < 13 {
+        println!("Running migration to version 13: Add image descriptions");
+        migrate_to_v13(conn)?;
+        record_migration(conn, 13)?;
+    }
+
+    if current_version < 14 {
+        println!("Running migration to version 14: Add exposure and lens settings");
+        migrate_to_v14(conn)?;
+        record_migration(conn, 14)?;
+    }
+
+    if current_version < 15 {
+        println!("Running migration to version 15: Add video stream properties");
+        migrate_to_v15(conn)?;
+        record_migration(conn, 15)?;
+    }
+
+    if current_version < 16 {
+        println!("Running migration to version 16: Add resumable upload sessions");
+        migrate_to_v16(conn)?;
+        record_migration(conn, 16)?;
+    }
+
+    if current_version < 17 {
+        println!("Running migration to version 17: Add upload quality of synced files");
+        migrate_to_v17(conn)?;
+        record_migration(conn, 17)?;
+    }
+
+    if current_version < 18 {
+        println!("Running migration to version 18: Track which metadata extraction read each file");
+        migrate_to_v18(conn)?;
+        record_migration(conn, 18)?;
+    }
+
+    if current_version < 19 {
+        println!("Running migration to version 19: Remember which images were reverse geocoded");
+        migrate_to_v19(conn)?;
+        record_migration(conn, 19)?;
+    }
+
     println!("Database schema is up to date at version {}", CURRENT_VERSION);
     Ok(())
 }
@@ -199,6 +301,457 @@ fn migrate_to_v2(conn: &Connection) -> Result<()> {
     Ok(())
 }
 
+/// Migration to version 3: Add perceptual hashes for duplicate detection
+fn migrate_to_v3(conn: &Connection) -> Result<()> {
+    // Hashes are 64-bit fingerprints stored as the bit pattern of a signed INTEGER
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS perceptual_hashes (
+            image_id INTEGER PRIMARY KEY,
+            dhash INTEGER NOT NULL,
+            phash INTEGER NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY (image_id) REFERENCES images(id) ON DELETE CASCADE
+        )",
+        [],
+    )?;
+
+    println!("Migration to version 3 completed successfully");
+    Ok(())
+}
+
+/// Migration to version 4: Store embeddings as little-endian f32 instead of f64
+fn migrate_to_v4(conn: &Connection) -> Result<()> {
+    let rows: Vec<(i64, Vec<u8>)> = conn
+        .prepare("SELECT id, embedding FROM embeddings")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    println!("Converting {} embeddings to f32", rows.len());
+
+    let tx = conn.unchecked_transaction()?;
+    for (id, bytes) in rows {
+        let converted: Vec<u8> = bytes
+            .chunks_exact(8)
+            .map(|chunk| f64::from_le_bytes(chunk.try_into().unwrap()) as f32)
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+
+        tx.execute(
+            "UPDATE embeddings SET embedding = ?1 WHERE id = ?2",
+            rusqlite::params![converted, id],
+        )?;
+    }
+    tx.commit()?;
+
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_embeddings_model_version ON embeddings(model_version)",
+        [],
+    )?;
+
+    println!("Migration to version 4 completed successfully");
+    Ok(())
+}
+
+/// Migration to version 5: FTS5 index over file names, folders, tags and camera
+///
+/// The index is a plain FTS5 table keyed by image id (its rowid) and kept in
+/// sync by triggers on `images` and `tags`, so writers never touch it directly.
+fn migrate_to_v5(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS images_fts USING fts5(
+            file_name,
+            folder,
+            tags,
+            camera_make,
+            camera_model,
+            tokenize = 'unicode61 remove_diacritics 2',
+            prefix = '2 3'
+        )",
+        [],
+    )?;
+
+    // Split paths on either separator; everything up to the last one is the folder
+    let normalized = "replace(new.path, '\\', '/')";
+    let folder = format!("rtrim({0}, replace({0}, '/', ''))", normalized);
+    let file_name = format!("substr({}, length({}) + 1)", normalized, folder);
+    let tags = "(SELECT group_concat(label, ' ') FROM tags WHERE image_id = new.id)";
+
+    conn.execute_batch(&format!(
+        "CREATE TRIGGER IF NOT EXISTS images_fts_insert AFTER INSERT ON images BEGIN
+            INSERT INTO images_fts (rowid, file_name, folder, tags, camera_make, camera_model)
+            VALUES (new.id, {file_name}, {folder}, {tags}, new.camera_make, new.camera_model);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS images_fts_update
+        AFTER UPDATE OF path, camera_make, camera_model ON images BEGIN
+            UPDATE images_fts
+            SET file_name = {file_name}, folder = {folder},
+                camera_make = new.camera_make, camera_model = new.camera_model
+            WHERE rowid = new.id;
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS images_fts_delete AFTER DELETE ON images BEGIN
+            DELETE FROM images_fts WHERE rowid = old.id;
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS tags_fts_insert AFTER INSERT ON tags BEGIN
+            UPDATE images_fts
+            SET tags = (SELECT group_concat(label, ' ') FROM tags WHERE image_id = new.image_id)
+            WHERE rowid = new.image_id;
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS tags_fts_update AFTER UPDATE ON tags BEGIN
+            UPDATE images_fts
+            SET tags = (SELECT group_concat(label, ' ') FROM tags WHERE image_id = old.image_id)
+            WHERE rowid = old.image_id;
+            UPDATE images_fts
+            SET tags = (SELECT group_concat(label, ' ') FROM tags WHERE image_id = new.image_id)
+            WHERE rowid = new.image_id;
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS tags_fts_delete AFTER DELETE ON tags BEGIN
+            UPDATE images_fts
+            SET tags = (SELECT group_concat(label, ' ') FROM tags WHERE image_id = old.image_id)
+            WHERE rowid = old.image_id;
+        END;",
+        file_name = file_name,
+        folder = folder,
+        tags = tags,
+    ))?;
+
+    // Index the existing library, aliasing images as `new` to reuse the expressions above
+    let backfill = format!(
+        "INSERT INTO images_fts (rowid, file_name, folder, tags, camera_make, camera_model)
+         SELECT new.id, {}, {}, {}, new.camera_make, new.camera_model
+         FROM images new
+         WHERE new.id NOT IN (SELECT rowid FROM images_fts)",
+        file_name, folder, tags
+    );
+    let indexed = conn.execute(&backfill, [])?;
+
+    println!("Indexed {} existing images for search", indexed);
+    println!("Migration to version 5 completed successfully");
+    Ok(())
+}
+
+/// Migration to version 6: R*Tree index over GPS coordinates
+///
+/// Each geotagged image is a point box keyed by image id, kept in sync by
+/// triggers on `images`.
+fn migrate_to_v6(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS images_geo USING rtree(
+            id,
+            min_lat, max_lat,
+            min_lon, max_lon
+        );
+
+        CREATE TRIGGER IF NOT EXISTS images_geo_insert AFTER INSERT ON images
+        WHEN new.gps_latitude IS NOT NULL AND new.gps_longitude IS NOT NULL BEGIN
+            INSERT INTO images_geo
+            VALUES (new.id, new.gps_latitude, new.gps_latitude, new.gps_longitude, new.gps_longitude);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS images_geo_update
+        AFTER UPDATE OF gps_latitude, gps_longitude ON images BEGIN
+            DELETE FROM images_geo WHERE id = old.id;
+            INSERT INTO images_geo
+            SELECT new.id, new.gps_latitude, new.gps_latitude, new.gps_longitude, new.gps_longitude
+            WHERE new.gps_latitude IS NOT NULL AND new.gps_longitude IS NOT NULL;
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS images_geo_delete AFTER DELETE ON images BEGIN
+            DELETE FROM images_geo WHERE id = old.id;
+        END;",
+    )?;
+
+    let indexed = conn.execute(
+        "INSERT INTO images_geo
+         SELECT id, gps_latitude, gps_latitude, gps_longitude, gps_longitude
+         FROM images
+         WHERE gps_latitude IS NOT NULL AND gps_longitude IS NOT NULL
+           AND id NOT IN (SELECT id FROM images_geo)",
+        [],
+    )?;
+
+    println!("Indexed {} existing geotagged images", indexed);
+    println!("Migration to version 6 completed successfully");
+    Ok(())
+}
+
+/// Migration to version 7: Place names reverse geocoded from GPS coordinates
+///
+/// Existing images are geocoded in the background after startup, not here.
+fn migrate_to_v7(conn: &Connection) -> Result<()> {
+    for column in ["country", "region", "city"] {
+        if !check_column_exists(conn, "images", column)? {
+            println!("Adding {} column", column);
+            conn.execute(&format!("ALTER TABLE images ADD COLUMN {} TEXT", column), [])?;
+        } else {
+            println!("{} column already exists, skipping", column);
+        }
+    }
+
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_images_place ON images(country, region, city)",
+        [],
+    )?;
+
+    println!("Migration to version 7 completed successfully");
+    Ok(())
+}
+
+/// Migration to version 8: Albums, album folders and manually ordered album items
+fn migrate_to_v8(conn: &Connection) -> Result<()> {
+    // Folders are albums that hold other albums instead of images
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS albums (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            parent_id INTEGER,
+            is_folder INTEGER NOT NULL DEFAULT 0,
+            cover_image_id INTEGER,
+            sort_order INTEGER NOT NULL DEFAULT 0,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY (parent_id) REFERENCES albums(id) ON DELETE CASCADE,
+            FOREIGN KEY (cover_image_id) REFERENCES images(id) ON DELETE SET NULL
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS album_items (
+            album_id INTEGER NOT NULL,
+            image_id INTEGER NOT NULL,
+            position INTEGER NOT NULL,
+            added_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (album_id, image_id),
+            FOREIGN KEY (album_id) REFERENCES albums(id) ON DELETE CASCADE,
+            FOREIGN KEY (image_id) REFERENCES images(id) ON DELETE CASCADE
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_albums_parent_id ON albums(parent_id)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_album_items_position ON album_items(album_id, position)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_album_items_image_id ON album_items(image_id)",
+        [],
+    )?;
+
+    println!("Migration to version 8 completed successfully");
+    Ok(())
+}
+
+/// Migration to version 9: Smart albums, whose images come from a saved filter
+fn migrate_to_v9(conn: &Connection) -> Result<()> {
+    // The filter is an `ImageFilter` as JSON; the query is the text it was parsed from, if any
+    for column in ["smart_filter", "smart_query"] {
+        if !check_column_exists(conn, "albums", column)? {
+            println!("Adding {} column", column);
+            conn.execute(&format!("ALTER TABLE albums ADD COLUMN {} TEXT", column), [])?;
+        } else {
+            println!("{} column already exists, skipping", column);
+        }
+    }
+
+    println!("Migration to version 9 completed successfully");
+    Ok(())
+}
+
+/// Migration to version 10: Curation fields for culling
+fn migrate_to_v10(conn: &Connection) -> Result<()> {
+    let columns = [
+        ("rating", "INTEGER NOT NULL DEFAULT 0 CHECK (rating BETWEEN 0 AND 5)"),
+        ("color_label", "TEXT"),
+        ("flag", "TEXT NOT NULL DEFAULT 'unflagged'"),
+        ("favorite", "INTEGER NOT NULL DEFAULT 0"),
+    ];
+    for (column, definition) in columns {
+        if !check_column_exists(conn, "images", column)? {
+            println!("Adding {} column", column);
+            conn.execute(&format!("ALTER TABLE images ADD COLUMN {} {}", column, definition), [])?;
+        } else {
+            println!("{} column already exists, skipping", column);
+        }
+    }
+
+    conn.execute("CREATE INDEX IF NOT EXISTS idx_images_rating ON images(rating)", [])?;
+    conn.execute("CREATE INDEX IF NOT EXISTS idx_images_flag ON images(flag)", [])?;
+
+    println!("Migration to version 10 completed successfully");
+    Ok(())
+}
+
+/// Migration to version 11: Soft deletion into the trash
+fn migrate_to_v11(conn: &Connection) -> Result<()> {
+    // Trashed images keep their tags, embeddings and album memberships until purged
+    for column in ["trashed_at", "trash_path"] {
+        if !check_column_exists(conn, "images", column)? {
+            println!("Adding {} column", column);
+            conn.execute(&format!("ALTER TABLE images ADD COLUMN {} TEXT", column), [])?;
+        } else {
+            println!("{} column already exists, skipping", column);
+        }
+    }
+
+    conn.execute("CREATE INDEX IF NOT EXISTS idx_images_trashed_at ON images(trashed_at)", [])?;
+
+    println!("Migration to version 11 completed successfully");
+    Ok(())
+}
+
+/// Migration to version 12: Non-destructive edit recipes, stored as JSON
+fn migrate_to_v12(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS image_edits (
+            image_id INTEGER PRIMARY KEY,
+            recipe TEXT NOT NULL,
+            updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY (image_id) REFERENCES images(id) ON DELETE CASCADE
+        )",
+        [],
+    )?;
+
+    println!("Migration to version 12 completed successfully");
+    Ok(())
+}
+
+/// Migration to version 13: Image descriptions, written to and read from XMP
+fn migrate_to_v13(conn: &Connection) -> Result<()> {
+    if !check_column_exists(conn, "images", "description")? {
+        println!("Adding description column");
+        conn.execute("ALTER TABLE images ADD COLUMN description TEXT", [])?;
+    } else {
+        println!("description column already exists, skipping");
+    }
+
+    println!("Migration to version 13 completed successfully");
+    Ok(())
+}
+
+/// Migration to version 14: Exposure, lens and timezone settings from EXIF
+fn migrate_to_v14(conn: &Connection) -> Result<()> {
+    // Images imported before this version are read again by the metadata backfill (version 18)
+    let columns = [
+        ("exposure_time", "REAL"),
+        ("aperture", "REAL"),
+        ("iso", "INTEGER"),
+        ("focal_length", "REAL"),
+        ("focal_length_35mm", "REAL"),
+        ("lens_model", "TEXT"),
+        ("flash", "INTEGER"),
+        ("orientation", "INTEGER"),
+        ("utc_offset_minutes", "INTEGER"),
+    ];
+    for (column, definition) in columns {
+        if !check_column_exists(conn, "images", column)? {
+            println!("Adding {} column", column);
+            conn.execute(&format!("ALTER TABLE images ADD COLUMN {} {}", column, definition), [])?;
+        } else {
+            println!("{} column already exists, skipping", column);
+        }
+    }
+
+    conn.execute("CREATE INDEX IF NOT EXISTS idx_images_aperture ON images(aperture)", [])?;
+    conn.execute("CREATE INDEX IF NOT EXISTS idx_images_lens_model ON images(lens_model)", [])?;
+
+    println!("Migration to version 14 completed successfully");
+    Ok(())
+}
+
+/// Migration to version 15: Frame rate, bitrate, rotation and audio of videos
+fn migrate_to_v15(conn: &Connection) -> Result<()> {
+    let columns = [
+        ("frame_rate", "REAL"),
+        ("bit_rate", "INTEGER"),
+        ("rotation", "INTEGER"),
+        ("audio_codec", "TEXT"),
+        ("audio_channels", "INTEGER"),
+    ];
+    for (column, definition) in columns {
+        if !check_column_exists(conn, "images", column)? {
+            println!("Adding {} column", column);
+            conn.execute(&format!("ALTER TABLE images ADD COLUMN {} {}", column, definition), [])?;
+        } else {
+            println!("{} column already exists, skipping", column);
+        }
+    }
+
+    println!("Migration to version 15 completed successfully");
+    Ok(())
+}
+
+/// Migration to version 16: Unfinished uploads, so sync can resume them after a restart
+fn migrate_to_v16(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS upload_sessions (
+            provider TEXT NOT NULL,
+            remote_name TEXT NOT NULL,
+            state TEXT NOT NULL,
+            uploaded INTEGER NOT NULL DEFAULT 0,
+            size INTEGER NOT NULL,
+            updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (provider, remote_name)
+        )",
+        [],
+    )?;
+
+    println!("Migration to version 16 completed successfully");
+    Ok(())
+}
+
+/// Migration to version 17: Quality tier each file was synced at
+fn migrate_to_v17(conn: &Connection) -> Result<()> {
+    if !check_column_exists(conn, "images", "sync_quality")? {
+        println!("Adding sync_quality column");
+        conn.execute("ALTER TABLE images ADD COLUMN sync_quality TEXT", [])?;
+    } else {
+        println!("sync_quality column already exists, skipping");
+    }
+
+    println!("Migration to version 17 completed successfully");
+    Ok(())
+}
+
+/// Migration to version 18: Version of the metadata extraction each file was last read with
+fn migrate_to_v18(conn: &Connection) -> Result<()> {
+    // Existing files start at 0, so the metadata backfill reads them again
+    if !check_column_exists(conn, "images", "metadata_version")? {
+        println!("Adding metadata_version column");
+        conn.execute("ALTER TABLE images ADD COLUMN metadata_version INTEGER NOT NULL DEFAULT 0", [])?;
+    } else {
+        println!("metadata_version column already exists, skipping");
+    }
+
+    println!("Migration to version 18 completed successfully");
+    Ok(())
+}
+
+/// Migration to version 19: Remember which images were reverse geocoded
+///
+/// Coordinates without a place, such as at sea, would otherwise be looked up again on every start.
+fn migrate_to_v19(conn: &Connection) -> Result<()> {
+    if !check_column_exists(conn, "images", "place_looked_up")? {
+        println!("Adding place_looked_up column");
+        conn.execute("ALTER TABLE images ADD COLUMN place_looked_up INTEGER NOT NULL DEFAULT 0", [])?;
+        // Images that already have a place were looked up by an earlier version
+        conn.execute("UPDATE images SET place_looked_up = 1 WHERE country IS NOT NULL", [])?;
+    } else {
+        println!("place_looked_up column already exists, skipping");
+    }
+
+    println!("Migration to version 19 completed successfully");
+    Ok(())
+}
+
 /// Check if a column exists in a table
 fn check_column_exists(conn: &Connection, table: &str, column: &str) -> Result<bool> {
     let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
@@ -234,7 +787,7 @@ mod tests {
                 |row| row.get(0),
             )
             .unwrap();
-        assert_eq!(version, 2);
+        assert_eq!(version, CURRENT_VERSION);
 
         // Verify images table has all columns including video support
         let columns: Vec<String> = conn
@@ -248,6 +801,22 @@ mod tests {
         assert!(columns.contains(&"media_type".to_string()));
         assert!(columns.contains(&"duration_seconds".to_string()));
         assert!(columns.contains(&"video_codec".to_string()));
+        assert!(columns.contains(&"country".to_string()));
+        assert!(columns.contains(&"city".to_string()));
+        assert!(columns.contains(&"rating".to_string()));
+        assert!(columns.contains(&"color_label".to_string()));
+        assert!(columns.contains(&"flag".to_string()));
+        assert!(columns.contains(&"favorite".to_string()));
+        assert!(columns.contains(&"trashed_at".to_string()));
+        assert!(columns.contains(&"trash_path".to_string()));
+        assert!(columns.contains(&"description".to_string()));
+        assert!(columns.contains(&"aperture".to_string()));
+        assert!(columns.contains(&"lens_model".to_string()));
+        assert!(columns.contains(&"utc_offset_minutes".to_string()));
+        assert!(columns.contains(&"frame_rate".to_string()));
+        assert!(columns.contains(&"audio_codec".to_string()));
+        assert!(columns.contains(&"metadata_version".to_string()));
+        assert!(columns.contains(&"place_looked_up".to_string()));
 
         // Verify indexes exist
         let indexes: Vec<String> = conn
@@ -259,6 +828,25 @@ mod tests {
             .unwrap();
 
         assert!(indexes.contains(&"idx_images_media_type".to_string()));
+        assert!(indexes.contains(&"idx_images_aperture".to_string()));
+
+        // Verify perceptual hash table exists
+        let tables: Vec<String> = conn
+            .prepare("SELECT name FROM sqlite_master WHERE type='table'")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert!(tables.contains(&"perceptual_hashes".to_string()));
+        assert!(tables.contains(&"images_fts".to_string()));
+        assert!(tables.contains(&"images_geo".to_string()));
+        assert!(tables.contains(&"albums".to_string()));
+        assert!(tables.contains(&"album_items".to_string()));
+        assert!(tables.contains(&"image_edits".to_string()));
+        assert!(check_column_exists(&conn, "albums", "smart_filter").unwrap());
+        assert!(check_column_exists(&conn, "albums", "smart_query").unwrap());
 
         // Clean up
         drop(conn);
@@ -375,6 +963,86 @@ mod tests {
         let _ = fs::remove_file(&db_path);
     }
 
+    #[test]
+    fn test_migration_to_v4_converts_embeddings() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let conn = Connection::open(temp_dir.path().join("test.db")).unwrap();
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS schema_version (
+                version INTEGER PRIMARY KEY,
+                applied_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )",
+            [],
+        ).unwrap();
+        migrate_to_v1(&conn).unwrap();
+        migrate_to_v2(&conn).unwrap();
+        migrate_to_v3(&conn).unwrap();
+
+        conn.execute(
+            "INSERT INTO images (
+                path, thumbnail_small, thumbnail_medium, checksum,
+                width, height, file_size, file_modified
+            ) VALUES ('/test/image.jpg', 's.jpg', 'm.jpg', 'checksum', 1, 1, 1, '2024-01-01T00:00:00Z')",
+            [],
+        ).unwrap();
+        let image_id = conn.last_insert_rowid();
+
+        // Embeddings were stored as f64 before version 4
+        let embedding: Vec<u8> = [0.5f64, -1.25, 3.0].iter().flat_map(|f| f.to_le_bytes()).collect();
+        conn.execute(
+            "INSERT INTO embeddings (image_id, embedding, model_version) VALUES (?1, ?2, 'v1')",
+            rusqlite::params![image_id, embedding],
+        ).unwrap();
+
+        migrate_to_v4(&conn).unwrap();
+
+        let bytes: Vec<u8> = conn
+            .query_row("SELECT embedding FROM embeddings WHERE image_id = ?1", [image_id], |row| row.get(0))
+            .unwrap();
+        let values: Vec<f32> = bytes
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        assert_eq!(values, vec![0.5, -1.25, 3.0]);
+    }
+
+    #[test]
+    fn test_migration_to_v5_indexes_existing_images() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let conn = Connection::open(temp_dir.path().join("test.db")).unwrap();
+
+        migrate_to_v1(&conn).unwrap();
+        migrate_to_v2(&conn).unwrap();
+
+        conn.execute(
+            "INSERT INTO images (
+                path, thumbnail_small, thumbnail_medium, checksum,
+                camera_make, width, height, file_size, file_modified
+            ) VALUES ('C:\\Photos\\Hawaii\\IMG_0042.jpg', 's.jpg', 'm.jpg', 'checksum',
+                      'Canon', 1, 1, 1, '2024-01-01T00:00:00Z')",
+            [],
+        ).unwrap();
+        let image_id = conn.last_insert_rowid();
+        conn.execute(
+            "INSERT INTO tags (image_id, label, confidence) VALUES (?1, 'beach', 0.9)",
+            [image_id],
+        ).unwrap();
+
+        migrate_to_v5(&conn).unwrap();
+
+        let (file_name, folder, tags): (String, String, String) = conn
+            .query_row(
+                "SELECT file_name, folder, tags FROM images_fts WHERE rowid = ?1",
+                [image_id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(file_name, "IMG_0042.jpg");
+        assert_eq!(folder, "C:/Photos/Hawaii/");
+        assert_eq!(tags, "beach");
+    }
+
     #[test]
     fn test_migration_idempotency() {
         let temp_dir = std::env::temp_dir();
@@ -388,7 +1056,7 @@ mod tests {
         run_migrations(&conn).unwrap();
         run_migrations(&conn).unwrap();
 
-        // Verify version is still 2
+        // Verify version is still current
         let version: i32 = conn
             .query_row(
                 "SELECT MAX(version) FROM schema_version",
@@ -396,9 +1064,9 @@ mod tests {
                 |row| row.get(0),
             )
             .unwrap();
-        assert_eq!(version, 2);
+        assert_eq!(version, CURRENT_VERSION);
 
-        // Verify only 2 migration records exist
+        // Verify only one record per migration exists
         let count: i32 = conn
             .query_row(
                 "SELECT COUNT(*) FROM schema_version",
@@ -406,7 +1074,7 @@ mod tests {
                 |row| row.get(0),
             )
             .unwrap();
-        assert_eq!(count, 2);
+        assert_eq!(count, CURRENT_VERSION);
 
         // Clean up
         drop(conn);
@@ -0,0 +1,430 @@
+use image::{DynamicImage, ImageFormat};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// Camera RAW file extensions
+const RAW_EXTENSIONS: &[&str] = &["raw", "cr2", "nef", "arw", "dng"];
+
+/// Upper bound on IFDs visited, guarding against corrupt offset loops
+const MAX_IFDS: usize = 64;
+
+// TIFF tags used to locate embedded images
+const TAG_NEW_SUBFILE_TYPE: u16 = 0x00FE;
+const TAG_IMAGE_WIDTH: u16 = 0x0100;
+const TAG_IMAGE_LENGTH: u16 = 0x0101;
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_SUB_IFDS: u16 = 0x014A;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+
+/// Check whether a path has a camera RAW extension
+pub fn is_raw_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| RAW_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// An image described by one IFD of a TIFF-based RAW file
+#[derive(Debug, Clone, Default, PartialEq)]
+struct IfdImage {
+    /// NewSubfileType (0 = full resolution, 1 = reduced resolution preview)
+    subfile_type: u32,
+    width: u32,
+    height: u32,
+    compression: u32,
+    /// Byte range of JPEG data stored in this IFD, if any
+    jpeg: Option<(usize, usize)>,
+}
+
+/// Minimal reader for the TIFF structure shared by CR2, NEF, ARW and DNG
+struct TiffReader<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> TiffReader<'a> {
+    fn new(data: &'a [u8]) -> Result<Self, String> {
+        let little_endian = match data.get(0..4) {
+            Some([b'I', b'I', 42, 0]) => true,
+            Some([b'M', b'M', 0, 42]) => false,
+            _ => return Err("Not a TIFF-based RAW file".to_string()),
+        };
+
+        Ok(Self { data, little_endian })
+    }
+
+    fn u16_at(&self, offset: usize) -> Option<u16> {
+        let bytes: [u8; 2] = self.data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    /// Read the integer values of an IFD entry (BYTE, SHORT, LONG or IFD types)
+    fn entry_values(&self, entry: usize) -> Vec<u32> {
+        let (field_type, count) = match (self.u16_at(entry + 2), self.u32_at(entry + 4)) {
+            (Some(t), Some(c)) => (t, c as usize),
+            _ => return Vec::new(),
+        };
+
+        let size = match field_type {
+            1 => 1,
+            3 => 2,
+            4 | 13 => 4,
+            _ => return Vec::new(),
+        };
+
+        // Values that fit in four bytes are stored inline
+        let start = if size * count <= 4 {
+            entry + 8
+        } else {
+            match self.u32_at(entry + 8) {
+                Some(offset) => offset as usize,
+                None => return Vec::new(),
+            }
+        };
+
+        (0..count)
+            .map_while(|i| {
+                let at = start + i * size;
+                match size {
+                    1 => self.data.get(at).map(|&b| b as u32),
+                    2 => self.u16_at(at).map(u32::from),
+                    _ => self.u32_at(at),
+                }
+            })
+            .collect()
+    }
+
+    /// Walk the IFD chain and any SubIFDs, collecting the images they describe
+    fn images(&self) -> Vec<IfdImage> {
+        let mut images = Vec::new();
+        let mut visited = HashSet::new();
+        let mut queue: Vec<usize> = self.u32_at(4).map(|o| vec![o as usize]).unwrap_or_default();
+
+        while let Some(offset) = queue.pop() {
+            if offset == 0 || visited.len() >= MAX_IFDS || !visited.insert(offset) {
+                continue;
+            }
+
+            let count = match self.u16_at(offset) {
+                Some(count) => count as usize,
+                None => continue,
+            };
+
+            let mut image = IfdImage::default();
+            let mut strips = (Vec::new(), Vec::new());
+            let mut jpeg = (None, None);
+
+            for i in 0..count {
+                let entry = offset + 2 + i * 12;
+                let tag = match self.u16_at(entry) {
+                    Some(tag) => tag,
+                    None => break,
+                };
+                let first = || self.entry_values(entry).first().copied();
+
+                match tag {
+                    TAG_NEW_SUBFILE_TYPE => image.subfile_type = first().unwrap_or(0),
+                    TAG_IMAGE_WIDTH => image.width = first().unwrap_or(0),
+                    TAG_IMAGE_LENGTH => image.height = first().unwrap_or(0),
+                    TAG_COMPRESSION => image.compression = first().unwrap_or(0),
+                    TAG_STRIP_OFFSETS => strips.0 = self.entry_values(entry),
+                    TAG_STRIP_BYTE_COUNTS => strips.1 = self.entry_values(entry),
+                    TAG_JPEG_OFFSET => jpeg.0 = first(),
+                    TAG_JPEG_LENGTH => jpeg.1 = first(),
+                    TAG_SUB_IFDS => queue.extend(self.entry_values(entry).into_iter().map(|o| o as usize)),
+                    _ => {}
+                }
+            }
+
+            image.jpeg = match (jpeg, strips) {
+                ((Some(offset), Some(length)), _) => Some((offset as usize, length as usize)),
+                // Old-style (6) and new-style (7) JPEG compression stored as a single strip
+                (_, (offsets, counts))
+                    if matches!(image.compression, 6 | 7) && offsets.len() == 1 && counts.len() == 1 =>
+                {
+                    Some((offsets[0] as usize, counts[0] as usize))
+                }
+                _ => None,
+            };
+
+            images.push(image);
+
+            if let Some(next) = self.u32_at(offset + 2 + count * 12) {
+                queue.push(next as usize);
+            }
+        }
+
+        images
+    }
+}
+
+/// Check whether JPEG data uses a DCT process the image crate can decode
+///
+/// RAW containers also use JPEG markers for lossless-compressed sensor data
+/// (SOF3), which must not be mistaken for a preview.
+fn is_displayable_jpeg(data: &[u8]) -> bool {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return false;
+    }
+
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return false;
+        }
+        let marker = data[pos + 1];
+        match marker {
+            // Baseline, extended sequential and progressive DCT
+            0xC0..=0xC2 => return true,
+            // Padding
+            0xFF => {
+                pos += 1;
+                continue;
+            }
+            // Other frame types, or scan data reached without a frame header
+            0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF | 0xDA | 0xD9 => return false,
+            // Markers without a length field
+            0x01 | 0xD0..=0xD7 => {
+                pos += 2;
+                continue;
+            }
+            _ => {}
+        }
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        pos += 2 + length;
+    }
+
+    false
+}
+
+/// Extract the largest embedded JPEG preview from a RAW file
+///
+/// Cameras store one or more JPEG renditions next to the sensor data, which
+/// is far cheaper to decode than demosaicing. The preview is returned as
+/// stored, without EXIF orientation applied.
+pub fn extract_preview(path: &Path) -> Result<DynamicImage, String> {
+    let data = fs::read(path).map_err(|e| format!("Failed to read RAW file: {}", e))?;
+    let reader = TiffReader::new(&data)
+        .map_err(|e| format!("Failed to read RAW file {}: {}", path.display(), e))?;
+
+    let mut previews: Vec<&[u8]> = reader
+        .images()
+        .iter()
+        .filter_map(|image| image.jpeg)
+        .filter_map(|(offset, length)| data.get(offset..offset.checked_add(length)?))
+        .filter(|jpeg| is_displayable_jpeg(jpeg))
+        .collect();
+
+    // Larger JPEG data means a larger preview
+    previews.sort_by_key(|jpeg| std::cmp::Reverse(jpeg.len()));
+
+    for jpeg in previews {
+        if let Ok(img) = image::load_from_memory_with_format(jpeg, ImageFormat::Jpeg) {
+            return Ok(img);
+        }
+    }
+
+    Err(format!("No embedded preview found in RAW file: {}", path.display()))
+}
+
+/// Get the full sensor image dimensions of a RAW file
+///
+/// RAW files often keep a small thumbnail in IFD0, so the generic EXIF image
+/// size is unreliable. This looks for the largest full-resolution image.
+pub fn image_dimensions(path: &Path) -> Option<(u32, u32)> {
+    let data = fs::read(path).ok()?;
+    let reader = TiffReader::new(&data).ok()?;
+
+    reader
+        .images()
+        .into_iter()
+        .filter(|image| image.subfile_type == 0 && image.width > 0 && image.height > 0)
+        .max_by_key(|image| image.width as u64 * image.height as u64)
+        .map(|image| (image.width, image.height))
+}
+
+/// Demosaic the sensor data of a RAW file
+///
+/// The result is scaled so its longest edge is at most `max_edge` pixels and
+/// is already rotated upright.
+#[cfg(feature = "raw-demosaic")]
+pub fn demosaic(path: &Path, max_edge: u32) -> Result<DynamicImage, String> {
+    let decoded = imagepipe::simple_decode_8bit(path, max_edge as usize, max_edge as usize)
+        .map_err(|e| format!("Failed to demosaic RAW file: {}", e))?;
+
+    image::RgbImage::from_raw(decoded.width as u32, decoded.height as u32, decoded.data)
+        .map(DynamicImage::ImageRgb8)
+        .ok_or_else(|| "Demosaiced RAW image has an unexpected size".to_string())
+}
+
+/// Demosaicing is unavailable when the app is built without `raw-demosaic`
+#[cfg(not(feature = "raw-demosaic"))]
+pub fn demosaic(path: &Path, _max_edge: u32) -> Result<DynamicImage, String> {
+    Err(format!(
+        "RAW demosaicing not supported in this build: {}. Rebuild with the `raw-demosaic` feature.",
+        path.display()
+    ))
+}
+
+/// Build a minimal little-endian TIFF laid out like a camera RAW file
+///
+/// IFD0 holds camera info and the embedded JPEG preview, a SubIFD describes the
+/// full-resolution sensor data and an EXIF IFD holds the capture date.
+#[cfg(test)]
+pub(crate) fn build_test_raw(preview: &[u8], sensor_width: u32, sensor_height: u32) -> Vec<u8> {
+    fn entry(out: &mut Vec<u8>, tag: u16, field_type: u16, count: u32, value: u32) {
+        out.extend_from_slice(&tag.to_le_bytes());
+        out.extend_from_slice(&field_type.to_le_bytes());
+        out.extend_from_slice(&count.to_le_bytes());
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    let make = b"Canon\0";
+    let model = b"Canon EOS R5\0";
+    let date = b"2024:06:15 14:30:00\0";
+    let sensor_data = [0u8; 16];
+
+    // Layout: header | IFD0 (11 entries) | SubIFD (6 entries) | EXIF IFD (1 entry) | data
+    let ifd_size = |entries: u32| 2 + entries * 12 + 4;
+    let ifd0 = 8;
+    let sub_ifd = ifd0 + ifd_size(11);
+    let exif_ifd = sub_ifd + ifd_size(6);
+    let make_at = exif_ifd + ifd_size(1);
+    let model_at = make_at + make.len() as u32;
+    let date_at = model_at + model.len() as u32;
+    let sensor_at = date_at + date.len() as u32;
+    let preview_at = sensor_at + sensor_data.len() as u32;
+
+    let mut out = vec![b'I', b'I', 42, 0];
+    out.extend_from_slice(&ifd0.to_le_bytes());
+
+    // IFD0: reduced-resolution preview plus camera info (tags in ascending order)
+    out.extend_from_slice(&11u16.to_le_bytes());
+    entry(&mut out, TAG_NEW_SUBFILE_TYPE, 4, 1, 1);
+    entry(&mut out, TAG_IMAGE_WIDTH, 4, 1, 160);
+    entry(&mut out, TAG_IMAGE_LENGTH, 4, 1, 120);
+    entry(&mut out, TAG_COMPRESSION, 3, 1, 6);
+    entry(&mut out, 0x010F, 2, make.len() as u32, make_at);
+    entry(&mut out, 0x0110, 2, model.len() as u32, model_at);
+    entry(&mut out, 0x0112, 3, 1, 1);
+    entry(&mut out, TAG_SUB_IFDS, 13, 1, sub_ifd);
+    entry(&mut out, TAG_JPEG_OFFSET, 4, 1, preview_at);
+    entry(&mut out, TAG_JPEG_LENGTH, 4, 1, preview.len() as u32);
+    entry(&mut out, 0x8769, 4, 1, exif_ifd);
+    out.extend_from_slice(&0u32.to_le_bytes());
+
+    // SubIFD: full-resolution, uncompressed sensor data
+    out.extend_from_slice(&6u16.to_le_bytes());
+    entry(&mut out, TAG_NEW_SUBFILE_TYPE, 4, 1, 0);
+    entry(&mut out, TAG_IMAGE_WIDTH, 4, 1, sensor_width);
+    entry(&mut out, TAG_IMAGE_LENGTH, 4, 1, sensor_height);
+    entry(&mut out, TAG_COMPRESSION, 3, 1, 1);
+    entry(&mut out, TAG_STRIP_OFFSETS, 4, 1, sensor_at);
+    entry(&mut out, TAG_STRIP_BYTE_COUNTS, 4, 1, sensor_data.len() as u32);
+    out.extend_from_slice(&0u32.to_le_bytes());
+
+    // EXIF IFD: DateTimeOriginal
+    out.extend_from_slice(&1u16.to_le_bytes());
+    entry(&mut out, 0x9003, 2, date.len() as u32, date_at);
+    out.extend_from_slice(&0u32.to_le_bytes());
+
+    out.extend_from_slice(make);
+    out.extend_from_slice(model);
+    out.extend_from_slice(date);
+    out.extend_from_slice(&sensor_data);
+    out.extend_from_slice(preview);
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgb};
+    use std::io::Cursor;
+
+    fn encode_jpeg(width: u32, height: u32) -> Vec<u8> {
+        let img = ImageBuffer::from_fn(width, height, |x, y| Rgb([(x % 256) as u8, (y % 256) as u8, 128]));
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgb8(img)
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Jpeg)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_is_raw_path() {
+        assert!(is_raw_path(Path::new("/photos/IMG_0001.CR2")));
+        assert!(is_raw_path(Path::new("/photos/DSC_0001.nef")));
+        assert!(is_raw_path(Path::new("/photos/DSC00001.ARW")));
+        assert!(is_raw_path(Path::new("/photos/L1000001.dng")));
+        assert!(!is_raw_path(Path::new("/photos/IMG_0001.jpg")));
+        assert!(!is_raw_path(Path::new("/photos/raw")));
+    }
+
+    #[test]
+    fn test_extract_preview() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let raw_path = temp_dir.path().join("IMG_0001.CR2");
+        fs::write(&raw_path, build_test_raw(&encode_jpeg(320, 213), 6000, 4000)).unwrap();
+
+        let preview = extract_preview(&raw_path).unwrap();
+        assert_eq!((preview.width(), preview.height()), (320, 213));
+    }
+
+    #[test]
+    fn test_extract_preview_without_preview() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let raw_path = temp_dir.path().join("IMG_0001.CR2");
+        fs::write(&raw_path, build_test_raw(b"not a jpeg", 6000, 4000)).unwrap();
+
+        let error = extract_preview(&raw_path).unwrap_err();
+        assert!(error.contains("No embedded preview"), "Unexpected error: {}", error);
+    }
+
+    #[test]
+    fn test_extract_preview_not_tiff() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let raw_path = temp_dir.path().join("IMG_0001.NEF");
+        fs::write(&raw_path, b"This is not a RAW file").unwrap();
+
+        assert!(extract_preview(&raw_path).is_err());
+    }
+
+    #[test]
+    fn test_image_dimensions_uses_full_resolution_ifd() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let raw_path = temp_dir.path().join("DSC_0001.NEF");
+        fs::write(&raw_path, build_test_raw(&encode_jpeg(64, 48), 6048, 4024)).unwrap();
+
+        assert_eq!(image_dimensions(&raw_path), Some((6048, 4024)));
+    }
+
+    #[test]
+    fn test_is_displayable_jpeg() {
+        assert!(is_displayable_jpeg(&encode_jpeg(16, 16)));
+
+        // Lossless JPEG (SOF3), as used for compressed sensor data
+        let lossless = [0xFF, 0xD8, 0xFF, 0xC3, 0x00, 0x0B, 0x0E, 0x00, 0x10, 0x00, 0x10, 0x01, 0x01, 0x11, 0x00];
+        assert!(!is_displayable_jpeg(&lossless));
+
+        assert!(!is_displayable_jpeg(b"not a jpeg"));
+    }
+}
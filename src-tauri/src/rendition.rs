@@ -0,0 +1,364 @@
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::database::{ImageRecord, MediaType};
+use crate::export::{self, ExportFormat, ExportOptions, MetadataMode};
+
+/// Quality files are uploaded at when syncing, from `SyncConfig::upload_quality`
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadQuality {
+    /// The original files, untouched
+    Original,
+    #[default]
+    High,
+    Medium,
+}
+
+/// Size and JPEG quality photos are re-encoded to
+struct ImageTarget {
+    long_edge: u32,
+    quality: u8,
+}
+
+/// Size and bitrates videos are transcoded to
+struct VideoTarget {
+    long_edge: u32,
+    video_kbps: u32,
+    audio_kbps: u32,
+}
+
+impl UploadQuality {
+    /// Parse the setting, which is validated when settings are saved
+    pub fn from_setting(value: &str) -> Result<Self, String> {
+        match value {
+            "original" => Ok(UploadQuality::Original),
+            "high" => Ok(UploadQuality::High),
+            "medium" => Ok(UploadQuality::Medium),
+            _ => Err(format!("Invalid upload quality: {}", value)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UploadQuality::Original => "original",
+            UploadQuality::High => "high",
+            UploadQuality::Medium => "medium",
+        }
+    }
+
+    fn image_target(&self) -> Option<ImageTarget> {
+        match self {
+            UploadQuality::Original => None,
+            UploadQuality::High => Some(ImageTarget { long_edge: 3840, quality: 90 }),
+            UploadQuality::Medium => Some(ImageTarget { long_edge: 2048, quality: 80 }),
+        }
+    }
+
+    fn video_target(&self) -> Option<VideoTarget> {
+        match self {
+            UploadQuality::Original => None,
+            UploadQuality::High => Some(VideoTarget { long_edge: 1920, video_kbps: 8000, audio_kbps: 192 }),
+            UploadQuality::Medium => Some(VideoTarget { long_edge: 1280, video_kbps: 4000, audio_kbps: 128 }),
+        }
+    }
+}
+
+/// Smaller copy of an original that is uploaded in its place
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rendition {
+    pub path: PathBuf,
+    /// Name of the original with the extension of the rendition
+    pub file_name: String,
+    pub mime_type: &'static str,
+}
+
+/// Whether an image or video is larger than an upload quality allows, so a rendition is uploaded in its place
+pub fn is_needed(image: &ImageRecord, quality: UploadQuality) -> bool {
+    let long_edge = image.width.max(image.height);
+
+    match image.media_type {
+        MediaType::Image => {
+            let Some(target) = quality.image_target() else {
+                return false;
+            };
+            let is_jpeg = Path::new(&image.path)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| matches!(ext.to_lowercase().as_str(), "jpg" | "jpeg"));
+            !is_jpeg || long_edge > target.long_edge
+        }
+        MediaType::Video => {
+            let Some(target) = quality.video_target() else {
+                return false;
+            };
+            let max_bit_rate = u64::from(target.video_kbps + target.audio_kbps) * 1000;
+            // Without a bitrate to go by, the resolution decides alone
+            let small_enough = bit_rate(image).map_or(true, |bit_rate| bit_rate <= max_bit_rate);
+            long_edge > target.long_edge || !small_enough
+        }
+    }
+}
+
+/// Overall bitrate of a video in bits per second, worked out from its size and
+/// duration when the container doesn't state it
+fn bit_rate(video: &ImageRecord) -> Option<u64> {
+    video.video_properties.bit_rate.or_else(|| {
+        video
+            .duration_seconds
+            .filter(|&duration| duration > 0.0)
+            .map(|duration| (video.file_size as f64 * 8.0 / duration) as u64)
+    })
+}
+
+/// Get the rendition of an image or video for an upload quality, creating it if needed
+///
+/// Returns None when the original should be uploaded as it is: at original
+/// quality, or when it is already no larger than the quality allows.
+/// Renditions are cached in `cache_dir` under the checksum of the original, so
+/// an upload resumed after a restart sends exactly the same bytes. Remove them
+/// with `remove` once they are uploaded.
+pub fn prepare(
+    image: &ImageRecord,
+    quality: UploadQuality,
+    cache_dir: &Path,
+) -> Result<Option<Rendition>, String> {
+    if !is_needed(image, quality) {
+        return Ok(None);
+    }
+
+    let source = Path::new(&image.path);
+    let stem = source
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| format!("Invalid filename: {}", image.path))?;
+
+    let (extension, mime_type) = match image.media_type {
+        MediaType::Image => ("jpg", "image/jpeg"),
+        MediaType::Video => ("mp4", "video/mp4"),
+    };
+    let rendition = Rendition {
+        path: cache_dir.join(format!("{}_{}.{}", image.checksum, quality.as_str(), extension)),
+        file_name: format!("{}.{}", stem, extension),
+        mime_type,
+    };
+
+    if !rendition.path.exists() {
+        match (&image.media_type, quality.image_target(), quality.video_target()) {
+            (MediaType::Image, Some(target), _) => render_image(image, &target, &rendition.path)?,
+            (MediaType::Video, _, Some(target)) => transcode_video(source, &target, &rendition.path)?,
+            _ => return Ok(None),
+        }
+    }
+
+    Ok(Some(rendition))
+}
+
+/// Delete a rendition that is no longer needed
+pub fn remove(rendition: &Rendition) {
+    if let Err(e) = fs::remove_file(&rendition.path) {
+        log::warn!("Failed to remove rendition {}: {}", rendition.path.display(), e);
+    }
+}
+
+/// Path a rendition is written to before it is complete, so a half-written one is never reused
+fn partial_path(destination: &Path) -> PathBuf {
+    let mut name = destination.as_os_str().to_os_string();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+fn render_image(image: &ImageRecord, target: &ImageTarget, destination: &Path) -> Result<(), String> {
+    create_parent(destination)?;
+    let options = ExportOptions {
+        format: ExportFormat::Jpeg,
+        quality: target.quality,
+        long_edge: Some(target.long_edge),
+        metadata: MetadataMode::Keep,
+        ..ExportOptions::default()
+    };
+
+    // Synced files are backups of the originals, so edits are left out
+    let partial = partial_path(destination);
+    export::convert_image(image, None, &partial, &options)?;
+    fs::rename(&partial, destination)
+        .map_err(|e| format!("Failed to move {} into place: {}", partial.display(), e))
+}
+
+fn transcode_video(source: &Path, target: &VideoTarget, destination: &Path) -> Result<(), String> {
+    create_parent(destination)?;
+    let partial = partial_path(destination);
+
+    let output = Command::new("ffmpeg")
+        .args(transcode_args(source, target, &partial))
+        .output()
+        .map_err(|e| format!("Failed to execute FFmpeg: {}. Make sure FFmpeg is installed and in PATH.", e))?;
+
+    if !output.status.success() {
+        let _ = fs::remove_file(&partial);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("FFmpeg failed to transcode {}: {}", source.display(), stderr));
+    }
+
+    fs::rename(&partial, destination)
+        .map_err(|e| format!("Failed to move {} into place: {}", partial.display(), e))
+}
+
+/// FFmpeg arguments to transcode a video to H.264 and AAC in an MP4 container
+///
+/// The long edge is bounded while keeping the aspect ratio, and metadata such
+/// as the creation time and location is carried over.
+fn transcode_args(source: &Path, target: &VideoTarget, destination: &Path) -> Vec<OsString> {
+    let scale = format!(
+        "scale=w='min({edge},iw)':h='min({edge},ih)':force_original_aspect_ratio=decrease:force_divisible_by=2",
+        edge = target.long_edge
+    );
+    let video_bitrate = format!("{}k", target.video_kbps);
+    let buffer_size = format!("{}k", target.video_kbps * 2);
+    let audio_bitrate = format!("{}k", target.audio_kbps);
+
+    let mut args: Vec<OsString> = vec!["-y".into(), "-v".into(), "error".into(), "-i".into(), source.into()];
+    for arg in [
+        "-map", "0:v:0", "-map", "0:a?",
+        "-vf", &scale,
+        "-c:v", "libx264", "-preset", "medium", "-pix_fmt", "yuv420p",
+        "-b:v", &video_bitrate, "-maxrate", &video_bitrate, "-bufsize", &buffer_size,
+        "-c:a", "aac", "-b:a", &audio_bitrate,
+        "-map_metadata", "0", "-movflags", "+faststart+use_metadata_tags",
+        "-f", "mp4",
+    ] {
+        args.push(arg.into());
+    }
+    args.push(destination.into());
+    args
+}
+
+fn create_parent(destination: &Path) -> Result<(), String> {
+    match destination.parent() {
+        Some(parent) => fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{insert_test_media, Database};
+    use image::{ImageBuffer, Rgb};
+
+    #[test]
+    fn test_upload_quality_from_setting() {
+        for quality in [UploadQuality::Original, UploadQuality::High, UploadQuality::Medium] {
+            assert_eq!(UploadQuality::from_setting(quality.as_str()), Ok(quality));
+        }
+        assert!(UploadQuality::from_setting("ultra").is_err());
+    }
+
+    #[test]
+    fn test_image_renditions() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let cache_dir = temp_dir.path().join("renditions");
+
+        let png = temp_dir.path().join("Panorama.png");
+        ImageBuffer::from_pixel(2400, 600, Rgb([90u8, 120, 200])).save(&png).unwrap();
+        let image = insert_test_media(&db, &png, MediaType::Image, 2400, 600);
+
+        // Originals are never touched
+        assert_eq!(prepare(&image, UploadQuality::Original, &cache_dir).unwrap(), None);
+
+        let rendition = prepare(&image, UploadQuality::Medium, &cache_dir).unwrap().unwrap();
+        assert_eq!(rendition.file_name, "Panorama.jpg");
+        assert_eq!(rendition.mime_type, "image/jpeg");
+        assert_eq!(rendition.path, cache_dir.join(format!("{}_medium.jpg", image.checksum)));
+        assert_eq!(image::image_dimensions(&rendition.path).unwrap(), (2048, 512));
+
+        // Cached renditions are reused
+        let modified = fs::metadata(&rendition.path).unwrap().modified().unwrap();
+        let cached = prepare(&image, UploadQuality::Medium, &cache_dir).unwrap().unwrap();
+        assert_eq!(cached, rendition);
+        assert_eq!(fs::metadata(&cached.path).unwrap().modified().unwrap(), modified);
+
+        // A PNG that fits is still converted, since JPEG is much smaller
+        let high = prepare(&image, UploadQuality::High, &cache_dir).unwrap().unwrap();
+        assert_eq!(image::image_dimensions(&high.path).unwrap(), (2400, 600));
+
+        remove(&rendition);
+        assert!(!rendition.path.exists());
+    }
+
+    #[test]
+    fn test_small_jpeg_is_uploaded_as_is() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let jpeg = temp_dir.path().join("IMG_0001.JPG");
+        ImageBuffer::from_pixel(64, 48, Rgb([200u8, 10, 10])).save_with_format(&jpeg, image::ImageFormat::Jpeg).unwrap();
+        let image = insert_test_media(&db, &jpeg, MediaType::Image, 64, 48);
+
+        assert_eq!(prepare(&image, UploadQuality::Medium, temp_dir.path()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_videos_within_limits_are_uploaded_as_is() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let video = temp_dir.path().join("clip.mov");
+        fs::write(&video, b"not really a video").unwrap();
+        let mut record = insert_test_media(&db, &video, MediaType::Video, 1280, 720);
+
+        record.video_properties.bit_rate = Some(3_000_000);
+        assert_eq!(prepare(&record, UploadQuality::Medium, temp_dir.path()).unwrap(), None);
+
+        // Too large, so a transcode is attempted, which fails on this fake video
+        record.video_properties.bit_rate = Some(20_000_000);
+        assert!(prepare(&record, UploadQuality::Medium, temp_dir.path()).is_err());
+        assert!(!temp_dir.path().join(format!("{}_medium.mp4", record.checksum)).exists());
+        assert!(!temp_dir.path().join(format!("{}_medium.mp4.part", record.checksum)).exists());
+    }
+
+    #[test]
+    fn test_videos_without_stated_bitrate() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let video = temp_dir.path().join("clip.mov");
+        fs::write(&video, b"not really a video").unwrap();
+        let mut record = insert_test_media(&db, &video, MediaType::Video, 1280, 720);
+        record.video_properties.bit_rate = None;
+
+        // The resolution decides when nothing else is known
+        assert!(!is_needed(&record, UploadQuality::Medium));
+
+        // otherwise the bitrate follows from the size and duration
+        record.file_size = 4_000_000;
+        record.duration_seconds = Some(10.0);
+        assert!(!is_needed(&record, UploadQuality::Medium));
+        record.duration_seconds = Some(1.0);
+        assert!(is_needed(&record, UploadQuality::Medium));
+
+        record.width = 1920;
+        record.duration_seconds = None;
+        assert!(is_needed(&record, UploadQuality::Medium));
+    }
+
+    #[test]
+    fn test_transcode_args() {
+        let target = UploadQuality::Medium.video_target().unwrap();
+        let args = transcode_args(Path::new("/videos/clip.mov"), &target, Path::new("/cache/abc_medium.mp4.part"));
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string_lossy().to_string()).collect();
+
+        let position = |flag: &str| args.iter().position(|arg| arg == flag).unwrap();
+        assert_eq!(args[position("-i") + 1], "/videos/clip.mov");
+        assert_eq!(
+            args[position("-vf") + 1],
+            "scale=w='min(1280,iw)':h='min(1280,ih)':force_original_aspect_ratio=decrease:force_divisible_by=2"
+        );
+        assert_eq!(args[position("-b:v") + 1], "4000k");
+        assert_eq!(args[position("-b:a") + 1], "128k");
+        assert_eq!(args[position("-f") + 1], "mp4");
+        assert_eq!(args.last().unwrap(), "/cache/abc_medium.mp4.part");
+    }
+}
@@ -6,7 +6,7 @@ use walkdir::WalkDir;
 use crate::settings::FormatConfig;
 
 /// Supported image file extensions (default)
-const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "heic", "raw", "cr2", "nef"];
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "heic", "heif", "raw", "cr2", "nef"];
 
 /// Media type enumeration
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
@@ -62,6 +62,20 @@ pub struct ScanProgress {
     pub current_file: String,
 }
 
+/// Determine the media type of a file from its extension
+/// Returns None when the extension is not enabled in the format configuration
+pub fn media_type_for_path(path: &Path, config: &FormatConfig) -> Option<MediaType> {
+    let ext = path.extension()?.to_string_lossy().to_lowercase();
+
+    if config.image_formats.iter().any(|f| f.to_lowercase() == ext) {
+        Some(MediaType::Image)
+    } else if config.video_formats.iter().any(|f| f.to_lowercase() == ext) {
+        Some(MediaType::Video)
+    } else {
+        None
+    }
+}
+
 /// Scan a folder recursively for media files (images and videos)
 pub fn scan_folder(folder_path: &str, config: Option<FormatConfig>) -> Result<ScanResult, String> {
     let path = Path::new(folder_path);
@@ -0,0 +1,322 @@
+use crate::settings::SyncConfig;
+use crate::sync::SyncResult;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Images synced per run of the sync callback, so pausing takes effect between batches
+const BATCH_SIZE: usize = 25;
+
+/// Snapshot of the background sync for the UI
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncSchedulerStatus {
+    pub enabled: bool,
+    pub paused: bool,
+    pub running: bool,
+    /// Images waiting to be synced
+    pub pending: usize,
+    pub last_run_at: Option<DateTime<Utc>>,
+    /// None while sync is disabled or paused
+    pub next_run_at: Option<DateTime<Utc>>,
+    /// Totals of the last run that had something to sync
+    pub last_result: Option<SyncResult>,
+    pub last_error: Option<String>,
+}
+
+struct SchedulerState {
+    paused: bool,
+    running: bool,
+    /// Set when something asks for a run before the next scheduled one
+    requested: bool,
+    stopped: bool,
+    last_run_at: Option<DateTime<Utc>>,
+    next_run_at: DateTime<Utc>,
+    last_result: Option<SyncResult>,
+    last_error: Option<String>,
+}
+
+struct Shared {
+    state: Mutex<SchedulerState>,
+    wake: Condvar,
+    config: Box<dyn Fn() -> SyncConfig + Send + Sync>,
+    queue: Box<dyn Fn() -> Result<Vec<i64>, String> + Send + Sync>,
+}
+
+impl Shared {
+    fn interval(&self) -> Duration {
+        Duration::minutes(i64::from((self.config)().sync_interval.max(1)))
+    }
+}
+
+/// Syncs the images waiting in the queue in the background
+///
+/// The queue lives in the database, so images still waiting when the app quits
+/// are synced after the next start. Runs happen at startup, every
+/// `sync_interval` minutes and, with `auto_sync`, after library changes, as long
+/// as sync is enabled and not paused.
+pub struct SyncScheduler {
+    shared: Arc<Shared>,
+}
+
+impl SyncScheduler {
+    /// Create a scheduler that passes batches of the IDs returned by `queue` to `sync`
+    ///
+    /// `config` is consulted before every run so that settings changes take
+    /// effect without recreating the scheduler. Syncing happens on a dedicated
+    /// thread.
+    pub fn new<C, Q, F>(config: C, queue: Q, mut sync: F) -> Self
+    where
+        C: Fn() -> SyncConfig + Send + Sync + 'static,
+        Q: Fn() -> Result<Vec<i64>, String> + Send + Sync + 'static,
+        F: FnMut(Vec<i64>) -> Result<SyncResult, String> + Send + 'static,
+    {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(SchedulerState {
+                paused: false,
+                running: false,
+                // Catch up on images left waiting when the app last quit
+                requested: true,
+                stopped: false,
+                last_run_at: None,
+                next_run_at: Utc::now(),
+                last_result: None,
+                last_error: None,
+            }),
+            wake: Condvar::new(),
+            config: Box::new(config),
+            queue: Box::new(queue),
+        });
+
+        let thread_shared = Arc::clone(&shared);
+        std::thread::spawn(move || {
+            while let Some(image_ids) = Self::wait_for_run(&thread_shared) {
+                let outcome = Self::sync_batches(&thread_shared, image_ids, &mut sync);
+
+                let mut state = thread_shared.state.lock().unwrap();
+                state.running = false;
+                state.last_run_at = Some(Utc::now());
+                state.next_run_at = Utc::now() + thread_shared.interval();
+                match outcome {
+                    Ok(Some(result)) => {
+                        state.last_result = Some(result);
+                        state.last_error = None;
+                    }
+                    Ok(None) => state.last_error = None,
+                    Err(e) => {
+                        log::warn!("Background sync failed: {}", e);
+                        state.last_error = Some(e);
+                    }
+                }
+            }
+        });
+
+        Self { shared }
+    }
+
+    /// Block until a run is due and return the queue, or None once the scheduler is dropped
+    fn wait_for_run(shared: &Shared) -> Option<Result<Vec<i64>, String>> {
+        let mut state = shared.state.lock().unwrap();
+        loop {
+            if state.stopped {
+                return None;
+            }
+
+            let now = Utc::now();
+            if state.requested || now >= state.next_run_at {
+                state.requested = false;
+                let config = (shared.config)();
+                if config.enabled && !state.paused {
+                    state.running = true;
+                    drop(state);
+                    return Some((shared.queue)());
+                }
+                state.next_run_at = now + shared.interval();
+                continue;
+            }
+
+            let timeout = (state.next_run_at - now).to_std().unwrap_or_default();
+            state = shared.wake.wait_timeout(state, timeout).unwrap().0;
+        }
+    }
+
+    /// Sync the queue in batches, stopping early when paused or disabled
+    fn sync_batches<F>(
+        shared: &Shared,
+        image_ids: Result<Vec<i64>, String>,
+        sync: &mut F,
+    ) -> Result<Option<SyncResult>, String>
+    where
+        F: FnMut(Vec<i64>) -> Result<SyncResult, String>,
+    {
+        let image_ids = image_ids?;
+        if image_ids.is_empty() {
+            return Ok(None);
+        }
+
+        let mut total = SyncResult {
+            uploaded: 0,
+            skipped: 0,
+            excluded: 0,
+            failed: Vec::new(),
+        };
+        for batch in image_ids.chunks(BATCH_SIZE) {
+            if shared.state.lock().unwrap().paused || !(shared.config)().enabled {
+                break;
+            }
+
+            let result = sync(batch.to_vec())?;
+            total.uploaded += result.uploaded;
+            total.skipped += result.skipped;
+            total.excluded += result.excluded;
+            total.failed.extend(result.failed);
+        }
+
+        Ok(Some(total))
+    }
+
+    fn request_run(&self) {
+        self.shared.state.lock().unwrap().requested = true;
+        self.shared.wake.notify_all();
+    }
+
+    /// Sync new and changed images when `auto_sync` is on
+    pub fn library_changed(&self) {
+        if (self.shared.config)().auto_sync {
+            self.request_run();
+        }
+    }
+
+    /// Pick up changed sync settings, such as a new interval, and sync right away if just enabled
+    pub fn settings_changed(&self) {
+        self.request_run();
+    }
+
+    /// Stop syncing after the current batch until resumed
+    pub fn pause(&self) {
+        self.shared.state.lock().unwrap().paused = true;
+    }
+
+    /// Resume syncing, starting with whatever queued up while paused
+    pub fn resume(&self) {
+        self.shared.state.lock().unwrap().paused = false;
+        self.request_run();
+    }
+
+    pub fn status(&self) -> Result<SyncSchedulerStatus, String> {
+        let enabled = (self.shared.config)().enabled;
+        let pending = (self.shared.queue)()?.len();
+        let state = self.shared.state.lock().unwrap();
+
+        Ok(SyncSchedulerStatus {
+            enabled,
+            paused: state.paused,
+            running: state.running,
+            pending,
+            last_run_at: state.last_run_at,
+            next_run_at: (enabled && !state.paused).then_some(state.next_run_at),
+            last_result: state.last_result.clone(),
+            last_error: state.last_error.clone(),
+        })
+    }
+}
+
+impl Drop for SyncScheduler {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().stopped = true;
+        self.shared.wake.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc;
+
+    const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+    /// Scheduler over a fixed queue that reports each batch it syncs
+    fn scheduler(
+        enabled: Arc<AtomicBool>,
+        queue: Vec<i64>,
+    ) -> (SyncScheduler, mpsc::Receiver<Vec<i64>>) {
+        let (tx, rx) = mpsc::channel();
+        let scheduler = SyncScheduler::new(
+            move || SyncConfig {
+                enabled: enabled.load(Ordering::SeqCst),
+                auto_sync: true,
+                ..SyncConfig::default()
+            },
+            move || Ok(queue.clone()),
+            move |image_ids| {
+                let uploaded = image_ids.len();
+                tx.send(image_ids).unwrap();
+                Ok(SyncResult {
+                    uploaded,
+                    skipped: 0,
+                    excluded: 0,
+                    failed: Vec::new(),
+                })
+            },
+        );
+        (scheduler, rx)
+    }
+
+    /// Wait for the scheduler thread to finish its current run
+    fn wait_until_idle(scheduler: &SyncScheduler) {
+        let deadline = std::time::Instant::now() + TIMEOUT;
+        while scheduler.status().unwrap().running || scheduler.shared.state.lock().unwrap().requested {
+            assert!(std::time::Instant::now() < deadline, "scheduler did not finish");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_syncs_queue_in_batches_at_startup() {
+        let queue: Vec<i64> = (1..=30).collect();
+        let (scheduler, rx) = scheduler(Arc::new(AtomicBool::new(true)), queue);
+
+        assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), (1..=25).collect::<Vec<i64>>());
+        assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), (26..=30).collect::<Vec<i64>>());
+        wait_until_idle(&scheduler);
+
+        let status = scheduler.status().unwrap();
+        assert_eq!(status.last_result.unwrap().uploaded, 30);
+        assert!(status.last_run_at.is_some());
+        assert!(status.next_run_at.unwrap() > Utc::now() + Duration::minutes(59));
+        assert_eq!(status.pending, 30);
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        let (scheduler, rx) = scheduler(Arc::new(AtomicBool::new(true)), vec![1, 2]);
+        assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), vec![1, 2]);
+        wait_until_idle(&scheduler);
+
+        scheduler.pause();
+        scheduler.library_changed();
+        assert!(rx.recv_timeout(std::time::Duration::from_millis(200)).is_err());
+        let status = scheduler.status().unwrap();
+        assert!(status.paused);
+        assert!(status.next_run_at.is_none());
+
+        scheduler.resume();
+        assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), vec![1, 2]);
+        assert!(!scheduler.status().unwrap().paused);
+    }
+
+    #[test]
+    fn test_disabled_sync_never_runs() {
+        let enabled = Arc::new(AtomicBool::new(false));
+        let (scheduler, rx) = scheduler(enabled.clone(), vec![1]);
+
+        scheduler.library_changed();
+        assert!(rx.recv_timeout(std::time::Duration::from_millis(200)).is_err());
+        assert!(!scheduler.status().unwrap().enabled);
+
+        // Turning sync on syncs what queued up
+        enabled.store(true, Ordering::SeqCst);
+        scheduler.settings_changed();
+        assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), vec![1]);
+    }
+}
@@ -0,0 +1,778 @@
+use chrono::{DateTime, Duration, Months, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Bound;
+
+use crate::database::{ColorLabel, Flag, ImageFilter, MediaType, MAX_RATING};
+
+/// Filters understood by the query language
+const FIELDS: &[&str] = &[
+    "camera", "make", "lens", "tag", "type", "after", "before", "date", "duration", "aperture",
+    "f", "focal", "iso", "shutter", "flash", "near", "country", "region", "city", "rating",
+    "label", "flag", "favorite",
+];
+
+/// Radius used by `near:` when none is given
+const DEFAULT_NEAR_RADIUS_KM: f64 = 1.0;
+
+/// A search query that could not be parsed
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct QueryError {
+    /// What is wrong, phrased for the user
+    pub message: String,
+    /// Character offset of the offending term
+    pub position: usize,
+    /// Length of the offending term in characters
+    pub length: usize,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at position {})", self.message, self.position + 1)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// How a field is compared with its value
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+impl Op {
+    fn as_str(self) -> &'static str {
+        match self {
+            Op::Eq => ":",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+        }
+    }
+}
+
+/// One whitespace-separated term of a query, with its byte span
+#[derive(Debug)]
+struct Term<'a> {
+    /// Field name for `field:value` terms, None for free text
+    field: Option<&'a str>,
+    op: Op,
+    value: &'a str,
+    /// Raw term text, used verbatim for free text
+    raw: &'a str,
+    start: usize,
+}
+
+/// Parse a search query such as `tag:beach after:2023-06 type:video duration>30s`
+///
+/// Words that are not `field:value` filters are matched as free text, with
+/// double quotes grouping words into a phrase. Values containing spaces can be
+/// quoted too, as in `camera:"EOS R5"`. Supported filters:
+///
+/// - `camera:` and `make:` match the camera model and manufacturer
+/// - `tag:` matches a tag label; repeat it to match any of several tags
+/// - `type:image` or `type:video`
+/// - `after:`, `before:` and `date:` take `YYYY`, `YYYY-MM` or `YYYY-MM-DD`;
+///   `date` also accepts `>`, `>=`, `<` and `<=`
+/// - `duration>`, `duration>=`, `duration<` and `duration<=` take seconds or
+///   values like `90s`, `2m`, `1h30m` and `1:30`
+/// - `lens:` matches part of the lens model, as in `lens:35mm`
+/// - `aperture:` (or `f:`), `focal:`, `iso:` and `shutter:` match exposure
+///   settings and also accept `>`, `>=`, `<` and `<=`; they take values like
+///   `f/1.4`, `35mm`, `800` and `1/250` or `2s`
+/// - `flash:` takes `yes` or `no`
+/// - `near:lat,lon[,radius]` with the radius in `km` (default), `m` or `mi`
+/// - `country:`, `region:` and `city:` match place names resolved from GPS data
+/// - `rating:` matches a star rating from 0 to 5 and also accepts `>`, `>=`,
+///   `<` and `<=`
+/// - `label:` takes a color label, `flag:` takes `pick`, `reject` or
+///   `unflagged`, and `favorite:` takes `yes` or `no`
+pub fn parse_query(input: &str) -> Result<ImageFilter, QueryError> {
+    let mut filter = ImageFilter::default();
+    let mut text = Vec::new();
+    let mut dates: (Option<DateTime<Utc>>, Option<DateTime<Utc>>) = (None, None);
+
+    for term in tokenize(input)? {
+        let Some(field) = term.field else {
+            text.push(term.raw);
+            continue;
+        };
+        let error = |message: String| error_at(input, &term, message);
+
+        if term.value.is_empty() {
+            return Err(error(format!("Missing value for '{}'", field)));
+        }
+
+        let name = field.to_lowercase();
+        let allows_comparison = matches!(
+            name.as_str(),
+            "date" | "duration" | "rating" | "aperture" | "f" | "focal" | "iso" | "shutter"
+        );
+        if term.op != Op::Eq && !allows_comparison {
+            return Err(error(format!(
+                "'{}' does not support '{}', use '{}:'",
+                field,
+                term.op.as_str(),
+                field
+            )));
+        }
+
+        match name.as_str() {
+            "camera" => set_once(&mut filter.camera_model, term.value.to_string(), field)
+                .map_err(error)?,
+            "make" => set_once(&mut filter.camera_make, term.value.to_string(), field)
+                .map_err(error)?,
+            "tag" => filter
+                .tags
+                .get_or_insert_with(Vec::new)
+                .push(term.value.to_string()),
+            "type" => {
+                let media_type = parse_media_type(term.value).map_err(error)?;
+                set_once(&mut filter.media_type, media_type, field).map_err(error)?;
+            }
+            "after" | "before" | "date" => {
+                let (start, end) = parse_date_span(term.value).map_err(error)?;
+                let (from, until) = match (name.as_str(), term.op) {
+                    ("after", _) | ("date", Op::Ge) => (Some(start), None),
+                    ("before", _) | ("date", Op::Lt) => (None, Some(start)),
+                    ("date", Op::Gt) => (Some(end), None),
+                    ("date", Op::Le) => (None, Some(end)),
+                    _ => (Some(start), Some(end)),
+                };
+                // Repeated date filters narrow the range
+                if let Some(from) = from {
+                    dates.0 = Some(dates.0.map_or(from, |d| d.max(from)));
+                }
+                if let Some(until) = until {
+                    dates.1 = Some(dates.1.map_or(until, |d| d.min(until)));
+                }
+            }
+            "duration" => {
+                let seconds = parse_duration(term.value).map_err(error)?;
+                if term.op == Op::Eq {
+                    return Err(error(format!(
+                        "'{}' needs a comparison, for example {}>30s",
+                        field, field
+                    )));
+                }
+                narrow_range(&mut filter.duration_range, term.op, seconds, 0.0);
+            }
+            // Cameras round what they display, so `:` allows for a little difference
+            "aperture" | "f" => {
+                let aperture = parse_aperture(term.value).map_err(error)?;
+                narrow_range(&mut filter.aperture_range, term.op, aperture, 0.05);
+            }
+            "focal" => {
+                let focal_length = parse_focal_length(term.value).map_err(error)?;
+                narrow_range(&mut filter.focal_length_range, term.op, focal_length, 0.5);
+            }
+            "iso" => {
+                let iso = parse_iso(term.value).map_err(error)?;
+                narrow_range(&mut filter.iso_range, term.op, iso, 0.0);
+            }
+            "shutter" => {
+                let seconds = parse_exposure_time(term.value).map_err(error)?;
+                narrow_range(&mut filter.exposure_time_range, term.op, seconds, seconds * 0.01);
+            }
+            "lens" => set_once(&mut filter.lens, term.value.to_string(), field)
+                .map_err(error)?,
+            "flash" => {
+                let flash = parse_yes_no(term.value).map_err(error)?;
+                set_once(&mut filter.flash, flash, field).map_err(error)?;
+            }
+            "country" => set_once(&mut filter.country, term.value.to_string(), field)
+                .map_err(error)?,
+            "region" => set_once(&mut filter.region, term.value.to_string(), field)
+                .map_err(error)?,
+            "city" => set_once(&mut filter.city, term.value.to_string(), field)
+                .map_err(error)?,
+            "near" => {
+                let location = parse_location(term.value).map_err(error)?;
+                set_once(&mut filter.location, location, field).map_err(error)?;
+            }
+            "rating" => {
+                let rating = parse_rating(term.value).map_err(error)?;
+                // Repeated rating filters narrow the range
+                let (mut min, mut max) = filter.rating_range.unwrap_or((0, MAX_RATING));
+                match term.op {
+                    Op::Eq => (min, max) = (min.max(rating), max.min(rating)),
+                    Op::Ge => min = min.max(rating),
+                    Op::Gt => min = min.max(rating + 1),
+                    Op::Le => max = max.min(rating),
+                    Op::Lt if rating == 0 => {
+                        return Err(error(format!("'{}<0' can never match", field)))
+                    }
+                    Op::Lt => max = max.min(rating - 1),
+                }
+                filter.rating_range = Some((min, max));
+            }
+            "label" => {
+                let label = ColorLabel::parse(term.value).ok_or_else(|| {
+                    error(format!(
+                        "Unknown label '{}', expected red, yellow, green, blue or purple",
+                        term.value
+                    ))
+                })?;
+                set_once(&mut filter.color_label, label, field).map_err(error)?;
+            }
+            "flag" => {
+                let flag = parse_flag(term.value).map_err(error)?;
+                set_once(&mut filter.flag, flag, field).map_err(error)?;
+            }
+            "favorite" => {
+                let favorite = parse_yes_no(term.value).map_err(error)?;
+                set_once(&mut filter.favorite, favorite, field).map_err(error)?;
+            }
+            _ => {
+                return Err(error(format!(
+                    "Unknown filter '{}', expected one of: {}",
+                    field,
+                    FIELDS.join(", ")
+                )))
+            }
+        }
+    }
+
+    if dates != (None, None) {
+        // The database range is inclusive, so stop just short of the exclusive end
+        filter.date_range = Some((
+            dates.0.unwrap_or_else(earliest_date),
+            dates.1.map_or_else(latest_date, |end| end - Duration::nanoseconds(1)),
+        ));
+    }
+
+    if !text.is_empty() {
+        filter.text = Some(text.join(" "));
+    }
+
+    Ok(filter)
+}
+
+/// Split a query into terms, keeping quoted sections together
+fn tokenize(input: &str) -> Result<Vec<Term<'_>>, QueryError> {
+    let mut terms = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut end = input.len();
+        let mut quote_start = None;
+        while let Some(&(i, c)) = chars.peek() {
+            if c == '"' {
+                quote_start = match quote_start {
+                    Some(_) => None,
+                    None => Some(i),
+                };
+            } else if c.is_whitespace() && quote_start.is_none() {
+                end = i;
+                break;
+            }
+            chars.next();
+        }
+
+        if let Some(quote) = quote_start {
+            return Err(QueryError {
+                message: "Missing closing quote".to_string(),
+                position: input[..quote].chars().count(),
+                length: 1,
+            });
+        }
+
+        terms.push(parse_term(&input[start..end], start));
+    }
+
+    Ok(terms)
+}
+
+/// Split a term into field, operator and unquoted value
+fn parse_term(raw: &str, start: usize) -> Term<'_> {
+    let free_text = Term {
+        field: None,
+        op: Op::Eq,
+        value: raw,
+        raw,
+        start,
+    };
+
+    let Some(split) = raw.find([':', '=', '<', '>']) else {
+        return free_text;
+    };
+    let field = &raw[..split];
+    // Things like "12:30" or "\"a:b\"" are text, not filters
+    if field.is_empty() || !field.chars().all(|c| c.is_ascii_alphabetic()) {
+        return free_text;
+    }
+
+    // GitHub-style `field:>value` is accepted alongside `field>value`
+    let rest = &raw[split..];
+    let rest = rest
+        .strip_prefix(':')
+        .filter(|r| r.starts_with(['<', '>']))
+        .unwrap_or(rest);
+    let (op, value) = [(">=", Op::Ge), ("<=", Op::Le), (">", Op::Gt), ("<", Op::Lt), (":", Op::Eq), ("=", Op::Eq)]
+        .iter()
+        .find_map(|(prefix, op)| rest.strip_prefix(prefix).map(|value| (*op, value)))
+        .unwrap_or((Op::Eq, rest));
+
+    let value = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value);
+
+    Term {
+        field: Some(field),
+        op,
+        value,
+        raw,
+        start,
+    }
+}
+
+fn error_at(input: &str, term: &Term, message: String) -> QueryError {
+    QueryError {
+        message,
+        position: input[..term.start].chars().count(),
+        length: term.raw.chars().count(),
+    }
+}
+
+fn set_once<T>(slot: &mut Option<T>, value: T, field: &str) -> Result<(), String> {
+    if slot.is_some() {
+        return Err(format!("'{}' can only be used once", field));
+    }
+    *slot = Some(value);
+    Ok(())
+}
+
+/// Narrow a numeric range by one comparison; `:` matches values within `tolerance`
+///
+/// Each comparison is intersected with the range so far, so contradicting
+/// terms such as `iso>800 iso:100` match nothing.
+fn narrow_range(range: &mut Option<(Bound<f64>, Bound<f64>)>, op: Op, value: f64, tolerance: f64) {
+    let (lower, upper) = range.unwrap_or((Bound::Unbounded, Bound::Unbounded));
+    let (new_lower, new_upper) = match op {
+        Op::Eq => (Bound::Included(value - tolerance), Bound::Included(value + tolerance)),
+        Op::Gt => (Bound::Excluded(value), Bound::Unbounded),
+        Op::Ge => (Bound::Included(value), Bound::Unbounded),
+        Op::Lt => (Bound::Unbounded, Bound::Excluded(value)),
+        Op::Le => (Bound::Unbounded, Bound::Included(value)),
+    };
+    *range = Some((
+        tighter_bound(lower, new_lower, true),
+        tighter_bound(upper, new_upper, false),
+    ));
+}
+
+/// The more restrictive of two lower (or upper) bounds, preferring the exclusive one on a tie
+fn tighter_bound(a: Bound<f64>, b: Bound<f64>, lower: bool) -> Bound<f64> {
+    match (a, b) {
+        (Bound::Unbounded, bound) | (bound, Bound::Unbounded) => bound,
+        (Bound::Included(x) | Bound::Excluded(x), Bound::Included(y) | Bound::Excluded(y)) => {
+            if x == y {
+                if matches!(a, Bound::Excluded(_)) { a } else { b }
+            } else if (x > y) == lower {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+fn parse_media_type(value: &str) -> Result<MediaType, String> {
+    match value.to_lowercase().as_str() {
+        "image" | "images" | "photo" | "photos" => Ok(MediaType::Image),
+        "video" | "videos" => Ok(MediaType::Video),
+        _ => Err(format!("Unknown type '{}', expected image or video", value)),
+    }
+}
+
+fn parse_rating(value: &str) -> Result<u8, String> {
+    value
+        .parse::<u8>()
+        .ok()
+        .filter(|rating| *rating <= MAX_RATING)
+        .ok_or_else(|| format!("Invalid rating '{}', expected 0 to {}", value, MAX_RATING))
+}
+
+fn parse_flag(value: &str) -> Result<Flag, String> {
+    match value.to_lowercase().as_str() {
+        "pick" | "picked" => Ok(Flag::Pick),
+        "reject" | "rejected" => Ok(Flag::Reject),
+        "unflagged" | "none" => Ok(Flag::Unflagged),
+        _ => Err(format!("Unknown flag '{}', expected pick, reject or unflagged", value)),
+    }
+}
+
+fn parse_yes_no(value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "yes" | "true" => Ok(true),
+        "no" | "false" => Ok(false),
+        _ => Err(format!("Invalid value '{}', expected yes or no", value)),
+    }
+}
+
+/// Parse a positive number, after stripping a unit prefix or suffix
+fn parse_positive(value: &str, prefix: &str, suffix: &str) -> Option<f64> {
+    let lower = value.to_lowercase();
+    let number = lower.strip_prefix(prefix).unwrap_or(&lower);
+    let number = number.strip_suffix(suffix).unwrap_or(number);
+    number.parse::<f64>().ok().filter(|n| n.is_finite() && *n > 0.0)
+}
+
+/// Parse an f-number such as `1.4`, `f1.4` or `f/1.4`
+fn parse_aperture(value: &str) -> Result<f64, String> {
+    let value_without_slash = value.replacen('/', "", 1);
+    parse_positive(&value_without_slash, "f", "")
+        .ok_or_else(|| format!("Invalid aperture '{}', expected a value like f/1.4", value))
+}
+
+/// Parse a focal length in millimeters, such as `35` or `35mm`
+fn parse_focal_length(value: &str) -> Result<f64, String> {
+    parse_positive(value, "", "mm")
+        .ok_or_else(|| format!("Invalid focal length '{}', expected a value like 35mm", value))
+}
+
+fn parse_iso(value: &str) -> Result<f64, String> {
+    value
+        .parse::<u32>()
+        .ok()
+        .filter(|iso| *iso > 0)
+        .map(|iso| iso as f64)
+        .ok_or_else(|| format!("Invalid ISO '{}', expected a value like 800", value))
+}
+
+/// Parse an exposure time such as `1/250`, `0.5` or `2s` into seconds
+fn parse_exposure_time(value: &str) -> Result<f64, String> {
+    let invalid = || format!("Invalid shutter speed '{}', expected a value like 1/250 or 2s", value);
+
+    match value.split_once('/') {
+        Some((numerator, denominator)) => {
+            let numerator = parse_positive(numerator, "", "").ok_or_else(invalid)?;
+            let denominator = parse_positive(denominator, "", "s").ok_or_else(invalid)?;
+            Ok(numerator / denominator)
+        }
+        None => parse_positive(value, "", "s").ok_or_else(invalid),
+    }
+}
+
+/// Parse `YYYY`, `YYYY-MM` or `YYYY-MM-DD` into the UTC span it covers
+fn parse_date_span(value: &str) -> Result<(DateTime<Utc>, DateTime<Utc>), String> {
+    let invalid = || format!("Invalid date '{}', expected YYYY, YYYY-MM or YYYY-MM-DD", value);
+
+    let parts = value
+        .split('-')
+        .map(|p| p.parse::<u32>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Four-digit years keep stored dates comparable as text
+    if !(1..=9999).contains(&parts[0]) {
+        return Err(invalid());
+    }
+
+    let (start, end) = match parts[..] {
+        [year] => {
+            let start = NaiveDate::from_ymd_opt(year as i32, 1, 1).ok_or_else(invalid)?;
+            (start, start.checked_add_months(Months::new(12)))
+        }
+        [year, month] => {
+            let start = NaiveDate::from_ymd_opt(year as i32, month, 1).ok_or_else(invalid)?;
+            (start, start.checked_add_months(Months::new(1)))
+        }
+        [year, month, day] => {
+            let start = NaiveDate::from_ymd_opt(year as i32, month, day).ok_or_else(invalid)?;
+            (start, start.succ_opt())
+        }
+        _ => return Err(invalid()),
+    };
+
+    let midnight = |date: NaiveDate| date.and_time(Default::default()).and_utc();
+    // The last day of year 9999 has no following day, so end at the latest date instead
+    Ok((midnight(start), end.map_or_else(latest_date, midnight)))
+}
+
+/// Parse seconds, `1h30m`-style or `1:30`-style durations into seconds
+fn parse_duration(value: &str) -> Result<f64, String> {
+    let invalid = || format!("Invalid duration '{}', expected a value like 30s, 2m, 1h30m or 1:30", value);
+    let number = |s: &str| {
+        s.parse::<f64>()
+            .ok()
+            .filter(|n| n.is_finite() && *n >= 0.0)
+            .ok_or_else(invalid)
+    };
+
+    if value.contains(':') {
+        return value
+            .split(':')
+            .try_fold(0.0, |total, part| Ok(total * 60.0 + number(part)?));
+    }
+
+    // A bare number is seconds
+    if let Ok(seconds) = number(value) {
+        return Ok(seconds);
+    }
+
+    let mut total = 0.0;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let unit_start = rest
+            .find(|c: char| c.is_ascii_alphabetic())
+            .ok_or_else(invalid)?;
+        let unit_end = rest[unit_start..]
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .map_or(rest.len(), |i| unit_start + i);
+
+        let amount = number(&rest[..unit_start])?;
+        let scale = match &rest[unit_start..unit_end] {
+            "s" | "sec" => 1.0,
+            "m" | "min" => 60.0,
+            "h" | "hr" => 3600.0,
+            _ => return Err(invalid()),
+        };
+
+        total += amount * scale;
+        rest = &rest[unit_end..];
+    }
+
+    Ok(total)
+}
+
+/// Parse `lat,lon[,radius]` with the radius in km, m or mi
+fn parse_location(value: &str) -> Result<(f64, f64, f64), String> {
+    let invalid = || format!("Invalid location '{}', expected near:lat,lon or near:lat,lon,5km", value);
+
+    let parts: Vec<&str> = value.split(',').map(str::trim).collect();
+    let (lat, lon, radius) = match parts[..] {
+        [lat, lon] => (lat, lon, None),
+        [lat, lon, radius] => (lat, lon, Some(radius)),
+        _ => return Err(invalid()),
+    };
+
+    let lat: f64 = lat.parse().map_err(|_| invalid())?;
+    let lon: f64 = lon.parse().map_err(|_| invalid())?;
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return Err(format!(
+            "Location '{}' is out of range, latitude must be within ±90 and longitude within ±180",
+            value
+        ));
+    }
+
+    let radius_km = match radius {
+        None => DEFAULT_NEAR_RADIUS_KM,
+        Some(radius) => {
+            let unit_start = radius
+                .find(|c: char| c.is_ascii_alphabetic())
+                .unwrap_or(radius.len());
+            let amount: f64 = radius[..unit_start].parse().map_err(|_| invalid())?;
+            let scale = match &radius[unit_start..] {
+                "" | "km" => 1.0,
+                "m" => 0.001,
+                "mi" => 1.609_344,
+                _ => return Err(invalid()),
+            };
+            if !(amount.is_finite() && amount > 0.0) {
+                return Err(format!("Radius in '{}' must be greater than zero", value));
+            }
+            amount * scale
+        }
+    };
+
+    Ok((lat, lon, radius_km))
+}
+
+/// Bounds used for open-ended date ranges
+fn earliest_date() -> DateTime<Utc> {
+    NaiveDate::from_ymd_opt(1, 1, 1)
+        .unwrap()
+        .and_time(Default::default())
+        .and_utc()
+}
+
+fn latest_date() -> DateTime<Utc> {
+    NaiveDate::from_ymd_opt(9999, 12, 31)
+        .unwrap()
+        .and_hms_opt(23, 59, 59)
+        .unwrap()
+        .and_utc()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn date(y: i32, m: u32, d: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, 0, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_parse_full_query() {
+        let filter = parse_query(
+            r#"camera:"X100V" tag:beach after:2023-06 type:video duration>30s near:48.85,2.35,5km sunset"#,
+        )
+        .unwrap();
+
+        assert_eq!(filter.camera_model.as_deref(), Some("X100V"));
+        assert_eq!(filter.tags, Some(vec!["beach".to_string()]));
+        assert_eq!(filter.media_type, Some(MediaType::Video));
+        assert_eq!(filter.duration_range, Some((Bound::Excluded(30.0), Bound::Unbounded)));
+        assert_eq!(filter.location, Some((48.85, 2.35, 5.0)));
+        assert_eq!(filter.text.as_deref(), Some("sunset"));
+
+        let places = parse_query(r#"country:France city:"Le Havre""#).unwrap();
+        assert_eq!(places.country.as_deref(), Some("France"));
+        assert_eq!(places.city.as_deref(), Some("Le Havre"));
+
+        let (start, end) = filter.date_range.unwrap();
+        assert_eq!(start, date(2023, 6, 1));
+        assert_eq!(end, latest_date());
+    }
+
+    #[test]
+    fn test_free_text_keeps_phrases() {
+        let filter = parse_query(r#""golden gate" bridge  12:30"#).unwrap();
+        assert_eq!(filter.text.as_deref(), Some(r#""golden gate" bridge 12:30"#));
+
+        let filter = parse_query("   ").unwrap();
+        assert!(filter.text.is_none());
+    }
+
+    #[test]
+    fn test_date_filters() {
+        let range = |q: &str| parse_query(q).unwrap().date_range.unwrap();
+        let just_before = |d: DateTime<Utc>| d - Duration::nanoseconds(1);
+
+        assert_eq!(range("date:2024"), (date(2024, 1, 1), just_before(date(2025, 1, 1))));
+        assert_eq!(range("date:2024-02-29"), (date(2024, 2, 29), just_before(date(2024, 3, 1))));
+        assert_eq!(range("before:2024-03"), (earliest_date(), just_before(date(2024, 3, 1))));
+        assert_eq!(range("date<=2024-03"), (earliest_date(), just_before(date(2024, 4, 1))));
+        assert_eq!(range("date:>2024-12"), (date(2025, 1, 1), latest_date()));
+        assert_eq!(
+            range("after:2023 before:2024-06 after:2023-03"),
+            (date(2023, 3, 1), just_before(date(2024, 6, 1)))
+        );
+    }
+
+    #[test]
+    fn test_duration_values() {
+        let lower = |q: &str| match parse_query(q).unwrap().duration_range.unwrap().0 {
+            Bound::Included(s) | Bound::Excluded(s) => s,
+            Bound::Unbounded => panic!("no lower bound"),
+        };
+
+        assert_eq!(lower("duration>45"), 45.0);
+        assert_eq!(lower("duration>2m"), 120.0);
+        assert_eq!(lower("duration>1h30m"), 5400.0);
+        assert_eq!(lower("duration>=1:02:03"), 3723.0);
+        assert_eq!(lower("duration>1.5min"), 90.0);
+
+        let filter = parse_query("duration>=10s duration<=1m").unwrap();
+        assert_eq!(filter.duration_range, Some((Bound::Included(10.0), Bound::Included(60.0))));
+    }
+
+    #[test]
+    fn test_near_units() {
+        let near = |q: &str| parse_query(q).unwrap().location.unwrap();
+
+        assert_eq!(near("near:48.85,2.35"), (48.85, 2.35, DEFAULT_NEAR_RADIUS_KM));
+        assert_eq!(near("near:48.85,2.35,500m"), (48.85, 2.35, 0.5));
+        assert_eq!(near("near:-33.9,151.2,2").2, 2.0);
+        assert!((near("near:0,0,10mi").2 - 16.09344).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_curation_filters() {
+        let rating = |q: &str| parse_query(q).unwrap().rating_range.unwrap();
+
+        assert_eq!(rating("rating:3"), (3, 3));
+        assert_eq!(rating("rating>=4"), (4, 5));
+        assert_eq!(rating("rating>2 rating<5"), (3, 4));
+        assert_eq!(rating("rating:<=1"), (0, 1));
+
+        let filter = parse_query("label:Red flag:pick favorite:yes").unwrap();
+        assert_eq!(filter.color_label, Some(ColorLabel::Red));
+        assert_eq!(filter.flag, Some(Flag::Pick));
+        assert_eq!(filter.favorite, Some(true));
+        assert_eq!(parse_query("flag:unflagged").unwrap().flag, Some(Flag::Unflagged));
+
+        let message = |q: &str| parse_query(q).unwrap_err().message;
+        assert!(message("rating:6").contains("Invalid rating"));
+        assert!(message("rating<0").contains("never match"));
+        assert!(message("label:orange").contains("Unknown label"));
+        assert!(message("flag:maybe").contains("Unknown flag"));
+        assert!(message("favorite:sometimes").contains("expected yes or no"));
+        assert!(message("label>red").contains("does not support '>'"));
+    }
+
+    #[test]
+    fn test_exposure_filters() {
+        let filter = parse_query("f:1.4 lens:35mm iso<=800 flash:no").unwrap();
+        assert_eq!(filter.aperture_range, Some((Bound::Included(1.4 - 0.05), Bound::Included(1.4 + 0.05))));
+        assert_eq!(filter.lens.as_deref(), Some("35mm"));
+        assert_eq!(filter.iso_range, Some((Bound::Unbounded, Bound::Included(800.0))));
+        assert_eq!(filter.flash, Some(false));
+
+        let aperture = |q: &str| parse_query(q).unwrap().aperture_range.unwrap();
+        assert_eq!(aperture("aperture:f/2.8").0, Bound::Included(2.8 - 0.05));
+        assert_eq!(aperture("aperture>=F4 aperture<8"), (Bound::Included(4.0), Bound::Excluded(8.0)));
+
+        let filter = parse_query("focal>=85mm shutter<1/250").unwrap();
+        assert_eq!(filter.focal_length_range, Some((Bound::Included(85.0), Bound::Unbounded)));
+        assert_eq!(filter.exposure_time_range, Some((Bound::Unbounded, Bound::Excluded(0.004))));
+        let shutter = parse_query("shutter:2s").unwrap().exposure_time_range.unwrap();
+        assert_eq!(shutter, (Bound::Included(2.0 - 0.02), Bound::Included(2.0 + 0.02)));
+
+        let message = |q: &str| parse_query(q).unwrap_err().message;
+        assert!(message("f:wide").contains("Invalid aperture"));
+        assert!(message("focal:0mm").contains("Invalid focal length"));
+        assert!(message("iso:1.5").contains("Invalid ISO"));
+        assert!(message("shutter:1/0").contains("Invalid shutter speed"));
+        assert!(message("lens>35mm").contains("does not support '>'"));
+    }
+
+    #[test]
+    fn test_comparisons_intersect() {
+        let iso = |q: &str| parse_query(q).unwrap().iso_range.unwrap();
+        assert_eq!(iso("iso>800 iso:100"), (Bound::Excluded(800.0), Bound::Included(100.0)));
+        assert_eq!(iso("iso:400 iso>=100"), (Bound::Included(400.0), Bound::Included(400.0)));
+        assert_eq!(iso("iso>=100 iso>800 iso>=200"), (Bound::Excluded(800.0), Bound::Unbounded));
+        assert_eq!(iso("iso<=800 iso<800"), (Bound::Unbounded, Bound::Excluded(800.0)));
+
+        let filter = parse_query("f>=2.8 f<=8").unwrap();
+        assert_eq!(filter.aperture_range, Some((Bound::Included(2.8), Bound::Included(8.0))));
+        assert!(parse_query("fstop:2").unwrap_err().message.contains("aperture, f, focal"));
+    }
+
+    #[test]
+    fn test_parse_errors_point_at_term() {
+        let error = parse_query("tag:beach camra:X100V").unwrap_err();
+        assert_eq!(error.position, 10);
+        assert_eq!(error.length, 11);
+        assert!(error.message.contains("Unknown filter 'camra'"));
+        assert!(error.to_string().ends_with("(at position 11)"));
+
+        let error = parse_query(r#"beach camera:"EOS R5"#).unwrap_err();
+        assert_eq!(error.message, "Missing closing quote");
+        assert_eq!(error.position, 13);
+
+        let message = |q: &str| parse_query(q).unwrap_err().message;
+        assert!(message("after:2023-13").contains("Invalid date"));
+        assert!(message("duration>30x").contains("Invalid duration"));
+        assert!(message("duration:30s").contains("needs a comparison"));
+        assert!(message("tag>beach").contains("does not support '>'"));
+        assert!(message("type:audio").contains("expected image or video"));
+        assert!(message("near:91,0").contains("out of range"));
+        assert!(message("near:0,0,-5km").contains("greater than zero"));
+        assert!(message("camera:a camera:b").contains("only be used once"));
+        assert!(message("tag:").contains("Missing value"));
+    }
+}
@@ -17,6 +17,48 @@ pub struct AppSettings {
     
     /// Format configuration for media files
     pub format_config: FormatConfig,
+    
+    /// Library folders watched for new, changed and deleted files
+    #[serde(default)]
+    pub watched_folders: Vec<String>,
+
+    /// Trash behaviour
+    #[serde(default)]
+    pub trash_config: TrashConfig,
+
+    /// Writing tags, ratings and descriptions back to the originals
+    #[serde(default)]
+    pub metadata_config: MetadataConfig,
+}
+
+/// Trash settings
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TrashConfig {
+    /// Days before trashed images are permanently deleted, 0 to keep them until the trash is emptied
+    pub retention_days: u32,
+
+    /// Whether to move originals into the trash folder instead of leaving them in place
+    pub move_files: bool,
+}
+
+/// Metadata write-back settings
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct MetadataConfig {
+    /// Where tags, ratings and descriptions are written when they change
+    pub write_back: WriteBackMode,
+}
+
+/// Destination of metadata written back to the originals
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WriteBackMode {
+    /// Metadata stays in the library database only
+    #[default]
+    Off,
+    /// XMP sidecar files next to the originals
+    Sidecar,
+    /// XMP embedded in JPEG originals, with sidecars for every other format
+    Embedded,
 }
 
 /// Format configuration for supported media types
@@ -44,8 +86,51 @@ pub struct SyncConfig {
     /// Upload quality: "original", "high", or "medium"
     pub upload_quality: String,
     
-    /// Glob patterns for files to exclude from sync
+    /// Glob patterns for files to exclude from sync, following .gitignore
+    /// conventions as described on `sync::ExcludeFilter`
     pub exclude_patterns: Vec<String>,
+
+    /// Whether exclude patterns distinguish upper and lower case
+    #[serde(default)]
+    pub exclude_case_sensitive: bool,
+
+    /// Where synced files are stored
+    #[serde(default)]
+    pub storage: StorageConfig,
+}
+
+/// Storage backend that files are synced to
+///
+/// Secrets such as the S3 secret key or the WebDAV password are kept in the
+/// system keychain rather than in the settings file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StorageConfig {
+    /// Google Drive, signed in with OAuth
+    #[default]
+    GoogleDrive,
+    /// Amazon S3 or a compatible service such as MinIO
+    S3 {
+        /// Service URL, such as "https://s3.eu-west-1.amazonaws.com" or "http://nas:9000"
+        endpoint: String,
+        bucket: String,
+        region: String,
+        /// Key prefix that synced files are stored under, such as "cura/"
+        #[serde(default)]
+        prefix: String,
+        access_key_id: String,
+    },
+    /// WebDAV server such as Nextcloud
+    #[serde(rename = "webdav")]
+    WebDav {
+        /// Folder that synced files are stored in
+        url: String,
+        username: String,
+    },
+    /// Folder on this computer or a mounted NAS share
+    Local {
+        path: String,
+    },
 }
 
 impl Default for AppSettings {
@@ -55,6 +140,18 @@ impl Default for AppSettings {
             ai_model: "mobilenet".to_string(),
             sync_config: SyncConfig::default(),
             format_config: FormatConfig::default(),
+            watched_folders: vec![],
+            trash_config: TrashConfig::default(),
+            metadata_config: MetadataConfig::default(),
+        }
+    }
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        Self {
+            retention_days: 30,
+            move_files: false,
         }
     }
 }
@@ -67,6 +164,7 @@ impl Default for FormatConfig {
                 "jpeg".to_string(),
                 "png".to_string(),
                 "heic".to_string(),
+                "heif".to_string(),
                 "raw".to_string(),
                 "cr2".to_string(),
                 "nef".to_string(),
@@ -102,6 +200,8 @@ impl Default for SyncConfig {
             sync_interval: 60, // 60 minutes
             upload_quality: "high".to_string(),
             exclude_patterns: vec![],
+            exclude_case_sensitive: false,
+            storage: StorageConfig::default(),
         }
     }
 }
@@ -196,6 +296,14 @@ impl SettingsManager {
         if settings.sync_config.sync_interval < 1 {
             return Err("Sync interval must be at least 1 minute.".to_string());
         }
+
+        // Validate exclude patterns
+        crate::sync::ExcludeFilter::new(
+            &settings.sync_config.exclude_patterns,
+            settings.sync_config.exclude_case_sensitive,
+        )?;
+
+        Self::validate_storage_config(&settings.sync_config.storage)?;
         
         // Validate thumbnail cache path is not empty
         if settings.thumbnail_cache_path.trim().is_empty() {
@@ -208,6 +316,36 @@ impl SettingsManager {
         Ok(())
     }
     
+    /// Validate that the storage backend has everything it needs to connect
+    fn validate_storage_config(config: &StorageConfig) -> Result<(), String> {
+        let required = |value: &str, name: &str| {
+            if value.trim().is_empty() {
+                Err(format!("Storage {} cannot be empty.", name))
+            } else {
+                Ok(())
+            }
+        };
+        let web_url = |value: &str, name: &str| match url::Url::parse(value) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Ok(()),
+            _ => Err(format!("Storage {} '{}' must be an http or https URL.", name, value)),
+        };
+
+        match config {
+            StorageConfig::GoogleDrive => Ok(()),
+            StorageConfig::S3 { endpoint, bucket, region, access_key_id, .. } => {
+                web_url(endpoint, "endpoint")?;
+                required(bucket, "bucket")?;
+                required(region, "region")?;
+                required(access_key_id, "access key ID")
+            }
+            StorageConfig::WebDav { url, username } => {
+                web_url(url, "URL")?;
+                required(username, "username")
+            }
+            StorageConfig::Local { path } => required(path, "folder"),
+        }
+    }
+
     /// Validate format configuration
     fn validate_format_config(config: &FormatConfig) -> Result<(), String> {
         // Validate image formats
@@ -328,6 +466,9 @@ mod tests {
         assert_eq!(settings.sync_config.enabled, false);
         assert_eq!(settings.sync_config.sync_interval, 60);
         assert_eq!(settings.sync_config.upload_quality, "high");
+        assert_eq!(settings.trash_config.retention_days, 30);
+        assert!(!settings.trash_config.move_files);
+        assert_eq!(settings.metadata_config.write_back, WriteBackMode::Off);
     }
     
     #[test]
@@ -341,8 +482,13 @@ mod tests {
                 sync_interval: 30,
                 upload_quality: "original".to_string(),
                 exclude_patterns: vec!["*.tmp".to_string()],
+                exclude_case_sensitive: false,
+                storage: StorageConfig::default(),
             },
             format_config: FormatConfig::default(),
+            watched_folders: vec![],
+            trash_config: TrashConfig::default(),
+            metadata_config: MetadataConfig::default(),
         };
         
         assert!(SettingsManager::validate_settings(&settings).is_ok());
@@ -355,6 +501,9 @@ mod tests {
             ai_model: "invalid_model".to_string(),
             sync_config: SyncConfig::default(),
             format_config: FormatConfig::default(),
+            watched_folders: vec![],
+            trash_config: TrashConfig::default(),
+            metadata_config: MetadataConfig::default(),
         };
         
         let result = SettingsManager::validate_settings(&settings);
@@ -373,8 +522,13 @@ mod tests {
                 sync_interval: 60,
                 upload_quality: "ultra".to_string(),
                 exclude_patterns: vec![],
+                exclude_case_sensitive: false,
+                storage: StorageConfig::default(),
             },
             format_config: FormatConfig::default(),
+            watched_folders: vec![],
+            trash_config: TrashConfig::default(),
+            metadata_config: MetadataConfig::default(),
         };
         
         let result = SettingsManager::validate_settings(&settings);
@@ -393,8 +547,13 @@ mod tests {
                 sync_interval: 0,
                 upload_quality: "high".to_string(),
                 exclude_patterns: vec![],
+                exclude_case_sensitive: false,
+                storage: StorageConfig::default(),
             },
             format_config: FormatConfig::default(),
+            watched_folders: vec![],
+            trash_config: TrashConfig::default(),
+            metadata_config: MetadataConfig::default(),
         };
         
         let result = SettingsManager::validate_settings(&settings);
@@ -402,6 +561,27 @@ mod tests {
         assert!(result.unwrap_err().contains("at least 1 minute"));
     }
     
+    #[test]
+    fn test_validate_settings_invalid_exclude_pattern() {
+        for pattern in ["Screenshots/[a-z", "!", "  ", "/Private/*.jpg"] {
+            let settings = AppSettings {
+                thumbnail_cache_path: "/tmp/cache".to_string(),
+                ai_model: "clip".to_string(),
+                sync_config: SyncConfig {
+                    exclude_patterns: vec!["**/*.tmp".to_string(), pattern.to_string()],
+                    ..SyncConfig::default()
+                },
+                format_config: FormatConfig::default(),
+                watched_folders: vec![],
+                trash_config: TrashConfig::default(),
+                metadata_config: MetadataConfig::default(),
+            };
+
+            let result = SettingsManager::validate_settings(&settings);
+            assert!(result.unwrap_err().contains("Invalid exclude pattern"), "{:?} was accepted", pattern);
+        }
+    }
+    
     #[test]
     fn test_validate_settings_empty_cache_path() {
         let settings = AppSettings {
@@ -409,6 +589,9 @@ mod tests {
             ai_model: "mobilenet".to_string(),
             sync_config: SyncConfig::default(),
             format_config: FormatConfig::default(),
+            watched_folders: vec![],
+            trash_config: TrashConfig::default(),
+            metadata_config: MetadataConfig::default(),
         };
         
         let result = SettingsManager::validate_settings(&settings);
@@ -432,8 +615,13 @@ mod tests {
                 sync_interval: 45,
                 upload_quality: "medium".to_string(),
                 exclude_patterns: vec!["*.raw".to_string()],
+                exclude_case_sensitive: false,
+                storage: StorageConfig::default(),
             },
             format_config: FormatConfig::default(),
+            watched_folders: vec![],
+            trash_config: TrashConfig::default(),
+            metadata_config: MetadataConfig::default(),
         };
         
         // Save settings
@@ -469,8 +657,9 @@ mod tests {
         assert!(config.image_formats.contains(&"jpeg".to_string()));
         assert!(config.image_formats.contains(&"png".to_string()));
         assert!(config.image_formats.contains(&"heic".to_string()));
+        assert!(config.image_formats.contains(&"heif".to_string()));
         assert!(config.image_formats.contains(&"raw".to_string()));
-        assert_eq!(config.image_formats.len(), 13);
+        assert_eq!(config.image_formats.len(), 14);
         
         // Verify default video formats
         assert!(config.video_formats.contains(&"mp4".to_string()));
@@ -556,6 +745,45 @@ mod tests {
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("cannot contain dots"));
     }
+
+    #[test]
+    fn test_validate_storage_config() {
+        let s3 = |endpoint: &str, bucket: &str| StorageConfig::S3 {
+            endpoint: endpoint.to_string(),
+            bucket: bucket.to_string(),
+            region: "us-east-1".to_string(),
+            prefix: String::new(),
+            access_key_id: "minio".to_string(),
+        };
+        assert!(SettingsManager::validate_storage_config(&s3("http://nas:9000", "photos")).is_ok());
+        let result = SettingsManager::validate_storage_config(&s3("nas:9000", "photos"));
+        assert!(result.unwrap_err().contains("http or https URL"));
+        let result = SettingsManager::validate_storage_config(&s3("http://nas:9000", " "));
+        assert!(result.unwrap_err().contains("bucket cannot be empty"));
+
+        let webdav = StorageConfig::WebDav {
+            url: "https://cloud.example.com/remote.php/dav/files/alex/Cura".to_string(),
+            username: String::new(),
+        };
+        let result = SettingsManager::validate_storage_config(&webdav);
+        assert!(result.unwrap_err().contains("username cannot be empty"));
+
+        let local = StorageConfig::Local { path: "/mnt/nas/photos".to_string() };
+        assert!(SettingsManager::validate_storage_config(&local).is_ok());
+    }
+
+    #[test]
+    fn test_storage_config_serialization() {
+        // Settings saved before storage backends existed sync to Google Drive
+        let json = r#"{"enabled":true,"auto_sync":false,"sync_interval":60,"upload_quality":"high","exclude_patterns":[]}"#;
+        let config: SyncConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(config.storage, StorageConfig::GoogleDrive);
+
+        let local = StorageConfig::Local { path: "/mnt/nas".to_string() };
+        let json = serde_json::to_string(&local).unwrap();
+        assert_eq!(json, r#"{"type":"local","path":"/mnt/nas"}"#);
+        assert_eq!(serde_json::from_str::<StorageConfig>(&json).unwrap(), local);
+    }
 }
 
 
@@ -574,15 +802,18 @@ mod property_tests {
             any::<bool>(),
             1u32..1000,
             prop_oneof!["original", "high", "medium"],
-            prop::collection::vec(any::<String>(), 0..5),
+            prop::collection::vec("!?(\\*\\*/)?[a-zA-Z0-9_-]{1,10}(/\\*)?(\\.[a-z*]{1,4})?", 0..5),
+            any::<bool>(),
         )
-            .prop_map(|(enabled, auto_sync, sync_interval, upload_quality, exclude_patterns)| {
+            .prop_map(|(enabled, auto_sync, sync_interval, upload_quality, exclude_patterns, exclude_case_sensitive)| {
                 SyncConfig {
                     enabled,
                     auto_sync,
                     sync_interval,
                     upload_quality: upload_quality.to_string(),
                     exclude_patterns,
+                    exclude_case_sensitive,
+                    storage: StorageConfig::default(),
                 }
             })
     }
@@ -599,6 +830,9 @@ mod property_tests {
                     ai_model: ai_model.to_string(),
                     sync_config,
                     format_config: FormatConfig::default(),
+                    watched_folders: vec![],
+                    trash_config: TrashConfig::default(),
+            metadata_config: MetadataConfig::default(),
                 }
             })
     }
@@ -627,6 +861,7 @@ mod property_tests {
             prop_assert_eq!(loaded_settings.sync_config.sync_interval, settings.sync_config.sync_interval);
             prop_assert_eq!(loaded_settings.sync_config.upload_quality, settings.sync_config.upload_quality);
             prop_assert_eq!(loaded_settings.sync_config.exclude_patterns, settings.sync_config.exclude_patterns);
+            prop_assert_eq!(loaded_settings.sync_config.exclude_case_sensitive, settings.sync_config.exclude_case_sensitive);
         }
     }
 
@@ -716,7 +951,7 @@ mod property_tests {
             
             // Verify default image formats are present
             let expected_image_formats = vec![
-                "jpg", "jpeg", "png", "heic", "raw", "cr2", "nef", "dng", 
+                "jpg", "jpeg", "png", "heic", "heif", "raw", "cr2", "nef", "dng", 
                 "arw", "webp", "gif", "bmp", "tiff"
             ];
             
@@ -819,6 +1054,9 @@ mod property_tests {
                 ai_model: invalid_model.clone(),
                 sync_config: SyncConfig::default(),
                 format_config: FormatConfig::default(),
+                watched_folders: vec![],
+                trash_config: TrashConfig::default(),
+            metadata_config: MetadataConfig::default(),
             };
             
             let result = SettingsManager::validate_settings(&settings);
@@ -839,8 +1077,13 @@ mod property_tests {
                     sync_interval: 60,
                     upload_quality: invalid_quality.clone(),
                     exclude_patterns: vec![],
+                    exclude_case_sensitive: false,
+                    storage: StorageConfig::default(),
                 },
                 format_config: FormatConfig::default(),
+                watched_folders: vec![],
+                trash_config: TrashConfig::default(),
+            metadata_config: MetadataConfig::default(),
             };
             
             let result = SettingsManager::validate_settings(&settings);
@@ -861,8 +1104,13 @@ mod property_tests {
                     sync_interval: interval,
                     upload_quality: "high".to_string(),
                     exclude_patterns: vec![],
+                    exclude_case_sensitive: false,
+                    storage: StorageConfig::default(),
                 },
                 format_config: FormatConfig::default(),
+                watched_folders: vec![],
+                trash_config: TrashConfig::default(),
+            metadata_config: MetadataConfig::default(),
             };
             
             let result = SettingsManager::validate_settings(&settings);
@@ -879,6 +1127,9 @@ mod property_tests {
                 ai_model: "clip".to_string(),
                 sync_config: SyncConfig::default(),
                 format_config: FormatConfig::default(),
+                watched_folders: vec![],
+                trash_config: TrashConfig::default(),
+            metadata_config: MetadataConfig::default(),
             };
             
             let result = SettingsManager::validate_settings(&settings);
@@ -0,0 +1,327 @@
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::database::Database;
+
+/// An image similar to the query, with its cosine similarity
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SimilarImage {
+    pub image_id: i64,
+    /// Cosine similarity in [-1, 1], higher is more similar
+    pub score: f32,
+}
+
+/// Normalized embeddings of one model version, stored contiguously
+struct ModelIndex {
+    /// Database state the index was built from
+    signature: (i64, i64, i64),
+    dimensions: usize,
+    ids: Vec<i64>,
+    /// `ids.len() * dimensions` unit-length vectors, row-major
+    vectors: Vec<f32>,
+}
+
+/// In-memory index over the embeddings table for nearest-neighbour search
+///
+/// Each model version is loaded lazily on its first search and reloaded when
+/// the embeddings table changes for that version, so callers never need to
+/// invalidate it by hand.
+#[derive(Default)]
+pub struct EmbeddingIndex {
+    models: RwLock<HashMap<String, Arc<ModelIndex>>>,
+}
+
+impl EmbeddingIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Find the `k` images most similar to an image, excluding the image itself
+    pub fn find_similar_to_image(
+        &self,
+        db: &Database,
+        image_id: i64,
+        k: usize,
+        model_version: &str,
+    ) -> Result<Vec<SimilarImage>, String> {
+        let query = db
+            .get_embedding(image_id, model_version)
+            .map_err(|e| format!("Database error: {}", e))?
+            .ok_or_else(|| format!("Image {} has no embedding for model {}", image_id, model_version))?;
+
+        let mut results = self.find_similar(db, &query, k.saturating_add(1), model_version)?;
+        results.retain(|r| r.image_id != image_id);
+        results.truncate(k);
+
+        Ok(results)
+    }
+
+    /// Find the `k` images whose embeddings are most similar to a vector
+    pub fn find_similar(
+        &self,
+        db: &Database,
+        query: &[f32],
+        k: usize,
+        model_version: &str,
+    ) -> Result<Vec<SimilarImage>, String> {
+        let index = self.model_index(db, model_version)?;
+
+        if index.ids.is_empty() || k == 0 {
+            return Ok(Vec::new());
+        }
+
+        if query.len() != index.dimensions {
+            return Err(format!(
+                "Query has {} dimensions but model {} uses {}",
+                query.len(),
+                model_version,
+                index.dimensions
+            ));
+        }
+
+        let query = normalize(query)
+            .ok_or_else(|| "Query vector must not be zero".to_string())?;
+
+        let mut results: Vec<SimilarImage> = index
+            .vectors
+            .par_chunks(index.dimensions)
+            .zip(index.ids.par_iter())
+            .map(|(vector, &image_id)| SimilarImage {
+                image_id,
+                score: dot(vector, &query),
+            })
+            .collect();
+
+        // Partition out the top k before sorting just those
+        let by_score = |a: &SimilarImage, b: &SimilarImage| {
+            b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal)
+        };
+        if results.len() > k {
+            results.select_nth_unstable_by(k - 1, by_score);
+            results.truncate(k);
+        }
+        results.sort_by(by_score);
+
+        Ok(results)
+    }
+
+    /// Get the index for a model version, (re)loading it if the table changed
+    fn model_index(&self, db: &Database, model_version: &str) -> Result<Arc<ModelIndex>, String> {
+        let signature = db
+            .get_embeddings_signature(model_version)
+            .map_err(|e| format!("Database error: {}", e))?;
+
+        if let Some(index) = self.models.read().unwrap().get(model_version) {
+            if index.signature == signature {
+                return Ok(index.clone());
+            }
+        }
+
+        let embeddings = db
+            .get_embeddings(Some(model_version))
+            .map_err(|e| format!("Database error: {}", e))?;
+        let index = Arc::new(build_index(signature, embeddings, model_version));
+
+        self.models
+            .write()
+            .unwrap()
+            .insert(model_version.to_string(), index.clone());
+
+        Ok(index)
+    }
+}
+
+/// Build a model index, skipping zero vectors and vectors of the wrong size
+fn build_index(signature: (i64, i64, i64), embeddings: Vec<(i64, Vec<f32>)>, model_version: &str) -> ModelIndex {
+    // The most common length wins in case a model version was reused with a new size
+    let mut lengths: HashMap<usize, usize> = HashMap::new();
+    for (_, embedding) in &embeddings {
+        *lengths.entry(embedding.len()).or_default() += 1;
+    }
+    let dimensions = lengths
+        .into_iter()
+        .max_by_key(|&(len, count)| (count, len))
+        .map(|(len, _)| len)
+        .unwrap_or(0);
+
+    let mut ids = Vec::with_capacity(embeddings.len());
+    let mut vectors = Vec::with_capacity(embeddings.len() * dimensions);
+    for (image_id, embedding) in embeddings {
+        if embedding.len() != dimensions {
+            log::warn!(
+                "Skipping embedding of image {} with {} dimensions, model {} uses {}",
+                image_id,
+                embedding.len(),
+                model_version,
+                dimensions
+            );
+            continue;
+        }
+        if let Some(normalized) = normalize(&embedding) {
+            ids.push(image_id);
+            vectors.extend_from_slice(&normalized);
+        }
+    }
+
+    ModelIndex {
+        signature,
+        dimensions,
+        ids,
+        vectors,
+    }
+}
+
+/// Scale a vector to unit length, or None for a zero vector
+fn normalize(vector: &[f32]) -> Option<Vec<f32>> {
+    let norm = dot(vector, vector).sqrt();
+    if norm == 0.0 || !norm.is_finite() {
+        return None;
+    }
+
+    Some(vector.iter().map(|v| v / norm).collect())
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::MediaType;
+    use chrono::Utc;
+
+    fn insert_image(db: &Database, path: &str) -> i64 {
+        db.insert_image(
+            path, "s.jpg", "m.jpg", path, MediaType::Image,
+            None, None, None, None, None, 100, 100, None, None, 1024, Utc::now(),
+        )
+        .unwrap()
+    }
+
+    fn create_test_db() -> (tempfile::TempDir, Database, Vec<i64>) {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+
+        let vectors: [&[f32]; 4] = [
+            &[1.0, 0.0, 0.0],
+            &[0.9, 0.1, 0.0],
+            &[0.0, 1.0, 0.0],
+            &[-1.0, 0.0, 0.0],
+        ];
+        let ids = vectors
+            .iter()
+            .enumerate()
+            .map(|(i, vector)| {
+                let id = insert_image(&db, &format!("/photos/{}.jpg", i));
+                db.save_embedding(id, vector, "clip-v1").unwrap();
+                id
+            })
+            .collect();
+
+        (temp_dir, db, ids)
+    }
+
+    #[test]
+    fn test_find_similar_by_vector() {
+        let (_temp_dir, db, ids) = create_test_db();
+        let index = EmbeddingIndex::new();
+
+        let results = index.find_similar(&db, &[2.0, 0.0, 0.0], 2, "clip-v1").unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].image_id, ids[0]);
+        assert!((results[0].score - 1.0).abs() < 1e-6);
+        assert_eq!(results[1].image_id, ids[1]);
+        assert!(results[0].score >= results[1].score);
+    }
+
+    #[test]
+    fn test_find_similar_to_image_excludes_itself() {
+        let (_temp_dir, db, ids) = create_test_db();
+        let index = EmbeddingIndex::new();
+
+        let results = index.find_similar_to_image(&db, ids[0], 3, "clip-v1").unwrap();
+
+        let result_ids: Vec<i64> = results.iter().map(|r| r.image_id).collect();
+        assert_eq!(result_ids, vec![ids[1], ids[2], ids[3]]);
+        assert!((results[2].score + 1.0).abs() < 1e-6);
+
+        // An unbounded k returns every other image
+        let results = index.find_similar_to_image(&db, ids[0], usize::MAX, "clip-v1").unwrap();
+        assert_eq!(results.len(), 3);
+    }
+
+    #[test]
+    fn test_find_similar_filters_by_model_version() {
+        let (_temp_dir, db, ids) = create_test_db();
+        let index = EmbeddingIndex::new();
+
+        let other = insert_image(&db, "/photos/other.jpg");
+        db.save_embedding(other, &[1.0, 0.0], "clip-v2").unwrap();
+
+        let results = index.find_similar(&db, &[1.0, 0.0], 10, "clip-v2").unwrap();
+        assert_eq!(results, vec![SimilarImage { image_id: other, score: 1.0 }]);
+
+        let results = index.find_similar(&db, &[1.0, 0.0, 0.0], 10, "clip-v1").unwrap();
+        assert_eq!(results.len(), ids.len());
+    }
+
+    #[test]
+    fn test_index_reloads_after_changes() {
+        let (_temp_dir, db, ids) = create_test_db();
+        let index = EmbeddingIndex::new();
+
+        let results = index.find_similar(&db, &[0.0, 0.0, 1.0], 1, "clip-v1").unwrap();
+        assert_ne!(results[0].image_id, ids[2]);
+
+        // Replacing an embedding is picked up on the next search
+        db.save_embedding(ids[2], &[0.0, 0.0, 1.0], "clip-v1").unwrap();
+        let results = index.find_similar(&db, &[0.0, 0.0, 1.0], 1, "clip-v1").unwrap();
+        assert_eq!(results[0].image_id, ids[2]);
+
+        // So is deleting the image
+        db.delete_image(ids[2]).unwrap();
+        let results = index.find_similar(&db, &[0.0, 0.0, 1.0], 10, "clip-v1").unwrap();
+        assert!(results.iter().all(|r| r.image_id != ids[2]));
+    }
+
+    #[test]
+    fn test_find_similar_ignores_trashed_images() {
+        let (_temp_dir, db, ids) = create_test_db();
+        let index = EmbeddingIndex::new();
+
+        db.trash_image(ids[1], None).unwrap();
+        let results = index.find_similar(&db, &[1.0, 0.0, 0.0], 10, "clip-v1").unwrap();
+        let result_ids: Vec<i64> = results.iter().map(|r| r.image_id).collect();
+        assert_eq!(result_ids, vec![ids[0], ids[2], ids[3]]);
+
+        // Swapping which image is in the trash is picked up too
+        db.restore_image(ids[1]).unwrap();
+        db.trash_image(ids[2], None).unwrap();
+        let results = index.find_similar(&db, &[1.0, 0.0, 0.0], 10, "clip-v1").unwrap();
+        let result_ids: Vec<i64> = results.iter().map(|r| r.image_id).collect();
+        assert_eq!(result_ids, vec![ids[0], ids[1], ids[3]]);
+    }
+
+    #[test]
+    fn test_find_similar_rejects_wrong_dimensions() {
+        let (_temp_dir, db, _ids) = create_test_db();
+        let index = EmbeddingIndex::new();
+
+        assert!(index.find_similar(&db, &[1.0, 0.0], 1, "clip-v1").is_err());
+        assert!(index.find_similar(&db, &[0.0, 0.0, 0.0], 1, "clip-v1").is_err());
+    }
+
+    #[test]
+    fn test_find_similar_to_image_without_embedding() {
+        let (_temp_dir, db, _ids) = create_test_db();
+        let index = EmbeddingIndex::new();
+
+        let id = insert_image(&db, "/photos/new.jpg");
+        assert!(index.find_similar_to_image(&db, id, 1, "clip-v1").is_err());
+    }
+}
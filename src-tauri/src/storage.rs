@@ -0,0 +1,349 @@
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::Event;
+use quick_xml::reader::Reader;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::auth::GoogleDriveAuth;
+use crate::settings::StorageConfig;
+
+mod drive;
+mod local;
+mod s3;
+mod webdav;
+
+pub use drive::GoogleDriveProvider;
+pub use local::LocalFolderProvider;
+pub use s3::S3Provider;
+pub use webdav::WebDavProvider;
+
+const KEYRING_SERVICE: &str = "cura-photo-manager";
+const KEYRING_S3_SECRET_KEY: &str = "s3-secret-access-key";
+const KEYRING_WEBDAV_PASSWORD: &str = "webdav-password";
+
+/// Size of the pieces files are uploaded in
+///
+/// A multiple of 256 KiB as Drive requires, and above the 5 MiB minimum part size of S3.
+pub const CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Future returned by storage providers, boxed so the provider can be chosen at runtime
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A file stored by a provider
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RemoteFile {
+    /// Identifies the file to the provider: a Drive file ID, an object key or a file name
+    pub id: String,
+    pub name: String,
+    pub size: Option<u64>,
+}
+
+/// Progress of an unfinished upload
+#[derive(Debug, Clone, PartialEq)]
+pub struct UploadSession {
+    /// Provider-specific state, such as the Drive session URI or the S3 upload ID and parts
+    pub state: String,
+    /// Bytes the provider has received
+    pub uploaded: u64,
+    /// Size of the whole file
+    pub size: u64,
+}
+
+/// Keeps the session of one upload, so it can be resumed after an interruption or a restart
+pub trait UploadSessionStore: Sync {
+    fn load(&self) -> Option<UploadSession>;
+
+    fn save(&self, session: &UploadSession);
+
+    /// Forget the session once the upload has finished or can't be resumed
+    fn clear(&self);
+}
+
+/// Somewhere synced files can be stored
+///
+/// Files are named `<checksum>_<file name>`, and renditions of them
+/// `<checksum>-<quality>_<file name>`, so providers can tell whether a file
+/// was already uploaded from the names alone.
+pub trait StorageProvider: Send + Sync {
+    /// Name of the service, for logs and messages
+    fn name(&self) -> &'static str;
+
+    /// All files stored by the provider
+    fn list(&self) -> BoxFuture<'_, Result<Vec<RemoteFile>, String>>;
+
+    /// Whether a file named after this checksum, or rendition key, has already been uploaded
+    fn exists<'a>(&'a self, checksum: &'a str) -> BoxFuture<'a, Result<bool, String>>;
+
+    /// Upload a local file under `name`
+    ///
+    /// Large files are read and sent in chunks. Providers that support it record
+    /// their progress in `sessions` and continue from there when the upload is
+    /// retried.
+    fn upload<'a>(
+        &'a self,
+        source: &'a Path,
+        name: &'a str,
+        mime_type: &'a str,
+        sessions: &'a dyn UploadSessionStore,
+    ) -> BoxFuture<'a, Result<RemoteFile, String>>;
+
+    /// Give up an unfinished upload, so the provider can free what it received
+    ///
+    /// Providers that discard unfinished uploads by themselves, as Drive does
+    /// after a week, have nothing to do.
+    fn discard_upload<'a>(&'a self, _name: &'a str, _session: &'a UploadSession) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Download the content of a stored file
+    fn download<'a>(&'a self, file: &'a RemoteFile) -> BoxFuture<'a, Result<Vec<u8>, String>>;
+
+    fn delete<'a>(&'a self, file: &'a RemoteFile) -> BoxFuture<'a, Result<(), String>>;
+}
+
+/// Name a file is stored under, prefixed with its checksum for deduplication
+pub fn remote_name(checksum: &str, file_name: &str) -> String {
+    format!("{}_{}", checksum, file_name)
+}
+
+/// Checksum, or rendition key, a stored file is named after
+pub fn key_of(name: &str) -> Option<&str> {
+    name.split_once('_').map(|(key, _)| key)
+}
+
+/// Whether a stored file name belongs to the file with this checksum
+fn has_checksum(name: &str, checksum: &str) -> bool {
+    name.strip_prefix(checksum)
+        .is_some_and(|rest| rest.starts_with('_'))
+}
+
+/// Create the provider selected in the sync settings
+///
+/// `drive_auth` is only used when syncing to Google Drive.
+pub fn create_provider(
+    config: &StorageConfig,
+    drive_auth: Arc<GoogleDriveAuth>,
+) -> Result<Arc<dyn StorageProvider>, String> {
+    let provider: Arc<dyn StorageProvider> = match config {
+        StorageConfig::GoogleDrive => Arc::new(GoogleDriveProvider::new(drive_auth)),
+        StorageConfig::S3 { endpoint, bucket, region, prefix, access_key_id } => {
+            let secret_key = load_secret(KEYRING_S3_SECRET_KEY)
+                .map_err(|e| format!("No S3 secret key saved: {}", e))?;
+            Arc::new(S3Provider::new(endpoint, bucket, region, prefix, access_key_id, &secret_key)?)
+        }
+        StorageConfig::WebDav { url, username } => {
+            let password = load_secret(KEYRING_WEBDAV_PASSWORD)
+                .map_err(|e| format!("No WebDAV password saved: {}", e))?;
+            Arc::new(WebDavProvider::new(url, username, &password)?)
+        }
+        StorageConfig::Local { path } => Arc::new(LocalFolderProvider::new(path)),
+    };
+
+    Ok(provider)
+}
+
+/// Store the S3 secret key or WebDAV password for a storage backend in the system keychain
+pub fn save_secret(config: &StorageConfig, secret: &str) -> Result<(), String> {
+    let key = match config {
+        StorageConfig::S3 { .. } => KEYRING_S3_SECRET_KEY,
+        StorageConfig::WebDav { .. } => KEYRING_WEBDAV_PASSWORD,
+        StorageConfig::GoogleDrive | StorageConfig::Local { .. } => {
+            return Err("This storage backend does not use a password".to_string())
+        }
+    };
+
+    keyring::Entry::new(KEYRING_SERVICE, key)
+        .and_then(|entry| entry.set_password(secret))
+        .map_err(|e| format!("Failed to store secret in keychain: {}", e))
+}
+
+fn load_secret(key: &str) -> Result<String, String> {
+    keyring::Entry::new(KEYRING_SERVICE, key)
+        .and_then(|entry| entry.get_password())
+        .map_err(|e| e.to_string())
+}
+
+/// Open a file to upload, along with its size
+async fn open_source(source: &Path) -> Result<(tokio::fs::File, u64), String> {
+    let file = tokio::fs::File::open(source)
+        .await
+        .map_err(|e| format!("Failed to open file {}: {}", source.display(), e))?;
+    let size = file
+        .metadata()
+        .await
+        .map_err(|e| format!("Failed to read file {}: {}", source.display(), e))?
+        .len();
+    Ok((file, size))
+}
+
+/// Read up to `CHUNK_SIZE` bytes of a file to upload, starting at `offset`
+async fn read_chunk(file: &mut tokio::fs::File, source: &Path, offset: u64) -> Result<Vec<u8>, String> {
+    let read_error = |e: std::io::Error| format!("Failed to read file {}: {}", source.display(), e);
+
+    file.seek(std::io::SeekFrom::Start(offset)).await.map_err(read_error)?;
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE as usize);
+    (&mut *file).take(CHUNK_SIZE).read_to_end(&mut chunk).await.map_err(read_error)?;
+    Ok(chunk)
+}
+
+/// Turn an unsuccessful HTTP response into an error with the server's explanation
+async fn check_response(
+    response: reqwest::Response,
+    action: &str,
+) -> Result<reqwest::Response, String> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+
+    let status = response.status();
+    let error_text = response
+        .text()
+        .await
+        .unwrap_or_else(|_| "Unknown error".to_string());
+    Err(format!("{} failed with status {}: {}", action, status, error_text))
+}
+
+/// Text of the `fields` elements inside each `record` element of an XML response
+///
+/// Elements are matched by local name, so namespace prefixes don't matter.
+fn parse_xml_records(
+    xml: &str,
+    record: &str,
+    fields: &[&str],
+) -> Result<Vec<HashMap<String, String>>, String> {
+    let invalid = |e: &dyn std::fmt::Display| format!("Invalid XML response: {}", e);
+    let mut reader = Reader::from_str(xml);
+
+    let mut records = Vec::new();
+    let mut current: Option<HashMap<String, String>> = None;
+    let mut field: Option<String> = None;
+    let mut text = String::new();
+
+    loop {
+        match reader.read_event().map_err(|e| invalid(&e))? {
+            Event::Start(e) => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                if name == record {
+                    current = Some(HashMap::new());
+                } else if current.is_some() && field.is_none() && fields.contains(&name.as_str()) {
+                    field = Some(name);
+                    text.clear();
+                }
+            }
+            Event::Text(e) if field.is_some() => text.push_str(&e.decode().map_err(|e| invalid(&e))?),
+            Event::GeneralRef(e) if field.is_some() => match e.resolve_char_ref().map_err(|e| invalid(&e))? {
+                Some(ch) => text.push(ch),
+                None => {
+                    let name = e.decode().map_err(|e| invalid(&e))?;
+                    let value = resolve_predefined_entity(&name)
+                        .ok_or_else(|| invalid(&format!("unknown entity &{};", name)))?;
+                    text.push_str(value);
+                }
+            },
+            Event::End(e) => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                if field.as_deref() == Some(name.as_str()) {
+                    if let (Some(current), Some(field)) = (current.as_mut(), field.take()) {
+                        current.insert(field, text.trim().to_string());
+                    }
+                } else if name == record {
+                    records.extend(current.take());
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(records)
+}
+
+/// Upload sessions kept in memory, for tests that don't need a database
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemorySessionStore(std::sync::Mutex<Option<UploadSession>>);
+
+#[cfg(test)]
+impl UploadSessionStore for MemorySessionStore {
+    fn load(&self) -> Option<UploadSession> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn save(&self, session: &UploadSession) {
+        *self.0.lock().unwrap() = Some(session.clone());
+    }
+
+    fn clear(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remote_names_carry_checksum() {
+        let name = remote_name("abc123", "IMG_0001.jpg");
+        assert_eq!(name, "abc123_IMG_0001.jpg");
+        assert!(has_checksum(&name, "abc123"));
+        assert!(!has_checksum(&name, "abc12"));
+        assert!(!has_checksum("abc123.jpg", "abc123"));
+
+        // Renditions are kept apart from the original
+        assert!(!has_checksum("abc123-medium_IMG_0001.jpg", "abc123"));
+        assert!(has_checksum("abc123-medium_IMG_0001.jpg", "abc123-medium"));
+        assert!(!has_checksum(&name, "abc123-medium"));
+
+        assert_eq!(key_of(&name), Some("abc123"));
+        assert_eq!(key_of("abc123-medium_IMG_0001.jpg"), Some("abc123-medium"));
+        assert_eq!(key_of("notes.txt"), None);
+    }
+
+    #[test]
+    fn test_parse_xml_records() {
+        let xml = r#"<?xml version="1.0"?>
+            <d:multistatus xmlns:d="DAV:">
+              <d:response><d:href>/a%20b.jpg</d:href><d:size>12</d:size></d:response>
+              <d:response><d:href>/Tom &amp; Jerry.jpg</d:href></d:response>
+            </d:multistatus>"#;
+
+        let records = parse_xml_records(xml, "response", &["href", "size"]).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["href"], "/a%20b.jpg");
+        assert_eq!(records[0]["size"], "12");
+        assert_eq!(records[1]["href"], "/Tom & Jerry.jpg");
+        assert!(!records[1].contains_key("size"));
+
+        assert!(parse_xml_records("<a><b></a>", "a", &["b"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_chunk() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let source = temp_dir.path().join("clip.mp4");
+        let content: Vec<u8> = (0..CHUNK_SIZE + 10).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&source, &content).unwrap();
+
+        let (mut file, size) = open_source(&source).await.unwrap();
+        assert_eq!(size, CHUNK_SIZE + 10);
+
+        let last = read_chunk(&mut file, &source, CHUNK_SIZE).await.unwrap();
+        assert_eq!(last, &content[CHUNK_SIZE as usize..]);
+        let first = read_chunk(&mut file, &source, 0).await.unwrap();
+        assert_eq!(first, &content[..CHUNK_SIZE as usize]);
+        assert!(read_chunk(&mut file, &source, size).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_save_secret_rejects_backends_without_password() {
+        let local = StorageConfig::Local { path: "/mnt/nas".to_string() };
+        assert!(save_secret(&local, "secret").is_err());
+        assert!(save_secret(&StorageConfig::GoogleDrive, "secret").is_err());
+    }
+}
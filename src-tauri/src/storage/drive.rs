@@ -0,0 +1,347 @@
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::{
+    check_response, has_checksum, open_source, read_chunk, BoxFuture, RemoteFile, StorageProvider,
+    UploadSession, UploadSessionStore,
+};
+use crate::auth::GoogleDriveAuth;
+
+const GOOGLE_DRIVE_API_URL: &str = "https://www.googleapis.com/drive/v3";
+const GOOGLE_DRIVE_UPLOAD_URL: &str = "https://www.googleapis.com/upload/drive/v3/files";
+
+/// Google Drive file metadata
+#[derive(Debug, Deserialize)]
+struct DriveFile {
+    id: String,
+    name: String,
+    /// Sizes are sent as strings
+    size: Option<String>,
+}
+
+impl From<DriveFile> for RemoteFile {
+    fn from(file: DriveFile) -> Self {
+        RemoteFile {
+            id: file.id,
+            name: file.name,
+            size: file.size.and_then(|size| size.parse().ok()),
+        }
+    }
+}
+
+/// Google Drive file list response
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DriveFileList {
+    files: Vec<DriveFile>,
+    next_page_token: Option<String>,
+}
+
+/// Where a resumable upload stands, according to Drive
+enum UploadStatus {
+    /// Drive has received this many bytes
+    Incomplete(u64),
+    Complete(RemoteFile),
+    /// The session is unknown or has expired, which happens a week after it started
+    Expired,
+}
+
+/// Google Drive, through the Drive v3 API
+///
+/// The app only has access to the files it created itself.
+pub struct GoogleDriveProvider {
+    auth: Arc<GoogleDriveAuth>,
+    client: reqwest::Client,
+}
+
+impl GoogleDriveProvider {
+    pub fn new(auth: Arc<GoogleDriveAuth>) -> Self {
+        Self {
+            auth,
+            // Resumable uploads answer 308 without being redirects
+            client: reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Query Drive for files that are not in its trash, following every result page
+    async fn query_files(&self, query: &str) -> Result<Vec<RemoteFile>, String> {
+        let access_token = self.auth.get_valid_access_token().await?;
+        let query = format!("{} and trashed = false", query);
+
+        let mut files = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut url = format!(
+                "{}/files?q={}&fields=nextPageToken,files(id,name,size)&pageSize=1000",
+                GOOGLE_DRIVE_API_URL,
+                urlencoding::encode(&query)
+            );
+            if let Some(token) = &page_token {
+                url.push_str(&format!("&pageToken={}", urlencoding::encode(token)));
+            }
+
+            let response = self
+                .client
+                .get(&url)
+                .bearer_auth(&access_token)
+                .send()
+                .await
+                .map_err(|e| format!("Failed to query Drive: {}", e))?;
+            let response = check_response(response, "Drive query").await?;
+
+            let file_list: DriveFileList = response
+                .json()
+                .await
+                .map_err(|e| format!("Failed to parse response: {}", e))?;
+
+            files.extend(file_list.files.into_iter().map(RemoteFile::from));
+            match file_list.next_page_token {
+                Some(token) => page_token = Some(token),
+                None => break,
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Upload a file with Drive's resumable upload protocol, one chunk per request
+    async fn upload_file(
+        &self,
+        source: &Path,
+        name: &str,
+        mime_type: &str,
+        sessions: &dyn UploadSessionStore,
+    ) -> Result<RemoteFile, String> {
+        let access_token = self.auth.get_valid_access_token().await?;
+        let (mut file, size) = open_source(source).await?;
+
+        let resumed = match sessions.load() {
+            Some(session) if session.size == size => {
+                match self.upload_status(&session.state, size, &access_token).await? {
+                    UploadStatus::Incomplete(uploaded) => Some(UploadSession { uploaded, ..session }),
+                    UploadStatus::Complete(file) => {
+                        sessions.clear();
+                        return Ok(file);
+                    }
+                    UploadStatus::Expired => None,
+                }
+            }
+            _ => None,
+        };
+        let mut session = match resumed {
+            Some(session) => session,
+            None => self.start_upload(name, mime_type, size, &access_token).await?,
+        };
+        sessions.save(&session);
+
+        loop {
+            let chunk = read_chunk(&mut file, source, session.uploaded).await?;
+            let content_range = if chunk.is_empty() {
+                format!("bytes */{}", size)
+            } else {
+                format!("bytes {}-{}/{}", session.uploaded, session.uploaded + chunk.len() as u64 - 1, size)
+            };
+
+            let response = self
+                .client
+                .put(&session.state)
+                .bearer_auth(&access_token)
+                .header("Content-Range", content_range)
+                .body(chunk)
+                .send()
+                .await
+                .map_err(|e| format!("Failed to upload {}: {}", name, e))?;
+
+            match Self::parse_upload_status(response).await? {
+                UploadStatus::Incomplete(uploaded) => {
+                    session.uploaded = uploaded;
+                    sessions.save(&session);
+                }
+                UploadStatus::Complete(file) => {
+                    sessions.clear();
+                    return Ok(file);
+                }
+                UploadStatus::Expired => {
+                    sessions.clear();
+                    return Err(format!("Upload session of {} expired", name));
+                }
+            }
+        }
+    }
+
+    /// Start a resumable upload, whose session URI receives the file content
+    async fn start_upload(
+        &self,
+        name: &str,
+        mime_type: &str,
+        size: u64,
+        access_token: &str,
+    ) -> Result<UploadSession, String> {
+        let metadata = serde_json::json!({
+            "name": name,
+            "description": "Uploaded from Cura",
+        });
+        let url = format!("{}?uploadType=resumable&fields=id,name,size", GOOGLE_DRIVE_UPLOAD_URL);
+
+        let response = self
+            .client
+            .post(&url)
+            .bearer_auth(access_token)
+            .header("X-Upload-Content-Type", mime_type)
+            .header("X-Upload-Content-Length", size)
+            .json(&metadata)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to start upload of {}: {}", name, e))?;
+        let response = check_response(response, "Starting upload").await?;
+
+        let session_uri = response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .ok_or_else(|| "Drive did not return an upload session".to_string())?;
+
+        Ok(UploadSession {
+            state: session_uri.to_string(),
+            uploaded: 0,
+            size,
+        })
+    }
+
+    /// Ask Drive how much of an interrupted upload it has received
+    async fn upload_status(
+        &self,
+        session_uri: &str,
+        size: u64,
+        access_token: &str,
+    ) -> Result<UploadStatus, String> {
+        let response = self
+            .client
+            .put(session_uri)
+            .bearer_auth(access_token)
+            .header("Content-Range", format!("bytes */{}", size))
+            .body(Vec::new())
+            .send()
+            .await
+            .map_err(|e| format!("Failed to query upload session: {}", e))?;
+
+        Self::parse_upload_status(response).await
+    }
+
+    async fn parse_upload_status(response: reqwest::Response) -> Result<UploadStatus, String> {
+        match response.status() {
+            StatusCode::PERMANENT_REDIRECT => {
+                let range = response
+                    .headers()
+                    .get(reqwest::header::RANGE)
+                    .and_then(|range| range.to_str().ok());
+                Ok(UploadStatus::Incomplete(range.and_then(parse_received_range).unwrap_or(0)))
+            }
+            StatusCode::NOT_FOUND | StatusCode::GONE => Ok(UploadStatus::Expired),
+            _ => {
+                let response = check_response(response, "Upload").await?;
+                let file: DriveFile = response
+                    .json()
+                    .await
+                    .map_err(|e| format!("Failed to parse response: {}", e))?;
+                Ok(UploadStatus::Complete(file.into()))
+            }
+        }
+    }
+
+    async fn download_file(&self, file: &RemoteFile) -> Result<Vec<u8>, String> {
+        let access_token = self.auth.get_valid_access_token().await?;
+        let url = format!("{}/files/{}?alt=media", GOOGLE_DRIVE_API_URL, urlencoding::encode(&file.id));
+
+        let response = self
+            .client
+            .get(&url)
+            .bearer_auth(&access_token)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to download {}: {}", file.name, e))?;
+        let response = check_response(response, "Download").await?;
+
+        response
+            .bytes()
+            .await
+            .map(|bytes| bytes.to_vec())
+            .map_err(|e| format!("Failed to download {}: {}", file.name, e))
+    }
+
+    async fn delete_file(&self, file: &RemoteFile) -> Result<(), String> {
+        let access_token = self.auth.get_valid_access_token().await?;
+        let url = format!("{}/files/{}", GOOGLE_DRIVE_API_URL, urlencoding::encode(&file.id));
+
+        let response = self
+            .client
+            .delete(&url)
+            .bearer_auth(&access_token)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to delete {}: {}", file.name, e))?;
+        check_response(response, "Delete").await?;
+
+        Ok(())
+    }
+}
+
+impl StorageProvider for GoogleDriveProvider {
+    fn name(&self) -> &'static str {
+        "Google Drive"
+    }
+
+    fn list(&self) -> BoxFuture<'_, Result<Vec<RemoteFile>, String>> {
+        Box::pin(self.query_files("mimeType != 'application/vnd.google-apps.folder'"))
+    }
+
+    fn exists<'a>(&'a self, checksum: &'a str) -> BoxFuture<'a, Result<bool, String>> {
+        Box::pin(async move {
+            let escaped = checksum.replace('\\', "\\\\").replace('\'', "\\'");
+            let files = self.query_files(&format!("name contains '{}'", escaped)).await?;
+            Ok(files.iter().any(|file| has_checksum(&file.name, checksum)))
+        })
+    }
+
+    fn upload<'a>(
+        &'a self,
+        source: &'a Path,
+        name: &'a str,
+        mime_type: &'a str,
+        sessions: &'a dyn UploadSessionStore,
+    ) -> BoxFuture<'a, Result<RemoteFile, String>> {
+        Box::pin(self.upload_file(source, name, mime_type, sessions))
+    }
+
+    fn download<'a>(&'a self, file: &'a RemoteFile) -> BoxFuture<'a, Result<Vec<u8>, String>> {
+        Box::pin(self.download_file(file))
+    }
+
+    fn delete<'a>(&'a self, file: &'a RemoteFile) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(self.delete_file(file))
+    }
+}
+
+/// Bytes received according to a `Range: bytes=0-<last>` header
+fn parse_received_range(range: &str) -> Option<u64> {
+    let last: u64 = range.strip_prefix("bytes=0-")?.parse().ok()?;
+    Some(last + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_received_range() {
+        assert_eq!(parse_received_range("bytes=0-8388607"), Some(8388608));
+        assert_eq!(parse_received_range("bytes=0-0"), Some(1));
+        assert_eq!(parse_received_range("bytes=5-10"), None);
+        assert_eq!(parse_received_range("garbage"), None);
+    }
+}
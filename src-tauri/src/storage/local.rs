@@ -0,0 +1,254 @@
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+use super::{
+    has_checksum, open_source, read_chunk, BoxFuture, RemoteFile, StorageProvider, UploadSession,
+    UploadSessionStore,
+};
+
+/// Folder on this computer or a mounted NAS share
+pub struct LocalFolderProvider {
+    root: PathBuf,
+}
+
+impl LocalFolderProvider {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Path of a stored file, refusing IDs that would escape the folder
+    fn file_path(&self, id: &str) -> Result<PathBuf, String> {
+        let mut components = Path::new(id).components();
+        match (components.next(), components.next()) {
+            (Some(std::path::Component::Normal(name)), None) => Ok(self.root.join(name)),
+            _ => Err(format!("Invalid file name: {}", id)),
+        }
+    }
+
+    async fn list_files(&self) -> Result<Vec<RemoteFile>, String> {
+        let mut entries = match tokio::fs::read_dir(&self.root).await {
+            Ok(entries) => entries,
+            // Nothing has been synced yet
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Failed to read {}: {}", self.root.display(), e)),
+        };
+
+        let mut files = Vec::new();
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", self.root.display(), e))?
+        {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let metadata = match entry.metadata().await {
+                Ok(metadata) if metadata.is_file() => metadata,
+                _ => continue,
+            };
+            // Uploads still being copied
+            if name.ends_with(".part") {
+                continue;
+            }
+            files.push(RemoteFile {
+                id: name.clone(),
+                name,
+                size: Some(metadata.len()),
+            });
+        }
+
+        Ok(files)
+    }
+
+    /// Temporary name a file is copied under until the copy is complete
+    fn partial_path(&self, name: &str) -> PathBuf {
+        self.root.join(format!("{}.part", name))
+    }
+
+    async fn upload_file(
+        &self,
+        source: &Path,
+        name: &str,
+        sessions: &dyn UploadSessionStore,
+    ) -> Result<RemoteFile, String> {
+        let destination = self.file_path(name)?;
+        tokio::fs::create_dir_all(&self.root)
+            .await
+            .map_err(|e| format!("Failed to create {}: {}", self.root.display(), e))?;
+
+        // Copy under a temporary name, so an interrupted copy is never taken for a synced file
+        let partial = self.partial_path(name);
+        let write_error = |e: std::io::Error| format!("Failed to write {}: {}", partial.display(), e);
+        let (mut file, size) = open_source(source).await?;
+
+        // An interrupted copy continues from what reached the temporary file
+        let mut uploaded = match sessions.load() {
+            Some(session) if session.size == size => tokio::fs::metadata(&partial)
+                .await
+                .map(|metadata| metadata.len().min(session.uploaded))
+                .unwrap_or(0),
+            _ => 0,
+        };
+
+        let mut output = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&partial)
+            .await
+            .map_err(write_error)?;
+        output.set_len(uploaded).await.map_err(write_error)?;
+        output.seek(std::io::SeekFrom::Start(uploaded)).await.map_err(write_error)?;
+
+        while uploaded < size {
+            let chunk = read_chunk(&mut file, source, uploaded).await?;
+            if chunk.is_empty() {
+                return Err(format!("{} shrank while it was being copied", source.display()));
+            }
+            output.write_all(&chunk).await.map_err(write_error)?;
+            output.sync_data().await.map_err(write_error)?;
+
+            uploaded += chunk.len() as u64;
+            sessions.save(&UploadSession {
+                state: String::new(),
+                uploaded,
+                size,
+            });
+        }
+        drop(output);
+
+        tokio::fs::rename(&partial, &destination)
+            .await
+            .map_err(|e| format!("Failed to move {} into place: {}", partial.display(), e))?;
+        sessions.clear();
+
+        Ok(RemoteFile {
+            id: name.to_string(),
+            name: name.to_string(),
+            size: Some(size),
+        })
+    }
+}
+
+impl StorageProvider for LocalFolderProvider {
+    fn name(&self) -> &'static str {
+        "local folder"
+    }
+
+    fn list(&self) -> BoxFuture<'_, Result<Vec<RemoteFile>, String>> {
+        Box::pin(self.list_files())
+    }
+
+    fn exists<'a>(&'a self, checksum: &'a str) -> BoxFuture<'a, Result<bool, String>> {
+        Box::pin(async move {
+            let files = self.list_files().await?;
+            Ok(files.iter().any(|file| has_checksum(&file.name, checksum)))
+        })
+    }
+
+    fn upload<'a>(
+        &'a self,
+        source: &'a Path,
+        name: &'a str,
+        _mime_type: &'a str,
+        sessions: &'a dyn UploadSessionStore,
+    ) -> BoxFuture<'a, Result<RemoteFile, String>> {
+        Box::pin(self.upload_file(source, name, sessions))
+    }
+
+    fn discard_upload<'a>(&'a self, name: &'a str, _session: &'a UploadSession) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            self.file_path(name)?;
+            let partial = self.partial_path(name);
+            match tokio::fs::remove_file(&partial).await {
+                Ok(()) => Ok(()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                Err(e) => Err(format!("Failed to remove {}: {}", partial.display(), e)),
+            }
+        })
+    }
+
+    fn download<'a>(&'a self, file: &'a RemoteFile) -> BoxFuture<'a, Result<Vec<u8>, String>> {
+        Box::pin(async move {
+            let path = self.file_path(&file.id)?;
+            tokio::fs::read(&path)
+                .await
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+        })
+    }
+
+    fn delete<'a>(&'a self, file: &'a RemoteFile) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let path = self.file_path(&file.id)?;
+            tokio::fs::remove_file(&path)
+                .await
+                .map_err(|e| format!("Failed to delete {}: {}", path.display(), e))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemorySessionStore;
+
+    #[tokio::test]
+    async fn test_local_folder_round_trip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let source = temp_dir.path().join("IMG_0001.jpg");
+        std::fs::write(&source, b"jpeg data").unwrap();
+        let provider = LocalFolderProvider::new(temp_dir.path().join("nas").join("cura"));
+
+        // The folder is created on the first upload
+        assert!(provider.list().await.unwrap().is_empty());
+        assert!(!provider.exists("abc123").await.unwrap());
+
+        let sessions = MemorySessionStore::default();
+        let file = provider
+            .upload(&source, "abc123_IMG_0001.jpg", "image/jpeg", &sessions)
+            .await
+            .unwrap();
+        assert_eq!(file.size, Some(9));
+        assert!(provider.exists("abc123").await.unwrap());
+        assert_eq!(provider.list().await.unwrap(), vec![file.clone()]);
+        assert_eq!(provider.download(&file).await.unwrap(), b"jpeg data");
+
+        provider.delete(&file).await.unwrap();
+        assert!(!provider.exists("abc123").await.unwrap());
+
+        let escaping = RemoteFile {
+            id: "../IMG_0001.jpg".to_string(),
+            name: "IMG_0001.jpg".to_string(),
+            size: None,
+        };
+        assert!(provider.delete(&escaping).await.is_err());
+        assert!(source.exists());
+    }
+
+    #[tokio::test]
+    async fn test_local_folder_resumes_copy() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let source = temp_dir.path().join("clip.mp4");
+        std::fs::write(&source, b"video data").unwrap();
+        let root = temp_dir.path().join("nas");
+        let provider = LocalFolderProvider::new(&root);
+
+        // A copy interrupted after 5 bytes, which differ from the source to show they are kept
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("abc_clip.mp4.part"), b"VIDEO").unwrap();
+        let sessions = MemorySessionStore::default();
+        sessions.save(&UploadSession {
+            state: String::new(),
+            uploaded: 5,
+            size: 10,
+        });
+
+        let file = provider.upload(&source, "abc_clip.mp4", "video/mp4", &sessions).await.unwrap();
+        assert_eq!(provider.download(&file).await.unwrap(), b"VIDEO data");
+        assert!(sessions.load().is_none());
+        assert!(!root.join("abc_clip.mp4.part").exists());
+
+        // Without a session the copy starts over
+        std::fs::write(root.join("abc_clip.mp4.part"), b"VIDEO").unwrap();
+        let file = provider.upload(&source, "abc_clip.mp4", "video/mp4", &sessions).await.unwrap();
+        assert_eq!(provider.download(&file).await.unwrap(), b"video data");
+    }
+}
@@ -0,0 +1,253 @@
+use reqwest::{Method, StatusCode};
+use std::path::Path;
+
+use super::{
+    check_response, has_checksum, open_source, parse_xml_records, BoxFuture, RemoteFile, StorageProvider,
+    UploadSessionStore,
+};
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:getcontentlength/><d:resourcetype/></d:prop></d:propfind>"#;
+
+/// WebDAV server such as Nextcloud or ownCloud
+///
+/// Files are stored directly in the folder at `url`, which is created on the
+/// first upload. WebDAV has no standard way to resume an upload, so files are
+/// streamed in a single request and sent again in full when it fails.
+pub struct WebDavProvider {
+    /// Folder URL, always ending with `/`
+    url: url::Url,
+    username: String,
+    password: String,
+    client: reqwest::Client,
+}
+
+impl WebDavProvider {
+    pub fn new(url: &str, username: &str, password: &str) -> Result<Self, String> {
+        let mut url = url::Url::parse(url).map_err(|e| format!("Invalid WebDAV URL {}: {}", url, e))?;
+        if !url.path().ends_with('/') {
+            let path = format!("{}/", url.path());
+            url.set_path(&path);
+        }
+
+        Ok(Self {
+            url,
+            username: username.to_string(),
+            password: password.to_string(),
+            client: reqwest::Client::new(),
+        })
+    }
+
+    fn file_url(&self, name: &str) -> Result<url::Url, String> {
+        if matches!(name, "" | "." | "..") || name.contains('/') {
+            return Err(format!("Invalid file name: {}", name));
+        }
+        self.url
+            .join(&urlencoding::encode(name))
+            .map_err(|e| format!("Invalid file name {}: {}", name, e))
+    }
+
+    fn request(&self, method: Method, url: url::Url) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, url)
+            .basic_auth(&self.username, Some(&self.password))
+    }
+
+    async fn list_files(&self) -> Result<Vec<RemoteFile>, String> {
+        let propfind = Method::from_bytes(b"PROPFIND").map_err(|e| e.to_string())?;
+        let response = self
+            .request(propfind, self.url.clone())
+            .header("Depth", "1")
+            .header("Content-Type", "application/xml")
+            .body(PROPFIND_BODY)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to reach WebDAV server: {}", e))?;
+
+        // Nothing has been synced yet
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+        let response = check_response(response, "WebDAV listing").await?;
+        let xml = response
+            .text()
+            .await
+            .map_err(|e| format!("Failed to read WebDAV listing: {}", e))?;
+
+        parse_multistatus(&xml)
+    }
+
+    /// Create the folder files are uploaded to
+    async fn create_folder(&self) -> Result<(), String> {
+        let mkcol = Method::from_bytes(b"MKCOL").map_err(|e| e.to_string())?;
+        let response = self
+            .request(mkcol, self.url.clone())
+            .send()
+            .await
+            .map_err(|e| format!("Failed to reach WebDAV server: {}", e))?;
+
+        // 405 means the folder already exists
+        if response.status() == StatusCode::METHOD_NOT_ALLOWED {
+            return Ok(());
+        }
+        check_response(response, "Creating WebDAV folder").await?;
+        Ok(())
+    }
+
+    async fn upload_file(&self, source: &Path, name: &str, mime_type: &str) -> Result<RemoteFile, String> {
+        let url = self.file_url(name)?;
+
+        let put = || async {
+            let (file, size) = open_source(source).await?;
+            self.request(Method::PUT, url.clone())
+                .header("Content-Type", mime_type)
+                .header(reqwest::header::CONTENT_LENGTH, size)
+                .body(reqwest::Body::from(file))
+                .send()
+                .await
+                .map(|response| (response, size))
+                .map_err(|e| format!("Failed to upload {}: {}", name, e))
+        };
+        let (mut response, mut size) = put().await?;
+
+        // The folder doesn't exist before the first upload
+        if matches!(response.status(), StatusCode::CONFLICT | StatusCode::NOT_FOUND) {
+            self.create_folder().await?;
+            (response, size) = put().await?;
+        }
+        check_response(response, "Upload").await?;
+
+        Ok(RemoteFile {
+            id: name.to_string(),
+            name: name.to_string(),
+            size: Some(size),
+        })
+    }
+
+    async fn download_file(&self, file: &RemoteFile) -> Result<Vec<u8>, String> {
+        let response = self
+            .request(Method::GET, self.file_url(&file.id)?)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to download {}: {}", file.name, e))?;
+        let response = check_response(response, "Download").await?;
+
+        response
+            .bytes()
+            .await
+            .map(|bytes| bytes.to_vec())
+            .map_err(|e| format!("Failed to download {}: {}", file.name, e))
+    }
+
+    async fn delete_file(&self, file: &RemoteFile) -> Result<(), String> {
+        let response = self
+            .request(Method::DELETE, self.file_url(&file.id)?)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to delete {}: {}", file.name, e))?;
+        check_response(response, "Delete").await?;
+        Ok(())
+    }
+}
+
+impl StorageProvider for WebDavProvider {
+    fn name(&self) -> &'static str {
+        "WebDAV"
+    }
+
+    fn list(&self) -> BoxFuture<'_, Result<Vec<RemoteFile>, String>> {
+        Box::pin(self.list_files())
+    }
+
+    fn exists<'a>(&'a self, checksum: &'a str) -> BoxFuture<'a, Result<bool, String>> {
+        Box::pin(async move {
+            let files = self.list_files().await?;
+            Ok(files.iter().any(|file| has_checksum(&file.name, checksum)))
+        })
+    }
+
+    fn upload<'a>(
+        &'a self,
+        source: &'a Path,
+        name: &'a str,
+        mime_type: &'a str,
+        _sessions: &'a dyn UploadSessionStore,
+    ) -> BoxFuture<'a, Result<RemoteFile, String>> {
+        Box::pin(self.upload_file(source, name, mime_type))
+    }
+
+    fn download<'a>(&'a self, file: &'a RemoteFile) -> BoxFuture<'a, Result<Vec<u8>, String>> {
+        Box::pin(self.download_file(file))
+    }
+
+    fn delete<'a>(&'a self, file: &'a RemoteFile) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(self.delete_file(file))
+    }
+}
+
+/// Files in a PROPFIND response, leaving out the listed folder and its subfolders
+fn parse_multistatus(xml: &str) -> Result<Vec<RemoteFile>, String> {
+    let files = parse_xml_records(xml, "response", &["href", "getcontentlength"])?
+        .into_iter()
+        .filter_map(|record| {
+            // Folder hrefs end with a slash, leaving no file name
+            let encoded = record.get("href")?.rsplit('/').next()?;
+            if encoded.is_empty() {
+                return None;
+            }
+            let name = urlencoding::decode(encoded).ok()?.into_owned();
+            Some(RemoteFile {
+                id: name.clone(),
+                name,
+                size: record.get("getcontentlength").and_then(|size| size.parse().ok()),
+            })
+        })
+        .collect();
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_multistatus() {
+        let xml = r#"<?xml version="1.0"?>
+            <d:multistatus xmlns:d="DAV:" xmlns:oc="http://owncloud.org/ns">
+              <d:response>
+                <d:href>/remote.php/dav/files/alice/Cura/</d:href>
+                <d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop></d:propstat>
+              </d:response>
+              <d:response>
+                <d:href>/remote.php/dav/files/alice/Cura/abc_IMG%200001.jpg</d:href>
+                <d:propstat><d:prop><d:getcontentlength>2048</d:getcontentlength></d:prop></d:propstat>
+              </d:response>
+              <d:response>
+                <d:href>/remote.php/dav/files/alice/Cura/Albums/</d:href>
+              </d:response>
+            </d:multistatus>"#;
+
+        let files = parse_multistatus(xml).unwrap();
+        assert_eq!(
+            files,
+            vec![RemoteFile {
+                id: "abc_IMG 0001.jpg".to_string(),
+                name: "abc_IMG 0001.jpg".to_string(),
+                size: Some(2048),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_file_urls_stay_in_folder() {
+        let provider = WebDavProvider::new("https://cloud.example.com/remote.php/dav/files/alice/Cura", "alice", "secret").unwrap();
+        assert_eq!(
+            provider.file_url("abc_IMG 0001.jpg").unwrap().as_str(),
+            "https://cloud.example.com/remote.php/dav/files/alice/Cura/abc_IMG%200001.jpg"
+        );
+        assert!(provider.file_url("../secret.txt").is_err());
+        assert!(provider.file_url("..").is_err());
+        assert!(provider.file_url("").is_err());
+    }
+}
@@ -1,24 +1,25 @@
-use crate::auth::GoogleDriveAuth;
 use crate::database::{Database, ImageRecord};
+use crate::rendition::{self, Rendition, UploadQuality};
+use crate::storage::{self, StorageProvider, UploadSession, UploadSessionStore};
 use chrono::Utc;
-use reqwest::multipart;
+use globset::{GlobBuilder, GlobMatcher};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::sleep;
 
-const GOOGLE_DRIVE_API_URL: &str = "https://www.googleapis.com/drive/v3";
-const GOOGLE_DRIVE_UPLOAD_URL: &str = "https://www.googleapis.com/upload/drive/v3/files";
-
 /// Result of a sync operation
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SyncResult {
     pub uploaded: usize,
     pub skipped: usize,
+    /// Files left out by the exclude patterns
+    pub excluded: usize,
     pub failed: Vec<SyncError>,
 }
 
@@ -39,29 +40,198 @@ pub struct SyncProgress {
     pub percentage: f64,
 }
 
-/// Google Drive file metadata
-#[derive(Debug, Deserialize)]
-struct DriveFile {
-    id: String,
-    name: String,
+/// One exclude pattern, compiled
+struct ExcludeRule {
+    matcher: GlobMatcher,
+    /// `!pattern` includes files that earlier patterns excluded
+    negated: bool,
+    /// Patterns without a slash match the file name in any folder
+    file_name_only: bool,
 }
 
-/// Google Drive file list response
-#[derive(Debug, Deserialize)]
-struct DriveFileList {
-    files: Vec<DriveFile>,
+/// Decides which files are left out of sync, from `SyncConfig::exclude_patterns`
+///
+/// Patterns follow .gitignore conventions: `*` stays within a folder while `**`
+/// spans folders, patterns without a slash match the file name anywhere, a
+/// trailing slash leaves out whole folders, and the last matching pattern wins,
+/// so `!pattern` re-includes files. Files can live in any library folder, so
+/// patterns match the end of the full path and can't be anchored with a leading
+/// slash.
+#[derive(Default)]
+pub struct ExcludeFilter {
+    rules: Vec<ExcludeRule>,
+}
+
+impl ExcludeFilter {
+    pub fn new(patterns: &[String], case_sensitive: bool) -> Result<Self, String> {
+        let rules = patterns
+            .iter()
+            .map(|pattern| Self::compile(pattern, case_sensitive))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { rules })
+    }
+
+    /// Compile the valid patterns, logging and skipping the others
+    ///
+    /// Settings saved by older versions can hold patterns that are rejected
+    /// now, which shouldn't stop every other file from syncing.
+    pub fn skipping_invalid(patterns: &[String], case_sensitive: bool) -> Self {
+        let rules = patterns
+            .iter()
+            .filter_map(|pattern| match Self::compile(pattern, case_sensitive) {
+                Ok(rule) => Some(rule),
+                Err(e) => {
+                    log::warn!("Ignoring exclude pattern: {}", e);
+                    None
+                }
+            })
+            .collect();
+        Self { rules }
+    }
+
+    fn compile(pattern: &str, case_sensitive: bool) -> Result<ExcludeRule, String> {
+        let (negated, glob) = match pattern.trim().strip_prefix('!') {
+            Some(glob) => (true, glob),
+            None => (false, pattern.trim()),
+        };
+        if glob.is_empty() {
+            return Err(format!("Invalid exclude pattern '{}': pattern is empty", pattern));
+        }
+
+        if glob.starts_with('/') {
+            return Err(format!(
+                "Invalid exclude pattern '{}': patterns match the end of a path, so they can't start with '/'",
+                pattern
+            ));
+        }
+
+        // `Folder/` leaves out everything inside folders of that name
+        let glob = match glob.strip_suffix('/') {
+            Some(folder) => format!("{}/**", folder),
+            None => glob.to_string(),
+        };
+        let file_name_only = !glob.contains('/');
+        // Patterns with a slash match the end of the path, like .gitignore patterns
+        let glob = if file_name_only || glob.starts_with("**/") {
+            glob
+        } else {
+            format!("**/{}", glob)
+        };
+
+        let matcher = GlobBuilder::new(&glob)
+            .literal_separator(true)
+            .case_insensitive(!case_sensitive)
+            .backslash_escape(true)
+            .build()
+            .map_err(|e| format!("Invalid exclude pattern '{}': {}", pattern, e))?
+            .compile_matcher();
+
+        Ok(ExcludeRule {
+            matcher,
+            negated,
+            file_name_only,
+        })
+    }
+
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        let file_name = path.file_name().map(Path::new);
+
+        self.rules.iter().fold(false, |excluded, rule| {
+            let matches = if rule.file_name_only {
+                file_name.is_some_and(|name| rule.matcher.is_match(name))
+            } else {
+                rule.matcher.is_match(path)
+            };
+            if matches {
+                !rule.negated
+            } else {
+                excluded
+            }
+        })
+    }
+}
+
+/// Days after which an upload session that hasn't made progress is given up
+const UPLOAD_SESSION_MAX_AGE_DAYS: i64 = 7;
+
+/// Held for the whole of a sync, so that the background sync and a sync started
+/// from the UI never upload the same file at the same time
+static SYNC_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Upload sessions of one file, kept in the database so uploads resume after a restart
+struct StoredUploadSessions<'a> {
+    db: &'a Database,
+    provider: &'static str,
+    remote_name: &'a str,
+}
+
+impl UploadSessionStore for StoredUploadSessions<'_> {
+    fn load(&self) -> Option<UploadSession> {
+        self.db
+            .get_upload_session(self.provider, self.remote_name)
+            .unwrap_or_else(|e| {
+                log::warn!("Failed to load upload session of {}: {}", self.remote_name, e);
+                None
+            })
+    }
+
+    fn save(&self, session: &UploadSession) {
+        if let Err(e) = self.db.set_upload_session(self.provider, self.remote_name, Some(session)) {
+            log::warn!("Failed to save upload session of {}: {}", self.remote_name, e);
+        }
+    }
+
+    fn clear(&self) {
+        if let Err(e) = self.db.set_upload_session(self.provider, self.remote_name, None) {
+            log::warn!("Failed to remove upload session of {}: {}", self.remote_name, e);
+        }
+    }
+}
+
+/// Prefix of the names an image is stored under at an upload quality
+///
+/// Renditions are told apart from the original and from each other by their
+/// quality, so changing the upload quality uploads the files again.
+fn upload_key(checksum: &str, quality: UploadQuality) -> String {
+    match quality {
+        UploadQuality::Original => checksum.to_string(),
+        _ => format!("{}-{}", checksum, quality.as_str()),
+    }
 }
 
 /// Cloud sync manager
 pub struct CloudSyncManager<'a> {
-    auth: Arc<GoogleDriveAuth>,
+    provider: Arc<dyn StorageProvider>,
     db: &'a Database,
+    quality: UploadQuality,
+    /// Where renditions are cached until they are uploaded
+    rendition_dir: PathBuf,
+    exclude: ExcludeFilter,
 }
 
 impl<'a> CloudSyncManager<'a> {
-    /// Create a new CloudSyncManager
-    pub fn new(auth: Arc<GoogleDriveAuth>, db: &'a Database) -> Self {
-        Self { auth, db }
+    /// Create a new CloudSyncManager that uploads the original files
+    pub fn new(provider: Arc<dyn StorageProvider>, db: &'a Database) -> Self {
+        Self {
+            provider,
+            db,
+            quality: UploadQuality::Original,
+            rendition_dir: PathBuf::new(),
+            exclude: ExcludeFilter::default(),
+        }
+    }
+
+    /// Upload renditions at `quality` instead of the originals, caching them in `rendition_dir`
+    pub fn with_upload_quality(mut self, quality: UploadQuality, rendition_dir: PathBuf) -> Self {
+        self.quality = quality;
+        self.rendition_dir = rendition_dir;
+        self
+    }
+
+    /// Leave out the files matched by `exclude`
+    pub fn with_exclude_filter(mut self, exclude: ExcludeFilter) -> Self {
+        self.exclude = exclude;
+        self
     }
 
     /// Compute SHA-256 checksum for a file
@@ -88,54 +258,26 @@ impl<'a> CloudSyncManager<'a> {
         Ok(format!("{:x}", hash))
     }
 
-    /// Check if a file with the given checksum exists in Google Drive
-    async fn file_exists_in_drive(
-        &self,
-        checksum: &str,
-        access_token: &str,
-    ) -> Result<bool, String> {
-        let client = reqwest::Client::new();
-
-        // Search for files with the checksum in the name or properties
-        let query = format!("name contains '{}'", checksum);
-        let url = format!(
-            "{}/files?q={}&fields=files(id,name)",
-            GOOGLE_DRIVE_API_URL,
-            urlencoding::encode(&query)
-        );
-
-        let response = client
-            .get(&url)
-            .bearer_auth(access_token)
-            .send()
-            .await
-            .map_err(|e| format!("Failed to query Drive: {}", e))?;
-
-        if !response.status().is_success() {
-            return Err(format!("Drive API error: {}", response.status()));
-        }
-
-        let file_list: DriveFileList = response
-            .json()
-            .await
-            .map_err(|e| format!("Failed to parse response: {}", e))?;
-
-        Ok(!file_list.files.is_empty())
-    }
-
-    /// Upload a file to Google Drive with retry logic
-    async fn upload_file_with_retry(
-        &self,
-        image: &ImageRecord,
-        access_token: &str,
-    ) -> Result<(), String> {
+    /// Upload a file to the storage provider with retry logic
+    ///
+    /// Returns the quality the file was uploaded at.
+    async fn upload_file_with_retry(&self, image: &ImageRecord) -> Result<UploadQuality, String> {
+        let rendition = self.prepare_rendition(image).await;
         let mut attempts = 0;
         let max_attempts = 3;
         let mut last_error = String::new();
 
         while attempts < max_attempts {
-            match self.upload_file(image, access_token).await {
-                Ok(_) => return Ok(()),
+            match self.upload_file(image, rendition.as_ref()).await {
+                Ok(_) => {
+                    return Ok(match &rendition {
+                        Some(rendition) => {
+                            rendition::remove(rendition);
+                            self.quality
+                        }
+                        None => UploadQuality::Original,
+                    });
+                }
                 Err(e) => {
                     attempts += 1;
                     last_error = e.clone();
@@ -178,6 +320,7 @@ impl<'a> CloudSyncManager<'a> {
                         "bmp" => "image/bmp",
                         "webp" => "image/webp",
                         "heic" => "image/heic",
+                        "heif" => "image/heif",
                         "tiff" | "tif" => "image/tiff",
                         _ => "image/jpeg", // Default for images
                     }
@@ -207,77 +350,86 @@ impl<'a> CloudSyncManager<'a> {
         }
     }
 
-    /// Upload a single file to Google Drive
-    async fn upload_file(&self, image: &ImageRecord, access_token: &str) -> Result<(), String> {
-        let file_path = Path::new(&image.path);
-
-        // Read file content
-        let mut file = File::open(file_path)
-            .map_err(|e| format!("Failed to open file {}: {}", image.path, e))?;
-
-        let mut file_content = Vec::new();
-        file.read_to_end(&mut file_content)
-            .map_err(|e| format!("Failed to read file {}: {}", image.path, e))?;
-
-        // Get filename
-        let filename = file_path
-            .file_name()
-            .and_then(|n| n.to_str())
-            .ok_or_else(|| format!("Invalid filename: {}", image.path))?;
-
-        // Create filename with checksum prefix for deduplication
-        let drive_filename = format!("{}_{}", image.checksum, filename);
+    /// Quality an image is uploaded at: the configured one, or original when no rendition is needed
+    fn upload_quality(&self, image: &ImageRecord) -> UploadQuality {
+        if rendition::is_needed(image, self.quality) {
+            self.quality
+        } else {
+            UploadQuality::Original
+        }
+    }
 
-        // Determine MIME type based on media type and file extension
-        let mime_type = Self::get_mime_type(file_path, &image.media_type);
+    /// Rendition to upload in place of the original, None to upload the original
+    ///
+    /// Originals are uploaded when a rendition can't be made, for example when
+    /// FFmpeg is missing, so the file is still backed up.
+    async fn prepare_rendition(&self, image: &ImageRecord) -> Option<Rendition> {
+        if self.quality == UploadQuality::Original {
+            return None;
+        }
 
-        // Create multipart form
-        let client = reqwest::Client::new();
+        let record = image.clone();
+        let quality = self.quality;
+        let rendition_dir = self.rendition_dir.clone();
+        let prepared = tokio::task::spawn_blocking(move || rendition::prepare(&record, quality, &rendition_dir))
+            .await
+            .unwrap_or_else(|e| Err(format!("Rendition task failed: {}", e)));
+
+        prepared.unwrap_or_else(|e| {
+            log::warn!(
+                "Failed to prepare {} quality rendition of {}, uploading the original: {}",
+                quality.as_str(),
+                image.path,
+                e
+            );
+            None
+        })
+    }
 
-        // Metadata for the file
-        let media_type_str = match image.media_type {
-            crate::database::MediaType::Image => "image",
-            crate::database::MediaType::Video => "video",
+    /// Upload a single file, or its rendition, named after its checksum and quality for deduplication
+    ///
+    /// An upload interrupted earlier, even before the app was restarted, continues
+    /// where it stopped when the provider supports it.
+    async fn upload_file(&self, image: &ImageRecord, rendition: Option<&Rendition>) -> Result<(), String> {
+        let (file_path, filename, mime_type, quality) = match rendition {
+            Some(rendition) => (
+                rendition.path.as_path(),
+                rendition.file_name.as_str(),
+                rendition.mime_type,
+                self.quality,
+            ),
+            None => {
+                let file_path = Path::new(&image.path);
+                let filename = file_path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .ok_or_else(|| format!("Invalid filename: {}", image.path))?;
+                (
+                    file_path,
+                    filename,
+                    Self::get_mime_type(file_path, &image.media_type),
+                    UploadQuality::Original,
+                )
+            }
         };
-        
-        let metadata = serde_json::json!({
-            "name": drive_filename,
-            "description": format!("Uploaded from Cura - Type: {} - Checksum: {}", media_type_str, image.checksum),
-        });
 
-        let metadata_part = multipart::Part::text(metadata.to_string())
-            .mime_str("application/json")
-            .map_err(|e| format!("Failed to create metadata part: {}", e))?;
+        let remote_name = storage::remote_name(&upload_key(&image.checksum, quality), filename);
 
-        let file_part = multipart::Part::bytes(file_content)
-            .file_name(filename.to_string())
-            .mime_str(mime_type)
-            .map_err(|e| format!("Failed to create file part: {}", e))?;
-
-        let form = multipart::Form::new()
-            .part("metadata", metadata_part)
-            .part("file", file_part);
-
-        // Upload file
-        let url = format!("{}?uploadType=multipart", GOOGLE_DRIVE_UPLOAD_URL);
-
-        let response = client
-            .post(&url)
-            .bearer_auth(access_token)
-            .multipart(form)
-            .send()
-            .await
-            .map_err(|e| format!("Failed to upload file: {}", e))?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response
-                .text()
-                .await
-                .unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(format!("Upload failed with status {}: {}", status, error_text));
+        let sessions = StoredUploadSessions {
+            db: self.db,
+            provider: self.provider.name(),
+            remote_name: &remote_name,
+        };
+        if let Some(session) = sessions.load() {
+            log::info!(
+                "Resuming upload of {} at {} of {} bytes",
+                image.path,
+                session.uploaded,
+                session.size
+            );
         }
 
+        self.provider.upload(file_path, &remote_name, mime_type, &sessions).await?;
         Ok(())
     }
 
@@ -296,8 +448,68 @@ impl<'a> CloudSyncManager<'a> {
         Ok(())
     }
 
-    /// Sync images and videos to Google Drive
-    pub async fn sync_to_drive<F>(
+    /// Record the quality a file was uploaded at
+    fn update_sync_quality(&self, image_id: i64, quality: UploadQuality) -> Result<(), String> {
+        let conn = self.db.connection().lock().unwrap();
+
+        conn.execute(
+            "UPDATE images SET sync_quality = ?1 WHERE id = ?2",
+            rusqlite::params![quality.as_str(), image_id],
+        )
+        .map_err(|e| format!("Failed to update sync quality: {}", e))?;
+
+        Ok(())
+    }
+
+    /// Give up the upload sessions that won't be resumed, so providers free their parts
+    async fn discard_abandoned_uploads(&self) {
+        let before = Utc::now() - chrono::Duration::days(UPLOAD_SESSION_MAX_AGE_DAYS);
+        let abandoned = match self.db.get_abandoned_upload_sessions(self.provider.name(), before) {
+            Ok(abandoned) => abandoned,
+            Err(e) => {
+                log::warn!("Failed to load upload sessions: {}", e);
+                return;
+            }
+        };
+
+        for (remote_name, session) in abandoned {
+            log::info!("Giving up the unfinished upload of {}", remote_name);
+            if let Err(e) = self.provider.discard_upload(&remote_name, &session).await {
+                log::warn!("Failed to discard the upload of {}: {}", remote_name, e);
+            }
+            if let Err(e) = self.db.set_upload_session(self.provider.name(), &remote_name, None) {
+                log::warn!("Failed to remove upload session of {}: {}", remote_name, e);
+            }
+        }
+    }
+
+    /// Keys of the files already stored, or None when the provider can't list them
+    async fn stored_keys(&self) -> Option<HashSet<String>> {
+        match self.provider.list().await {
+            Ok(files) => Some(
+                files
+                    .iter()
+                    .filter_map(|file| storage::key_of(&file.name))
+                    .map(str::to_string)
+                    .collect(),
+            ),
+            Err(e) => {
+                log::warn!(
+                    "Failed to list files in {}, checking each file instead: {}",
+                    self.provider.name(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Sync images and videos to the storage provider
+    ///
+    /// Waits for any sync already in progress, whose files are then skipped as
+    /// already uploaded. The stored files are listed once up front rather than
+    /// looked up for every file.
+    pub async fn sync<F>(
         &self,
         image_ids: Vec<i64>,
         progress_callback: F,
@@ -305,14 +517,20 @@ impl<'a> CloudSyncManager<'a> {
     where
         F: Fn(SyncProgress),
     {
-        // Get valid access token
-        let access_token = self.auth.get_valid_access_token().await?;
+        let _running = SYNC_LOCK.lock().await;
+        self.discard_abandoned_uploads().await;
 
         let mut uploaded = 0;
         let mut skipped = 0;
+        let mut excluded = 0;
         let mut failed = Vec::new();
 
         let total = image_ids.len();
+        let mut stored = if image_ids.is_empty() {
+            None
+        } else {
+            self.stored_keys().await
+        };
 
         for (index, image_id) in image_ids.iter().enumerate() {
             // Get media record from database (images or videos)
@@ -345,16 +563,33 @@ impl<'a> CloudSyncManager<'a> {
             };
             progress_callback(progress);
 
-            // Check if file already exists in Drive
-            match self
-                .file_exists_in_drive(&image.checksum, &access_token)
-                .await
-            {
+            // Excluded files leave the queue until the exclude patterns change
+            if self.exclude.is_excluded(Path::new(&image.path)) {
+                log::info!("File {} matches an exclude pattern, skipping", image.path);
+                excluded += 1;
+                let _ = self.update_sync_status(*image_id, "excluded");
+                continue;
+            }
+
+            // Check if file was already uploaded at this quality
+            let quality = self.upload_quality(&image);
+            let key = upload_key(&image.checksum, quality);
+            let exists = match &stored {
+                Some(stored) => Ok(stored.contains(&key)),
+                None => self.provider.exists(&key).await,
+            };
+            match exists {
                 Ok(true) => {
-                    log::info!("File {} already exists in Drive, skipping", image.path);
+                    log::info!(
+                        "File {} already exists in {} at {} quality, skipping",
+                        image.path,
+                        self.provider.name(),
+                        quality.as_str()
+                    );
                     skipped += 1;
                     // Update status to synced even if skipped
                     let _ = self.update_sync_status(*image_id, "synced");
+                    let _ = self.update_sync_quality(*image_id, quality);
                     continue;
                 }
                 Ok(false) => {
@@ -367,15 +602,27 @@ impl<'a> CloudSyncManager<'a> {
             }
 
             // Upload file with retry logic
-            match self.upload_file_with_retry(&image, &access_token).await {
-                Ok(_) => {
-                    log::info!("Successfully uploaded {} ({:?})", image.path, image.media_type);
+            match self.upload_file_with_retry(&image).await {
+                Ok(quality) => {
+                    log::info!(
+                        "Successfully uploaded {} ({:?}, {} quality)",
+                        image.path,
+                        image.media_type,
+                        quality.as_str()
+                    );
                     uploaded += 1;
+                    // Copies of the file later in the queue are skipped
+                    if let Some(stored) = &mut stored {
+                        stored.insert(upload_key(&image.checksum, quality));
+                    }
 
                     // Update sync status in database
                     if let Err(e) = self.update_sync_status(*image_id, "synced") {
                         log::error!("Failed to update sync status: {}", e);
                     }
+                    if let Err(e) = self.update_sync_quality(*image_id, quality) {
+                        log::error!("Failed to update sync quality: {}", e);
+                    }
                 }
                 Err(e) => {
                     log::error!("Failed to upload {}: {}", image.path, e);
@@ -394,6 +641,7 @@ impl<'a> CloudSyncManager<'a> {
         Ok(SyncResult {
             uploaded,
             skipped,
+            excluded,
             failed,
         })
     }
@@ -402,6 +650,7 @@ impl<'a> CloudSyncManager<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::database::{insert_test_media, MediaType};
     use std::fs;
     use std::io::Write;
 
@@ -471,6 +720,7 @@ mod tests {
             ("test.bmp", MediaType::Image, "image/bmp"),
             ("test.webp", MediaType::Image, "image/webp"),
             ("test.heic", MediaType::Image, "image/heic"),
+            ("test.heif", MediaType::Image, "image/heif"),
             ("test.tiff", MediaType::Image, "image/tiff"),
             ("test.tif", MediaType::Image, "image/tiff"),
             ("test.unknown", MediaType::Image, "image/jpeg"), // Default
@@ -764,6 +1014,356 @@ mod tests {
         // Clean up
         let _ = fs::remove_file(&test_video);
     }
+
+    #[tokio::test]
+    async fn test_sync_to_local_folder() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let photo = temp_dir.path().join("IMG_0001.jpg");
+        fs::write(&photo, b"jpeg data").unwrap();
+        let record = insert_test_media(&db, &photo, MediaType::Image, 100, 100);
+        let (image_id, checksum) = (record.id, record.checksum);
+
+        let provider = Arc::new(storage::LocalFolderProvider::new(temp_dir.path().join("backup")));
+        let manager = CloudSyncManager::new(provider.clone(), &db);
+        assert_eq!(db.get_unsynced_image_ids().unwrap(), vec![image_id]);
+
+        let result = manager.sync(vec![image_id], |_| {}).await.unwrap();
+        assert_eq!((result.uploaded, result.skipped), (1, 0));
+        assert_eq!(db.get_image_by_id(image_id).unwrap().unwrap().sync_status, "synced");
+        assert!(db.get_unsynced_image_ids().unwrap().is_empty());
+
+        let files = provider.list().await.unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].name, format!("{}_IMG_0001.jpg", checksum));
+        assert_eq!(provider.download(&files[0]).await.unwrap(), b"jpeg data");
+
+        // Already uploaded files are skipped
+        let result = manager.sync(vec![image_id], |_| {}).await.unwrap();
+        assert_eq!((result.uploaded, result.skipped), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn test_sync_uploads_rendition_at_configured_quality() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let photo = temp_dir.path().join("Scan.png");
+        image::ImageBuffer::from_pixel(3000, 2000, image::Rgb([200u8, 180, 40]))
+            .save(&photo)
+            .unwrap();
+        let record = insert_test_media(&db, &photo, MediaType::Image, 3000, 2000);
+        let (image_id, checksum) = (record.id, record.checksum);
+
+        let rendition_dir = temp_dir.path().join("renditions");
+        let provider = Arc::new(storage::LocalFolderProvider::new(temp_dir.path().join("backup")));
+        let manager = CloudSyncManager::new(provider.clone(), &db)
+            .with_upload_quality(UploadQuality::Medium, rendition_dir.clone());
+
+        let result = manager.sync(vec![image_id], |_| {}).await.unwrap();
+        assert_eq!(result.uploaded, 1);
+        let record = db.get_image_by_id(image_id).unwrap().unwrap();
+        assert_eq!(record.sync_status, "synced");
+        assert_eq!(record.sync_quality.as_deref(), Some("medium"));
+
+        // The JPEG rendition is uploaded under the original's checksum and its quality
+        let files = provider.list().await.unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].name, format!("{}-medium_Scan.jpg", checksum));
+        let uploaded = image::load_from_memory(&provider.download(&files[0]).await.unwrap()).unwrap();
+        assert_eq!((uploaded.width(), uploaded.height()), (2048, 1365));
+
+        // and removed from the cache once uploaded
+        assert_eq!(fs::read_dir(&rendition_dir).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_sync_uploads_again_when_quality_changes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let photo = temp_dir.path().join("Scan.png");
+        image::ImageBuffer::from_pixel(3000, 2000, image::Rgb([200u8, 180, 40]))
+            .save(&photo)
+            .unwrap();
+        let image_id = insert_test_media(&db, &photo, MediaType::Image, 3000, 2000).id;
+
+        let rendition_dir = temp_dir.path().join("renditions");
+        let provider = Arc::new(storage::LocalFolderProvider::new(temp_dir.path().join("backup")));
+        let sync_at = |quality| {
+            CloudSyncManager::new(provider.clone(), &db).with_upload_quality(quality, rendition_dir.clone())
+        };
+
+        let result = sync_at(UploadQuality::Medium).sync(vec![image_id], |_| {}).await.unwrap();
+        assert_eq!((result.uploaded, result.skipped), (1, 0));
+
+        // The medium rendition does not count as the original
+        let result = sync_at(UploadQuality::Original).sync(vec![image_id], |_| {}).await.unwrap();
+        assert_eq!((result.uploaded, result.skipped), (1, 0));
+        assert_eq!(provider.list().await.unwrap().len(), 2);
+        let record = db.get_image_by_id(image_id).unwrap().unwrap();
+        assert_eq!(record.sync_quality.as_deref(), Some("original"));
+
+        // Skipping a file records the quality found
+        let result = sync_at(UploadQuality::Medium).sync(vec![image_id], |_| {}).await.unwrap();
+        assert_eq!((result.uploaded, result.skipped), (0, 1));
+        let record = db.get_image_by_id(image_id).unwrap().unwrap();
+        assert_eq!(record.sync_quality.as_deref(), Some("medium"));
+    }
+
+    #[test]
+    fn test_exclude_filter() {
+        let patterns: Vec<String> = ["*.tmp", "**/Screenshots/**", "Private/*.jpg", "!keep.tmp", "[!a-z]*.png"]
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        let filter = ExcludeFilter::new(&patterns, false).unwrap();
+        let excluded = |path: &str| filter.is_excluded(Path::new(path));
+
+        // File name patterns apply in any folder, ignoring case by default
+        assert!(excluded("/photos/draft.tmp"));
+        assert!(excluded("/photos/2024/DRAFT.TMP"));
+        assert!(!excluded("/photos/tmp/IMG_0001.jpg"));
+
+        // `**` spans folders
+        assert!(excluded("/photos/Screenshots/2024/01/shot.png"));
+        assert!(!excluded("/photos/Screenshots.png"));
+
+        // `*` stays within a folder, and patterns with a slash match the end of the path
+        assert!(excluded("/home/me/Private/IMG_0001.jpg"));
+        assert!(!excluded("/home/me/Private/2024/IMG_0001.jpg"));
+        assert!(!excluded("/home/me/NotPrivate/IMG_0001.jpg"));
+
+        // A trailing slash leaves out whole folders
+        let folders = ExcludeFilter::new(&["Screenshots/".to_string(), "Camera/Trash/".to_string()], false).unwrap();
+        assert!(folders.is_excluded(Path::new("/photos/Screenshots/shot.png")));
+        assert!(folders.is_excluded(Path::new("/photos/2024/screenshots/01/shot.png")));
+        assert!(folders.is_excluded(Path::new("/phone/Camera/Trash/IMG_0001.jpg")));
+        assert!(!folders.is_excluded(Path::new("/photos/Screenshots.png")));
+        assert!(!folders.is_excluded(Path::new("/photos/Trash/IMG_0001.jpg")));
+
+        // Later negated patterns include files again
+        assert!(!excluded("/photos/keep.tmp"));
+
+        // `[!...]` is a character class, not a negation
+        assert!(excluded("/photos/1.png"));
+        assert!(!excluded("/photos/x.png"));
+
+        let case_sensitive = ExcludeFilter::new(&patterns, true).unwrap();
+        assert!(case_sensitive.is_excluded(Path::new("/photos/draft.tmp")));
+        assert!(!case_sensitive.is_excluded(Path::new("/photos/DRAFT.TMP")));
+
+        assert!(!ExcludeFilter::default().is_excluded(Path::new("/photos/draft.tmp")));
+        assert!(ExcludeFilter::new(&["{a,b".to_string()], false).is_err());
+        assert!(ExcludeFilter::new(&["/Private/*.jpg".to_string()], false).is_err());
+
+        // Patterns saved before they were validated don't stop the valid ones working
+        let saved = ["{a,b".to_string(), "*.tmp".to_string(), " ".to_string()];
+        let lenient = ExcludeFilter::skipping_invalid(&saved, false);
+        assert!(lenient.is_excluded(Path::new("/photos/draft.tmp")));
+        assert!(!lenient.is_excluded(Path::new("/photos/a")));
+    }
+
+    #[tokio::test]
+    async fn test_sync_reports_excluded_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let mut image_ids = Vec::new();
+        for name in ["IMG_0001.jpg", "Screenshot_0001.png"] {
+            let path = temp_dir.path().join(name);
+            fs::write(&path, name).unwrap();
+            image_ids.push(insert_test_media(&db, &path, MediaType::Image, 100, 100).id);
+        }
+
+        let provider = Arc::new(storage::LocalFolderProvider::new(temp_dir.path().join("backup")));
+        let exclude = ExcludeFilter::new(&["screenshot_*".to_string()], false).unwrap();
+        let manager = CloudSyncManager::new(provider.clone(), &db).with_exclude_filter(exclude);
+
+        let result = manager.sync(image_ids.clone(), |_| {}).await.unwrap();
+        assert_eq!((result.uploaded, result.skipped, result.excluded), (1, 0, 1));
+        assert_eq!(provider.list().await.unwrap().len(), 1);
+
+        // Excluded files leave the queue until the patterns change
+        assert_eq!(db.get_image_by_id(image_ids[1]).unwrap().unwrap().sync_status, "excluded");
+        assert!(db.get_unsynced_image_ids().unwrap().is_empty());
+        assert_eq!(db.requeue_excluded_images().unwrap(), 1);
+        assert_eq!(db.get_unsynced_image_ids().unwrap(), vec![image_ids[1]]);
+    }
+
+    /// Local folder that takes its time over uploads and counts how many overlap,
+    /// and how often it is asked what it stores
+    struct SlowProvider {
+        inner: storage::LocalFolderProvider,
+        active: std::sync::atomic::AtomicUsize,
+        max_active: std::sync::atomic::AtomicUsize,
+        lookups: std::sync::atomic::AtomicUsize,
+    }
+
+    impl StorageProvider for SlowProvider {
+        fn name(&self) -> &'static str {
+            self.inner.name()
+        }
+
+        fn list(&self) -> storage::BoxFuture<'_, Result<Vec<storage::RemoteFile>, String>> {
+            self.lookups.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.inner.list()
+        }
+
+        fn exists<'a>(&'a self, checksum: &'a str) -> storage::BoxFuture<'a, Result<bool, String>> {
+            self.lookups.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.inner.exists(checksum)
+        }
+
+        fn upload<'a>(
+            &'a self,
+            source: &'a Path,
+            name: &'a str,
+            mime_type: &'a str,
+            sessions: &'a dyn UploadSessionStore,
+        ) -> storage::BoxFuture<'a, Result<storage::RemoteFile, String>> {
+            use std::sync::atomic::Ordering;
+            Box::pin(async move {
+                let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+                self.max_active.fetch_max(active, Ordering::SeqCst);
+                sleep(Duration::from_millis(50)).await;
+                let result = self.inner.upload(source, name, mime_type, sessions).await;
+                self.active.fetch_sub(1, Ordering::SeqCst);
+                result
+            })
+        }
+
+        fn download<'a>(&'a self, file: &'a storage::RemoteFile) -> storage::BoxFuture<'a, Result<Vec<u8>, String>> {
+            self.inner.download(file)
+        }
+
+        fn delete<'a>(&'a self, file: &'a storage::RemoteFile) -> storage::BoxFuture<'a, Result<(), String>> {
+            self.inner.delete(file)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_overlapping_syncs_run_one_at_a_time() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let mut image_ids = Vec::new();
+        for name in ["IMG_0001.jpg", "IMG_0002.jpg"] {
+            let path = temp_dir.path().join(name);
+            fs::write(&path, name).unwrap();
+            image_ids.push(insert_test_media(&db, &path, MediaType::Image, 100, 100).id);
+        }
+
+        let provider = Arc::new(SlowProvider {
+            inner: storage::LocalFolderProvider::new(temp_dir.path().join("backup")),
+            active: Default::default(),
+            max_active: Default::default(),
+            lookups: Default::default(),
+        });
+        let background = CloudSyncManager::new(provider.clone(), &db);
+        let manual = CloudSyncManager::new(provider.clone(), &db);
+
+        let (first, second) = tokio::join!(
+            background.sync(image_ids.clone(), |_| {}),
+            manual.sync(image_ids.clone(), |_| {})
+        );
+        let (first, second) = (first.unwrap(), second.unwrap());
+
+        // Every file was uploaded once, by whichever sync got there first
+        assert_eq!(provider.max_active.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!((first.uploaded + second.uploaded, first.skipped + second.skipped), (2, 2));
+        assert_eq!(provider.list().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_sync_lists_stored_files_once() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let mut image_ids = Vec::new();
+        for (name, content) in [("IMG_0001.jpg", "a"), ("IMG_0002.jpg", "b"), ("IMG_0001 copy.jpg", "a")] {
+            let path = temp_dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            image_ids.push(insert_test_media(&db, &path, MediaType::Image, 100, 100).id);
+        }
+
+        let provider = Arc::new(SlowProvider {
+            inner: storage::LocalFolderProvider::new(temp_dir.path().join("backup")),
+            active: Default::default(),
+            max_active: Default::default(),
+            lookups: Default::default(),
+        });
+        let manager = CloudSyncManager::new(provider.clone(), &db);
+
+        // The copy is skipped without asking the provider again
+        let result = manager.sync(image_ids.clone(), |_| {}).await.unwrap();
+        assert_eq!((result.uploaded, result.skipped), (2, 1));
+        assert_eq!(provider.lookups.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        let result = manager.sync(image_ids, |_| {}).await.unwrap();
+        assert_eq!((result.uploaded, result.skipped), (0, 3));
+        assert_eq!(provider.lookups.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_sync_resumes_interrupted_upload() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let video = temp_dir.path().join("clip.mp4");
+        fs::write(&video, b"video data").unwrap();
+        let record = insert_test_media(&db, &video, MediaType::Video, 100, 100);
+        let (image_id, checksum) = (record.id, record.checksum);
+
+        // A previous run copied half of the file before the app quit
+        let backup = temp_dir.path().join("backup");
+        let remote_name = storage::remote_name(&checksum, "clip.mp4");
+        fs::create_dir_all(&backup).unwrap();
+        fs::write(backup.join(format!("{}.part", remote_name)), b"VIDEO").unwrap();
+        let session = UploadSession {
+            state: String::new(),
+            uploaded: 5,
+            size: 10,
+        };
+        db.set_upload_session("local folder", &remote_name, Some(&session)).unwrap();
+        assert_eq!(db.get_upload_session("local folder", &remote_name).unwrap(), Some(session));
+
+        let provider = Arc::new(storage::LocalFolderProvider::new(&backup));
+        let manager = CloudSyncManager::new(provider, &db);
+        let result = manager.sync(vec![image_id], |_| {}).await.unwrap();
+        assert_eq!(result.uploaded, 1);
+
+        // Only the missing half was copied, and the finished session is gone
+        assert_eq!(fs::read(backup.join(&remote_name)).unwrap(), b"VIDEO data");
+        assert_eq!(db.get_upload_session("local folder", &remote_name).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_sync_discards_uploads_that_wont_resume() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let video = temp_dir.path().join("Screen Recording.mp4");
+        fs::write(&video, b"video data").unwrap();
+        let checksum = insert_test_media(&db, &video, MediaType::Video, 100, 100).checksum;
+
+        // A copy was interrupted, and the file has been excluded since
+        let backup = temp_dir.path().join("backup");
+        let remote_name = storage::remote_name(&checksum, "Screen Recording.mp4");
+        let partial = backup.join(format!("{}.part", remote_name));
+        fs::create_dir_all(&backup).unwrap();
+        fs::write(&partial, b"VIDEO").unwrap();
+        let session = UploadSession {
+            state: String::new(),
+            uploaded: 5,
+            size: 10,
+        };
+        db.set_upload_session("local folder", &remote_name, Some(&session)).unwrap();
+        db.connection()
+            .lock()
+            .unwrap()
+            .execute("UPDATE images SET sync_status = 'excluded'", [])
+            .unwrap();
+
+        let provider = Arc::new(storage::LocalFolderProvider::new(&backup));
+        CloudSyncManager::new(provider, &db).sync(Vec::new(), |_| {}).await.unwrap();
+
+        assert!(!partial.exists());
+        assert_eq!(db.get_upload_session("local folder", &remote_name).unwrap(), None);
+    }
 }
 
 #[cfg(test)]
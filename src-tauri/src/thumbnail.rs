@@ -10,6 +10,8 @@ use std::sync::{Arc, Mutex};
 use std::collections::HashMap;
 use log::{info, warn, debug};
 
+use crate::edits::EditRecipe;
+
 /// Thumbnail sizes
 const THUMBNAIL_SMALL_WIDTH: u32 = 150;
 const THUMBNAIL_MEDIUM_WIDTH: u32 = 600;
@@ -98,6 +100,38 @@ pub fn generate_thumbnails(
         return Err(format!("Path is not a file: {}", image_path));
     }
 
+    // Compute checksum for the file
+    let checksum = compute_checksum(path)?;
+
+    generate_thumbnails_with_checksum(image_path, &checksum, cache_dir)
+}
+
+/// Generate thumbnails for an image whose checksum is already known
+/// Avoids hashing the file a second time when the caller has computed it
+pub(crate) fn generate_thumbnails_with_checksum(
+    image_path: &str,
+    checksum: &str,
+    cache_dir: &Path,
+) -> Result<ThumbnailPaths, String> {
+    generate_edited_thumbnails(image_path, checksum, None, cache_dir)
+}
+
+/// Generate thumbnails with an edit recipe applied
+/// Edited thumbnails are cached under the checksum and the recipe hash, so
+/// every version of the edits gets its own files
+pub(crate) fn generate_edited_thumbnails(
+    image_path: &str,
+    checksum: &str,
+    recipe: Option<&EditRecipe>,
+    cache_dir: &Path,
+) -> Result<ThumbnailPaths, String> {
+    let path = Path::new(image_path);
+    let recipe = recipe.filter(|r| !r.is_identity());
+    let cache_key = match recipe {
+        Some(recipe) => format!("{}_{}", checksum, recipe.hash()),
+        None => checksum.to_string(),
+    };
+
     // Get file metadata for mtime comparison
     let file_metadata = fs::metadata(path)
         .map_err(|e| format!("Failed to read file metadata: {}", e))?;
@@ -105,16 +139,13 @@ pub fn generate_thumbnails(
         .modified()
         .map_err(|e| format!("Failed to read file modified time: {}", e))?;
 
-    // Compute checksum for the file
-    let checksum = compute_checksum(path)?;
-
     // Create cache directory if it doesn't exist
     fs::create_dir_all(cache_dir)
         .map_err(|e| format!("Failed to create cache directory: {}", e))?;
 
     // Generate thumbnail paths
-    let small_path = cache_dir.join(format!("{}_small.jpg", checksum));
-    let medium_path = cache_dir.join(format!("{}_medium.jpg", checksum));
+    let small_path = cache_dir.join(format!("{}_small.jpg", cache_key));
+    let medium_path = cache_dir.join(format!("{}_medium.jpg", cache_key));
 
     // Check if thumbnails already exist and source file is unchanged
     let should_regenerate = should_regenerate_thumbnails(
@@ -131,17 +162,37 @@ pub fn generate_thumbnails(
         });
     }
 
-    // Load and decode the image
+    // Load and decode the image, rotated upright according to its orientation
+    // and then cropped and rotated by the edits
     let img = load_image(path)?;
-
-    // Apply EXIF orientation transformation
-    let img = apply_orientation(img, path)?;
+    let img = match recipe {
+        Some(recipe) => recipe.apply_geometry(img),
+        None => img,
+    };
 
     // Generate small thumbnail
-    generate_thumbnail_size(&img, &small_path, THUMBNAIL_SMALL_WIDTH)?;
+    generate_thumbnail_size(&img, &small_path, THUMBNAIL_SMALL_WIDTH, recipe)?;
+
+    // RAW files are thumbnailed from their embedded preview; when demosaicing is
+    // available the medium size is rendered from the sensor data instead. Twice
+    // the medium width keeps the short edge of portrait shots wide enough.
+    let demosaiced = if crate::raw::is_raw_path(path) {
+        match crate::raw::demosaic(path, THUMBNAIL_MEDIUM_WIDTH * 2) {
+            Ok(img) => Some(match recipe {
+                Some(recipe) => recipe.apply_geometry(img),
+                None => img,
+            }),
+            Err(e) => {
+                debug!("Using embedded preview for medium thumbnail: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
 
     // Generate medium thumbnail
-    generate_thumbnail_size(&img, &medium_path, THUMBNAIL_MEDIUM_WIDTH)?;
+    generate_thumbnail_size(demosaiced.as_ref().unwrap_or(&img), &medium_path, THUMBNAIL_MEDIUM_WIDTH, recipe)?;
 
     Ok(ThumbnailPaths {
         small: small_path.to_string_lossy().to_string(),
@@ -150,7 +201,7 @@ pub fn generate_thumbnails(
 }
 
 /// Compute SHA-256 checksum for a file
-fn compute_checksum(path: &Path) -> Result<String, String> {
+pub(crate) fn compute_checksum(path: &Path) -> Result<String, String> {
     let data = fs::read(path)
         .map_err(|e| format!("Failed to read file for checksum: {}", e))?;
     
@@ -193,7 +244,8 @@ fn should_regenerate_thumbnails(
 }
 
 /// Load an image from a file, handling different formats
-fn load_image(path: &Path) -> Result<DynamicImage, String> {
+/// The returned image is upright, with any orientation metadata already applied
+pub(crate) fn load_image(path: &Path) -> Result<DynamicImage, String> {
     let extension = path
         .extension()
         .and_then(|e| e.to_str())
@@ -201,40 +253,113 @@ fn load_image(path: &Path) -> Result<DynamicImage, String> {
         .unwrap_or_default();
 
     match extension.as_str() {
-        "heic" => load_heic_image(path),
-        "raw" | "cr2" | "nef" => load_raw_image(path),
+        "heic" | "heif" => load_heic_image(path),
+        _ if crate::raw::is_raw_path(path) => {
+            load_raw_image(path).and_then(|img| apply_orientation(img, path))
+        }
         _ => {
             // Use standard image crate for JPEG, PNG, etc.
-            ImageReader::open(path)
+            let img = ImageReader::open(path)
                 .map_err(|e| format!("Failed to open image: {}", e))?
                 .decode()
-                .map_err(|e| format!("Failed to decode image: {}", e))
+                .map_err(|e| format!("Failed to decode image: {}", e))?;
+
+            // Apply EXIF orientation transformation
+            apply_orientation(img, path)
         }
     }
 }
 
-/// Load a HEIC image and convert to standard format
+/// Load a HEIC/HEIF image and convert to standard format
+///
+/// Multi-image containers (bursts, Live Photo stills, depth maps) are decoded
+/// through their primary image, falling back to the first top-level image.
+/// Grid-tiled images are stitched by libheif.
+///
+/// Orientation: when the file carries an EXIF orientation, the image is decoded
+/// without the container's own transformations and the EXIF orientation is
+/// applied instead, so the two are never combined. Files without one (or with
+/// orientation 1) keep libheif's irot/imir/clap transformations.
+#[cfg(feature = "heic")]
+fn load_heic_image(path: &Path) -> Result<DynamicImage, String> {
+    use image::{RgbImage, RgbaImage};
+    use libheif_rs::{ColorSpace, DecodingOptions, HeifContext, LibHeif, RgbChroma};
+
+    let path_str = path
+        .to_str()
+        .ok_or_else(|| format!("Invalid HEIC path: {}", path.display()))?;
+
+    let lib_heif = LibHeif::new();
+    let context = HeifContext::read_from_file(path_str)
+        .map_err(|e| format!("Failed to open HEIC image: {}", e))?;
+
+    let handle = match context.primary_image_handle() {
+        Ok(handle) => handle,
+        Err(primary_error) => context
+            .top_level_image_handles()
+            .into_iter()
+            .next()
+            .ok_or_else(|| format!("HEIC file contains no images: {}", primary_error))?,
+    };
+
+    let orientation = read_orientation(path);
+    let mut options = DecodingOptions::new();
+    if orientation != 1 {
+        if let Some(options) = options.as_mut() {
+            options.set_ignore_transformations(true);
+        }
+    }
+
+    let has_alpha = handle.has_alpha_channel();
+    let chroma = if has_alpha { RgbChroma::Rgba } else { RgbChroma::Rgb };
+    let decoded = lib_heif
+        .decode(&handle, ColorSpace::Rgb(chroma), options)
+        .map_err(|e| format!("Failed to decode HEIC image: {}", e))?;
+
+    let planes = decoded.planes();
+    let plane = planes
+        .interleaved
+        .ok_or_else(|| "Decoded HEIC image has no interleaved plane".to_string())?;
+
+    // Copy rows individually since libheif pads each row to its stride
+    let channels = if has_alpha { 4 } else { 3 };
+    let row_len = plane.width as usize * channels;
+    let mut pixels = Vec::with_capacity(row_len * plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        pixels.extend_from_slice(&row[..row_len]);
+    }
+
+    let img = if has_alpha {
+        RgbaImage::from_raw(plane.width, plane.height, pixels).map(DynamicImage::ImageRgba8)
+    } else {
+        RgbImage::from_raw(plane.width, plane.height, pixels).map(DynamicImage::ImageRgb8)
+    }
+    .ok_or_else(|| "Decoded HEIC image has an unexpected size".to_string())?;
+
+    Ok(orient(img, orientation))
+}
+
+/// Load a HEIC/HEIF image when the app is built without libheif
+#[cfg(not(feature = "heic"))]
 fn load_heic_image(path: &Path) -> Result<DynamicImage, String> {
-    // For now, return an error indicating HEIC support is not yet implemented
-    // In a full implementation, you would use libheif-rs here
     Err(format!(
-        "HEIC format not yet supported: {}. Please install libheif-rs dependency.",
+        "HEIC format not supported in this build: {}. Rebuild with the `heic` feature.",
         path.display()
     ))
 }
 
-/// Load a RAW image and convert to standard format
+/// Load a RAW image from its embedded JPEG preview
 fn load_raw_image(path: &Path) -> Result<DynamicImage, String> {
-    // For now, return an error indicating RAW support is not yet implemented
-    // In a full implementation, you would use rawloader crate here
-    Err(format!(
-        "RAW format not yet supported: {}. Please install rawloader dependency.",
-        path.display()
-    ))
+    crate::raw::extract_preview(path)
 }
 
 /// Apply EXIF orientation transformation to an image
 fn apply_orientation(img: DynamicImage, path: &Path) -> Result<DynamicImage, String> {
+    Ok(orient(img, read_orientation(path)))
+}
+
+/// Read the EXIF orientation of an image file, defaulting to 1 (normal)
+fn read_orientation(path: &Path) -> u16 {
     use exif::{In, Reader, Tag};
     use std::fs::File;
     use std::io::BufReader;
@@ -242,7 +367,7 @@ fn apply_orientation(img: DynamicImage, path: &Path) -> Result<DynamicImage, Str
     // Try to read EXIF orientation
     let file = match File::open(path) {
         Ok(f) => f,
-        Err(_) => return Ok(img), // No EXIF data, return image as-is
+        Err(_) => return 1, // No EXIF data, image is as-is
     };
 
     let mut bufreader = BufReader::new(&file);
@@ -250,19 +375,22 @@ fn apply_orientation(img: DynamicImage, path: &Path) -> Result<DynamicImage, Str
     
     let exif = match exif_reader.read_from_container(&mut bufreader) {
         Ok(e) => e,
-        Err(_) => return Ok(img), // No EXIF data, return image as-is
+        Err(_) => return 1, // No EXIF data, image is as-is
     };
 
-    let orientation = exif
+    exif
         .get_field(Tag::Orientation, In::PRIMARY)
         .and_then(|f| match f.value {
             exif::Value::Short(ref v) if !v.is_empty() => Some(v[0]),
             _ => None,
         })
-        .unwrap_or(1);
+        .unwrap_or(1)
+}
 
+/// Transform an image according to an EXIF orientation value
+fn orient(img: DynamicImage, orientation: u16) -> DynamicImage {
     // Apply transformation based on orientation value
-    let transformed = match orientation {
+    match orientation {
         1 => img, // Normal
         2 => img.fliph(), // Flip horizontal
         3 => img.rotate180(), // Rotate 180
@@ -272,16 +400,16 @@ fn apply_orientation(img: DynamicImage, path: &Path) -> Result<DynamicImage, Str
         7 => img.rotate270().fliph(), // Rotate 270 CW and flip horizontal
         8 => img.rotate270(), // Rotate 270 CW
         _ => img, // Unknown orientation, return as-is
-    };
-
-    Ok(transformed)
+    }
 }
 
 /// Generate a thumbnail of a specific size
+/// Tone adjustments of the edits are applied after resizing, since they work per pixel
 fn generate_thumbnail_size(
     img: &DynamicImage,
     output_path: &Path,
     target_width: u32,
+    recipe: Option<&EditRecipe>,
 ) -> Result<(), String> {
     let (width, height) = img.dimensions();
     
@@ -291,6 +419,10 @@ fn generate_thumbnail_size(
     // Resize using Lanczos3 filter for high quality
     // Use resize_exact to ensure exact dimensions
     let thumbnail = img.resize_exact(target_width, target_height, image::imageops::FilterType::Lanczos3);
+    let thumbnail = match recipe {
+        Some(recipe) => recipe.apply_adjustments(thumbnail),
+        None => thumbnail,
+    };
 
     // Save as JPEG
     thumbnail
@@ -316,6 +448,21 @@ pub fn generate_video_thumbnails(
         return Err(format!("Path is not a file: {}", video_path));
     }
 
+    // Compute checksum for the file
+    let checksum = compute_checksum(path)?;
+
+    generate_video_thumbnails_with_checksum(video_path, &checksum, cache_dir)
+}
+
+/// Generate thumbnails for a video whose checksum is already known
+/// Avoids hashing the file a second time when the caller has computed it
+pub(crate) fn generate_video_thumbnails_with_checksum(
+    video_path: &str,
+    checksum: &str,
+    cache_dir: &Path,
+) -> Result<ThumbnailPaths, String> {
+    let path = Path::new(video_path);
+
     // Get file metadata for mtime comparison
     let file_metadata = fs::metadata(path)
         .map_err(|e| format!("Failed to read file metadata: {}", e))?;
@@ -323,9 +470,6 @@ pub fn generate_video_thumbnails(
         .modified()
         .map_err(|e| format!("Failed to read file modified time: {}", e))?;
 
-    // Compute checksum for the file
-    let checksum = compute_checksum(path)?;
-
     // Create cache directory if it doesn't exist
     fs::create_dir_all(cache_dir)
         .map_err(|e| format!("Failed to create cache directory: {}", e))?;
@@ -360,10 +504,10 @@ pub fn generate_video_thumbnails(
         .map_err(|e| format!("Failed to decode video frame: {}", e))?;
 
     // Generate small thumbnail
-    generate_thumbnail_size(&img, &small_path, THUMBNAIL_SMALL_WIDTH)?;
+    generate_thumbnail_size(&img, &small_path, THUMBNAIL_SMALL_WIDTH, None)?;
 
     // Generate medium thumbnail
-    generate_thumbnail_size(&img, &medium_path, THUMBNAIL_MEDIUM_WIDTH)?;
+    generate_thumbnail_size(&img, &medium_path, THUMBNAIL_MEDIUM_WIDTH, None)?;
 
     Ok(ThumbnailPaths {
         small: small_path.to_string_lossy().to_string(),
@@ -695,6 +839,53 @@ mod tests {
         // and are not part of the thumbnail generation module itself.
     }
 
+    #[test]
+    fn test_heic_corrupt_file_returns_error() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let cache_dir = temp_dir.path().join("cache");
+
+        let heic_path = temp_dir.path().join("IMG_0001.HEIC");
+        fs::write(&heic_path, b"This is not a valid HEIC file").unwrap();
+
+        let result = generate_thumbnails(heic_path.to_str().unwrap(), &cache_dir);
+
+        // HEIC files must go through the HEIC decoder rather than the image crate
+        let error_msg = result.unwrap_err();
+        assert!(error_msg.contains("HEIC"), "Expected a HEIC error, got: {}", error_msg);
+    }
+
+    #[test]
+    fn test_raw_thumbnails_from_embedded_preview() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let cache_dir = temp_dir.path().join("cache");
+
+        let mut preview = Vec::new();
+        create_test_image(800, 600)
+            .write_to(&mut Cursor::new(&mut preview), ImageFormat::Jpeg)
+            .unwrap();
+        let raw_path = temp_dir.path().join("IMG_0001.CR2");
+        fs::write(&raw_path, crate::raw::build_test_raw(&preview, 6000, 4500)).unwrap();
+
+        let result = generate_thumbnails(raw_path.to_str().unwrap(), &cache_dir).unwrap();
+
+        let small_img = image::open(&result.small).unwrap();
+        assert_eq!(small_img.dimensions(), (150, 113));
+        let medium_img = image::open(&result.medium).unwrap();
+        assert_eq!(medium_img.width(), 600);
+    }
+
+    #[test]
+    fn test_orient_swaps_dimensions_for_rotations() {
+        let img = create_test_image(40, 20);
+
+        for orientation in 1..=4 {
+            assert_eq!(orient(img.clone(), orientation).dimensions(), (40, 20));
+        }
+        for orientation in 5..=8 {
+            assert_eq!(orient(img.clone(), orientation).dimensions(), (20, 40));
+        }
+    }
+
     #[test]
     fn test_thumbnail_generation_with_empty_file() {
         let temp_dir = tempfile::tempdir().unwrap();
@@ -1117,9 +1308,9 @@ mod property_tests {
             let medium_img = image::open(&paths.medium).unwrap();
             prop_assert!(medium_img.width() > 0, "Medium thumbnail should be valid");
 
-            // Note: HEIC and RAW format testing would require actual implementation
-            // of libheif-rs and rawloader dependencies. For now, we verify that
-            // the system correctly handles format conversion for supported formats.
+            // Note: HEIC decoding needs real HEIC samples and RAW previews are
+            // covered in the raw module. Here we verify that the system correctly
+            // handles format conversion for formats the image crate supports.
         }
 
         // Feature: cura-photo-manager, Property 9: Thumbnail Generation Idempotence
@@ -0,0 +1,370 @@
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::database::{Database, ImageRecord};
+use crate::thumbnail;
+
+/// Outcome of a trash operation on several images
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrashReport {
+    /// Images that were trashed, restored or purged
+    pub image_ids: Vec<i64>,
+    pub failed: Vec<TrashError>,
+}
+
+impl TrashReport {
+    fn fail(&mut self, image_id: i64, path: &str, error: String) {
+        self.failed.push(TrashError {
+            image_id,
+            path: path.to_string(),
+            error,
+        });
+    }
+}
+
+/// An image the operation failed for
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashError {
+    pub image_id: i64,
+    pub path: String,
+    pub error: String,
+}
+
+/// Move images to the trash, keeping their tags, embeddings and albums
+///
+/// With a `trash_dir`, originals are moved there so they disappear from the
+/// library folders; otherwise they stay where they are until purged. Images
+/// already in the trash are skipped.
+pub fn move_to_trash(db: &Database, image_ids: &[i64], trash_dir: Option<&Path>) -> TrashReport {
+    let mut report = TrashReport::default();
+
+    for &image_id in image_ids {
+        let image = match get_image(db, image_id) {
+            Ok(image) if image.trashed_at.is_some() => continue,
+            Ok(image) => image,
+            Err(error) => {
+                report.fail(image_id, "", error);
+                continue;
+            }
+        };
+
+        match trash_image(db, &image, trash_dir) {
+            Ok(()) => report.image_ids.push(image_id),
+            Err(error) => report.fail(image_id, &image.path, error),
+        }
+    }
+
+    report
+}
+
+/// Take images out of the trash, moving their originals back if they were moved
+pub fn restore(db: &Database, image_ids: &[i64]) -> TrashReport {
+    let mut report = TrashReport::default();
+
+    for &image_id in image_ids {
+        let image = match get_image(db, image_id) {
+            Ok(image) if image.trashed_at.is_none() => continue,
+            Ok(image) => image,
+            Err(error) => {
+                report.fail(image_id, "", error);
+                continue;
+            }
+        };
+
+        match restore_image(db, &image) {
+            Ok(()) => report.image_ids.push(image_id),
+            Err(error) => report.fail(image_id, &image.path, error),
+        }
+    }
+
+    report
+}
+
+/// Permanently delete everything in the trash
+pub fn empty_trash(db: &Database) -> Result<TrashReport, String> {
+    let images = db
+        .get_trashed_images()
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    Ok(purge(db, images))
+}
+
+/// Permanently delete images that have been in the trash longer than `retention_days`
+///
+/// A retention of 0 days keeps trashed images until the trash is emptied, as
+/// does one reaching further back than dates go.
+pub fn purge_expired(db: &Database, retention_days: u32) -> Result<TrashReport, String> {
+    let cutoff = match Utc::now().checked_sub_signed(Duration::days(retention_days as i64)) {
+        Some(cutoff) if retention_days > 0 => cutoff,
+        _ => return Ok(TrashReport::default()),
+    };
+    let images = db
+        .get_images_trashed_before(Some(cutoff))
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    Ok(purge(db, images))
+}
+
+/// Delete the files of trashed images, then their records
+fn purge(db: &Database, images: Vec<ImageRecord>) -> TrashReport {
+    let mut report = TrashReport::default();
+
+    for image in images {
+        // An original trashed in place may have been replaced by another file
+        // since, so it is only deleted while it is still the trashed one
+        let file = match image.trash_path.as_deref() {
+            Some(trash_path) => Some(trash_path),
+            None if is_trashed_original(&image) => Some(image.path.as_str()),
+            None => None,
+        };
+
+        if let Some(file) = file {
+            if let Err(e) = fs::remove_file(file) {
+                // A file that is already gone needs no deleting
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    report.fail(image.id, file, format!("Failed to delete file: {}", e));
+                    continue;
+                }
+            }
+        }
+
+        match db.delete_image(image.id) {
+            Ok(_) => report.image_ids.push(image.id),
+            Err(e) => report.fail(image.id, &image.path, format!("Database error: {}", e)),
+        }
+    }
+
+    report
+}
+
+/// Whether the file at an image's path is still the one that was trashed
+fn is_trashed_original(image: &ImageRecord) -> bool {
+    let path = Path::new(&image.path);
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.len() == image.file_size => {
+            thumbnail::compute_checksum(path).is_ok_and(|checksum| checksum == image.checksum)
+        }
+        _ => false,
+    }
+}
+
+fn trash_image(db: &Database, image: &ImageRecord, trash_dir: Option<&Path>) -> Result<(), String> {
+    let destination = match trash_dir {
+        Some(dir) if Path::new(&image.path).exists() => Some(trash_destination(dir, image)?),
+        _ => None,
+    };
+
+    // Mark the record first, so the watcher keeps it when the file disappears
+    db.trash_image(image.id, destination.as_ref().and_then(|d| d.to_str()))
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    if let Some(destination) = destination {
+        if let Err(error) = move_file(Path::new(&image.path), &destination) {
+            let _ = db.restore_image(image.id);
+            return Err(error);
+        }
+    }
+
+    Ok(())
+}
+
+fn restore_image(db: &Database, image: &ImageRecord) -> Result<(), String> {
+    if let Some(trash_path) = &image.trash_path {
+        let original = Path::new(&image.path);
+        if original.exists() {
+            return Err(format!("Cannot restore, a file already exists at {}", image.path));
+        }
+        if let Some(parent) = original.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to recreate folder {}: {}", parent.display(), e))?;
+        }
+        move_file(Path::new(trash_path), original)?;
+    }
+
+    db.restore_image(image.id)
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    Ok(())
+}
+
+/// Path in the trash folder for an image, prefixed with its ID to keep names unique
+fn trash_destination(trash_dir: &Path, image: &ImageRecord) -> Result<PathBuf, String> {
+    fs::create_dir_all(trash_dir)
+        .map_err(|e| format!("Failed to create trash folder: {}", e))?;
+
+    let file_name = Path::new(&image.path)
+        .file_name()
+        .ok_or_else(|| format!("Invalid file path: {}", image.path))?;
+
+    Ok(trash_dir.join(format!("{}_{}", image.id, file_name.to_string_lossy())))
+}
+
+/// Move a file, copying it when the destination is on another volume
+fn move_file(from: &Path, to: &Path) -> Result<(), String> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    fs::copy(from, to)
+        .and_then(|_| fs::remove_file(from))
+        .map_err(|e| format!("Failed to move {} to {}: {}", from.display(), to.display(), e))
+}
+
+fn get_image(db: &Database, image_id: i64) -> Result<ImageRecord, String> {
+    db.get_image_by_id(image_id)
+        .map_err(|e| format!("Database error: {}", e))?
+        .ok_or_else(|| format!("Image {} not found", image_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::albums;
+    use crate::database::{insert_test_media, ImageFilter, MediaType};
+
+    /// Library with one image file per name, and the IDs of their records
+    fn create_library(names: &[&str]) -> (tempfile::TempDir, Database, Vec<i64>) {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let library = temp_dir.path().join("library");
+        fs::create_dir_all(&library).unwrap();
+
+        let ids = names
+            .iter()
+            .map(|name| {
+                let path = library.join(name);
+                fs::write(&path, name.as_bytes()).unwrap();
+                insert_test_media(&db, &path, MediaType::Image, 100, 100).id
+            })
+            .collect();
+
+        (temp_dir, db, ids)
+    }
+
+    fn library_ids(db: &Database) -> Vec<i64> {
+        let mut ids: Vec<i64> = db
+            .query_images(&ImageFilter::default())
+            .unwrap()
+            .into_iter()
+            .map(|i| i.id)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn test_trash_and_restore_in_place() {
+        let (_temp_dir, db, ids) = create_library(&["a.jpg", "b.jpg"]);
+        db.insert_tag(ids[0], "beach", 0.9).unwrap();
+        let album = albums::create_album(&db, "Album", None, false).unwrap();
+        albums::add_images(&db, album.id, &ids).unwrap();
+
+        let report = move_to_trash(&db, &[ids[0], 9999], None);
+        assert_eq!(report.image_ids, vec![ids[0]]);
+        assert_eq!(report.failed.len(), 1);
+
+        // Trashed images leave the library and albums but keep their data
+        assert_eq!(library_ids(&db), vec![ids[1]]);
+        assert_eq!(albums::get_images(&db, album.id).unwrap().len(), 1);
+        let trashed = db.get_trashed_images().unwrap();
+        assert_eq!(trashed.len(), 1);
+        assert!(trashed[0].trash_path.is_none());
+        assert!(Path::new(&trashed[0].path).exists());
+        assert_eq!(db.get_tags_for_image(ids[0]).unwrap().len(), 1);
+
+        // A vanished file does not remove a trashed record
+        assert_eq!(db.delete_image_by_path(&trashed[0].path).unwrap(), 0);
+
+        let report = restore(&db, &[ids[0], ids[1]]);
+        assert_eq!(report.image_ids, vec![ids[0]]);
+        assert_eq!(library_ids(&db), ids);
+        assert_eq!(albums::get_images(&db, album.id).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_trash_folder_moves_originals() {
+        let (temp_dir, db, ids) = create_library(&["a.jpg"]);
+        let trash_dir = temp_dir.path().join("trash");
+        let original = db.get_image_by_id(ids[0]).unwrap().unwrap().path;
+
+        let report = move_to_trash(&db, &ids, Some(&trash_dir));
+        assert!(report.failed.is_empty());
+        let trashed = db.get_image_by_id(ids[0]).unwrap().unwrap();
+        let trash_path = trashed.trash_path.unwrap();
+        assert!(!Path::new(&original).exists());
+        assert!(Path::new(&trash_path).starts_with(&trash_dir));
+        assert_eq!(fs::read(&trash_path).unwrap(), b"a.jpg");
+
+        // Restoring refuses to overwrite a file that took the original's place
+        fs::write(&original, b"new").unwrap();
+        assert_eq!(restore(&db, &ids).failed.len(), 1);
+        fs::remove_file(&original).unwrap();
+
+        let report = restore(&db, &ids);
+        assert!(report.failed.is_empty());
+        assert_eq!(fs::read(&original).unwrap(), b"a.jpg");
+        assert!(!Path::new(&trash_path).exists());
+        assert!(db.get_image_by_id(ids[0]).unwrap().unwrap().trash_path.is_none());
+    }
+
+    #[test]
+    fn test_purge_deletes_files_and_records() {
+        let (temp_dir, db, ids) = create_library(&["a.jpg", "b.jpg", "c.jpg"]);
+        let trash_dir = temp_dir.path().join("trash");
+        move_to_trash(&db, &[ids[0]], Some(&trash_dir));
+        move_to_trash(&db, &[ids[1]], None);
+        let moved = db.get_image_by_id(ids[0]).unwrap().unwrap().trash_path.unwrap();
+        let in_place = db.get_image_by_id(ids[1]).unwrap().unwrap().path;
+
+        // Nothing has been in the trash for a day yet
+        assert!(purge_expired(&db, 1).unwrap().image_ids.is_empty());
+        assert!(purge_expired(&db, 0).unwrap().image_ids.is_empty());
+
+        let long_ago = (Utc::now() - Duration::days(40)).to_rfc3339();
+        db.connection()
+            .lock()
+            .unwrap()
+            .execute("UPDATE images SET trashed_at = ?1 WHERE id = ?2", rusqlite::params![long_ago, ids[0]])
+            .unwrap();
+
+        let report = purge_expired(&db, 30).unwrap();
+        assert_eq!(report.image_ids, vec![ids[0]]);
+        assert!(!Path::new(&moved).exists());
+        assert!(Path::new(&in_place).exists());
+        assert!(db.get_image_by_id(ids[0]).unwrap().is_none());
+
+        let report = empty_trash(&db).unwrap();
+        assert_eq!(report.image_ids, vec![ids[1]]);
+        assert!(!Path::new(&in_place).exists());
+        assert!(db.get_image_by_id(ids[1]).unwrap().is_none());
+        assert_eq!(library_ids(&db), vec![ids[2]]);
+    }
+
+    #[test]
+    fn test_purge_keeps_files_that_replaced_the_original() {
+        let (_temp_dir, db, ids) = create_library(&["a.jpg", "b.jpg"]);
+        move_to_trash(&db, &ids, None);
+        let replaced = db.get_image_by_id(ids[0]).unwrap().unwrap().path;
+        let unchanged = db.get_image_by_id(ids[1]).unwrap().unwrap().path;
+
+        // A new file with the same name and size is not the trashed one
+        fs::write(&replaced, b"A.JPG").unwrap();
+
+        let mut report = empty_trash(&db).unwrap();
+        report.image_ids.sort();
+        assert_eq!(report.image_ids, ids);
+        assert_eq!(fs::read(&replaced).unwrap(), b"A.JPG");
+        assert!(!Path::new(&unchanged).exists());
+    }
+
+    #[test]
+    fn test_purge_expired_with_huge_retention() {
+        let (_temp_dir, db, ids) = create_library(&["a.jpg"]);
+        move_to_trash(&db, &ids, None);
+
+        assert!(purge_expired(&db, u32::MAX).unwrap().image_ids.is_empty());
+        assert_eq!(db.get_trashed_images().unwrap().len(), 1);
+    }
+}
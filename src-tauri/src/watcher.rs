@@ -0,0 +1,717 @@
+use notify_debouncer_full::notify::event::{EventKind, ModifyKind, RenameMode};
+use notify_debouncer_full::notify::{Event, RecommendedWatcher, RecursiveMode};
+use notify_debouncer_full::{new_debouncer, DebounceEventResult, Debouncer, RecommendedCache};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+
+use crate::database::Database;
+use crate::importer::{self, FileChange, ImportError, MovedFile, RescanReport};
+use crate::scanner::{self, MediaFile};
+use crate::settings::FormatConfig;
+
+/// How long a path must be quiet before its events are delivered
+const DEBOUNCE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A coalesced change to a watched path
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum WatchChange {
+    Created { path: String },
+    Modified { path: String },
+    Renamed { from: String, to: String },
+    Removed { path: String },
+}
+
+/// Pending state of a single path while coalescing a batch of events
+#[derive(Debug, Clone)]
+enum Pending {
+    Created,
+    Modified,
+    Removed,
+    /// The path is the destination of a rename from the given original path
+    RenamedFrom(PathBuf),
+}
+
+/// Coalesce a batch of debounced filesystem events into one change per path
+///
+/// A file created and deleted within the same batch produces nothing, chained
+/// renames collapse into a single rename, and modifications after a create are
+/// folded into the create. Paths with extensions that are not enabled in the
+/// format configuration are dropped. Extension-less paths are kept, since they
+/// are usually folders whose contents need to be reconciled.
+pub fn coalesce_events(events: &[Event], config: &FormatConfig) -> Vec<WatchChange> {
+    let mut order: Vec<PathBuf> = Vec::new();
+    let mut pending: HashMap<PathBuf, Pending> = HashMap::new();
+
+    for event in events {
+        match &event.kind {
+            EventKind::Create(_) => {
+                for path in &event.paths {
+                    let state = match pending.get(path) {
+                        // A file replaced within the batch is a modification
+                        Some(Pending::Removed) => Pending::Modified,
+                        Some(Pending::RenamedFrom(from)) => Pending::RenamedFrom(from.clone()),
+                        _ => Pending::Created,
+                    };
+                    record(&mut order, &mut pending, path, state);
+                }
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                let (from, to) = (&event.paths[0], &event.paths[1]);
+                let state = match pending.remove(from) {
+                    Some(Pending::Created) => Pending::Created,
+                    Some(Pending::RenamedFrom(original)) => Pending::RenamedFrom(original),
+                    _ => Pending::RenamedFrom(from.clone()),
+                };
+                record(&mut order, &mut pending, to, state);
+            }
+            EventKind::Modify(ModifyKind::Name(mode)) => {
+                // Unpaired rename halves behave like a delete or a create
+                for path in &event.paths {
+                    let gone = match mode {
+                        RenameMode::From => true,
+                        RenameMode::To => false,
+                        _ => !path.exists(),
+                    };
+                    let state = if gone { removal_state(&mut order, &mut pending, path) } else { Some(Pending::Created) };
+                    match state {
+                        Some(state) => record(&mut order, &mut pending, path, state),
+                        None => {
+                            pending.remove(path);
+                        }
+                    }
+                }
+            }
+            EventKind::Modify(_) => {
+                for path in &event.paths {
+                    let state = match pending.get(path) {
+                        Some(Pending::Created) => Pending::Created,
+                        Some(Pending::RenamedFrom(from)) => Pending::RenamedFrom(from.clone()),
+                        _ => Pending::Modified,
+                    };
+                    record(&mut order, &mut pending, path, state);
+                }
+            }
+            EventKind::Remove(_) => {
+                for path in &event.paths {
+                    match removal_state(&mut order, &mut pending, path) {
+                        Some(state) => record(&mut order, &mut pending, path, state),
+                        None => {
+                            pending.remove(path);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut emitted = HashSet::new();
+    let mut changes = Vec::new();
+
+    for path in order {
+        if !emitted.insert(path.clone()) {
+            continue;
+        }
+        let state = match pending.remove(&path) {
+            Some(state) => state,
+            None => continue,
+        };
+
+        let path_str = path.to_string_lossy().to_string();
+        let change = match state {
+            Pending::Created if is_relevant(&path, config) => WatchChange::Created { path: path_str },
+            Pending::Modified if is_relevant(&path, config) => WatchChange::Modified { path: path_str },
+            Pending::Removed if is_relevant(&path, config) => WatchChange::Removed { path: path_str },
+            Pending::RenamedFrom(from) => {
+                let from_str = from.to_string_lossy().to_string();
+                let from_media = is_media(&from, config);
+                let to_media = is_media(&path, config);
+                let folders = from.extension().is_none() && path.extension().is_none();
+
+                if (from_media && to_media) || folders {
+                    WatchChange::Renamed { from: from_str, to: path_str }
+                } else if to_media {
+                    // e.g. a tethering app renaming "IMG_0001.jpg.part" when the transfer completes
+                    WatchChange::Created { path: path_str }
+                } else if from_media {
+                    WatchChange::Removed { path: from_str }
+                } else {
+                    continue;
+                }
+            }
+            _ => continue,
+        };
+
+        changes.push(change);
+    }
+
+    changes
+}
+
+/// Record the pending state of a path, remembering the order paths were first seen
+fn record(order: &mut Vec<PathBuf>, pending: &mut HashMap<PathBuf, Pending>, path: &Path, state: Pending) {
+    if !pending.contains_key(path) {
+        order.push(path.to_path_buf());
+    }
+    pending.insert(path.to_path_buf(), state);
+}
+
+/// State of a path after a removal, or None if the path should be forgotten
+fn removal_state(
+    order: &mut Vec<PathBuf>,
+    pending: &mut HashMap<PathBuf, Pending>,
+    path: &Path,
+) -> Option<Pending> {
+    match pending.get(path) {
+        // Created and deleted within the batch (e.g. a temp file)
+        Some(Pending::Created) => None,
+        // Renamed and then deleted: the original path is what the library knows about
+        Some(Pending::RenamedFrom(from)) => {
+            let from = from.clone();
+            pending.remove(path);
+            record(order, pending, &from, Pending::Removed);
+            None
+        }
+        _ => Some(Pending::Removed),
+    }
+}
+
+/// Whether a path has an extension enabled in the format configuration
+fn is_media(path: &Path, config: &FormatConfig) -> bool {
+    scanner::media_type_for_path(path, config).is_some()
+}
+
+/// Whether a path is a media file or possibly a folder
+fn is_relevant(path: &Path, config: &FormatConfig) -> bool {
+    path.extension().is_none() || is_media(path, config)
+}
+
+/// Apply coalesced changes to the library
+pub fn apply_changes(
+    changes: &[WatchChange],
+    config: &FormatConfig,
+    cache_dir: &Path,
+    db: &Database,
+) -> RescanReport {
+    let mut report = RescanReport::default();
+
+    for change in changes {
+        match change {
+            WatchChange::Created { path } | WatchChange::Modified { path } => {
+                reconcile_path(path, config, cache_dir, db, &mut report);
+            }
+            WatchChange::Renamed { from, to } => {
+                apply_rename(from, to, config, cache_dir, db, &mut report);
+            }
+            WatchChange::Removed { path } => {
+                apply_removal(path, db, &mut report);
+            }
+        }
+    }
+
+    report
+}
+
+/// Import or refresh a file, or every media file in a folder
+fn reconcile_path(
+    path: &str,
+    config: &FormatConfig,
+    cache_dir: &Path,
+    db: &Database,
+    report: &mut RescanReport,
+) {
+    let fs_path = Path::new(path);
+
+    let files = if fs_path.is_dir() {
+        match scanner::scan_folder(path, Some(config.clone())) {
+            Ok(scan) => scan.media_files,
+            Err(error) => {
+                report.failed.push(ImportError {
+                    path: path.to_string(),
+                    error,
+                });
+                return;
+            }
+        }
+    } else if fs_path.is_file() {
+        match scanner::media_type_for_path(fs_path, config) {
+            Some(media_type) => vec![MediaFile {
+                path: path.to_string(),
+                media_type,
+            }],
+            None => return,
+        }
+    } else {
+        // Gone again before we got to it
+        return;
+    };
+
+    for file in files {
+        match importer::reconcile_file(&file, cache_dir, db) {
+            Ok(FileChange::Added) => report.added.push(file.path),
+            Ok(FileChange::Updated) => report.updated.push(file.path),
+            Ok(_) => report.unchanged += 1,
+            Err(error) => report.failed.push(ImportError {
+                path: file.path,
+                error,
+            }),
+        }
+    }
+}
+
+/// Move library records to follow a renamed file or folder
+fn apply_rename(
+    from: &str,
+    to: &str,
+    config: &FormatConfig,
+    cache_dir: &Path,
+    db: &Database,
+    report: &mut RescanReport,
+) {
+    if Path::new(to).is_dir() {
+        match db.rename_folder_paths(from, to) {
+            Ok(0) => reconcile_path(to, config, cache_dir, db, report),
+            Ok(_) => report.moved.push(MovedFile {
+                from: from.to_string(),
+                to: to.to_string(),
+            }),
+            Err(e) => report.failed.push(ImportError {
+                path: to.to_string(),
+                error: format!("Failed to update moved folder: {}", e),
+            }),
+        }
+        return;
+    }
+
+    // A file moved over another one in the library takes the place of its record
+    if db.get_image_by_path(from).is_ok_and(|image| image.is_some()) {
+        if let Err(error) = remove_replaced(to, db) {
+            report.failed.push(ImportError {
+                path: to.to_string(),
+                error,
+            });
+            return;
+        }
+    }
+
+    match db.rename_image_path(from, to) {
+        Ok(0) => {
+            // Not in the library under the old name, treat as a new file
+            reconcile_path(to, config, cache_dir, db, report);
+        }
+        Ok(_) => {
+            report.moved.push(MovedFile {
+                from: from.to_string(),
+                to: to.to_string(),
+            });
+        }
+        Err(e) => report.failed.push(ImportError {
+            path: to.to_string(),
+            error: format!("Failed to update moved file: {}", e),
+        }),
+    }
+}
+
+/// Remove the record of a file that was overwritten by a moved file, along
+/// with its thumbnails unless another image shares them
+fn remove_replaced(path: &str, db: &Database) -> Result<(), String> {
+    let replaced = match db.get_image_by_path(path) {
+        Ok(Some(image)) => image,
+        Ok(None) => return Ok(()),
+        Err(e) => return Err(format!("Database error: {}", e)),
+    };
+
+    db.delete_image(replaced.id)
+        .map_err(|e| format!("Failed to remove overwritten file: {}", e))?;
+
+    if db.count_images_by_checksum(&replaced.checksum).is_ok_and(|count| count == 0) {
+        let _ = fs::remove_file(&replaced.thumbnail_small);
+        let _ = fs::remove_file(&replaced.thumbnail_medium);
+    }
+
+    Ok(())
+}
+
+/// Remove library records for a deleted file or folder
+fn apply_removal(path: &str, db: &Database, report: &mut RescanReport) {
+    if Path::new(path).exists() {
+        // Recreated since the event was emitted
+        return;
+    }
+
+    match db.delete_image_by_path(path) {
+        Ok(0) => {}
+        Ok(_) => {
+            report.removed.push(path.to_string());
+            return;
+        }
+        Err(e) => {
+            report.failed.push(ImportError {
+                path: path.to_string(),
+                error: format!("Failed to remove deleted file: {}", e),
+            });
+            return;
+        }
+    }
+
+    // Not a known file, so it may have been a folder
+    let images = match db.get_images_under_path(path) {
+        Ok(images) => images,
+        Err(e) => {
+            report.failed.push(ImportError {
+                path: path.to_string(),
+                error: format!("Database error: {}", e),
+            });
+            return;
+        }
+    };
+
+    for image in images {
+        if Path::new(&image.path).exists() || image.trashed_at.is_some() {
+            continue;
+        }
+        match db.delete_image(image.id) {
+            Ok(_) => report.removed.push(image.path),
+            Err(e) => report.failed.push(ImportError {
+                path: image.path,
+                error: format!("Failed to remove deleted file: {}", e),
+            }),
+        }
+    }
+}
+
+/// Watches library folders and delivers debounced, coalesced changes
+pub struct LibraryWatcher {
+    debouncer: Mutex<Debouncer<RecommendedWatcher, RecommendedCache>>,
+    folders: Mutex<Vec<String>>,
+}
+
+impl LibraryWatcher {
+    /// Create a watcher that calls `on_changes` with each batch of coalesced changes
+    ///
+    /// `format_config` is consulted for every batch so that format changes in
+    /// settings take effect without recreating the watcher. Changes are
+    /// delivered on a dedicated thread, so the handler may do slow work such
+    /// as importing files without stalling event collection.
+    pub fn new<C, F>(format_config: C, mut on_changes: F) -> Result<Self, String>
+    where
+        C: Fn() -> FormatConfig + Send + 'static,
+        F: FnMut(Vec<WatchChange>) + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel::<DebounceEventResult>();
+
+        let debouncer = new_debouncer(DEBOUNCE_TIMEOUT, None, tx)
+            .map_err(|e| format!("Failed to create file watcher: {}", e))?;
+
+        // The loop ends when the debouncer (and with it the sender) is dropped
+        std::thread::spawn(move || {
+            for result in rx {
+                match result {
+                    Ok(events) => {
+                        let events: Vec<Event> = events.into_iter().map(|e| e.event).collect();
+                        let changes = coalesce_events(&events, &format_config());
+                        if !changes.is_empty() {
+                            on_changes(changes);
+                        }
+                    }
+                    Err(errors) => {
+                        for error in errors {
+                            log::warn!("File watcher error: {}", error);
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            debouncer: Mutex::new(debouncer),
+            folders: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Start watching a folder recursively
+    pub fn watch(&self, folder: &str) -> Result<(), String> {
+        let path = Path::new(folder);
+        if !path.is_dir() {
+            return Err(format!("Path is not a directory: {}", folder));
+        }
+
+        let mut folders = self.folders.lock().unwrap();
+        if folders.iter().any(|f| f == folder) {
+            return Ok(());
+        }
+
+        self.debouncer
+            .lock()
+            .unwrap()
+            .watch(path, RecursiveMode::Recursive)
+            .map_err(|e| format!("Failed to watch {}: {}", folder, e))?;
+
+        folders.push(folder.to_string());
+        Ok(())
+    }
+
+    /// Stop watching a folder
+    pub fn unwatch(&self, folder: &str) -> Result<(), String> {
+        let mut folders = self.folders.lock().unwrap();
+        if !folders.iter().any(|f| f == folder) {
+            return Ok(());
+        }
+
+        self.debouncer
+            .lock()
+            .unwrap()
+            .unwatch(Path::new(folder))
+            .map_err(|e| format!("Failed to unwatch {}: {}", folder, e))?;
+
+        folders.retain(|f| f != folder);
+        Ok(())
+    }
+
+    /// Folders currently being watched
+    pub fn watched_folders(&self) -> Vec<String> {
+        self.folders.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgb};
+    use notify_debouncer_full::notify::event::{CreateKind, DataChange, RemoveKind};
+
+    fn event(kind: EventKind, paths: &[&str]) -> Event {
+        paths
+            .iter()
+            .fold(Event::new(kind), |e, p| e.add_path(PathBuf::from(p)))
+    }
+
+    fn create(path: &str) -> Event {
+        event(EventKind::Create(CreateKind::File), &[path])
+    }
+
+    fn modify(path: &str) -> Event {
+        event(EventKind::Modify(ModifyKind::Data(DataChange::Content)), &[path])
+    }
+
+    fn remove(path: &str) -> Event {
+        event(EventKind::Remove(RemoveKind::File), &[path])
+    }
+
+    fn rename(from: &str, to: &str) -> Event {
+        event(EventKind::Modify(ModifyKind::Name(RenameMode::Both)), &[from, to])
+    }
+
+    fn create_test_image(path: &Path) {
+        let img = ImageBuffer::from_fn(64, 48, |x, y| Rgb([x as u8, y as u8, 200]));
+        img.save(path).unwrap();
+    }
+
+    #[test]
+    fn test_coalesce_create_then_modify() {
+        let changes = coalesce_events(
+            &[create("/lib/a.jpg"), modify("/lib/a.jpg"), modify("/lib/a.jpg")],
+            &FormatConfig::default(),
+        );
+        assert_eq!(changes, vec![WatchChange::Created { path: "/lib/a.jpg".to_string() }]);
+    }
+
+    #[test]
+    fn test_coalesce_create_then_remove_cancels() {
+        let changes = coalesce_events(
+            &[create("/lib/a.jpg"), modify("/lib/a.jpg"), remove("/lib/a.jpg")],
+            &FormatConfig::default(),
+        );
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn test_coalesce_chained_renames() {
+        let changes = coalesce_events(
+            &[rename("/lib/a.jpg", "/lib/b.jpg"), rename("/lib/b.jpg", "/lib/c.jpg")],
+            &FormatConfig::default(),
+        );
+        assert_eq!(
+            changes,
+            vec![WatchChange::Renamed {
+                from: "/lib/a.jpg".to_string(),
+                to: "/lib/c.jpg".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_coalesce_partial_file_renamed_into_place() {
+        let changes = coalesce_events(
+            &[
+                create("/lib/IMG_0001.jpg.part"),
+                modify("/lib/IMG_0001.jpg.part"),
+                rename("/lib/IMG_0001.jpg.part", "/lib/IMG_0001.jpg"),
+            ],
+            &FormatConfig::default(),
+        );
+        assert_eq!(changes, vec![WatchChange::Created { path: "/lib/IMG_0001.jpg".to_string() }]);
+    }
+
+    #[test]
+    fn test_coalesce_ignores_unsupported_extensions() {
+        let changes = coalesce_events(
+            &[create("/lib/notes.txt"), modify("/lib/sidecar.xmp"), remove("/lib/old.psd")],
+            &FormatConfig::default(),
+        );
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn test_coalesce_respects_format_config() {
+        let config = FormatConfig {
+            image_formats: vec!["png".to_string()],
+            video_formats: vec!["mp4".to_string()],
+        };
+        let changes = coalesce_events(&[create("/lib/a.jpg"), create("/lib/b.PNG")], &config);
+        assert_eq!(changes, vec![WatchChange::Created { path: "/lib/b.PNG".to_string() }]);
+    }
+
+    #[test]
+    fn test_coalesce_rename_then_remove_removes_original() {
+        let changes = coalesce_events(
+            &[rename("/lib/a.jpg", "/lib/b.jpg"), remove("/lib/b.jpg")],
+            &FormatConfig::default(),
+        );
+        assert_eq!(changes, vec![WatchChange::Removed { path: "/lib/a.jpg".to_string() }]);
+    }
+
+    #[test]
+    fn test_apply_changes_updates_library() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let library = temp_dir.path().join("library");
+        fs::create_dir_all(&library).unwrap();
+        let db = Database::new(temp_dir.path().join("cura.db")).unwrap();
+        let cache_dir = temp_dir.path().join("thumbnails");
+        let config = FormatConfig::default();
+        let path_of = |name: &str| library.join(name).to_str().unwrap().to_string();
+
+        // A new file shows up
+        create_test_image(&library.join("a.png"));
+        let report = apply_changes(
+            &[WatchChange::Created { path: path_of("a.png") }],
+            &config,
+            &cache_dir,
+            &db,
+        );
+        assert_eq!(report.added, vec![path_of("a.png")]);
+        let id = db.get_image_by_path(&path_of("a.png")).unwrap().unwrap().id;
+
+        // It gets renamed
+        fs::rename(library.join("a.png"), library.join("b.png")).unwrap();
+        let report = apply_changes(
+            &[WatchChange::Renamed { from: path_of("a.png"), to: path_of("b.png") }],
+            &config,
+            &cache_dir,
+            &db,
+        );
+        assert_eq!(report.moved.len(), 1);
+        assert_eq!(db.get_image_by_path(&path_of("b.png")).unwrap().unwrap().id, id);
+
+        // And finally deleted
+        fs::remove_file(library.join("b.png")).unwrap();
+        let report = apply_changes(
+            &[WatchChange::Removed { path: path_of("b.png") }],
+            &config,
+            &cache_dir,
+            &db,
+        );
+        assert_eq!(report.removed, vec![path_of("b.png")]);
+        assert!(db.get_image_by_id(id).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_apply_changes_moves_file_over_another() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let library = temp_dir.path().join("library");
+        fs::create_dir_all(&library).unwrap();
+        let db = Database::new(temp_dir.path().join("cura.db")).unwrap();
+        let cache_dir = temp_dir.path().join("thumbnails");
+        let config = FormatConfig::default();
+        let path_of = |name: &str| library.join(name).to_str().unwrap().to_string();
+
+        create_test_image(&library.join("a.png"));
+        ImageBuffer::from_fn(64, 48, |x, y| Rgb([200, x as u8, y as u8]))
+            .save(library.join("b.png"))
+            .unwrap();
+        apply_changes(
+            &[
+                WatchChange::Created { path: path_of("a.png") },
+                WatchChange::Created { path: path_of("b.png") },
+            ],
+            &config,
+            &cache_dir,
+            &db,
+        );
+        let moved = db.get_image_by_path(&path_of("a.png")).unwrap().unwrap();
+        let replaced = db.get_image_by_path(&path_of("b.png")).unwrap().unwrap();
+
+        // a.png is moved onto b.png, overwriting it
+        fs::rename(library.join("a.png"), library.join("b.png")).unwrap();
+        let report = apply_changes(
+            &[WatchChange::Renamed { from: path_of("a.png"), to: path_of("b.png") }],
+            &config,
+            &cache_dir,
+            &db,
+        );
+        assert!(report.failed.is_empty());
+        assert_eq!(report.moved.len(), 1);
+        assert_eq!(db.get_image_by_path(&path_of("b.png")).unwrap().unwrap().id, moved.id);
+        assert!(db.get_image_by_id(replaced.id).unwrap().is_none());
+        assert!(!Path::new(&replaced.thumbnail_small).exists());
+        assert!(Path::new(&moved.thumbnail_small).exists());
+    }
+
+    #[test]
+    fn test_apply_changes_handles_folders() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let library = temp_dir.path().join("library");
+        fs::create_dir_all(library.join("shoot")).unwrap();
+        let db = Database::new(temp_dir.path().join("cura.db")).unwrap();
+        let cache_dir = temp_dir.path().join("thumbnails");
+        let config = FormatConfig::default();
+        let path_of = |name: &str| library.join(name).to_str().unwrap().to_string();
+
+        // A whole folder is copied in
+        create_test_image(&library.join("shoot/1.png"));
+        create_test_image(&library.join("shoot/2.jpg"));
+        let report = apply_changes(
+            &[WatchChange::Created { path: path_of("shoot") }],
+            &config,
+            &cache_dir,
+            &db,
+        );
+        assert_eq!(report.added.len(), 2);
+
+        // The folder is renamed
+        fs::rename(library.join("shoot"), library.join("beach")).unwrap();
+        let report = apply_changes(
+            &[WatchChange::Renamed { from: path_of("shoot"), to: path_of("beach") }],
+            &config,
+            &cache_dir,
+            &db,
+        );
+        assert_eq!(report.moved.len(), 1);
+        assert!(db.get_image_by_path(&path_of("beach/1.png")).unwrap().is_some());
+
+        // The folder is deleted
+        fs::remove_dir_all(library.join("beach")).unwrap();
+        let report = apply_changes(
+            &[WatchChange::Removed { path: path_of("beach") }],
+            &config,
+            &cache_dir,
+            &db,
+        );
+        assert_eq!(report.removed.len(), 2);
+    }
+}
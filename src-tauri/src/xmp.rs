@@ -0,0 +1,847 @@
+use quick_xml::escape::{escape, resolve_predefined_entity};
+use quick_xml::events::{BytesStart, BytesText, Event};
+use quick_xml::name::ResolveResult;
+use quick_xml::reader::NsReader;
+use quick_xml::writer::Writer;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::database::{Database, Flag, ImageRecord, MAX_RATING};
+use crate::settings::WriteBackMode;
+use crate::thumbnail;
+
+const NS_RDF: &[u8] = b"http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+const NS_DC: &str = "http://purl.org/dc/elements/1.1/";
+const NS_XMP: &str = "http://ns.adobe.com/xap/1.0/";
+
+/// Signature that starts the APP1 segment holding XMP in a JPEG
+const JPEG_XMP_SIGNATURE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// Largest XMP packet that fits in a single JPEG segment
+const MAX_JPEG_XMP_SIZE: usize = 65533 - JPEG_XMP_SIGNATURE.len();
+
+/// `xmp:Rating` value Lightroom and darktable use for rejected images
+const REJECTED_RATING: i32 = -1;
+
+/// Confidence given to tags read from XMP, which were added by a person
+const XMP_TAG_CONFIDENCE: f64 = 1.0;
+
+/// Line break and indentation before each property of a description
+const PROPERTY_INDENT: &str = "\n   ";
+
+/// Packet that new XMP documents are built from
+const EMPTY_DOCUMENT: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"/>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+
+/// The metadata Cura reads from and writes to XMP
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct XmpMetadata {
+    /// Keywords, stored in `dc:subject`
+    pub keywords: Vec<String>,
+    /// `xmp:Rating`: 0 to 5 stars, or -1 for rejected images
+    pub rating: Option<i32>,
+    /// Caption, stored in `dc:description`
+    pub description: Option<String>,
+}
+
+impl XmpMetadata {
+    /// Metadata of a library image, as it should appear in XMP
+    pub fn from_image(image: &ImageRecord, labels: impl IntoIterator<Item = String>) -> Self {
+        let mut seen = HashSet::new();
+        let keywords = labels
+            .into_iter()
+            .filter(|label| seen.insert(label.to_lowercase()))
+            .collect();
+
+        let rating = if image.flag == Flag::Reject {
+            REJECTED_RATING
+        } else {
+            image.rating as i32
+        };
+
+        Self {
+            keywords,
+            rating: Some(rating),
+            description: image.description.clone(),
+        }
+    }
+}
+
+/// Outcome of writing metadata for several images
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WriteBackReport {
+    /// Files that were written, sidecars or originals
+    pub written: Vec<String>,
+    pub failed: Vec<WriteBackError>,
+}
+
+/// An image whose metadata could not be written
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WriteBackError {
+    pub image_id: i64,
+    pub path: String,
+    pub error: String,
+}
+
+/// Write the tags, rating and description of images to XMP
+///
+/// Existing XMP is updated in place, so develop settings and other metadata
+/// written by other applications are kept. Trashed images are skipped.
+pub fn write_back(db: &Database, image_ids: &[i64], mode: WriteBackMode) -> WriteBackReport {
+    let mut report = WriteBackReport::default();
+    if mode == WriteBackMode::Off {
+        return report;
+    }
+
+    for &image_id in image_ids {
+        let image = match db.get_image_by_id(image_id) {
+            Ok(Some(image)) if image.trashed_at.is_some() => continue,
+            Ok(Some(image)) => image,
+            Ok(None) => {
+                report.failed.push(WriteBackError {
+                    image_id,
+                    path: String::new(),
+                    error: format!("Image {} not found", image_id),
+                });
+                continue;
+            }
+            Err(e) => {
+                report.failed.push(WriteBackError {
+                    image_id,
+                    path: String::new(),
+                    error: format!("Database error: {}", e),
+                });
+                continue;
+            }
+        };
+
+        match write_image(db, &image, mode) {
+            Ok(path) => report.written.push(path.to_string_lossy().to_string()),
+            Err(error) => report.failed.push(WriteBackError {
+                image_id,
+                path: image.path.clone(),
+                error,
+            }),
+        }
+    }
+
+    report
+}
+
+fn write_image(db: &Database, image: &ImageRecord, mode: WriteBackMode) -> Result<PathBuf, String> {
+    let tags = db
+        .get_tags_for_image(image.id)
+        .map_err(|e| format!("Database error: {}", e))?;
+    let metadata = XmpMetadata::from_image(image, tags.into_iter().map(|tag| tag.label));
+    let path = Path::new(&image.path);
+
+    if mode == WriteBackMode::Embedded && is_jpeg_path(path) {
+        write_embedded(path, &metadata)?;
+
+        // Only the metadata changed, so keep the rescan from re-processing the file
+        let checksum = thumbnail::compute_checksum(path)?;
+        let file_metadata = fs::metadata(path)
+            .map_err(|e| format!("Failed to read file metadata: {}", e))?;
+        let file_modified = file_metadata
+            .modified()
+            .map_err(|e| format!("Failed to read file modified time: {}", e))?;
+        db.update_file_checksum(image.id, &checksum, file_metadata.len(), file_modified.into())
+            .map_err(|e| format!("Database error: {}", e))?;
+
+        return Ok(path.to_path_buf());
+    }
+
+    write_sidecar(path, &metadata)
+}
+
+/// Merge the XMP of a newly imported file into its library record
+///
+/// Keywords become tags. The rating, reject flag and description are only
+/// taken when the library has none, so nothing set in Cura is overwritten.
+/// Returns whether the file had any XMP.
+pub fn import_metadata(db: &Database, image_id: i64, image_path: &Path) -> Result<bool, String> {
+    let metadata = match read_metadata(image_path)? {
+        Some(metadata) => metadata,
+        None => return Ok(false),
+    };
+
+    let image = db
+        .get_image_by_id(image_id)
+        .map_err(|e| format!("Database error: {}", e))?
+        .ok_or_else(|| format!("Image {} not found", image_id))?;
+    let mut known: HashSet<String> = db
+        .get_tags_for_image(image_id)
+        .map_err(|e| format!("Database error: {}", e))?
+        .into_iter()
+        .map(|tag| tag.label.to_lowercase())
+        .collect();
+
+    for keyword in &metadata.keywords {
+        if known.insert(keyword.to_lowercase()) {
+            db.insert_tag(image_id, keyword, XMP_TAG_CONFIDENCE)
+                .map_err(|e| format!("Database error: {}", e))?;
+        }
+    }
+
+    match metadata.rating {
+        Some(REJECTED_RATING) if image.flag == Flag::Unflagged => {
+            db.set_images_flag(&[image_id], Flag::Reject)
+                .map_err(|e| format!("Database error: {}", e))?;
+        }
+        Some(rating) if image.rating == 0 && (1..=MAX_RATING as i32).contains(&rating) => {
+            db.set_images_rating(&[image_id], rating as u8)
+                .map_err(|e| format!("Database error: {}", e))?;
+        }
+        _ => {}
+    }
+
+    if image.description.is_none() && metadata.description.is_some() {
+        db.set_images_description(&[image_id], metadata.description.as_deref())
+            .map_err(|e| format!("Database error: {}", e))?;
+    }
+
+    Ok(true)
+}
+
+/// Read the XMP of a file from its sidecar, or from the file itself for JPEGs
+pub fn read_metadata(image_path: &Path) -> Result<Option<XmpMetadata>, String> {
+    if let Some(sidecar) = find_sidecar(image_path) {
+        let xml = fs::read_to_string(&sidecar)
+            .map_err(|e| format!("Failed to read {}: {}", sidecar.display(), e))?;
+        return parse(&xml).map(Some);
+    }
+
+    if is_jpeg_path(image_path) {
+        let data = fs::read(image_path)
+            .map_err(|e| format!("Failed to read {}: {}", image_path.display(), e))?;
+        let jpeg = JpegSegments::parse(&data)?;
+        if let Some(packet) = jpeg.xmp_packet(&data) {
+            return parse(&String::from_utf8_lossy(packet)).map(Some);
+        }
+    }
+
+    Ok(None)
+}
+
+/// Sidecar names used by other applications, darktable's first
+///
+/// darktable appends `.xmp` to the full file name (`IMG_1234.CR2.xmp`),
+/// Lightroom replaces the extension (`IMG_1234.xmp`).
+pub fn sidecar_paths(image_path: &Path) -> [PathBuf; 2] {
+    let mut full_name = image_path.as_os_str().to_owned();
+    full_name.push(".xmp");
+    [PathBuf::from(full_name), image_path.with_extension("xmp")]
+}
+
+/// The existing sidecar of a file, if it has one
+pub fn find_sidecar(image_path: &Path) -> Option<PathBuf> {
+    sidecar_paths(image_path).into_iter().find(|path| path.is_file())
+}
+
+/// Write metadata to the sidecar of a file, updating it if one exists
+///
+/// New sidecars use darktable's naming, which keeps RAW+JPEG pairs apart.
+pub fn write_sidecar(image_path: &Path, metadata: &XmpMetadata) -> Result<PathBuf, String> {
+    let (sidecar, xml) = match find_sidecar(image_path) {
+        Some(sidecar) => {
+            let existing = fs::read_to_string(&sidecar)
+                .map_err(|e| format!("Failed to read {}: {}", sidecar.display(), e))?;
+            let xml = merge(&existing, metadata)?;
+            (sidecar, xml)
+        }
+        None => {
+            let [sidecar, _] = sidecar_paths(image_path);
+            let xml = format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{}\n",
+                merge(EMPTY_DOCUMENT, metadata)?
+            );
+            (sidecar, xml)
+        }
+    };
+
+    fs::write(&sidecar, xml).map_err(|e| format!("Failed to write {}: {}", sidecar.display(), e))?;
+    Ok(sidecar)
+}
+
+/// Embed metadata in the XMP segment of a JPEG, updating it if one exists
+pub fn write_embedded(jpeg_path: &Path, metadata: &XmpMetadata) -> Result<(), String> {
+    let data = fs::read(jpeg_path)
+        .map_err(|e| format!("Failed to read {}: {}", jpeg_path.display(), e))?;
+    let jpeg = JpegSegments::parse(&data)?;
+
+    let packet = match jpeg.xmp_packet(&data) {
+        Some(packet) => merge(&String::from_utf8_lossy(packet), metadata)?,
+        None => format!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n{}\n<?xpacket end=\"w\"?>",
+            merge(EMPTY_DOCUMENT, metadata)?
+        ),
+    };
+    if packet.len() > MAX_JPEG_XMP_SIZE {
+        return Err("Metadata is too large to embed in a JPEG".to_string());
+    }
+
+    let mut segment = vec![0xFF, 0xE1];
+    segment.extend_from_slice(&((2 + JPEG_XMP_SIGNATURE.len() + packet.len()) as u16).to_be_bytes());
+    segment.extend_from_slice(JPEG_XMP_SIGNATURE);
+    segment.extend_from_slice(packet.as_bytes());
+
+    let mut output = Vec::with_capacity(data.len() + segment.len());
+    match &jpeg.xmp {
+        Some(range) => {
+            output.extend_from_slice(&data[..range.start]);
+            output.extend_from_slice(&segment);
+            output.extend_from_slice(&data[range.end..]);
+        }
+        None => {
+            output.extend_from_slice(&data[..jpeg.insert_at]);
+            output.extend_from_slice(&segment);
+            output.extend_from_slice(&data[jpeg.insert_at..]);
+        }
+    }
+
+    // Write next to the original and swap it in, so a failure never leaves a broken file
+    let mut temp_name = jpeg_path.as_os_str().to_owned();
+    temp_name.push(".tmp");
+    let temp_path = PathBuf::from(temp_name);
+    fs::write(&temp_path, &output)
+        .and_then(|_| fs::rename(&temp_path, jpeg_path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&temp_path);
+            format!("Failed to write {}: {}", jpeg_path.display(), e)
+        })
+}
+
+fn is_jpeg_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| matches!(e.to_lowercase().as_str(), "jpg" | "jpeg"))
+        .unwrap_or(false)
+}
+
+/// Layout of the metadata segments at the start of a JPEG
+struct JpegSegments {
+    /// Byte range of the XMP segment, including its marker
+    xmp: Option<std::ops::Range<usize>>,
+    /// Where a new XMP segment goes: after the JFIF and EXIF segments
+    insert_at: usize,
+}
+
+impl JpegSegments {
+    fn parse(data: &[u8]) -> Result<Self, String> {
+        if !data.starts_with(&[0xFF, 0xD8]) {
+            return Err("Not a JPEG file".to_string());
+        }
+
+        let mut segments = JpegSegments { xmp: None, insert_at: 2 };
+        let mut leading_app_segments = true;
+        let mut pos = 2;
+
+        // Segments before the image data, which starts at SOS
+        while pos + 4 <= data.len() && data[pos] == 0xFF {
+            let marker = data[pos + 1];
+            if marker == 0xDA || marker == 0xD9 {
+                break;
+            }
+            let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+            let end = pos + 2 + length;
+            if length < 2 || end > data.len() {
+                return Err("Corrupt JPEG segment".to_string());
+            }
+
+            if marker == 0xE1 && data[pos + 4..end].starts_with(JPEG_XMP_SIGNATURE) {
+                segments.xmp = Some(pos..end);
+            }
+            if leading_app_segments && (marker == 0xE0 || marker == 0xE1) {
+                segments.insert_at = end;
+            } else {
+                leading_app_segments = false;
+            }
+
+            pos = end;
+        }
+
+        Ok(segments)
+    }
+
+    fn xmp_packet<'a>(&self, data: &'a [u8]) -> Option<&'a [u8]> {
+        self.xmp
+            .as_ref()
+            .map(|range| &data[range.start + 4 + JPEG_XMP_SIGNATURE.len()..range.end])
+    }
+}
+
+/// Namespaces of the elements and attributes Cura looks at
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Namespace {
+    Rdf,
+    Dc,
+    Xmp,
+    Other,
+}
+
+impl Namespace {
+    fn of(result: &ResolveResult) -> Self {
+        match result {
+            ResolveResult::Bound(ns) if ns.0 == NS_RDF => Namespace::Rdf,
+            ResolveResult::Bound(ns) if ns.0 == NS_DC.as_bytes() => Namespace::Dc,
+            ResolveResult::Bound(ns) if ns.0 == NS_XMP.as_bytes() => Namespace::Xmp,
+            _ => Namespace::Other,
+        }
+    }
+}
+
+/// Properties of `rdf:Description` that Cura reads and writes
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Property {
+    Subject,
+    Description,
+    Rating,
+}
+
+impl Property {
+    fn of(namespace: Namespace, local_name: &[u8]) -> Option<Self> {
+        match (namespace, local_name) {
+            (Namespace::Dc, b"subject") => Some(Property::Subject),
+            (Namespace::Dc, b"description") => Some(Property::Description),
+            (Namespace::Xmp, b"Rating") => Some(Property::Rating),
+            _ => None,
+        }
+    }
+}
+
+fn is_description(namespace: Namespace, element: &BytesStart) -> bool {
+    namespace == Namespace::Rdf && element.local_name().as_ref() == b"Description"
+}
+
+fn invalid(error: impl std::fmt::Display) -> String {
+    format!("Invalid XMP: {}", error)
+}
+
+fn parse_rating(value: &str) -> Option<i32> {
+    value
+        .trim()
+        .parse::<i32>()
+        .ok()
+        .filter(|rating| (REJECTED_RATING..=MAX_RATING as i32).contains(rating))
+}
+
+/// Read keywords, rating and description from an XMP document
+pub fn parse(xml: &str) -> Result<XmpMetadata, String> {
+    let mut reader = NsReader::from_str(xml);
+    let mut metadata = XmpMetadata::default();
+    // Property being read and the depth of its element
+    let mut property: Option<(Property, usize)> = None;
+    let mut depth = 0;
+    let mut text = String::new();
+
+    loop {
+        let (namespace, event) = reader.read_resolved_event().map_err(invalid)?;
+        let namespace = Namespace::of(&namespace);
+
+        match &event {
+            Event::Start(e) | Event::Empty(e) => {
+                // Simple properties may be written as attributes of the description
+                if is_description(namespace, e) {
+                    for attribute in e.attributes() {
+                        let attribute = attribute.map_err(invalid)?;
+                        let (attribute_ns, local_name) = reader.resolve_attribute(attribute.key);
+                        if Property::of(Namespace::of(&attribute_ns), local_name.as_ref()) == Some(Property::Rating) {
+                            metadata.rating = parse_rating(&attribute.unescape_value().map_err(invalid)?);
+                        }
+                    }
+                }
+
+                if let Event::Start(_) = event {
+                    depth += 1;
+                    if property.is_none() {
+                        property = Property::of(namespace, e.local_name().as_ref()).map(|found| (found, depth));
+                    }
+                }
+                text.clear();
+            }
+            Event::Text(e) => text.push_str(&e.decode().map_err(invalid)?),
+            Event::CData(e) => text.push_str(&e.decode().map_err(invalid)?),
+            Event::GeneralRef(e) => match e.resolve_char_ref().map_err(invalid)? {
+                Some(ch) => text.push(ch),
+                None => {
+                    let name = e.decode().map_err(invalid)?;
+                    let value = resolve_predefined_entity(&name)
+                        .ok_or_else(|| invalid(format!("unknown entity &{};", name)))?;
+                    text.push_str(value);
+                }
+            },
+            Event::End(e) => {
+                if let Some((current, start_depth)) = property {
+                    let value = text.trim();
+                    if depth == start_depth {
+                        if current == Property::Rating {
+                            metadata.rating = parse_rating(value);
+                        }
+                        property = None;
+                    } else if e.local_name().as_ref() == b"li" && !value.is_empty() {
+                        match current {
+                            Property::Subject => metadata.keywords.push(value.to_string()),
+                            Property::Description if metadata.description.is_none() => {
+                                metadata.description = Some(value.to_string());
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                text.clear();
+                depth -= 1;
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(metadata)
+}
+
+/// Replace Cura's properties in an XMP document, keeping everything else
+///
+/// The properties are written into the first `rdf:Description`, and removed
+/// from any other description.
+fn merge(xml: &str, metadata: &XmpMetadata) -> Result<String, String> {
+    let mut reader = NsReader::from_str(xml);
+    let mut writer = Writer::new(Vec::new());
+    let mut written = false;
+    // Whether each open element is a description
+    let mut open: Vec<bool> = Vec::new();
+    // Depth of the property being dropped, while inside one
+    let mut skipping: Option<usize> = None;
+
+    loop {
+        let (namespace, event) = reader.read_resolved_event().map_err(invalid)?;
+        let namespace = Namespace::of(&namespace);
+
+        if let Some(depth) = skipping {
+            match event {
+                Event::Start(_) => open.push(false),
+                Event::End(_) => {
+                    open.pop();
+                    if open.len() == depth {
+                        skipping = None;
+                    }
+                }
+                Event::Eof => return Err(invalid("unexpected end of document")),
+                _ => {}
+            }
+            continue;
+        }
+
+        let in_description = open.last() == Some(&true);
+        match event {
+            Event::Start(e) if is_description(namespace, &e) => {
+                let start = rewrite_description(&reader, &e, !written)?;
+                writer.write_event(Event::Start(start)).map_err(invalid)?;
+                if !written {
+                    write_properties(&mut writer, &e, metadata)?;
+                    written = true;
+                }
+                open.push(true);
+            }
+            Event::Empty(e) if is_description(namespace, &e) => {
+                let start = rewrite_description(&reader, &e, !written)?;
+                if written {
+                    writer.write_event(Event::Empty(start)).map_err(invalid)?;
+                } else {
+                    let end = start.to_end().into_owned();
+                    writer.write_event(Event::Start(start)).map_err(invalid)?;
+                    write_properties(&mut writer, &e, metadata)?;
+                    writer.write_event(Event::Text(BytesText::from_escaped("\n  "))).map_err(invalid)?;
+                    writer.write_event(Event::End(end)).map_err(invalid)?;
+                    written = true;
+                }
+            }
+            Event::Start(e) if in_description && Property::of(namespace, e.local_name().as_ref()).is_some() => {
+                skipping = Some(open.len());
+                open.push(false);
+            }
+            Event::Empty(e) if in_description && Property::of(namespace, e.local_name().as_ref()).is_some() => {}
+            // Properties are laid out one per line, so repeated merges give the same document
+            Event::Text(e) if in_description && e.iter().all(u8::is_ascii_whitespace) => {}
+            Event::Start(e) => {
+                if in_description {
+                    writer.write_event(Event::Text(BytesText::from_escaped(PROPERTY_INDENT))).map_err(invalid)?;
+                }
+                writer.write_event(Event::Start(e)).map_err(invalid)?;
+                open.push(false);
+            }
+            Event::Empty(e) if in_description => {
+                writer.write_event(Event::Text(BytesText::from_escaped(PROPERTY_INDENT))).map_err(invalid)?;
+                writer.write_event(Event::Empty(e)).map_err(invalid)?;
+            }
+            Event::End(e) => {
+                if in_description {
+                    writer.write_event(Event::Text(BytesText::from_escaped("\n  "))).map_err(invalid)?;
+                }
+                writer.write_event(Event::End(e)).map_err(invalid)?;
+                open.pop();
+            }
+            Event::Eof => break,
+            event => writer.write_event(event).map_err(invalid)?,
+        }
+    }
+
+    if !written {
+        return Err(invalid("no rdf:Description element"));
+    }
+
+    String::from_utf8(writer.into_inner()).map_err(invalid)
+}
+
+/// Copy the start tag of a description without the rating attribute
+///
+/// The first description also gets the namespaces of Cura's properties.
+fn rewrite_description(
+    reader: &NsReader<&[u8]>,
+    element: &BytesStart,
+    declare_namespaces: bool,
+) -> Result<BytesStart<'static>, String> {
+    let name = String::from_utf8_lossy(element.name().as_ref()).to_string();
+    let mut start = BytesStart::new(name);
+    let mut declared = HashSet::new();
+
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(invalid)?;
+        let (namespace, local_name) = reader.resolve_attribute(attribute.key);
+        if Property::of(Namespace::of(&namespace), local_name.as_ref()) == Some(Property::Rating) {
+            continue;
+        }
+        declared.insert(attribute.key.as_ref().to_vec());
+        start.push_attribute(attribute);
+    }
+
+    if declare_namespaces {
+        for (key, namespace) in [("xmlns:dc", NS_DC), ("xmlns:xmp", NS_XMP)] {
+            if !declared.contains(key.as_bytes()) {
+                start.push_attribute((key, namespace));
+            }
+        }
+    }
+
+    Ok(start)
+}
+
+/// Write Cura's properties as children of a description
+fn write_properties(
+    writer: &mut Writer<Vec<u8>>,
+    description: &BytesStart,
+    metadata: &XmpMetadata,
+) -> Result<(), String> {
+    // RDF elements use the same prefix as the description
+    let rdf = match description.name().prefix() {
+        Some(prefix) => format!("{}:", String::from_utf8_lossy(prefix.as_ref())),
+        None => String::new(),
+    };
+
+    let mut xml = String::new();
+    if !metadata.keywords.is_empty() {
+        xml.push_str(&format!("{}<dc:subject>\n    <{}Bag>", PROPERTY_INDENT, rdf));
+        for keyword in &metadata.keywords {
+            xml.push_str(&format!("\n     <{0}li>{1}</{0}li>", rdf, escape(keyword.as_str())));
+        }
+        xml.push_str(&format!("\n    </{}Bag>\n   </dc:subject>", rdf));
+    }
+    if let Some(description) = &metadata.description {
+        xml.push_str(&format!(
+            "{2}<dc:description>\n    <{0}Alt>\n     <{0}li xml:lang=\"x-default\">{1}</{0}li>\n    </{0}Alt>\n   </dc:description>",
+            rdf,
+            escape(description.as_str()),
+            PROPERTY_INDENT
+        ));
+    }
+    if let Some(rating) = metadata.rating {
+        xml.push_str(&format!("{}<xmp:Rating>{}</xmp:Rating>", PROPERTY_INDENT, rating));
+    }
+
+    writer
+        .write_event(Event::Text(BytesText::from_escaped(xml)))
+        .map_err(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::MediaType;
+    use chrono::Utc;
+    use image::{DynamicImage, RgbImage};
+
+    /// Sidecar as Lightroom writes it, with the rating as an attribute and develop settings
+    const LIGHTROOM_SIDECAR: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/" x:xmptk="Adobe XMP Core 7.0">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:crs="http://ns.adobe.com/camera-raw-settings/1.0/"
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+   xmp:Rating="4"
+   crs:Exposure2012="+0.35">
+   <dc:subject>
+    <rdf:Bag>
+     <rdf:li>Beach</rdf:li>
+     <rdf:li>Fish &amp; Chips</rdf:li>
+    </rdf:Bag>
+   </dc:subject>
+   <dc:description>
+    <rdf:Alt>
+     <rdf:li xml:lang="x-default">Sunset at the pier</rdf:li>
+    </rdf:Alt>
+   </dc:description>
+   <crs:ToneCurvePV2012>
+    <rdf:Seq>
+     <rdf:li>0, 0</rdf:li>
+    </rdf:Seq>
+   </crs:ToneCurvePV2012>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+
+    fn create_library() -> (tempfile::TempDir, Database, i64, PathBuf) {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let path = temp_dir.path().join("photo.jpg");
+        DynamicImage::ImageRgb8(RgbImage::new(8, 8)).save(&path).unwrap();
+        let path_str = path.to_str().unwrap();
+
+        let image_id = db
+            .insert_image(
+                path_str, "s.jpg", "m.jpg", "checksum", MediaType::Image,
+                None, None, None, None, None, 8, 8, None, None, 1024, Utc::now(),
+            )
+            .unwrap();
+
+        (temp_dir, db, image_id, path)
+    }
+
+    #[test]
+    fn test_parse_lightroom_sidecar() {
+        let metadata = parse(LIGHTROOM_SIDECAR).unwrap();
+        assert_eq!(metadata.keywords, vec!["Beach", "Fish & Chips"]);
+        assert_eq!(metadata.rating, Some(4));
+        assert_eq!(metadata.description.as_deref(), Some("Sunset at the pier"));
+
+        // darktable writes the rating as an element
+        let darktable = EMPTY_DOCUMENT.replace(
+            "xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"/>",
+            "xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"><xmp:Rating>-1</xmp:Rating></rdf:Description>",
+        );
+        assert_eq!(parse(&darktable).unwrap().rating, Some(REJECTED_RATING));
+        assert!(parse("<x:xmpmeta></rdf:RDF>").is_err());
+    }
+
+    #[test]
+    fn test_merge_keeps_other_metadata() {
+        let metadata = XmpMetadata {
+            keywords: vec!["Dog".to_string(), "<Park>".to_string()],
+            rating: Some(2),
+            description: None,
+        };
+
+        let merged = merge(LIGHTROOM_SIDECAR, &metadata).unwrap();
+        assert_eq!(parse(&merged).unwrap(), metadata);
+        assert!(merged.contains("crs:Exposure2012=\"+0.35\""));
+        assert!(merged.contains("<crs:ToneCurvePV2012>"));
+        assert!(!merged.contains("Beach"));
+        assert!(!merged.contains("xmp:Rating=\"4\""));
+
+        // Merging again gives the same document
+        assert_eq!(merge(&merged, &metadata).unwrap(), merged);
+
+        assert_eq!(parse(&merge(EMPTY_DOCUMENT, &metadata).unwrap()).unwrap(), metadata);
+        assert!(merge("<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>", &metadata).is_err());
+    }
+
+    #[test]
+    fn test_write_back_to_sidecar_and_import() {
+        let (_temp_dir, db, image_id, path) = create_library();
+        db.insert_tag(image_id, "beach", 0.9).unwrap();
+        db.insert_tag(image_id, "Beach", 0.7).unwrap();
+        db.set_images_rating(&[image_id], 3).unwrap();
+        db.set_images_description(&[image_id], Some("Low tide")).unwrap();
+
+        assert!(write_back(&db, &[image_id], WriteBackMode::Off).written.is_empty());
+        let report = write_back(&db, &[image_id, 9999], WriteBackMode::Sidecar);
+        assert_eq!(report.failed.len(), 1);
+
+        // New sidecars are named like darktable's
+        let sidecar = PathBuf::from(format!("{}.xmp", path.display()));
+        assert_eq!(report.written, vec![sidecar.to_string_lossy().to_string()]);
+        let metadata = read_metadata(&path).unwrap().unwrap();
+        assert_eq!(metadata.keywords, vec!["beach"]);
+        assert_eq!(metadata.rating, Some(3));
+        assert_eq!(metadata.description.as_deref(), Some("Low tide"));
+
+        // A Lightroom sidecar is updated rather than joined by a second one
+        fs::remove_file(&sidecar).unwrap();
+        fs::write(path.with_extension("xmp"), LIGHTROOM_SIDECAR).unwrap();
+        write_back(&db, &[image_id], WriteBackMode::Sidecar);
+        assert!(!sidecar.exists());
+        let updated = fs::read_to_string(path.with_extension("xmp")).unwrap();
+        assert!(updated.contains("crs:Exposure2012"));
+        assert_eq!(parse(&updated).unwrap().keywords, vec!["beach"]);
+
+        // Importing keeps what is already in the library
+        fs::write(path.with_extension("xmp"), LIGHTROOM_SIDECAR).unwrap();
+        assert!(import_metadata(&db, image_id, &path).unwrap());
+        let mut labels: Vec<String> = db
+            .get_tags_for_image(image_id)
+            .unwrap()
+            .into_iter()
+            .map(|tag| tag.label)
+            .collect();
+        labels.sort();
+        assert_eq!(labels, vec!["Beach", "Fish & Chips", "beach"]);
+        let image = db.get_image_by_id(image_id).unwrap().unwrap();
+        assert_eq!(image.rating, 3);
+        assert_eq!(image.description.as_deref(), Some("Low tide"));
+    }
+
+    #[test]
+    fn test_write_back_embedded_in_jpeg() {
+        let (_temp_dir, db, image_id, path) = create_library();
+        db.insert_tag(image_id, "forest", 0.8).unwrap();
+        db.set_images_flag(&[image_id], Flag::Reject).unwrap();
+
+        let report = write_back(&db, &[image_id], WriteBackMode::Embedded);
+        assert!(report.failed.is_empty());
+        assert!(find_sidecar(&path).is_none());
+
+        let metadata = read_metadata(&path).unwrap().unwrap();
+        assert_eq!(metadata.keywords, vec!["forest"]);
+        assert_eq!(metadata.rating, Some(REJECTED_RATING));
+        assert_eq!(image::open(&path).unwrap().width(), 8);
+
+        // The record follows the rewritten file, so rescans leave it alone
+        let image = db.get_image_by_id(image_id).unwrap().unwrap();
+        assert_eq!(image.checksum, thumbnail::compute_checksum(&path).unwrap());
+        assert_eq!(image.file_size, fs::metadata(&path).unwrap().len());
+
+        // Writing again replaces the segment instead of adding another
+        db.insert_tag(image_id, "moss", 0.8).unwrap();
+        let size = fs::metadata(&path).unwrap().len();
+        write_back(&db, &[image_id], WriteBackMode::Embedded);
+        assert_eq!(read_metadata(&path).unwrap().unwrap().keywords, vec!["forest", "moss"]);
+        assert!(fs::metadata(&path).unwrap().len() < size + 100);
+
+        // Other formats fall back to sidecars
+        let png = path.with_extension("png");
+        DynamicImage::ImageRgb8(RgbImage::new(8, 8)).save(&png).unwrap();
+        let png_id = db
+            .insert_image(
+                png.to_str().unwrap(), "s.jpg", "m.jpg", "checksum2", MediaType::Image,
+                None, None, None, None, None, 8, 8, None, None, 1024, Utc::now(),
+            )
+            .unwrap();
+        write_back(&db, &[png_id], WriteBackMode::Embedded);
+        assert!(find_sidecar(&png).is_some());
+    }
+}